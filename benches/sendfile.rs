@@ -0,0 +1,87 @@
+//! Throughput of large file downloads with and without `sendfile`.
+//!
+//! Run with `cargo bench --bench sendfile`. Set `TINYSERVE_BENCH_MB` to
+//! change the file size (default 512).
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tinyserve::Server;
+use tinyserve::core::{Config, Layer};
+
+const ROUNDS: usize = 5;
+
+fn start(root: &str, sendfile: bool) -> SocketAddr {
+    let mut config = Config::default();
+    config.set_raw("root", root, Layer::Cli).unwrap();
+    config.set_raw("port", "0", Layer::Cli).unwrap();
+    config
+        .set_raw("sendfile", &sendfile.to_string(), Layer::Cli)
+        .unwrap();
+    let server = Server::bind(config).unwrap();
+    let addr = server.local_addr();
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// Downloads `/big.bin` once and returns the body size.
+fn fetch(addr: SocketAddr) -> u64 {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /big.bin HTTP/1.1\r\nHost: bench\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::with_capacity(1 << 16, stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap() > 2 {
+        line.clear();
+    }
+    let mut buf = vec![0u8; 1 << 16];
+    let mut total = 0;
+    loop {
+        match reader.read(&mut buf).unwrap() {
+            0 => return total,
+            n => total += n as u64,
+        }
+    }
+}
+
+fn measure(name: &str, addr: SocketAddr, size: u64) {
+    fetch(addr);
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let started = Instant::now();
+        assert_eq!(fetch(addr), size);
+        best = best.min(started.elapsed());
+    }
+    let mb = size as f64 / (1024.0 * 1024.0);
+    println!(
+        "{name:>12}: best of {ROUNDS} {:>8.1} ms  {:>8.1} MB/s",
+        best.as_secs_f64() * 1000.0,
+        mb / best.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mb: u64 = env::var("TINYSERVE_BENCH_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(512);
+    let dir = env::temp_dir().join(format!("tinyserve-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut file = File::create(dir.join("big.bin")).unwrap();
+    let block: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+    for _ in 0..mb {
+        file.write_all(&block).unwrap();
+    }
+    drop(file);
+
+    let root = dir.to_str().unwrap();
+    let size = mb << 20;
+    measure("read/write", start(root, false), size);
+    measure("sendfile", start(root, true), size);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,38 @@
+//! Arbitrary bytes as a connection's input: request heads, their framing
+//! and bodies, pipelined until the input or the parser gives out.
+//!
+//! ```sh
+//! cargo +nightly fuzz run request
+//! ```
+
+#![no_main]
+
+use std::io::BufReader;
+use std::net::{Ipv4Addr, SocketAddr};
+
+use libfuzzer_sys::fuzz_target;
+use tinyserve::http::request::Request;
+
+fuzz_target!(|data: &[u8]| {
+    let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let mut src = BufReader::new(data);
+    let mut buf = [0u8; 4096];
+    loop {
+        let Ok(Some(req)) = Request::read_head(&mut src, peer) else {
+            return;
+        };
+        let Ok(mut framing) = req.framing() else {
+            return;
+        };
+        loop {
+            match framing.read(&mut src, &mut buf) {
+                Ok(0) => break,
+                Ok(n) => assert!(n <= buf.len()),
+                Err(_) => return,
+            }
+        }
+        if !req.keep_alive() {
+            return;
+        }
+    }
+});
@@ -0,0 +1,441 @@
+//! Per-directory access rules from `.tinyserve-access` files.
+//!
+//! With `accessFiles` on, a `.tinyserve-access` file guards its directory
+//! and everything below it, so one share can have public and private
+//! areas. It is a JSON object; every key is optional:
+//!
+//! ```json
+//! {"users": ["alice:secret"], "token": "s3cret", "realm": "Team",
+//!  "allow": ["192.168.1.0/24", "::1"], "public": false, "for": "all"}
+//! ```
+//!
+//! `users` and `token` are accepted here on top of the global `authUsers`
+//! and `authToken` and any registered
+//! [`AuthProvider`](crate::auth::AuthProvider), and make credentials
+//! necessary for `for` requests (`all`, or `writes`); other requests still
+//! answer to the global `authFor`. `public` lifts the credential
+//! requirement for reads only: writes still need the global credentials.
+//! `allow` turns away other peers whatever their credentials. Files deeper
+//! in the tree override the keys they set, and setting `users` or `token`
+//! ends a `public` inherited from above.
+//!
+//! Access files are never served, listed or written through the server.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow, bail};
+use serde_json::Value;
+
+use crate::auth::{self, AuthProvider, Guard};
+use crate::files::{self, resolve};
+use crate::http::{Request, Response};
+use crate::log;
+use crate::server::{Context, debug};
+use crate::webdav;
+
+/// Name of the access file in a directory.
+pub const FILE_NAME: &str = ".tinyserve-access";
+
+/// The rules of one file, or several merged down a path.
+#[derive(Clone, Debug, Default)]
+struct Rule {
+    users: Option<Vec<String>>,
+    token: Option<String>,
+    realm: Option<String>,
+    allow: Option<Vec<Net>>,
+    public: Option<bool>,
+    writes_only: Option<bool>,
+}
+
+impl Rule {
+    fn parse(text: &str) -> Result<Self> {
+        let Value::Object(map) = serde_json::from_str(text)? else {
+            bail!("expected a JSON object");
+        };
+        let mut rule = Rule::default();
+        for (key, value) in map {
+            let string = |v: &Value| {
+                v.as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("`{key}` must be a string"))
+            };
+            let strings = |v: &Value| -> Result<Vec<String>> {
+                v.as_array()
+                    .ok_or_else(|| anyhow!("`{key}` must be an array of strings"))?
+                    .iter()
+                    .map(string)
+                    .collect()
+            };
+            match key.as_str() {
+                "users" => rule.users = Some(strings(&value)?),
+                "token" => rule.token = Some(string(&value)?),
+                "realm" => rule.realm = Some(string(&value)?),
+                "allow" => {
+                    let nets: Result<Vec<Net>> =
+                        strings(&value)?.iter().map(|s| Net::parse(s)).collect();
+                    rule.allow = Some(nets?);
+                }
+                "public" => {
+                    rule.public = Some(
+                        value
+                            .as_bool()
+                            .ok_or_else(|| anyhow!("`public` must be true or false"))?,
+                    );
+                }
+                "for" => match value.as_str() {
+                    Some("all") => rule.writes_only = Some(false),
+                    Some("writes") => rule.writes_only = Some(true),
+                    _ => bail!("`for` must be \"all\" or \"writes\""),
+                },
+                _ => bail!("unknown key `{key}`"),
+            }
+        }
+        Ok(rule)
+    }
+
+    /// Applies a file deeper in the tree.
+    fn merge(&mut self, inner: &Rule) {
+        if inner.users.is_some() || inner.token.is_some() {
+            self.public = None;
+        }
+        self.users = inner.users.clone().or(self.users.take());
+        self.token = inner.token.clone().or(self.token.take());
+        self.realm = inner.realm.clone().or(self.realm.take());
+        self.allow = inner.allow.clone().or(self.allow.take());
+        self.public = inner.public.or(self.public);
+        self.writes_only = inner.writes_only.or(self.writes_only);
+    }
+}
+
+/// An address or CIDR block from `allow`.
+#[derive(Clone, Copy, Debug)]
+struct Net {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Net {
+    fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("`{s}` is not an address or CIDR block"))?;
+        let addr = addr.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .trim()
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| anyhow!("bad prefix length in `{s}`"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let shift = 32 - self.prefix;
+                u32::from(net).checked_shr(shift).unwrap_or(0)
+                    == u32::from(ip).checked_shr(shift).unwrap_or(0)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let shift = 128 - self.prefix;
+                u128::from(net).checked_shr(shift).unwrap_or(0)
+                    == u128::from(ip).checked_shr(shift).unwrap_or(0)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A parsed file with the modification time it was read at.
+type Cached = (Option<SystemTime>, Arc<Rule>);
+
+/// Parsed access files, re-read when they change.
+#[derive(Debug, Default)]
+pub struct AccessFiles {
+    cache: Mutex<HashMap<PathBuf, Cached>>,
+}
+
+impl AccessFiles {
+    /// The rules for `url_path`, merged from the root down.
+    fn rule(&self, ctx: &Context, url_path: &str) -> Result<Rule, Response> {
+        let mut rule = Rule::default();
+        let Some(target) = resolve::resolve(&ctx.root, url_path, true) else {
+            return Ok(rule);
+        };
+        let mut dir = ctx.root.clone();
+        if let Some(file) = self.load(ctx, &dir)? {
+            rule.merge(&file);
+        }
+        let rest = target.strip_prefix(&ctx.root).unwrap_or(Path::new(""));
+        for component in rest.components() {
+            dir.push(component);
+            if let Some(file) = self.load(ctx, &dir)? {
+                rule.merge(&file);
+            }
+        }
+        Ok(rule)
+    }
+
+    /// The access file of `dir`, if it has one.
+    fn load(&self, ctx: &Context, dir: &Path) -> Result<Option<Arc<Rule>>, Response> {
+        let path = dir.join(FILE_NAME);
+        let Ok(meta) = files::metadata(ctx, &path) else {
+            return Ok(None);
+        };
+        if !meta.is_file {
+            return Ok(None);
+        }
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((modified, rule)) = cache.get(&path)
+            && *modified == meta.modified
+        {
+            return Ok(Some(rule.clone()));
+        }
+        let parsed = files::read(ctx, &path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Rule::parse(&String::from_utf8_lossy(&bytes)));
+        match parsed {
+            Ok(rule) => {
+                let rule = Arc::new(rule);
+                cache.insert(path, (meta.modified, rule.clone()));
+                Ok(Some(rule))
+            }
+            Err(e) => {
+                // Fail closed: a broken file must not open up its subtree.
+                log::warn(&format!("{}: {e:#}", path.display()));
+                Err(Response::error_with(500, "an access file is invalid"))
+            }
+        }
+    }
+}
+
+/// Whether `name` is reserved for access files.
+pub fn is_access_file(name: &str) -> bool {
+    name == FILE_NAME
+}
+
+/// The response turning `req` away, if its access rules (or, where none
+/// speak, the global auth settings) do. `COPY` and `MOVE` must also pass
+/// the rules of their destination.
+pub fn check(ctx: &Context, req: &Request) -> Option<Response> {
+    let files = ctx.access.as_ref()?;
+    let destination = matches!(req.method.as_str(), "COPY" | "MOVE")
+        .then(|| webdav::destination(req))
+        .flatten();
+    std::iter::once(req.path.as_str())
+        .chain(destination.as_deref())
+        .find_map(|path| check_rules(ctx, files, req, path))
+}
+
+/// The response turning `req` away from `url_path`, which it reaches
+/// indirectly (a server-side include, say), if the access rules do.
+pub fn check_path(ctx: &Context, req: &Request, url_path: &str) -> Option<Response> {
+    check_rules(ctx, ctx.access.as_ref()?, req, url_path)
+}
+
+fn check_rules(ctx: &Context, files: &AccessFiles, req: &Request, path: &str) -> Option<Response> {
+    let rule = match files.rule(ctx, path) {
+        Ok(rule) => rule,
+        Err(resp) => return Some(resp),
+    };
+    let resp = enforce(ctx, req, &rule)?;
+    debug::note(|| format!("turned away by the access rules of {path}"));
+    Some(resp)
+}
+
+fn enforce(ctx: &Context, req: &Request, rule: &Rule) -> Option<Response> {
+    if let Some(allow) = &rule.allow
+        && !allow.iter().any(|net| net.contains(req.peer.ip()))
+    {
+        return Some(Response::error(403));
+    }
+    let write = auth::is_write_method(&req.method);
+    let global = || {
+        if auth::required(ctx, req) {
+            auth::authenticate(ctx, req).err()
+        } else {
+            None
+        }
+    };
+    if rule.public == Some(true) {
+        return if write { global() } else { None };
+    }
+    if rule.users.is_none() && rule.token.is_none() {
+        return global();
+    }
+    if rule.writes_only == Some(true) && !write {
+        return global();
+    }
+    let mut guard = Guard::global(ctx);
+    // The built-in provider, first in `ctx.auth` when there is one, only
+    // knows the global credentials; `guard` takes its place.
+    let builtin = usize::from(!guard.users.is_empty() || !guard.tokens.is_empty());
+    guard.users.extend(rule.users.iter().flatten().cloned());
+    guard.tokens.extend(rule.token.clone());
+    if let Some(realm) = &rule.realm {
+        guard.realm.clone_from(realm);
+    }
+    let providers = std::iter::once(&guard as &dyn AuthProvider)
+        .chain(ctx.auth.iter().skip(builtin).map(|p| &**p));
+    auth::authenticate_with(providers, req).err()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::AuthProvider;
+    use crate::http::{Request, Response};
+    use crate::test::TestServer;
+
+    /// Accepts `X-User: alice`.
+    struct Header;
+
+    impl AuthProvider for Header {
+        fn verify(&self, req: &Request) -> Option<String> {
+            (req.headers.get("x-user") == Some("alice")).then(|| "alice".to_owned())
+        }
+
+        fn challenge(&self, resp: &mut Response) {
+            resp.headers.append("WWW-Authenticate", "Header");
+        }
+    }
+
+    #[test]
+    fn rules_accept_registered_providers() {
+        let server = TestServer::with(|b| {
+            b.option("accessFiles", true)
+                .option("authToken", "global")
+                .auth_provider(Header)
+        })
+        .unwrap();
+        server
+            .write("team/.tinyserve-access", r#"{"token": "team"}"#)
+            .unwrap();
+        server.write("team/a.txt", "a").unwrap();
+        let status = |headers: &[(&str, &str)]| {
+            server
+                .request("GET", "/team/a.txt", headers, b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(status(&[]), 401);
+        assert_eq!(status(&[("Authorization", "Bearer team")]), 200);
+        assert_eq!(status(&[("Authorization", "Bearer global")]), 200);
+        assert_eq!(status(&[("X-User", "alice")]), 200);
+        assert_eq!(status(&[("X-User", "bob")]), 401);
+
+        let resp = server.get("/team/a.txt").unwrap();
+        let challenges: Vec<&str> = resp.headers.get_all("www-authenticate").collect();
+        assert_eq!(challenges.len(), 2, "{challenges:?}");
+    }
+
+    #[test]
+    fn write_only_rules_leave_reads_to_the_global_setting() {
+        let server = TestServer::with(|b| {
+            b.option("accessFiles", true)
+                .option("readOnly", false)
+                .option("authToken", "global")
+                .option("authFor", "all")
+        })
+        .unwrap();
+        server
+            .write(
+                "team/.tinyserve-access",
+                r#"{"token": "team", "for": "writes"}"#,
+            )
+            .unwrap();
+        server.write("team/a.txt", "a").unwrap();
+        let status = |method: &str, headers: &[(&str, &str)]| {
+            server
+                .request(method, "/team/a.txt", headers, b"b")
+                .unwrap()
+                .status
+        };
+        assert_eq!(status("GET", &[]), 401);
+        assert_eq!(status("GET", &[("Authorization", "Bearer global")]), 200);
+        assert_eq!(status("PUT", &[]), 401);
+        assert!(status("PUT", &[("Authorization", "Bearer team")]) < 300);
+    }
+
+    #[test]
+    fn public_areas_still_need_credentials_for_writes() {
+        let server = TestServer::with(|b| {
+            b.option("accessFiles", true)
+                .option("readOnly", false)
+                .option("authToken", "global")
+        })
+        .unwrap();
+        server
+            .write("pub/.tinyserve-access", r#"{"public": true}"#)
+            .unwrap();
+        server.write("pub/a.txt", "a").unwrap();
+        let status = |method: &str, path: &str, headers: &[(&str, &str)]| {
+            server.request(method, path, headers, b"b").unwrap().status
+        };
+        assert_eq!(status("GET", "/pub/a.txt", &[]), 200);
+        assert_eq!(status("PUT", "/pub/b.txt", &[]), 401);
+        assert_eq!(status("DELETE", "/pub/a.txt", &[]), 401);
+        assert_eq!(server.get("/pub/a.txt").unwrap().text(), "a");
+        let auth = [("Authorization", "Bearer global")];
+        assert!(status("PUT", "/pub/b.txt", &auth) < 300);
+        assert!(status("DELETE", "/pub/a.txt", &auth) < 300);
+    }
+
+    #[test]
+    fn credentials_deeper_down_end_an_inherited_public() {
+        let server =
+            TestServer::with(|b| b.option("accessFiles", true).option("authToken", "global"))
+                .unwrap();
+        server
+            .write("pub/.tinyserve-access", r#"{"public": true}"#)
+            .unwrap();
+        server
+            .write("pub/inner/.tinyserve-access", r#"{"token": "inner"}"#)
+            .unwrap();
+        server.write("pub/a.txt", "a").unwrap();
+        server.write("pub/inner/b.txt", "b").unwrap();
+        let status = |path: &str, headers: &[(&str, &str)]| {
+            server.request("GET", path, headers, b"").unwrap().status
+        };
+        assert_eq!(status("/pub/a.txt", &[]), 200);
+        assert_eq!(status("/pub/inner/b.txt", &[]), 401);
+        assert_eq!(
+            status("/pub/inner/b.txt", &[("Authorization", "Bearer inner")]),
+            200
+        );
+    }
+
+    #[test]
+    fn allow_turns_away_other_peers_whatever_their_credentials() {
+        let server =
+            TestServer::with(|b| b.option("accessFiles", true).option("authToken", "global"))
+                .unwrap();
+        server
+            .write(
+                "lan/.tinyserve-access",
+                r#"{"allow": ["10.0.0.0/8"], "public": true}"#,
+            )
+            .unwrap();
+        server
+            .write("local/.tinyserve-access", r#"{"allow": ["127.0.0.0/8"]}"#)
+            .unwrap();
+        server.write("lan/a.txt", "a").unwrap();
+        server.write("local/a.txt", "a").unwrap();
+        let auth = [("Authorization", "Bearer global")];
+        let status = |path: &str| server.request("GET", path, &auth, b"").unwrap().status;
+        assert_eq!(status("/lan/a.txt"), 403);
+        assert_eq!(status("/local/a.txt"), 200);
+    }
+}
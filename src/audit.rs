@@ -0,0 +1,329 @@
+//! Audit log of write requests (`auditLog`), kept apart from the access log
+//! for shares where it matters who changed what.
+//!
+//! With `readOnly` off, every request with a [write
+//! method](auth::is_write_method) appends one JSON object to the file: the
+//! time, client address, authenticated user, method, path, `Destination`
+//! of a `COPY` or `MOVE`, status, and the request body bytes read. Refused
+//! attempts are logged too. The file is only ever appended to.
+//!
+//! With `auditKey` set (and the `audit-hmac` feature), each entry also
+//! carries `mac`: HMAC-SHA256 under the key of the previous entry's `mac`
+//! in hex (empty for the first) followed by the entry serialized without
+//! `mac`. An entry edited, removed or reordered breaks the chain from there
+//! on; `tinyserve audit verify FILE --key-file PATH` checks it. A restart
+//! continues the chain from the last entry in the file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use anyhow::{Context as _, Result, bail};
+use serde_json::{Map, Value, json};
+
+use crate::auth;
+use crate::core::Config;
+use crate::http::{Request, RequestBody, date};
+use crate::log;
+use crate::server::Context;
+
+/// The open log and the last entry's `mac`, in hex.
+struct Output {
+    file: File,
+    prev: String,
+    /// Set once writing failed, so the failure is reported once.
+    failed: bool,
+}
+
+pub struct Audit {
+    path: PathBuf,
+    key: Option<Vec<u8>>,
+    output: Mutex<Output>,
+}
+
+impl Audit {
+    /// `None` when `auditLog` is empty or `readOnly` is on.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let path = match config.str("auditLog") {
+            "" => return Ok(None),
+            path => PathBuf::from(path),
+        };
+        if config.bool("readOnly") {
+            return Ok(None);
+        }
+        let key = match config.str("auditKey") {
+            "" => None,
+            _ if !cfg!(feature = "audit-hmac") => {
+                bail!("auditKey needs the `audit-hmac` feature")
+            }
+            key => Some(key.as_bytes().to_vec()),
+        };
+        let prev = match &key {
+            Some(_) => last_mac(&path).with_context(|| format!("auditLog {}", path.display()))?,
+            None => String::new(),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("auditLog {}", path.display()))?;
+        Ok(Some(Self {
+            path,
+            key,
+            output: Mutex::new(Output {
+                file,
+                prev,
+                failed: false,
+            }),
+        }))
+    }
+
+    /// Counts what the handler reads of `req`'s body, when `req` is audited.
+    pub fn watch(&self, req: &mut Request) -> Option<Arc<AtomicU64>> {
+        if !auth::is_write_method(&req.method) {
+            return None;
+        }
+        let read = Arc::new(AtomicU64::new(0));
+        let inner = std::mem::replace(&mut req.body, RequestBody::empty());
+        req.body = RequestBody::from_reader(Counted {
+            inner,
+            read: Arc::clone(&read),
+        });
+        Some(read)
+    }
+
+    /// Appends the entry for a request [`watch`](Self::watch) counted.
+    pub fn record(&self, ctx: &Context, req: &Request, status: u16, read: &AtomicU64) {
+        let user = auth::enabled(ctx)
+            .then(|| auth::authenticate(ctx, req).ok())
+            .flatten();
+        let mut entry = json!({
+            "at": date::format_iso(SystemTime::now()),
+            "client": req.peer.ip().to_canonical().to_string(),
+            "user": user,
+            "method": req.method,
+            "path": req.path,
+            "status": status,
+            "bytes": read.load(Ordering::Relaxed),
+        });
+        if let Some(dest) = req.headers.get("destination") {
+            entry["destination"] = Value::from(dest);
+        }
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = &self.key {
+            let mac = chain(key, &output.prev, &entry.to_string());
+            entry["mac"] = Value::from(mac.as_str());
+            output.prev = mac;
+        }
+        let line = format!("{entry}\n");
+        if let Err(e) = output.file.write_all(line.as_bytes())
+            && !output.failed
+        {
+            output.failed = true;
+            log::error(&format!(
+                "cannot write audit log {}: {e}",
+                self.path.display()
+            ));
+        }
+    }
+}
+
+/// Counts the bytes read through it.
+struct Counted {
+    inner: RequestBody,
+    read: Arc<AtomicU64>,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// The `mac` of an entry following one whose `mac` was `prev`.
+#[cfg(feature = "audit-hmac")]
+fn chain(key: &[u8], prev: &str, entry: &str) -> String {
+    let mut mac = hmac_sha256::HMAC::new(key);
+    mac.update(prev);
+    mac.update(entry);
+    mac.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(not(feature = "audit-hmac"))]
+fn chain(_key: &[u8], _prev: &str, _entry: &str) -> String {
+    unreachable!("auditKey is refused without the audit-hmac feature")
+}
+
+/// The `mac` of the last entry of the log at `path`, empty if there is none.
+fn last_mac(path: &Path) -> Result<String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(line) = text.lines().rev().find(|l| !l.trim().is_empty()) else {
+        return Ok(String::new());
+    };
+    let entry: Value = serde_json::from_str(line).context("last entry is not JSON")?;
+    match entry["mac"].as_str() {
+        Some(mac) => Ok(mac.to_owned()),
+        None => bail!("last entry has no mac; start a new file to chain entries"),
+    }
+}
+
+/// Runs `tinyserve audit` with the arguments after it.
+pub fn run(args: &[String]) -> Result<()> {
+    const USAGE: &str = "usage: tinyserve audit verify FILE --key-file PATH";
+    let (file, key_file) = match args {
+        [verb, file, flag, key_file] if verb == "verify" && flag == "--key-file" => {
+            (file, key_file)
+        }
+        _ => bail!(USAGE),
+    };
+    if !cfg!(feature = "audit-hmac") {
+        bail!("verifying needs the `audit-hmac` feature");
+    }
+    let key = fs::read_to_string(key_file).with_context(|| format!("reading {key_file}"))?;
+    let key = key.trim_end_matches(['\r', '\n']);
+    let text = fs::read_to_string(file).with_context(|| format!("reading {file}"))?;
+    let mut prev = String::new();
+    let mut count = 0;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = i + 1;
+        let mut entry: Map<String, Value> = serde_json::from_str(line)
+            .with_context(|| format!("{file}:{line_no}: not a JSON object"))?;
+        let Some(Value::String(mac)) = entry.remove("mac") else {
+            bail!("{file}:{line_no}: entry has no mac");
+        };
+        let expected = chain(key.as_bytes(), &prev, &Value::Object(entry).to_string());
+        if !auth::ct_eq(mac.as_bytes(), expected.as_bytes()) {
+            bail!(
+                "{file}:{line_no}: mac does not match; the log was changed at or before this entry"
+            );
+        }
+        prev = mac;
+        count += 1;
+    }
+    println!("{file}: {count} entries, chain intact");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{AUTH, TestServer};
+
+    fn log_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tinyserve-audit-{}-{name}.log", std::process::id()))
+    }
+
+    fn entries(path: &Path) -> Vec<Map<String, Value>> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn records_writes_and_refused_attempts() {
+        let path = log_path("record");
+        let _ = fs::remove_file(&path);
+        let server = TestServer::with_auth(|b| {
+            b.option("readOnly", false)
+                .option("auditLog", path.to_string_lossy().into_owned())
+        })
+        .unwrap();
+        assert_eq!(
+            server
+                .request("PUT", "/a.txt", &[AUTH], b"hello")
+                .unwrap()
+                .status,
+            201
+        );
+        assert_eq!(
+            server
+                .request("PUT", "/b.txt", &[], b"nope")
+                .unwrap()
+                .status,
+            401
+        );
+        assert_eq!(server.get("/a.txt").unwrap().status, 200);
+
+        let entries = entries(&path);
+        assert_eq!(entries.len(), 2);
+        let (written, refused) = (&entries[0], &entries[1]);
+        assert_eq!(written["user"], "token");
+        assert_eq!(written["method"], "PUT");
+        assert_eq!(written["path"], "/a.txt");
+        assert_eq!(written["status"], 201);
+        assert_eq!(written["bytes"], 5);
+        assert_eq!(written["client"], "127.0.0.1");
+        assert_eq!(refused["user"], Value::Null);
+        assert_eq!(refused["status"], 401);
+        assert!(!written.contains_key("mac"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "audit-hmac")]
+    #[test]
+    fn chains_entries_across_restarts() {
+        let path = log_path("chain");
+        let key_file = log_path("chain-key");
+        let _ = fs::remove_file(&path);
+        fs::write(&key_file, "k3y\n").unwrap();
+        let start = || {
+            TestServer::with_auth(|b| {
+                b.option("readOnly", false)
+                    .option("auditLog", path.to_string_lossy().into_owned())
+                    .option("auditKey", "k3y")
+            })
+            .unwrap()
+        };
+        let server = start();
+        server.request("PUT", "/a.txt", &[AUTH], b"a").unwrap();
+        server.request("DELETE", "/a.txt", &[AUTH], b"").unwrap();
+        drop(server);
+        start().request("PUT", "/b.txt", &[AUTH], b"b").unwrap();
+
+        let entries = entries(&path);
+        assert_eq!(entries.len(), 3);
+        let mut prev = String::new();
+        for mut entry in entries {
+            let Some(Value::String(mac)) = entry.remove("mac") else {
+                panic!("no mac in {entry:?}");
+            };
+            assert_eq!(mac, chain(b"k3y", &prev, &Value::Object(entry).to_string()));
+            prev = mac;
+        }
+        let verify = || {
+            let args = [
+                "verify",
+                path.to_str().unwrap(),
+                "--key-file",
+                key_file.to_str().unwrap(),
+            ];
+            run(&args.map(str::to_owned))
+        };
+        verify().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, text.replacen("/a.txt", "/z.txt", 1)).unwrap();
+        let err = verify().unwrap_err().to_string();
+        assert!(
+            err.ends_with(":1: mac does not match; the log was changed at or before this entry"),
+            "{err}"
+        );
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&key_file).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! HTTP authentication: Basic credentials from `authUsers` and a Bearer
+//! token from `authToken`, plus any [`AuthProvider`] an embedder registers
+//! with [`ServerBuilder::auth_provider`](crate::ServerBuilder::auth_provider).
+//!
+//! Providers are asked in order: the built-in credentials first, then the
+//! registered ones. The first to recognize the credentials decides whether
+//! the user may make the request; when none does, the client gets a `401`
+//! carrying every provider's challenge.
+
+use crate::core::Config;
+use crate::http::{Request, Response};
+use crate::server::Context;
+
+/// A source of users: checks credentials, decides what their owner may do,
+/// and says how to ask for them.
+pub trait AuthProvider: Send + Sync {
+    /// The user `req`'s credentials belong to, or `None` when it carries
+    /// none this provider accepts.
+    fn verify(&self, req: &Request) -> Option<String>;
+
+    /// Whether `user` may make `req`, judging by its method and path; every
+    /// request by default. A refusal is answered `403`.
+    fn authorize(&self, user: &str, req: &Request) -> bool {
+        let _ = (user, req);
+        true
+    }
+
+    /// Adds a `WWW-Authenticate` challenge to a `401`.
+    fn challenge(&self, resp: &mut Response);
+}
+
+/// Methods that modify the served tree.
+pub fn is_write_method(method: &str) -> bool {
+    matches!(
+        method,
+        "PUT" | "POST" | "DELETE" | "MKCOL" | "COPY" | "MOVE" | "PROPPATCH"
+    )
+}
+
+/// Whether any credentials are configured or a provider is registered.
+pub fn enabled(ctx: &Context) -> bool {
+    !ctx.auth.is_empty()
+}
+
+/// The built-in provider, when `authUsers` or `authToken` is set.
+pub fn from_config(config: &Config) -> Option<Box<dyn AuthProvider>> {
+    let guard = Guard::from_config(config);
+    (!guard.users.is_empty() || !guard.tokens.is_empty()).then(|| Box::new(guard) as _)
+}
+
+/// Whether `req` has to carry credentials under the `authFor` setting.
+pub fn required(ctx: &Context, req: &Request) -> bool {
+    enabled(ctx) && (ctx.config.str("authFor") == "all" || is_write_method(&req.method))
+}
+
+/// Returns the authenticated user name (`"token"` for bearer auth), or the
+/// `401` or `403` to send back.
+pub fn authenticate(ctx: &Context, req: &Request) -> Result<String, Response> {
+    authenticate_with(ctx.auth.iter().map(|p| &**p), req)
+}
+
+/// `401` with a challenge from each provider.
+pub fn challenge(ctx: &Context) -> Response {
+    challenge_with(ctx.auth.iter().map(|p| &**p))
+}
+
+/// [`authenticate`] against `providers`, in order.
+pub fn authenticate_with<'a>(
+    providers: impl Iterator<Item = &'a dyn AuthProvider> + Clone,
+    req: &Request,
+) -> Result<String, Response> {
+    for provider in providers.clone() {
+        if let Some(user) = provider.verify(req) {
+            return if provider.authorize(&user, req) {
+                Ok(user)
+            } else {
+                Err(Response::error(403))
+            };
+        }
+    }
+    Err(challenge_with(providers))
+}
+
+fn challenge_with<'a>(providers: impl Iterator<Item = &'a dyn AuthProvider>) -> Response {
+    let mut resp = Response::error(401);
+    for provider in providers {
+        provider.challenge(&mut resp);
+    }
+    resp
+}
+
+/// The credentials accepted for a request and the realm they belong to.
+#[derive(Clone, Debug, Default)]
+pub struct Guard {
+    /// `user:password` pairs for Basic auth.
+    pub users: Vec<String>,
+    /// Bearer tokens.
+    pub tokens: Vec<String>,
+    pub realm: String,
+}
+
+impl Guard {
+    /// `authUsers`, `authToken` and `authRealm`.
+    pub fn global(ctx: &Context) -> Self {
+        Self::from_config(&ctx.config)
+    }
+
+    fn from_config(config: &Config) -> Self {
+        let token = config.str("authToken");
+        Self {
+            users: config.list("authUsers"),
+            tokens: (!token.is_empty())
+                .then(|| token.to_owned())
+                .into_iter()
+                .collect(),
+            realm: config.str("authRealm").to_owned(),
+        }
+    }
+
+    /// Returns the authenticated user name (`"token"` for bearer auth), or
+    /// the 401 challenge to send back.
+    pub fn authenticate(&self, req: &Request) -> Result<String, Response> {
+        self.verify(req).ok_or_else(|| self.challenge())
+    }
+
+    /// `401` with a challenge for each scheme that has credentials.
+    pub fn challenge(&self) -> Response {
+        let mut resp = Response::error(401);
+        AuthProvider::challenge(self, &mut resp);
+        resp
+    }
+}
+
+impl AuthProvider for Guard {
+    fn verify(&self, req: &Request) -> Option<String> {
+        let header = req.headers.get("authorization").unwrap_or_default();
+        let (scheme, credentials) = header.split_once(' ').unwrap_or((header, ""));
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("basic")
+            && let Some(decoded) = base64_decode(credentials)
+            && let Ok(pair) = String::from_utf8(decoded)
+            && let Some((user, _)) = pair.split_once(':')
+            && self
+                .users
+                .iter()
+                .any(|entry| ct_eq(entry.as_bytes(), pair.as_bytes()))
+        {
+            return Some(user.to_owned());
+        }
+        if scheme.eq_ignore_ascii_case("bearer")
+            && self
+                .tokens
+                .iter()
+                .any(|token| ct_eq(token.as_bytes(), credentials.as_bytes()))
+        {
+            return Some("token".to_owned());
+        }
+        None
+    }
+
+    fn challenge(&self, resp: &mut Response) {
+        let realm = self.realm.replace('"', "");
+        if !self.users.is_empty() {
+            resp.headers.append(
+                "WWW-Authenticate",
+                format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+            );
+        }
+        if !self.tokens.is_empty() {
+            resp.headers
+                .append("WWW-Authenticate", format!("Bearer realm=\"{realm}\""));
+        }
+    }
+}
+
+/// Compares without an early exit on the first differing byte.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Standard base64 with optional padding.
+pub fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
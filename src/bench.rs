@@ -0,0 +1,293 @@
+//! `tinyserve bench`: a small closed-loop load generator.
+//!
+//! ```text
+//! tinyserve bench [URL|DIR] [-c N] [-d SECS] [--path /p] [--no-keep-alive]
+//! ```
+//!
+//! Given a URL, requests it from a running server; given a directory (or
+//! nothing, meaning `.`), serves it on an ephemeral port first. Each of the
+//! `-c` workers sends one request at a time for `-d` seconds.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::core::{Config, Layer};
+use crate::http::body::Framing;
+use crate::server::Server;
+
+/// Per-response cap on drained body bytes.
+const MAX_BODY: u64 = 1 << 40;
+
+struct Options {
+    target: String,
+    path: Option<String>,
+    concurrency: usize,
+    duration: Duration,
+    keep_alive: bool,
+}
+
+#[derive(Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    errors: u64,
+    non_2xx: u64,
+}
+
+fn parse(args: &[String]) -> Result<Options> {
+    let mut opts = Options {
+        target: ".".to_owned(),
+        path: None,
+        concurrency: 16,
+        duration: Duration::from_secs(10),
+        keep_alive: true,
+    };
+    let mut args = args.iter();
+    let mut target = None;
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| anyhow!("`{name}` needs a value"))
+        };
+        match name {
+            "-c" | "--concurrency" => {
+                opts.concurrency = value()?.parse().context("--concurrency")?;
+            }
+            "-d" | "--duration" => {
+                opts.duration = Duration::from_secs_f64(value()?.parse().context("--duration")?);
+            }
+            "--path" => opts.path = Some(value()?),
+            "--no-keep-alive" => opts.keep_alive = false,
+            _ if name.starts_with('-') => bail!("unknown bench option `{arg}`"),
+            _ if target.is_none() => target = Some(arg.clone()),
+            _ => bail!("unexpected argument `{arg}`"),
+        }
+    }
+    if let Some(target) = target {
+        opts.target = target;
+    }
+    if opts.concurrency == 0 {
+        bail!("--concurrency must be at least 1");
+    }
+    Ok(opts)
+}
+
+/// Runs `tinyserve bench` with the arguments after `bench`.
+pub fn run(args: &[String]) -> Result<()> {
+    let opts = parse(args)?;
+    let (addr, host, path) = if let Some(rest) = opts.target.strip_prefix("http://") {
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let addr = with_default_port(authority)
+            .to_socket_addrs()
+            .with_context(|| format!("cannot resolve {authority}"))?
+            .next()
+            .ok_or_else(|| anyhow!("cannot resolve {authority}"))?;
+        let path = opts.path.clone().unwrap_or_else(|| match path {
+            "" => "/".to_owned(),
+            p => p.to_owned(),
+        });
+        (addr, authority.to_owned(), path)
+    } else if opts.target.contains("://") {
+        bail!("only http:// URLs can be benchmarked");
+    } else {
+        let addr = spawn_server(Path::new(&opts.target))?;
+        (
+            addr,
+            addr.to_string(),
+            opts.path.clone().unwrap_or("/".into()),
+        )
+    };
+
+    println!(
+        "Benchmarking http://{host}{path} with {} connections for {:.0?}",
+        opts.concurrency, opts.duration
+    );
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: tinyserve-bench\r\n{}\r\n",
+        if opts.keep_alive {
+            ""
+        } else {
+            "Connection: close\r\n"
+        }
+    );
+    let deadline = Instant::now() + opts.duration;
+    let started = Instant::now();
+    let workers: Vec<_> = (0..opts.concurrency)
+        .map(|_| {
+            let request = request.clone();
+            thread::spawn(move || load(addr, &request, deadline))
+        })
+        .collect();
+    let mut total = Tally::default();
+    for worker in workers {
+        let tally = worker
+            .join()
+            .map_err(|_| anyhow!("bench worker panicked"))?;
+        total.latencies.extend(tally.latencies);
+        total.bytes += tally.bytes;
+        total.errors += tally.errors;
+        total.non_2xx += tally.non_2xx;
+    }
+    report(&mut total, started.elapsed());
+    Ok(())
+}
+
+fn with_default_port(authority: &str) -> String {
+    if authority
+        .rsplit_once(':')
+        .is_some_and(|(_, p)| !p.contains(']'))
+    {
+        authority.to_owned()
+    } else {
+        format!("{authority}:80")
+    }
+}
+
+/// Serves `root` on a loopback port for the duration of the benchmark.
+fn spawn_server(root: &Path) -> Result<SocketAddr> {
+    if !root.is_dir() {
+        bail!(
+            "{} is neither an http:// URL nor a directory",
+            root.display()
+        );
+    }
+    let mut config = Config::default();
+    let root = root.to_string_lossy();
+    for (key, value) in [
+        ("root", root.as_ref()),
+        ("host", "127.0.0.1"),
+        ("port", "0"),
+        ("accessLog", "false"),
+    ] {
+        config.set_raw(key, value, Layer::Cli)?;
+    }
+    let server = Server::bind(config)?;
+    let addr = server.local_addr();
+    thread::spawn(move || server.run());
+    Ok(addr)
+}
+
+/// One worker: sends requests back to back until `deadline`.
+fn load(addr: SocketAddr, request: &str, deadline: Instant) -> Tally {
+    let mut tally = Tally::default();
+    let mut conn: Option<BufReader<TcpStream>> = None;
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        let result = (|| -> io::Result<(u16, u64, bool)> {
+            let reader = match &mut conn {
+                Some(reader) => reader,
+                None => conn.insert(BufReader::new(TcpStream::connect(addr)?)),
+            };
+            reader.get_mut().write_all(request.as_bytes())?;
+            exchange(reader)
+        })();
+        match result {
+            Ok((status, bytes, keep_alive)) => {
+                tally.latencies.push(started.elapsed());
+                tally.bytes += bytes;
+                if !(200..300).contains(&status) {
+                    tally.non_2xx += 1;
+                }
+                if !keep_alive {
+                    conn = None;
+                }
+            }
+            Err(_) => {
+                tally.errors += 1;
+                conn = None;
+            }
+        }
+    }
+    tally
+}
+
+/// Reads one response, returning its status, body size and whether the
+/// connection stays open.
+fn exchange(reader: &mut BufReader<TcpStream>) -> io::Result<(u16, u64, bool)> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let mut framing = None;
+    let mut keep_alive = true;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            framing = value.parse().ok().map(Framing::Length);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            framing = Some(Framing::Chunked { remaining: 0 });
+        } else if name.eq_ignore_ascii_case("connection") {
+            keep_alive = !value.eq_ignore_ascii_case("close");
+        }
+    }
+    let Some(mut framing) = framing else {
+        // Close-delimited: read to the end.
+        let n = io::copy(reader, &mut io::sink())?;
+        return Ok((status, n, false));
+    };
+    let mut bytes = 0;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match framing.read(reader, &mut buf)? {
+            0 => break,
+            n => bytes += n as u64,
+        }
+        if bytes > MAX_BODY {
+            return Err(io::ErrorKind::FileTooLarge.into());
+        }
+    }
+    Ok((status, bytes, keep_alive))
+}
+
+fn report(total: &mut Tally, elapsed: Duration) {
+    let count = total.latencies.len();
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "\n  Requests:   {count} ({:.0}/s)\n  Transfer:   {:.1} MB ({:.1} MB/s)\n  Errors:     {} connection, {} non-2xx",
+        count as f64 / secs,
+        total.bytes as f64 / 1e6,
+        total.bytes as f64 / 1e6 / secs,
+        total.errors,
+        total.non_2xx
+    );
+    if count == 0 {
+        return;
+    }
+    total.latencies.sort_unstable();
+    let at = |p: f64| total.latencies[((count as f64 * p) as usize).min(count - 1)];
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    println!(
+        "  Latency:    p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms",
+        ms(at(0.50)),
+        ms(at(0.90)),
+        ms(at(0.99)),
+        ms(total.latencies[count - 1])
+    );
+}
@@ -0,0 +1,469 @@
+//! Command-line parsing and the `tinyserve` entry point.
+//!
+//! ```text
+//! tinyserve [serve] [ROOT] [--option[=value]]...
+//! tinyserve bench [URL|DIR] [-c N] [-d SECS]
+//! tinyserve precompress [DIR] [--formats gz,br,zst] [--manifest FILE]
+//! tinyserve completions <bash|zsh|fish|powershell>
+//! tinyserve audit verify FILE --key-file PATH
+//! tinyserve explain OPTION [--option[=value]]...
+//! tinyserve stop|status [--option[=value]]...
+//! tinyserve service <install [ROOT] [--option[=value]]...|start|stop|uninstall>
+//! tinyserve up [status] [FILE] [--option[=value]]...
+//! ```
+//!
+//! Every option in [`OPTIONS`] is accepted as a flag under any of its
+//! spellings; boolean options may be given bare (`--show-dir`) or negated
+//! (`--no-show-dir`).
+//!
+//! `--config-json '<object>'` or `--stdin-config` supply the whole config
+//! instead of the configs dir, which is then neither created nor read.
+
+use std::env;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde_json::Value;
+
+use crate::core::config::{ENV_PREFIX, value_aliases};
+use crate::core::dirs::{self, CONFIG_FILE, HOOKS_FILE, LOCALES_DIR, PLUGINS_DIR};
+use crate::core::{Aliases, Config, Kind, Layer, OPTIONS};
+use crate::daemon::Daemon;
+use crate::interfaces;
+use crate::log::{self, Style};
+use crate::server::{Server, status};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Serve,
+    Help,
+    Version,
+}
+
+/// A parsed command line.
+#[derive(Debug)]
+struct Invocation {
+    action: Action,
+    config_file: Option<PathBuf>,
+    /// A config object from `--config-json`.
+    config_json: Option<String>,
+    /// Whether the config object is read from stdin.
+    stdin_config: bool,
+    show_config: bool,
+    /// Canonical key and raw value, in command-line order.
+    overrides: Vec<(&'static str, String)>,
+}
+
+/// Runs tinyserve with the given arguments (without the program name).
+pub fn run<I: IntoIterator<Item = String>>(args: I) -> Result<()> {
+    let args: Vec<String> = args.into_iter().collect();
+    if args.first().is_some_and(|a| a == "bench") {
+        return crate::bench::run(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "precompress") {
+        return crate::precompress::run(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "audit") {
+        return crate::audit::run(&args[1..]);
+    }
+    if args.first().is_some_and(|a| a == "completions") {
+        return crate::completions::run(&args[1..]);
+    }
+    // With the whole config given inline, the home directory is left alone:
+    // it may be read-only, or missing, in a container.
+    let inline = args
+        .iter()
+        .take_while(|a| *a != "--")
+        .any(|a| a == "--stdin-config" || a.starts_with("--config-json"));
+    let configs_dir = if inline {
+        None
+    } else {
+        Some(dirs::ensure_default_configs_dir()?)
+    };
+    let aliases = match &configs_dir {
+        Some(dir) => Aliases::shared(dir)?,
+        None => Arc::new(Aliases::builtin()),
+    };
+    let home = || match &configs_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => dirs::default_configs_dir(),
+    };
+    if args.first().is_some_and(|a| a == "explain") {
+        let Some(name) = args.get(1) else {
+            bail!("usage: tinyserve explain OPTION [OPTIONS]");
+        };
+        let inv = parse(&args[2..], &aliases)?;
+        let config = load_config(&inv, configs_dir.as_deref(), &aliases)?;
+        print!(
+            "{}",
+            explain(name, &config, &aliases, &inv, configs_dir.as_deref())?
+        );
+        return Ok(());
+    }
+    if args.first().is_some_and(|a| a == "service") {
+        return crate::service::run(&args[1..], &home()?, |args| {
+            let inv = parse(args, &aliases)?;
+            load_config(&inv, configs_dir.as_deref(), &aliases)
+        });
+    }
+    if args.first().is_some_and(|a| a == "up") {
+        return crate::sites::run(&args[1..], &home()?, |args| {
+            let inv = parse(args, &aliases)?;
+            load_config(&inv, configs_dir.as_deref(), &aliases)
+        });
+    }
+    if let Some(command @ ("stop" | "status")) = args.first().map(String::as_str) {
+        let inv = parse(&args[1..], &aliases)?;
+        let config = load_config(&inv, configs_dir.as_deref(), &aliases)?;
+        log::set_style(Style::from_config(&config));
+        return match command {
+            "stop" => crate::daemon::stop(&config, &home()?),
+            _ => crate::daemon::status(&config, &home()?),
+        };
+    }
+    let inv = parse(&args, &aliases)?;
+    match inv.action {
+        Action::Help => {
+            print!("{}", help());
+            return Ok(());
+        }
+        Action::Version => {
+            println!("tinyserve {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Action::Serve => {}
+    }
+
+    let config = load_config(&inv, configs_dir.as_deref(), &aliases)?;
+    log::set_style(Style::from_config(&config));
+    if inv.show_config {
+        println!("{}", serde_json::to_string_pretty(&config.to_json())?);
+        return Ok(());
+    }
+    if config.bool("tui") {
+        if config.bool("daemon") {
+            bail!("--tui and --daemon cannot be used together");
+        }
+        return crate::tui::run(config);
+    }
+    let daemon = config
+        .bool("daemon")
+        .then(|| Daemon::start(&config, &home()?))
+        .transpose()?;
+    let server = match (Server::bind(config).map_err(anyhow::Error::from), daemon) {
+        (Ok(server), Some(daemon)) => {
+            daemon.ready()?;
+            server
+        }
+        (Ok(server), None) => server,
+        (Err(e), Some(daemon)) => {
+            daemon.failed(&e);
+            return Err(e);
+        }
+        (Err(e), None) => return Err(e),
+    };
+    let addr = server.local_addr();
+    let options: Vec<(&str, String)> = status::options(&server.context().config)
+        .into_iter()
+        .filter(|(key, _, _)| !["root", "host", "port"].contains(key))
+        .map(|(key, value, _)| (key, value))
+        .collect();
+    log::serving(
+        &server.context().root,
+        &format!("http://{addr}/"),
+        &interfaces::urls(addr, server.stack()),
+        server.stack(),
+        &options,
+    );
+    Ok(server.run()?)
+}
+
+/// The config from the configs dir, or from `--config-json` or stdin when
+/// `configs_dir` is `None`, overlaid by the environment and the flags.
+fn load_config(inv: &Invocation, configs_dir: Option<&Path>, aliases: &Aliases) -> Result<Config> {
+    let mut config = Config::default();
+    match configs_dir {
+        Some(configs_dir) => load_configs_dir(&mut config, inv, configs_dir, aliases)?,
+        None => {
+            if inv.config_file.is_some() || (inv.config_json.is_some() && inv.stdin_config) {
+                bail!("use one of --config, --config-json and --stdin-config");
+            }
+            let text = match &inv.config_json {
+                Some(json) => json.clone(),
+                None => {
+                    let mut text = String::new();
+                    io::stdin()
+                        .read_to_string(&mut text)
+                        .context("reading the config from stdin")?;
+                    text
+                }
+            };
+            let source = if inv.stdin_config {
+                "stdin"
+            } else {
+                "--config-json"
+            };
+            let json: Value =
+                serde_json::from_str(&text).with_context(|| format!("parsing {source}"))?;
+            let object = json
+                .as_object()
+                .ok_or_else(|| anyhow!("{source}: expected a JSON object"))?;
+            config
+                .merge_json(object, aliases, Layer::File)
+                .with_context(|| format!("in {source}"))?;
+        }
+    }
+    config.merge_env(env::vars(), aliases)?;
+    for (key, raw) in &inv.overrides {
+        config.set_raw(key, raw, Layer::Cli)?;
+    }
+    Ok(config)
+}
+
+/// Defaults found in the configs dir, then its config file or `--config`.
+fn load_configs_dir(
+    config: &mut Config,
+    inv: &Invocation,
+    configs_dir: &Path,
+    aliases: &Aliases,
+) -> Result<()> {
+    let plugins = configs_dir.join(PLUGINS_DIR);
+    if plugins.is_dir() {
+        config.set_raw("plugins", &plugins.to_string_lossy(), Layer::Default)?;
+    }
+    let locales = configs_dir.join(LOCALES_DIR);
+    if locales.is_dir() {
+        config.set_raw("localeDir", &locales.to_string_lossy(), Layer::Default)?;
+    }
+    let hooks = configs_dir.join(HOOKS_FILE);
+    if hooks.is_file() {
+        config.set_raw("hooks", &hooks.to_string_lossy(), Layer::Default)?;
+    }
+    match &inv.config_file {
+        Some(path) => config.merge_file(path, aliases)?,
+        None => {
+            let path = configs_dir.join(CONFIG_FILE);
+            if path.is_file() {
+                config.merge_file(&path, aliases)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse(args: &[String], aliases: &Aliases) -> Result<Invocation> {
+    let mut inv = Invocation {
+        action: Action::Serve,
+        config_file: None,
+        config_json: None,
+        stdin_config: false,
+        show_config: false,
+        overrides: Vec::new(),
+    };
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            positional.push(arg.clone());
+            continue;
+        };
+        if flag == "-" {
+            positional.extend(args.by_ref().cloned());
+            break;
+        }
+        let (name, inline) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (flag, None),
+        };
+        match name.trim_start_matches('-') {
+            "h" | "help" => inv.action = Action::Help,
+            "V" | "version" => inv.action = Action::Version,
+            "show-config" => inv.show_config = true,
+            "config" => {
+                let path = inline
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| anyhow!("--config needs a path"))?;
+                inv.config_file = Some(PathBuf::from(path));
+            }
+            "config-json" => {
+                let json = inline
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| anyhow!("--config-json needs a JSON object"))?;
+                inv.config_json = Some(json);
+            }
+            "stdin-config" => inv.stdin_config = true,
+            option => {
+                let (key, negated) = match aliases.resolve(option) {
+                    Some(key) => (key, false),
+                    None => match option.strip_prefix("no-").and_then(|o| aliases.resolve(o)) {
+                        Some(key) => (key, true),
+                        None => bail!("unknown option `{arg}` (see --help)"),
+                    },
+                };
+                let kind = crate::core::config::spec(key).map(|s| s.kind);
+                let raw = match (kind, negated, inline) {
+                    (Some(Kind::Bool), true, None) => "false".to_owned(),
+                    (_, true, _) => bail!("`{arg}`: only boolean options can be negated"),
+                    (Some(Kind::Bool), false, None) => "true".to_owned(),
+                    (_, false, Some(value)) => value,
+                    (_, false, None) => args
+                        .next()
+                        .cloned()
+                        .ok_or_else(|| anyhow!("`{arg}` needs a value"))?,
+                };
+                inv.overrides.push((key, raw));
+            }
+        }
+    }
+
+    let mut positional = positional.into_iter().peekable();
+    if positional.peek().is_some_and(|p| p == "serve") {
+        positional.next();
+    }
+    if let Some(root) = positional.next() {
+        inv.overrides.push(("root", root));
+    }
+    if let Some(extra) = positional.next() {
+        bail!("unexpected argument `{extra}`");
+    }
+    Ok(inv)
+}
+
+/// Describes the option spelled `name`: its canonical key and spellings,
+/// type, default, effective value and where that value came from.
+fn explain(
+    name: &str,
+    config: &Config,
+    aliases: &Aliases,
+    inv: &Invocation,
+    configs_dir: Option<&Path>,
+) -> Result<String> {
+    let Some(spec) = aliases.resolve(name).and_then(crate::core::config::spec) else {
+        let wanted = aliases.normalize(name);
+        let near: Vec<String> = OPTIONS
+            .iter()
+            .filter(|s| aliases.normalize(s.key).contains(&wanted))
+            .map(|s| format!("--{}", kebab(s.key)))
+            .collect();
+        if near.is_empty() {
+            bail!("unknown option `{name}`");
+        }
+        bail!("unknown option `{name}`; did you mean {}?", near.join(", "));
+    };
+    let key = spec.key;
+    let env_name = format!(
+        "{ENV_PREFIX}{}",
+        kebab(key).replace('-', "_").to_uppercase()
+    );
+    let mut spellings: Vec<String> = spec.aliases.iter().map(|a| a.to_string()).collect();
+    let mut user: Vec<&str> = aliases
+        .entries()
+        .filter(|(alias, target)| {
+            *target == key
+                && *alias != aliases.normalize(key)
+                && !spec.aliases.iter().any(|a| aliases.normalize(a) == *alias)
+        })
+        .map(|(alias, _)| alias)
+        .collect();
+    user.sort_unstable();
+    spellings.extend(user.iter().map(|a| format!("{a} (aliases.json)")));
+
+    let kind = match spec.kind {
+        Kind::Enum(choices) => choices.join("|"),
+        kind => kind.name().to_owned(),
+    };
+    let source = match (config.layer(key), configs_dir) {
+        (Layer::Default, Some(dir)) if *config.get(key) != spec.kind.parse(spec.default)? => {
+            format!("default, found in {}", dir.display())
+        }
+        (Layer::Default, _) => "default".to_owned(),
+        (Layer::File, None) if inv.stdin_config => "config from stdin".to_owned(),
+        (Layer::File, None) => "--config-json".to_owned(),
+        (Layer::File, Some(dir)) => {
+            let file = match &inv.config_file {
+                Some(path) => path.clone(),
+                None => dir.join(CONFIG_FILE),
+            };
+            format!("config file {}", file.display())
+        }
+        (Layer::Env, _) => {
+            let vars: Vec<String> = env::vars()
+                .filter_map(|(var, _)| {
+                    let rest = var.strip_prefix(ENV_PREFIX)?;
+                    (aliases.resolve(rest) == Some(key)).then_some(var)
+                })
+                .collect();
+            format!("environment ({})", vars.join(", "))
+        }
+        (Layer::Cli, _) => "command line".to_owned(),
+    };
+    let mut out = format!("{key}\n  {}\n", spec.help);
+    out.push_str(&format!("  flag:      --{}\n", kebab(key)));
+    out.push_str(&format!("  env:       {env_name}\n"));
+    if !spellings.is_empty() {
+        out.push_str(&format!("  aliases:   {}\n", spellings.join(", ")));
+    }
+    out.push_str(&format!("  type:      {kind}\n"));
+    let values: Vec<String> = value_aliases(spec)
+        .map(|(alias, canonical)| format!("{alias}={canonical}"))
+        .collect();
+    if !values.is_empty() {
+        out.push_str(&format!("  values:    {}\n", values.join(", ")));
+    }
+    out.push_str(&format!(
+        "  default:   {}\n",
+        spec.kind.parse(spec.default)?
+    ));
+    out.push_str(&format!("  value:     {}\n", config.shown(key)));
+    out.push_str(&format!("  set by:    {source}\n"));
+    Ok(out)
+}
+
+/// `showDir` -> `show-dir`.
+pub fn kebab(key: &str) -> String {
+    let mut out = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('-');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn help() -> String {
+    let mut out = format!(
+        "tinyserve {}\n{}\n\nUsage: tinyserve [serve] [ROOT] [OPTIONS]\n       tinyserve bench [URL|DIR] [-c N] [-d SECS] [--path P] [--no-keep-alive]\n       tinyserve precompress [DIR] [--formats gz,br,zst] [--min-size N] [--types T,...] [--exclude GLOB]... [--manifest FILE [--fingerprint GLOB]...] [--force]\n       tinyserve completions <bash|zsh|fish|powershell>\n       tinyserve audit verify FILE --key-file PATH\n       tinyserve explain OPTION [OPTIONS]\n       tinyserve stop|status [OPTIONS]\n       tinyserve service <install [ROOT] [OPTIONS]|start|stop|uninstall>\n       tinyserve up [status] [FILE] [OPTIONS]\n\nOptions:\n",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_DESCRIPTION")
+            .split(". ")
+            .next()
+            .unwrap_or_default()
+    );
+    for spec in OPTIONS {
+        let value = match spec.kind {
+            Kind::Bool => String::new(),
+            Kind::Enum(choices) => format!(" <{}>", choices.join("|")),
+            kind => format!(" <{}>", kind.name()),
+        };
+        let flag = format!("--{}{value}", kebab(spec.key));
+        out.push_str(&format!(
+            "  {flag:<34} {} [default: {}]\n",
+            spec.help, spec.default
+        ));
+    }
+    out.push_str(concat!(
+        "  --config <path>                    Config file (default: ~/.tinyserve/configs/config.json)\n",
+        "  --config-json <json>               Whole config as a JSON object; the configs dir is not used\n",
+        "  --stdin-config                     Read the whole config as JSON from stdin instead\n",
+        "  --show-config                      Print the effective configuration and exit\n",
+        "  -h, --help                         Print help\n",
+        "  -V, --version                      Print version\n",
+        "\nEvery option can also be set as TINYSERVE_<OPTION> in the environment.\n",
+    ));
+    out
+}
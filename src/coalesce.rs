@@ -0,0 +1,212 @@
+//! Request coalescing: when several threads need the same expensive result
+//! at once (a file read into the cache, a fetch from the origin), the
+//! first one does the work and the others wait for its result instead of
+//! repeating it.
+//!
+//! Only work in progress is shared; once it finishes, the next caller
+//! starts afresh, so callers still keep the result somewhere (a cache)
+//! themselves. Errors are shared too, as an error of the same kind and
+//! message. If the thread doing the work panics, the waiting ones each
+//! retry on their own.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+enum State<T> {
+    Running,
+    Done(Result<T, (io::ErrorKind, String)>),
+    Abandoned,
+}
+
+struct Flight<T> {
+    state: Mutex<State<T>>,
+    landed: Condvar,
+}
+
+/// Work in progress by key.
+pub struct Flights<K, T> {
+    running: Mutex<HashMap<K, Arc<Flight<T>>>>,
+}
+
+impl<K, T> Default for Flights<K, T> {
+    fn default() -> Self {
+        Self {
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Flights<K, T> {
+    /// Runs `work` for `key`, or waits for the same key's work already
+    /// running elsewhere and returns its result.
+    pub fn run(&self, key: &K, work: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let (flight, leader) = {
+            let mut running = lock(&self.running);
+            match running.get(key) {
+                Some(flight) => (Arc::clone(flight), false),
+                None => {
+                    let flight = Arc::new(Flight {
+                        state: Mutex::new(State::Running),
+                        landed: Condvar::new(),
+                    });
+                    running.insert(key.clone(), Arc::clone(&flight));
+                    (flight, true)
+                }
+            }
+        };
+        if !leader {
+            let mut state = lock(&flight.state);
+            while matches!(*state, State::Running) {
+                state = flight.landed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            return match &*state {
+                State::Done(Ok(value)) => Ok(value.clone()),
+                State::Done(Err((kind, message))) => Err(io::Error::new(*kind, message.clone())),
+                State::Abandoned | State::Running => {
+                    drop(state);
+                    work()
+                }
+            };
+        }
+        let landing = Landing {
+            flights: self,
+            key,
+            flight: &flight,
+        };
+        let result = work();
+        *lock(&flight.state) = State::Done(match &result {
+            Ok(value) => Ok(value.clone()),
+            Err(e) => Err((e.kind(), e.to_string())),
+        });
+        drop(landing);
+        result
+    }
+}
+
+/// Ends a flight when its leader is done, or unwinds.
+struct Landing<'a, K: Eq + Hash, T> {
+    flights: &'a Flights<K, T>,
+    key: &'a K,
+    flight: &'a Flight<T>,
+}
+
+impl<K: Eq + Hash, T> Drop for Landing<'_, K, T> {
+    fn drop(&mut self) {
+        lock(&self.flights.running).remove(self.key);
+        let mut state = lock(&self.flight.state);
+        if matches!(*state, State::Running) {
+            *state = State::Abandoned;
+        }
+        self.flight.landed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    const FOLLOWERS: usize = 4;
+
+    /// Blocks until `FOLLOWERS` callers have joined the flight for `key`.
+    fn await_followers(flights: &Flights<&str, u32>, key: &str) {
+        // The table and the leader hold one reference each.
+        while lock(&flights.running).get(key).map_or(0, Arc::strong_count) < 2 + FOLLOWERS {
+            thread::yield_now();
+        }
+    }
+
+    /// Runs a leader whose work is `work` once every follower waits on it,
+    /// and returns what each caller got, the leader's first.
+    fn fly(
+        flights: &Flights<&'static str, u32>,
+        work: impl Fn() -> io::Result<u32> + Sync,
+    ) -> Vec<thread::Result<io::Result<u32>>> {
+        thread::scope(|s| {
+            let leader = s.spawn(|| {
+                flights.run(&"key", || {
+                    await_followers(flights, "key");
+                    work()
+                })
+            });
+            while !lock(&flights.running).contains_key("key") {
+                thread::yield_now();
+            }
+            let followers: Vec<_> = (0..FOLLOWERS)
+                .map(|_| s.spawn(|| flights.run(&"key", &work)))
+                .collect();
+            std::iter::once(leader)
+                .chain(followers)
+                .map(|h| h.join())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn concurrent_callers_share_one_result() {
+        let flights = Flights::default();
+        let runs = AtomicUsize::new(0);
+        let results = fly(&flights, || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(42)
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result.unwrap().unwrap(), 42);
+        }
+        assert!(lock(&flights.running).is_empty());
+    }
+
+    #[test]
+    fn concurrent_callers_share_one_error() {
+        let flights = Flights::default();
+        let runs = AtomicUsize::new(0);
+        let results = fly(&flights, || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::new(io::ErrorKind::NotFound, "gone"))
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for result in results {
+            let e = result.unwrap().unwrap_err();
+            assert_eq!(
+                (e.kind(), e.to_string()),
+                (io::ErrorKind::NotFound, "gone".into())
+            );
+        }
+    }
+
+    #[test]
+    fn finished_work_is_not_reused() {
+        let flights = Flights::default();
+        assert_eq!(flights.run(&"key", || Ok(1)).unwrap(), 1);
+        assert_eq!(flights.run(&"key", || Ok(2)).unwrap(), 2);
+        assert_eq!(flights.run(&"other", || Ok(3)).unwrap(), 3);
+    }
+
+    #[test]
+    fn followers_retry_when_the_leader_panics() {
+        let flights = Flights::default();
+        let runs = AtomicUsize::new(0);
+        let results = fly(&flights, || {
+            if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("leader fails");
+            }
+            Ok(7)
+        });
+        let mut results = results.into_iter();
+        assert!(results.next().unwrap().is_err());
+        for result in results {
+            assert_eq!(result.unwrap().unwrap(), 7);
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1 + FOLLOWERS);
+        assert!(lock(&flights.running).is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+//! `tinyserve completions`: shell completion scripts.
+//!
+//! ```text
+//! tinyserve completions <bash|zsh|fish|powershell>
+//! ```
+//!
+//! The scripts ask the binary for candidates as they complete, through
+//! `completions --list` (every flag, tab, its help) and
+//! `completions --values FLAG` (the choices of an enum option), so options
+//! and the aliases in `aliases.json` are always current.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+
+use crate::cli::kebab;
+use crate::core::config::spec;
+use crate::core::{Aliases, Kind, OPTIONS, dirs};
+
+/// Subcommands offered for the first word.
+const COMMANDS: &[(&str, &str)] = &[
+    ("serve", "Serve a directory (the default)"),
+    ("bench", "Measure throughput and latency"),
+    ("precompress", "Write compressed sidecars for a directory"),
+    ("completions", "Print a shell completion script"),
+    ("audit", "Verify the HMAC chain of an audit log"),
+    (
+        "explain",
+        "Describe an option and where its value comes from",
+    ),
+    ("stop", "Stop the server started with --daemon"),
+    ("status", "Report whether the --daemon server is running"),
+    ("service", "Manage the Windows service"),
+    ("up", "Serve and supervise the sites in sites.json"),
+];
+
+/// Flags handled by the command line itself rather than the option table.
+const FIXED_FLAGS: &[(&str, &str)] = &[
+    ("--config", "Config file"),
+    ("--config-json", "Whole config as a JSON object"),
+    ("--stdin-config", "Read the whole config as JSON from stdin"),
+    (
+        "--show-config",
+        "Print the effective configuration and exit",
+    ),
+    ("--help", "Print help"),
+    ("--version", "Print version"),
+];
+
+const BASH: &str = r#"_tinyserve() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local bin="${COMP_WORDS[0]}"
+    if [[ $prev == -* ]]; then
+        local values
+        values=$("$bin" completions --values "$prev" 2>/dev/null)
+        if [[ -n $values ]]; then
+            COMPREPLY=($(compgen -W "$values" -- "$cur"))
+            return
+        fi
+    fi
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W "$("$bin" completions --list 2>/dev/null | cut -f1)" -- "$cur"))
+        return
+    fi
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur"))
+    fi
+    COMPREPLY+=($(compgen -f -- "$cur"))
+}
+complete -o filenames -F _tinyserve tinyserve
+"#;
+
+const ZSH: &str = r#"#compdef tinyserve
+
+_tinyserve() {
+    local prev=$words[CURRENT-1] line
+    local -a candidates
+    if [[ $prev == -* ]]; then
+        candidates=(${(f)"$($words[1] completions --values $prev 2>/dev/null)"})
+        if (( $#candidates )); then
+            compadd -a candidates
+            return
+        fi
+    fi
+    if [[ $PREFIX == -* ]]; then
+        for line in ${(f)"$($words[1] completions --list 2>/dev/null)"}; do
+            candidates+=("${line%%$'\t'*}:${line#*$'\t'}")
+        done
+        _describe -t options option candidates
+        return
+    fi
+    if (( CURRENT == 2 )); then
+        candidates=(@COMMANDS@)
+        _describe -t commands command candidates
+    fi
+    _files
+}
+
+if [[ $funcstack[1] == _tinyserve ]]; then
+    _tinyserve "$@"
+else
+    compdef _tinyserve tinyserve
+fi
+"#;
+
+const FISH: &str = r#"function __tinyserve_values
+    set -l prev (commandline -opc)[-1]
+    string match -q -- '-*' $prev; or return 1
+    set -l values (tinyserve completions --values $prev 2>/dev/null)
+    test (count $values) -gt 0; or return 1
+    printf '%s\n' $values
+end
+
+complete -c tinyserve -f -n __tinyserve_values -a '(__tinyserve_values)'
+complete -c tinyserve -n 'string match -q -- "-*" (commandline -ct)' -a '(tinyserve completions --list 2>/dev/null)'
+@COMMANDS@
+"#;
+
+const POWERSHELL: &str = r#"Register-ArgumentCompleter -Native -CommandName tinyserve -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })
+    $prev = if ($wordToComplete) { $words[-2] } else { $words[-1] }
+    if ($prev -like '-*') {
+        $values = @(tinyserve completions --values $prev 2>$null)
+        if ($values.Count -gt 0) {
+            $values | Where-Object { $_ -like "$wordToComplete*" } | ForEach-Object {
+                [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+            }
+            return
+        }
+    }
+    if ($wordToComplete -like '-*') {
+        tinyserve completions --list 2>$null | ForEach-Object {
+            $flag, $help = $_ -split "`t", 2
+            if ($flag -like "$wordToComplete*") {
+                [System.Management.Automation.CompletionResult]::new($flag, $flag, 'ParameterName', $(if ($help) { $help } else { $flag }))
+            }
+        }
+        return
+    }
+    if ($words.Count -le 2) {
+        @(@COMMANDS@) | Where-Object { $_[0] -like "$wordToComplete*" } | ForEach-Object {
+            [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'Command', $_[1])
+        }
+    }
+}
+"#;
+
+/// Runs `tinyserve completions` with the arguments after it.
+pub fn run(args: &[String]) -> Result<()> {
+    match args {
+        [flag] if flag == "--list" => {
+            for (flag, help) in flags(&*aliases()?) {
+                println!("{flag}\t{help}");
+            }
+        }
+        [flag, option] if flag == "--values" => {
+            for value in values(&*aliases()?, option) {
+                println!("{value}");
+            }
+        }
+        [shell] => print!("{}", script(shell)?),
+        _ => bail!("usage: tinyserve completions <bash|zsh|fish|powershell>"),
+    }
+    Ok(())
+}
+
+/// The alias table, user aliases included. Completing must not create the
+/// configs dir, so a missing one just means builtin aliases.
+fn aliases() -> Result<Arc<Aliases>> {
+    match dirs::default_configs_dir() {
+        Ok(dir) => Aliases::shared(&dir),
+        Err(_) => Ok(Arc::new(Aliases::builtin())),
+    }
+}
+
+/// The completion script for `shell`.
+fn script(shell: &str) -> Result<String> {
+    let (template, commands) = match shell {
+        "bash" => (
+            BASH,
+            COMMANDS
+                .iter()
+                .map(|(c, _)| *c)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        "zsh" => (
+            ZSH,
+            COMMANDS
+                .iter()
+                .map(|(c, help)| format!("'{c}:{help}'"))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        "fish" => (
+            FISH,
+            COMMANDS
+                .iter()
+                .map(|(c, help)| {
+                    format!("complete -c tinyserve -n __fish_use_subcommand -a {c} -d '{help}'")
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        "powershell" => (
+            POWERSHELL,
+            COMMANDS
+                .iter()
+                .map(|(c, help)| format!("@('{c}', '{help}')"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        other => bail!("unknown shell `{other}` (bash, zsh, fish, powershell)"),
+    };
+    Ok(template.replace("@COMMANDS@", &commands))
+}
+
+/// Every flag with a line of help: each option under its kebab-case name,
+/// `--no-` forms of options on by default, and the aliases.
+fn flags(aliases: &Aliases) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut seen = HashSet::new();
+    for spec in OPTIONS {
+        let name = kebab(spec.key);
+        seen.insert(aliases.normalize(&name));
+        out.push((format!("--{name}"), spec.help.to_owned()));
+        if spec.kind == Kind::Bool && spec.default == "true" {
+            out.push((format!("--no-{name}"), format!("Turn off --{name}")));
+        }
+        for alias in spec.aliases {
+            seen.insert(aliases.normalize(alias));
+            let dashes = if alias.len() == 1 { "-" } else { "--" };
+            out.push((format!("{dashes}{alias}"), format!("Same as --{name}")));
+        }
+    }
+    // Aliases from `aliases.json`, known only in normalized form.
+    let mut user: Vec<_> = aliases
+        .entries()
+        .filter(|(alias, _)| !seen.contains(*alias))
+        .collect();
+    user.sort();
+    for (alias, key) in user {
+        out.push((format!("--{alias}"), format!("Same as --{}", kebab(key))));
+    }
+    for (flag, help) in FIXED_FLAGS {
+        out.push(((*flag).to_owned(), (*help).to_owned()));
+    }
+    out
+}
+
+/// The values `flag` takes, for options with a fixed set of them.
+fn values(aliases: &Aliases, flag: &str) -> Vec<&'static str> {
+    let Some(spec) = aliases.resolve(flag).and_then(spec) else {
+        return Vec::new();
+    };
+    match spec.kind {
+        Kind::Enum(choices) => choices.to_vec(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,282 @@
+//! Alternative spellings for option keys.
+//!
+//! Every option can be written in several ways (`show-dir`, `show_dir`,
+//! `SHOW_DIR`, `showDir`), plus any short aliases declared in the option table
+//! or in the user's `aliases.json`. [`Aliases::resolve`] maps all of them to the
+//! canonical key.
+//!
+//! Which spellings count as the same is up to a [`KeyNormalizer`]. The
+//! default, [`Separators::default`], ignores case, `-` and `_`; embedders
+//! whose own settings use other conventions (`server.show.dir`,
+//! `server:showDir`) can pass their own to [`Aliases::builtin_with`].
+//!
+//! Within a process the table is loaded once: [`Aliases::shared`] hands
+//! every caller the same copy and builds a new one only when the contents
+//! of `aliases.json` have changed since, so a reload of the configuration
+//! picks up edits to it, even ones that keep its size and timestamp,
+//! without rebuilding the table for an unchanged file.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context as _, Result, bail};
+use serde_json::Value;
+
+use super::config::OPTIONS;
+
+/// File name of the user alias table inside the configs dir.
+pub const ALIASES_FILE: &str = "aliases.json";
+
+/// The table last built by [`Aliases::shared`], with the configs dir and
+/// the `aliases.json` it was built from.
+struct Shared {
+    dir: PathBuf,
+    stamp: Option<u64>,
+    aliases: Arc<Aliases>,
+}
+
+static SHARED: Mutex<Option<Shared>> = Mutex::new(None);
+
+/// A hash of the contents of `path`, `None` if it cannot be read.
+fn stamp(path: &Path) -> Option<u64> {
+    let contents = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Reduces a key to the form used for lookups: ASCII-lowercased, with `-` and
+/// `_` removed and leading dashes stripped.
+pub fn normalize_key(key: &str) -> String {
+    Separators::default().normalize(key)
+}
+
+/// Reduces every spelling of a key to one lookup form.
+///
+/// Two spellings name the same option when they normalize to the same
+/// string. Keys arrive as written, including the leading dashes of
+/// command-line flags. Any `Fn(&str) -> String` is a normalizer.
+pub trait KeyNormalizer: Send + Sync {
+    fn normalize(&self, key: &str) -> String;
+}
+
+impl<F: Fn(&str) -> String + Send + Sync> KeyNormalizer for F {
+    fn normalize(&self, key: &str) -> String {
+        self(key)
+    }
+}
+
+/// Normalizer that drops separator characters and, optionally, case.
+/// Leading dashes are always stripped.
+#[derive(Clone, Debug)]
+pub struct Separators {
+    separators: Vec<char>,
+    fold_case: bool,
+}
+
+impl Separators {
+    /// Ignores case and each of `separators`.
+    pub fn new(separators: &[char]) -> Self {
+        Self {
+            separators: separators.to_vec(),
+            fold_case: true,
+        }
+    }
+
+    /// Whether `showDir` and `SHOWDIR` are the same key (default true).
+    pub fn fold_case(mut self, fold: bool) -> Self {
+        self.fold_case = fold;
+        self
+    }
+}
+
+impl Default for Separators {
+    /// `-` and `_`, ignoring case: tinyserve's own convention.
+    fn default() -> Self {
+        Self::new(&['-', '_'])
+    }
+}
+
+impl KeyNormalizer for Separators {
+    fn normalize(&self, key: &str) -> String {
+        key.trim_start_matches('-')
+            .chars()
+            .filter(|c| !self.separators.contains(c))
+            .map(|c| match self.fold_case {
+                true => c.to_ascii_lowercase(),
+                false => c,
+            })
+            .collect()
+    }
+}
+
+/// Index from normalized spellings to canonical option keys.
+#[derive(Clone)]
+pub struct Aliases {
+    index: HashMap<String, &'static str>,
+    normalizer: Arc<dyn KeyNormalizer>,
+}
+
+impl fmt::Debug for Aliases {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aliases")
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Aliases {
+    /// The canonical keys and the aliases declared in [`OPTIONS`].
+    pub fn builtin() -> Self {
+        Self::builtin_with(Separators::default())
+    }
+
+    /// [`builtin`](Self::builtin), matching spellings with `normalizer`.
+    pub fn builtin_with(normalizer: impl KeyNormalizer + 'static) -> Self {
+        let mut aliases = Self {
+            index: HashMap::new(),
+            normalizer: Arc::new(normalizer),
+        };
+        for spec in OPTIONS {
+            aliases.insert(spec.key, spec.key);
+            for alias in spec.aliases {
+                aliases.insert(alias, spec.key);
+            }
+        }
+        aliases
+    }
+
+    /// Builtin aliases extended with `aliases.json` from `configs_dir`, if present.
+    pub fn load(configs_dir: &Path) -> Result<Self> {
+        Self::load_with(configs_dir, Separators::default())
+    }
+
+    /// [`load`](Self::load), shared: the same table as the last call for
+    /// `configs_dir` unless `aliases.json` changed in between. A table that
+    /// fails to load is not kept, so the next call tries again.
+    pub fn shared(configs_dir: &Path) -> Result<Arc<Self>> {
+        let stamp = stamp(&configs_dir.join(ALIASES_FILE));
+        let mut shared = SHARED.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(current) = shared.as_ref()
+            && current.dir == configs_dir
+            && current.stamp == stamp
+        {
+            return Ok(Arc::clone(&current.aliases));
+        }
+        let aliases = Arc::new(Self::load(configs_dir)?);
+        *shared = Some(Shared {
+            dir: configs_dir.to_owned(),
+            stamp,
+            aliases: Arc::clone(&aliases),
+        });
+        Ok(aliases)
+    }
+
+    /// [`load`](Self::load), matching spellings with `normalizer`.
+    pub fn load_with(configs_dir: &Path, normalizer: impl KeyNormalizer + 'static) -> Result<Self> {
+        let mut aliases = Self::builtin_with(normalizer);
+        let path = configs_dir.join(ALIASES_FILE);
+        if path.is_file() {
+            let text =
+                fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+            let table: Value = serde_json::from_str(&text)
+                .with_context(|| format!("parsing {}", path.display()))?;
+            aliases
+                .extend_from_json(&table)
+                .with_context(|| format!("in {}", path.display()))?;
+        }
+        Ok(aliases)
+    }
+
+    /// Adds `{"alias": "canonicalKey"}` pairs. The target may itself be any
+    /// spelling that already resolves.
+    pub fn extend_from_json(&mut self, table: &Value) -> Result<()> {
+        let Some(map) = table.as_object() else {
+            bail!("alias table must be a JSON object");
+        };
+        for (alias, target) in map {
+            let Some(target) = target.as_str() else {
+                bail!("alias `{alias}` must map to a string");
+            };
+            let Some(canonical) = self.resolve(target) else {
+                bail!("alias `{alias}` points at unknown option `{target}`");
+            };
+            self.insert(alias, canonical);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, alias: &str, canonical: &'static str) {
+        self.index.insert(self.normalize(alias), canonical);
+    }
+
+    /// The lookup form of `key` under this index's normalizer.
+    pub fn normalize(&self, key: &str) -> String {
+        self.normalizer.normalize(key)
+    }
+
+    /// Returns the canonical key for any known spelling of an option.
+    pub fn resolve(&self, key: &str) -> Option<&'static str> {
+        self.index.get(&self.normalize(key)).copied()
+    }
+
+    /// Every spelling known to the index (normalized), with its canonical key.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        self.index.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestServer;
+
+    #[test]
+    fn servers_take_keys_spelled_the_embedders_way() {
+        let dotted = || Aliases::builtin_with(Separators::new(&['.']));
+        let server = TestServer::with(|b| b.aliases(dotted()).option("show.dir", true)).unwrap();
+        server.write("a.txt", "a").unwrap();
+        let listing = server.get("/").unwrap();
+        assert_eq!(listing.status, 200);
+        assert!(listing.text().contains("a.txt"));
+
+        // `-` is no separator to this normalizer.
+        let err = TestServer::with(|b| b.aliases(dotted()).option("show-dir", true))
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("unknown option `show-dir`"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn shared_tables_follow_the_file_contents() {
+        let dir = std::env::temp_dir().join(format!("tinyserve-aliases-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(ALIASES_FILE);
+        fs::write(&file, r#"{"sd": "showDir"}"#).unwrap();
+        let first = Aliases::shared(&dir).unwrap();
+        assert!(Arc::ptr_eq(&first, &Aliases::shared(&dir).unwrap()));
+        assert_eq!(first.resolve("sd"), Some("showDir"));
+
+        // Same length and timestamp, new contents.
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        fs::write(&file, r#"{"sd": "workers"}"#).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let second = Aliases::shared(&dir).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.resolve("sd"), Some("workers"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
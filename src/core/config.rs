@@ -0,0 +1,1604 @@
+//! Option schema and layered configuration.
+//!
+//! Options are declared once in [`OPTIONS`]. A [`Config`] starts from the
+//! declared defaults and is overlaid, in order, by the config file,
+//! `TINYSERVE_*` environment variables and command-line flags. Each value
+//! remembers the [`Layer`] that set it.
+//!
+//! A config file may name others under [`INCLUDE_KEY`]; see
+//! [`Config::merge_file`].
+//!
+//! Values given as text from any source first go through [`VALUE_ALIASES`],
+//! so `on` works wherever `true` does and `w` wherever `weak` does.
+//!
+//! `${env:VAR}` and `${file:PATH}` in a value are replaced by the variable or
+//! the file's contents when the value is set (`~/` in `PATH` is the home
+//! directory), so secrets need not be written into config files. [`Config::to_json`] and [`Config::shown`] give such
+//! values as written, never resolved.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, anyhow};
+use serde_json::{Map, Value};
+
+use crate::Error;
+
+use super::aliases::Aliases;
+use super::dirs;
+
+/// Prefix of environment variables read as options.
+pub const ENV_PREFIX: &str = "TINYSERVE_";
+
+/// Key of a config file listing other config files to read first.
+pub const INCLUDE_KEY: &str = "include";
+
+/// The type of an option value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Bool,
+    /// Non-negative integer.
+    Int,
+    /// Byte count, optionally with a binary unit suffix (`512k`, `10MB`).
+    Size,
+    Str,
+    /// Comma-separated on the command line, an array in JSON.
+    List,
+    /// One of a fixed set of lowercase words.
+    Enum(&'static [&'static str]),
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Bool => "bool",
+            Kind::Int => "int",
+            Kind::Size => "size",
+            Kind::Str => "string",
+            Kind::List => "list",
+            Kind::Enum(_) => "enum",
+        }
+    }
+
+    /// Parses a value given as text (command line, environment).
+    pub fn parse(self, raw: &str) -> crate::Result<Value> {
+        let raw = raw.trim();
+        Ok(match self {
+            Kind::Bool => match raw.to_ascii_lowercase().as_str() {
+                "true" | "1" => Value::Bool(true),
+                "false" | "0" => Value::Bool(false),
+                _ => {
+                    return Err(Error::Config(format!(
+                        "expected true or false, got `{raw}`"
+                    )));
+                }
+            },
+            Kind::Int => Value::from(raw.parse::<u64>().map_err(|_| {
+                Error::Config(format!("expected a non-negative integer, got `{raw}`"))
+            })?),
+            Kind::Size => Value::from(parse_size(raw)?),
+            Kind::Str => Value::from(raw),
+            Kind::List => Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(Value::from)
+                    .collect(),
+            ),
+            Kind::Enum(choices) => {
+                let word = raw.to_ascii_lowercase();
+                match choices.iter().find(|c| **c == word) {
+                    Some(c) => Value::from(*c),
+                    None => {
+                        return Err(Error::Config(format!(
+                            "expected one of {}, got `{raw}`",
+                            choices.join(", ")
+                        )));
+                    }
+                }
+            }
+        })
+    }
+
+    /// Validates a value given as JSON, accepting strings for every kind.
+    pub fn coerce(self, value: &Value) -> crate::Result<Value> {
+        match (self, value) {
+            (_, Value::String(s)) if self != Kind::Str => self.parse(s),
+            (Kind::Bool, Value::Bool(_)) | (Kind::Str, Value::String(_)) => Ok(value.clone()),
+            (Kind::Int | Kind::Size, Value::Number(n)) if n.is_u64() => Ok(value.clone()),
+            (Kind::List, Value::Array(items)) if items.iter().all(Value::is_string) => {
+                Ok(value.clone())
+            }
+            _ => Err(Error::Config(format!(
+                "expected {}, got `{value}`",
+                self.name()
+            ))),
+        }
+    }
+}
+
+/// Parses `1024`, `512k`, `10MB`, `2GiB`; units are powers of 1024.
+pub fn parse_size(raw: &str) -> crate::Result<u64> {
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (digits, unit) = raw.split_at(split);
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| Error::Config(format!("expected a size like 512k or 10MB, got `{raw}`")))?;
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => return Err(Error::Config(format!("unknown size unit in `{raw}`"))),
+    };
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| Error::Config(format!("size `{raw}` is too large")))
+}
+
+/// Other spellings of option values, as `(key, alias, canonical)`. An empty
+/// key applies to every `Bool` option. Aliases match case-insensitively.
+pub static VALUE_ALIASES: &[(&str, &str, &str)] = &[
+    ("", "on", "true"),
+    ("", "yes", "true"),
+    ("", "y", "true"),
+    ("", "off", "false"),
+    ("", "no", "false"),
+    ("", "n", "false"),
+    ("listingTheme", "default", "plain"),
+    ("etag", "s", "strong"),
+    ("etag", "w", "weak"),
+    ("etag", "none", "off"),
+    ("etag", "false", "off"),
+    ("etag", "no", "off"),
+    ("runtime", "thread", "threads"),
+    ("runtime", "async", "tokio"),
+    ("backpressure", "wait", "queue"),
+    ("backpressure", "refuse", "reject"),
+    ("authFor", "everything", "all"),
+    ("authFor", "write", "writes"),
+    ("output", "plain", "text"),
+];
+
+/// The aliases of `spec`'s values, as `(alias, canonical)`.
+pub fn value_aliases(spec: &OptionSpec) -> impl Iterator<Item = (&'static str, &'static str)> {
+    VALUE_ALIASES
+        .iter()
+        .filter(move |(key, _, _)| *key == spec.key || (key.is_empty() && spec.kind == Kind::Bool))
+        .map(|(_, alias, canonical)| (*alias, *canonical))
+}
+
+/// `raw`, or the canonical spelling if it is an alias of a `spec` value.
+pub fn normalize_value<'a>(spec: &OptionSpec, raw: &'a str) -> &'a str {
+    let trimmed = raw.trim();
+    value_aliases(spec)
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(trimmed))
+        .map_or(raw, |(_, canonical)| canonical)
+}
+
+/// `text` with its `${env:VAR}` and `${file:PATH}` placeholders resolved,
+/// or `None` if it has none. Other `${...}` are left alone. Trailing line
+/// breaks of a file are dropped.
+fn expand(text: &str) -> Result<Option<String>> {
+    if !text.contains("${env:") && !text.contains("${file:") {
+        return Ok(None);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start + 2..];
+        let Some(end) = tail.find('}') else {
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let inner = &tail[..end];
+        if let Some(var) = inner.strip_prefix("env:") {
+            let value =
+                env::var(var).map_err(|_| anyhow!("environment variable {var} is not set"))?;
+            out.push_str(&value);
+        } else if let Some(path) = inner.strip_prefix("file:") {
+            let value = fs::read_to_string(home_relative(path)?)
+                .with_context(|| format!("reading {path}"))?;
+            out.push_str(value.trim_end_matches(['\r', '\n']));
+        } else {
+            out.push_str(&rest[start..start + end + 3]);
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Some(out))
+}
+
+/// `path` with a leading `~/` standing for the home directory.
+fn home_relative(path: &str) -> Result<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(rest) => Ok(dirs::home_dir()?.join(rest)),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// [`expand`] over a JSON string or array of strings.
+fn expand_json(value: &Value) -> Result<Option<Value>> {
+    match value {
+        Value::String(s) => Ok(expand(s)?.map(Value::from)),
+        Value::Array(items) => {
+            let mut changed = false;
+            let mut out = Vec::with_capacity(items.len());
+            for item in items {
+                match item.as_str().map(expand).transpose()?.flatten() {
+                    Some(expanded) => {
+                        changed = true;
+                        out.push(Value::from(expanded));
+                    }
+                    None => out.push(item.clone()),
+                }
+            }
+            Ok(changed.then_some(Value::Array(out)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Declaration of a single option.
+#[derive(Debug)]
+pub struct OptionSpec {
+    /// Canonical camelCase key.
+    pub key: &'static str,
+    pub kind: Kind,
+    /// Default value in the textual form accepted by [`Kind::parse`].
+    pub default: &'static str,
+    /// Extra spellings besides the case/separator variants of `key`.
+    pub aliases: &'static [&'static str],
+    pub help: &'static str,
+}
+
+const fn opt(
+    key: &'static str,
+    kind: Kind,
+    default: &'static str,
+    aliases: &'static [&'static str],
+    help: &'static str,
+) -> OptionSpec {
+    OptionSpec {
+        key,
+        kind,
+        default,
+        aliases,
+        help,
+    }
+}
+
+/// Every option tinyserve understands.
+pub static OPTIONS: &[OptionSpec] = &[
+    opt(
+        "root",
+        Kind::Str,
+        ".",
+        &["dir", "r"],
+        "Directory, .zip/.tar archive, or http(s):// or s3:// origin to serve",
+    ),
+    opt(
+        "host",
+        Kind::Str,
+        "127.0.0.1",
+        &["bind", "address", "a"],
+        "Address to listen on (:: for IPv6, and IPv4 per dualStack)",
+    ),
+    opt(
+        "dualStack",
+        Kind::Enum(&["auto", "mapped", "separate", "off"]),
+        "auto",
+        &[],
+        "With host ::, also take IPv4: on the same socket (mapped), a second one (separate), or not (off)",
+    ),
+    opt(
+        "port",
+        Kind::Int,
+        "8080",
+        &["p"],
+        "Port to listen on (0 picks a free port)",
+    ),
+    opt(
+        "showDir",
+        Kind::Bool,
+        "false",
+        &["listing", "d"],
+        "Render directory listings",
+    ),
+    opt(
+        "listingTheme",
+        Kind::Enum(&["plain", "modern"]),
+        "plain",
+        &["theme"],
+        "Directory listing look: plain, or modern with icons, breadcrumbs and dark mode",
+    ),
+    opt(
+        "listingRows",
+        Kind::Int,
+        "1000",
+        &[],
+        "Rows on a listing page (0: all); the filter box searches the rest",
+    ),
+    opt(
+        "listingMaxEntries",
+        Kind::Int,
+        "100000",
+        &[],
+        "Directories with more entries are not listed (0: no limit)",
+    ),
+    opt(
+        "archiveMaxBytes",
+        Kind::Size,
+        "0",
+        &[],
+        "Refuse a .zip/.tar root whose entries add up to more (0: no limit)",
+    ),
+    opt(
+        "archiveMaxDepth",
+        Kind::Int,
+        "32",
+        &[],
+        "Refuse a .zip/.tar root with entries nested deeper (0: no limit)",
+    ),
+    opt(
+        "readme",
+        Kind::List,
+        "README.md,README.txt,README",
+        &[],
+        "Files shown above a directory listing, first found wins (empty disables)",
+    ),
+    opt(
+        "locale",
+        Kind::Str,
+        "auto",
+        &[],
+        "Language of listings and error pages: auto (from Accept-Language) or a locale like de",
+    ),
+    opt(
+        "localeDir",
+        Kind::Str,
+        "",
+        &[],
+        "Directory of <locale>.json string tables (default: locales in the configs dir)",
+    ),
+    opt(
+        "showHidden",
+        Kind::Bool,
+        "false",
+        &["dotfiles"],
+        "Serve and list dotfiles",
+    ),
+    opt(
+        "index",
+        Kind::List,
+        "index.html",
+        &[],
+        "Files served for a directory request",
+    ),
+    opt(
+        "pathNormalize",
+        Kind::Enum(&["off", "nfc", "nfd"]),
+        "off",
+        &[],
+        "Unicode form request paths are put in before lookup (nfc and nfd need the `unicode-normalization` feature)",
+    ),
+    opt(
+        "pathInvalidUtf8",
+        Kind::Enum(&["reject", "replace"]),
+        "reject",
+        &[],
+        "Request paths whose escapes are not UTF-8: answer 400, or replace the bad bytes with U+FFFD",
+    ),
+    opt(
+        "etag",
+        Kind::Enum(&["strong", "weak", "off"]),
+        "strong",
+        &[],
+        "ETag validators for files",
+    ),
+    opt(
+        "download",
+        Kind::List,
+        "",
+        &[],
+        "Globs of files sent as attachments, to download rather than open (?download forces it)",
+    ),
+    opt(
+        "deterministic",
+        Kind::Bool,
+        "false",
+        &[],
+        "Byte-identical responses for golden tests: fixed Date and times, content ETags, no version",
+    ),
+    opt(
+        "deterministicDate",
+        Kind::Int,
+        "946684800",
+        &[],
+        "Unix time given as Date and every modification time in deterministic mode",
+    ),
+    opt(
+        "shed",
+        Kind::Bool,
+        "false",
+        &[],
+        "Answer 503 instead of queueing once the server is overloaded",
+    ),
+    opt(
+        "shedInFlight",
+        Kind::Int,
+        "0",
+        &[],
+        "Requests in flight that count as overload (0 uses the worker count)",
+    ),
+    opt(
+        "shedQueueDelay",
+        Kind::Int,
+        "1000",
+        &[],
+        "Milliseconds waited for a worker that count as overload (0 ignores)",
+    ),
+    opt(
+        "shedFdPercent",
+        Kind::Int,
+        "90",
+        &[],
+        "Open file descriptors, in percent of the limit, that count as overload (0 ignores)",
+    ),
+    opt(
+        "runtime",
+        Kind::Enum(&["threads", "tokio"]),
+        "threads",
+        &[],
+        "Connection model: a thread per connection, or tokio (needs the `tokio` feature)",
+    ),
+    opt(
+        "workers",
+        Kind::Int,
+        "0",
+        &["threads"],
+        "Most connection worker threads, started on demand (0 picks 64 per CPU, at most 1024)",
+    ),
+    opt(
+        "acceptQueue",
+        Kind::Int,
+        "1024",
+        &[],
+        "Accepted connections that may wait for a free worker",
+    ),
+    opt(
+        "backpressure",
+        Kind::Enum(&["queue", "reject"]),
+        "queue",
+        &[],
+        "When the accept queue is full: wait for space, or answer 503 at once",
+    ),
+    opt(
+        "queueTimeout",
+        Kind::Int,
+        "5000",
+        &[],
+        "Milliseconds a connection may wait for a worker before getting 503",
+    ),
+    opt(
+        "sendfile",
+        Kind::Bool,
+        "true",
+        &[],
+        "Send file bodies with sendfile where the platform supports it",
+    ),
+    opt(
+        "mmap",
+        Kind::Bool,
+        "false",
+        &[],
+        "Serve large files from memory maps (only for files never truncated in place)",
+    ),
+    opt(
+        "mmapThreshold",
+        Kind::Size,
+        "1GB",
+        &[],
+        "Smallest file served from a memory map",
+    ),
+    opt(
+        "cacheSize",
+        Kind::Size,
+        "0",
+        &[],
+        "Memory for caching small file bodies (0 disables)",
+    ),
+    opt(
+        "cacheMaxFile",
+        Kind::Size,
+        "1MB",
+        &[],
+        "Largest file kept in the body cache",
+    ),
+    opt(
+        "statTtl",
+        Kind::Int,
+        "0",
+        &[],
+        "Milliseconds to reuse file metadata and failed lookups (0 disables)",
+    ),
+    opt(
+        "notFoundTtl",
+        Kind::Int,
+        "0",
+        &[],
+        "Milliseconds to remember that a path does not exist, if longer than statTtl (0 disables)",
+    ),
+    opt(
+        "preload",
+        Kind::List,
+        "",
+        &[],
+        "Globs of files read into the caches at startup, before accepting connections",
+    ),
+    opt(
+        "originCache",
+        Kind::Str,
+        "",
+        &[],
+        "Directory caching files pulled from an origin root (default: under the temp dir)",
+    ),
+    opt(
+        "originTtl",
+        Kind::Int,
+        "60000",
+        &[],
+        "Milliseconds before a file pulled from an origin root is revalidated",
+    ),
+    opt(
+        "originCacheControl",
+        Kind::Bool,
+        "false",
+        &[],
+        "Revalidate files pulled from an origin root when the origin's Cache-Control or Expires says",
+    ),
+    opt(
+        "languages",
+        Kind::Bool,
+        "false",
+        &[],
+        "Serve name.<lang>.ext variants chosen by Accept-Language",
+    ),
+    opt(
+        "defaultLanguage",
+        Kind::Str,
+        "en",
+        &[],
+        "Variant served when no Accept-Language preference matches",
+    ),
+    opt(
+        "throttle",
+        Kind::List,
+        "",
+        &[],
+        "Simulated slow network as [GLOB=]LATENCY[/RATE], e.g. 50ms/1Mbps or *.mp4=200ms/512kbps",
+    ),
+    opt(
+        "bandwidth",
+        Kind::Str,
+        "",
+        &[],
+        "Cap on what all connections together send, e.g. 20Mbps or 5MB/s (empty disables)",
+    ),
+    opt(
+        "bandwidthLimits",
+        Kind::List,
+        "",
+        &[],
+        "Caps on single responses as [GLOB=]RATE, e.g. *.iso=2MB/s",
+    ),
+    opt(
+        "chaos",
+        Kind::List,
+        "",
+        &[],
+        "Injected faults as [GLOB=]FAULT:PERCENT, FAULT being error, truncate, stall or drop",
+    ),
+    opt(
+        "chaosStall",
+        Kind::Int,
+        "30000",
+        &[],
+        "How long a stalled response is held, in milliseconds",
+    ),
+    opt(
+        "auditLog",
+        Kind::Str,
+        "",
+        &[],
+        "File to append an entry to for every write request (empty disables)",
+    ),
+    opt(
+        "auditKey",
+        Kind::Str,
+        "",
+        &[],
+        "Key chaining auditLog entries with HMAC-SHA256 (feature audit-hmac)",
+    ),
+    opt(
+        "record",
+        Kind::Str,
+        "",
+        &[],
+        "HAR file to record requests and responses to (empty disables)",
+    ),
+    opt(
+        "recordBody",
+        Kind::Size,
+        "1M",
+        &[],
+        "Body bytes kept per request or response in the `record` file",
+    ),
+    opt(
+        "recordLimit",
+        Kind::Size,
+        "256M",
+        &[],
+        "Size at which the `record` file stops growing",
+    ),
+    opt(
+        "headers",
+        Kind::List,
+        "",
+        &["header", "H"],
+        "Headers added to every response, as `Name: value`",
+    ),
+    opt(
+        "earlyHints",
+        Kind::List,
+        "",
+        &[],
+        "Resources to preload for matching pages, as GLOB=URL[ URL...], sent as 103 Early Hints and Link headers",
+    ),
+    opt(
+        "earlyHintsAuto",
+        Kind::Bool,
+        "false",
+        &[],
+        "Derive early hints from the <head> of the HTML files at startup",
+    ),
+    opt(
+        "serverHeader",
+        Kind::Str,
+        "full",
+        &[],
+        "Server header and page footers: full (with version), name, off, random, or a literal name",
+    ),
+    opt(
+        "injectHtml",
+        Kind::Str,
+        "",
+        &[],
+        "Snippet inserted before </body> in HTML responses (@file reads it from a file)",
+    ),
+    opt(
+        "csp",
+        Kind::List,
+        "",
+        &[],
+        "Content-Security-Policy directives, as `directive source...`",
+    ),
+    opt(
+        "cspNonce",
+        Kind::Bool,
+        "false",
+        &[],
+        "Add a fresh nonce to <script> and <style> tags and to script-src and style-src",
+    ),
+    opt(
+        "cspReportOnly",
+        Kind::Bool,
+        "false",
+        &[],
+        "Send the policy as Content-Security-Policy-Report-Only",
+    ),
+    opt(
+        "robots",
+        Kind::Str,
+        "off",
+        &[],
+        "robots.txt when the root has none: off, allow, disallow or a file",
+    ),
+    opt(
+        "favicon",
+        Kind::Str,
+        "off",
+        &[],
+        "favicon.ico when the root has none: off, builtin or an image file",
+    ),
+    opt(
+        "precompressed",
+        Kind::Bool,
+        "false",
+        &[],
+        "Serve up-to-date name.br, name.zst or name.gz sidecars to clients accepting them",
+    ),
+    opt(
+        "manifest",
+        Kind::Str,
+        "",
+        &[],
+        "Asset manifest mapping logical names to fingerprinted files (empty disables)",
+    ),
+    opt(
+        "internalPrefix",
+        Kind::Str,
+        "/__tinyserve/",
+        &[],
+        "URL prefix of built-in endpoints; <prefix>-/path reaches the file <prefix>path instead",
+    ),
+    opt(
+        "statusPage",
+        Kind::Bool,
+        "false",
+        &[],
+        "Serve an HTML status page at /__tinyserve/status",
+    ),
+    opt(
+        "lastRequests",
+        Kind::Int,
+        "0",
+        &[],
+        "Requests kept in memory for /__tinyserve/last-requests and the status page (0 disables)",
+    ),
+    opt(
+        "events",
+        Kind::Bool,
+        "false",
+        &[],
+        "Expose the /__tinyserve/events change stream",
+    ),
+    opt(
+        "ssi",
+        Kind::Bool,
+        "false",
+        &[],
+        "Process server-side includes and ${TINYSERVE_*} variables",
+    ),
+    opt(
+        "ssiExtensions",
+        Kind::List,
+        "shtml",
+        &[],
+        "Extensions of files processed for includes",
+    ),
+    opt(
+        "ssiVars",
+        Kind::List,
+        "",
+        &[],
+        "Variables for ${TINYSERVE_NAME} as NAME=value",
+    ),
+    opt(
+        "readOnly",
+        Kind::Bool,
+        "true",
+        &["ro"],
+        "Refuse requests that modify files; false enables uploads",
+    ),
+    opt(
+        "maxUploadSize",
+        Kind::Size,
+        "100MB",
+        &[],
+        "Largest accepted upload",
+    ),
+    opt(
+        "resumableUploads",
+        Kind::Bool,
+        "false",
+        &[],
+        "Keep interrupted PUT uploads as .part files that Content-Range requests can finish",
+    ),
+    opt(
+        "partialTtl",
+        Kind::Int,
+        "86400",
+        &[],
+        "Seconds an untouched partial upload is kept before it is deleted",
+    ),
+    opt(
+        "uploadExtensions",
+        Kind::List,
+        "",
+        &[],
+        "File extensions accepted for uploads (empty allows any but SSI and FastCGI files)",
+    ),
+    opt(
+        "webdav",
+        Kind::Bool,
+        "false",
+        &["dav"],
+        "Answer WebDAV class 1 methods (writes also need readOnly=false)",
+    ),
+    opt(
+        "authUsers",
+        Kind::List,
+        "",
+        &["auth", "users"],
+        "Basic auth credentials as user:password",
+    ),
+    opt(
+        "authToken",
+        Kind::Str,
+        "",
+        &["token"],
+        "Bearer token accepted instead of a password",
+    ),
+    opt(
+        "authRealm",
+        Kind::Str,
+        "tinyserve",
+        &["realm"],
+        "Realm sent in authentication challenges",
+    ),
+    opt(
+        "authFor",
+        Kind::Enum(&["all", "writes"]),
+        "all",
+        &[],
+        "Which requests need credentials when auth is configured",
+    ),
+    opt(
+        "accessFiles",
+        Kind::Bool,
+        "false",
+        &[],
+        "Honor .tinyserve-access files guarding their directory trees",
+    ),
+    opt(
+        "policy",
+        Kind::List,
+        "",
+        &[],
+        "Ordered GLOB=ACTION path rules, ACTION being allow, deny, require-auth or local-only",
+    ),
+    opt(
+        "schedule",
+        Kind::List,
+        "",
+        &[],
+        "Ordered GLOB=WINDOWS rules keeping paths reachable only then, e.g. /**=mon-fri 08:00-18:00",
+    ),
+    opt(
+        "scheduleStatus",
+        Kind::Enum(&["503", "403"]),
+        "503",
+        &[],
+        "Status outside the schedule: 503 with Retry-After, or 403",
+    ),
+    opt(
+        "schedulePage",
+        Kind::Str,
+        "",
+        &[],
+        "HTML file sent outside the schedule instead of the built-in error page",
+    ),
+    opt(
+        "allowedHosts",
+        Kind::List,
+        "",
+        &[],
+        "Host names requests may be for, globs allowed; others get 421 (localhost and IPs always pass)",
+    ),
+    opt(
+        "cors",
+        Kind::Bool,
+        "false",
+        &[],
+        "Let any origin read responses and pass any preflight (development only)",
+    ),
+    opt(
+        "corsCredentials",
+        Kind::Bool,
+        "false",
+        &[],
+        "With cors, reflect the Origin and allow credentials instead of answering *",
+    ),
+    opt(
+        "forwardAuth",
+        Kind::Str,
+        "",
+        &[],
+        "Auth service URL asked about every gated request, e.g. oauth2-proxy (empty disables)",
+    ),
+    opt(
+        "forwardAuthLogin",
+        Kind::Str,
+        "",
+        &[],
+        "Sign-in page for browsers the auth service refuses; {url} is the page asked for",
+    ),
+    opt(
+        "forwardAuthPaths",
+        Kind::List,
+        "",
+        &[],
+        "URL path globs gated by forwardAuth (empty gates everything)",
+    ),
+    opt(
+        "forwardAuthHeaders",
+        Kind::List,
+        "X-Auth-Request-User,X-Auth-Request-Email,Remote-User,Remote-Email",
+        &[],
+        "Headers of the auth service's answer set on the request",
+    ),
+    opt(
+        "forwardAuthTtl",
+        Kind::Int,
+        "30000",
+        &[],
+        "Milliseconds an accepted session is trusted before asking again (0: every request)",
+    ),
+    opt(
+        "maxStreams",
+        Kind::Int,
+        "64",
+        &[],
+        "Event and tail streams open at once, each holding a worker (0: no limit)",
+    ),
+    opt(
+        "tailDir",
+        Kind::Str,
+        "",
+        &[],
+        "Directory whose files /__tinyserve/tail/ may follow (empty disables)",
+    ),
+    opt(
+        "tailFiles",
+        Kind::List,
+        "*.log",
+        &[],
+        "Globs of files that may be tailed",
+    ),
+    opt(
+        "fastcgi",
+        Kind::Str,
+        "",
+        &["fcgi"],
+        "FastCGI backend as host:port or unix:/path (empty disables)",
+    ),
+    opt(
+        "fastcgiFiles",
+        Kind::List,
+        "*.php",
+        &[],
+        "Globs of scripts handed to the FastCGI backend",
+    ),
+    opt(
+        "mocks",
+        Kind::Str,
+        "",
+        &[],
+        "JSON file or directory of canned API responses, served before files (empty disables)",
+    ),
+    opt(
+        "hooks",
+        Kind::Str,
+        "",
+        &[],
+        "Rhai script with on_request/on_response hooks (needs the `rhai` feature; empty disables)",
+    ),
+    opt(
+        "plugins",
+        Kind::Str,
+        "",
+        &[],
+        "Directory of WebAssembly request plugins (needs the `wasm` feature; empty disables)",
+    ),
+    opt(
+        "pluginFuel",
+        Kind::Int,
+        "10000000",
+        &[],
+        "Instructions a plugin may execute per request",
+    ),
+    opt(
+        "pluginMemory",
+        Kind::Size,
+        "16M",
+        &[],
+        "Linear memory a plugin instance may grow to",
+    ),
+    opt(
+        "accessLog",
+        Kind::Bool,
+        "true",
+        &[],
+        "Print a line per request on stdout",
+    ),
+    opt(
+        "quiet",
+        Kind::Bool,
+        "false",
+        &["q"],
+        "Print only warnings and errors: no banner, messages or access log",
+    ),
+    opt(
+        "color",
+        Kind::Bool,
+        "true",
+        &[],
+        "Color console output on terminals (NO_COLOR in the environment also turns it off)",
+    ),
+    opt(
+        "output",
+        Kind::Enum(&["text", "json"]),
+        "text",
+        &[],
+        "Console output format: text, or one JSON object per line for scripts",
+    ),
+    opt(
+        "debugRequests",
+        Kind::Bool,
+        "false",
+        &[],
+        "Print each request's headers, how it was answered and the response headers on stderr",
+    ),
+    opt(
+        "debugPaths",
+        Kind::List,
+        "",
+        &[],
+        "Path globs to dump as with `debugRequests`; when set, only these are dumped",
+    ),
+    opt(
+        "tui",
+        Kind::Bool,
+        "false",
+        &[],
+        "Show a live dashboard of requests instead of the access log",
+    ),
+    opt(
+        "daemon",
+        Kind::Bool,
+        "false",
+        &[],
+        "Run in the background (Unix); stop with `tinyserve stop`",
+    ),
+    opt(
+        "notify",
+        Kind::Bool,
+        "false",
+        &[],
+        "Tell systemd (Type=notify) when the server is ready and when it stops",
+    ),
+    opt(
+        "readyFile",
+        Kind::Str,
+        "",
+        &[],
+        "File written as JSON (pid, addresses, url, started_at) once the server accepts connections",
+    ),
+    opt(
+        "pidFile",
+        Kind::Str,
+        "",
+        &[],
+        "PID file written by `daemon` (default: tinyserve.pid in the configs dir)",
+    ),
+    opt(
+        "logDir",
+        Kind::Str,
+        "",
+        &[],
+        "Where `daemon` appends access.log and error.log (default: logs in the configs dir)",
+    ),
+    opt(
+        "sandbox",
+        Kind::Bool,
+        "false",
+        &[],
+        "After binding, confine the process to the root with Landlock (Linux)",
+    ),
+    opt(
+        "sandboxUser",
+        Kind::Str,
+        "",
+        &[],
+        "User (and :group) to switch to after binding, when started as root",
+    ),
+    opt(
+        "sandboxSeccomp",
+        Kind::Bool,
+        "true",
+        &[],
+        "With `sandbox`, also refuse exec, ptrace, mount and similar system calls",
+    ),
+    opt(
+        "watchInterval",
+        Kind::Int,
+        "500",
+        &[],
+        "File watcher poll interval in milliseconds",
+    ),
+];
+
+/// Looks up the declaration of a canonical key.
+pub fn spec(key: &str) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|s| s.key == key)
+}
+
+fn unknown(key: &str) -> Error {
+    Error::Config(format!("unknown option `{key}`"))
+}
+
+/// A value for `key` whose placeholders cannot be resolved.
+fn invalid(key: &str, e: anyhow::Error) -> Error {
+    Error::from_anyhow(e).context(format_args!("option `{key}`"))
+}
+
+/// Where `file`, named under [`INCLUDE_KEY`] in a config file in `base`,
+/// is: `~/` is the home directory, other relative paths start at `base`.
+fn include_path(base: &Path, file: &str) -> crate::Result<PathBuf> {
+    Ok(base.join(home_relative(file).map_err(Error::from_anyhow)?))
+}
+
+/// Where an effective value came from, lowest precedence first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layer::Default => "default",
+            Layer::File => "config file",
+            Layer::Env => "environment",
+            Layer::Cli => "command line",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    value: Value,
+    layer: Layer,
+    /// The value as written, when it had placeholders.
+    written: Option<Value>,
+}
+
+/// Effective option values.
+#[derive(Clone, Debug)]
+pub struct Config {
+    values: BTreeMap<&'static str, Entry>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let values = OPTIONS
+            .iter()
+            .map(|spec| {
+                let value = spec
+                    .kind
+                    .parse(spec.default)
+                    .unwrap_or_else(|e| panic!("bad default for `{}`: {e}", spec.key));
+                (
+                    spec.key,
+                    Entry {
+                        value,
+                        layer: Layer::Default,
+                        written: None,
+                    },
+                )
+            })
+            .collect();
+        Self { values }
+    }
+}
+
+impl Config {
+    /// Sets a canonical key from text.
+    pub fn set_raw(&mut self, key: &'static str, raw: &str, layer: Layer) -> crate::Result<()> {
+        let spec = spec(key).ok_or_else(|| unknown(key))?;
+        let expanded = expand(raw).map_err(|e| invalid(key, e))?;
+        let value = spec
+            .kind
+            .parse(normalize_value(spec, expanded.as_deref().unwrap_or(raw)))
+            .map_err(|e| e.context(format_args!("option `{key}`")))?;
+        let written = expanded.map(|_| {
+            spec.kind
+                .parse(raw)
+                .unwrap_or_else(|_| Value::from(raw.trim()))
+        });
+        self.values.insert(
+            key,
+            Entry {
+                value,
+                layer,
+                written,
+            },
+        );
+        Ok(())
+    }
+
+    /// Sets a canonical key from JSON.
+    pub fn set_json(
+        &mut self,
+        key: &'static str,
+        value: &Value,
+        layer: Layer,
+    ) -> crate::Result<()> {
+        let spec = spec(key).ok_or_else(|| unknown(key))?;
+        let expanded = expand_json(value).map_err(|e| invalid(key, e))?;
+        let written = expanded.is_some().then(|| value.clone());
+        let value = expanded.as_ref().unwrap_or(value);
+        let normalized;
+        let value = match value {
+            Value::String(s) => {
+                normalized = Value::from(normalize_value(spec, s));
+                &normalized
+            }
+            _ => value,
+        };
+        let value = spec
+            .kind
+            .coerce(value)
+            .map_err(|e| e.context(format_args!("option `{key}`")))?;
+        self.values.insert(
+            key,
+            Entry {
+                value,
+                layer,
+                written,
+            },
+        );
+        Ok(())
+    }
+
+    /// Overlays a JSON object whose keys may use any alias.
+    pub fn merge_json(
+        &mut self,
+        object: &Map<String, Value>,
+        aliases: &Aliases,
+        layer: Layer,
+    ) -> crate::Result<()> {
+        for (key, value) in object {
+            let canonical = aliases.resolve(key).ok_or_else(|| unknown(key))?;
+            self.set_json(canonical, value, layer)?;
+        }
+        Ok(())
+    }
+
+    /// Overlays a JSON config file.
+    ///
+    /// Files listed under `include` (a path or an array of paths, relative to
+    /// the including file, `~/` for the home directory) are overlaid first,
+    /// in order, so later includes override earlier ones and the file's own
+    /// keys override them all. A file including itself, directly or not, is
+    /// an error; the same file included twice side by side is not.
+    pub fn merge_file(&mut self, path: &Path, aliases: &Aliases) -> crate::Result<()> {
+        self.merge_included(path, aliases, &mut Vec::new())
+    }
+
+    /// [`merge_file`](Self::merge_file) with the chain of files including
+    /// `path`.
+    fn merge_included(
+        &mut self,
+        path: &Path,
+        aliases: &Aliases,
+        chain: &mut Vec<PathBuf>,
+    ) -> crate::Result<()> {
+        let text = fs::read_to_string(path).map_err(|source| Error::Io {
+            context: format!("reading {}", path.display()),
+            source,
+        })?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if chain.contains(&canonical) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .chain([&canonical])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(Error::Config(format!(
+                "config files include each other: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        let json: Value = serde_json::from_str(&text)
+            .map_err(|e| Error::Config(format!("parsing {}: {e}", path.display())))?;
+        let Value::Object(mut object) = json else {
+            return Err(Error::Config(format!(
+                "{}: expected a JSON object",
+                path.display()
+            )));
+        };
+        if let Some(include) = object.remove(INCLUDE_KEY) {
+            let files = match include {
+                Value::String(file) => vec![file],
+                Value::Array(files) if files.iter().all(Value::is_string) => files
+                    .into_iter()
+                    .filter_map(|f| f.as_str().map(str::to_owned))
+                    .collect(),
+                _ => {
+                    return Err(Error::Config(format!(
+                        "{}: `{INCLUDE_KEY}` must be a path or an array of paths",
+                        path.display()
+                    )));
+                }
+            };
+            let base = path.parent().unwrap_or(Path::new("."));
+            chain.push(canonical);
+            for file in files {
+                let included = include_path(base, &file)?;
+                self.merge_included(&included, aliases, chain)
+                    .map_err(|e| e.context(format_args!("included from {}", path.display())))?;
+            }
+            chain.pop();
+        }
+        self.merge_json(&object, aliases, Layer::File)
+            .map_err(|e| e.context(format_args!("in {}", path.display())))
+    }
+
+    /// Overlays `TINYSERVE_*` variables. Unknown names are ignored so that
+    /// unrelated variables sharing the prefix do not break startup.
+    pub fn merge_env<I>(&mut self, vars: I, aliases: &Aliases) -> crate::Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if let Some(canonical) = aliases.resolve(key) {
+                self.set_raw(canonical, &raw, Layer::Env)
+                    .map_err(|e| e.context(format_args!("environment variable {name}")))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> &Value {
+        &self.entry(key).value
+    }
+
+    pub fn layer(&self, key: &str) -> Layer {
+        self.entry(key).layer
+    }
+
+    pub fn bool(&self, key: &str) -> bool {
+        self.get(key).as_bool().unwrap_or_default()
+    }
+
+    pub fn int(&self, key: &str) -> u64 {
+        self.get(key).as_u64().unwrap_or_default()
+    }
+
+    pub fn str(&self, key: &str) -> &str {
+        self.get(key).as_str().unwrap_or_default()
+    }
+
+    pub fn list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The value of `key` fit to show: as written if it had placeholders.
+    pub fn shown(&self, key: &str) -> &Value {
+        let entry = self.entry(key);
+        entry.written.as_ref().unwrap_or(&entry.value)
+    }
+
+    /// All values as a JSON object, for `--show-config`; placeholders are
+    /// kept rather than resolved.
+    pub fn to_json(&self) -> Value {
+        Value::Object(
+            self.values
+                .keys()
+                .map(|k| (k.to_string(), self.shown(k).clone()))
+                .collect(),
+        )
+    }
+
+    fn entry(&self, key: &str) -> &Entry {
+        self.values
+            .get(key)
+            .unwrap_or_else(|| panic!("undeclared option `{key}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for config files, removed by the caller.
+    fn dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tinyserve-config-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn load(path: &Path) -> crate::Result<Config> {
+        let mut config = Config::default();
+        config.merge_file(path, &Aliases::builtin())?;
+        Ok(config)
+    }
+
+    #[test]
+    fn value_aliases_are_stored_canonical() {
+        let aliases = Aliases::builtin();
+        let mut config = Config::default();
+        config.set_raw("showDir", "YES", Layer::Cli).unwrap();
+        config.set_raw("etag", "w", Layer::Cli).unwrap();
+        let file = serde_json::json!({"cors": "on", "authFor": "write"});
+        config
+            .merge_json(file.as_object().unwrap(), &aliases, Layer::File)
+            .unwrap();
+        let env = [("TINYSERVE_BACKPRESSURE".to_owned(), "wait".to_owned())];
+        config.merge_env(env, &aliases).unwrap();
+
+        let shown = config.to_json();
+        assert_eq!(shown["showDir"], Value::Bool(true));
+        assert_eq!(shown["etag"], Value::from("weak"));
+        assert_eq!(shown["cors"], Value::Bool(true));
+        assert_eq!(shown["authFor"], Value::from("writes"));
+        assert_eq!(shown["backpressure"], Value::from("queue"));
+    }
+
+    #[test]
+    fn value_aliases_only_apply_to_their_options() {
+        let mut config = Config::default();
+        // `w` means `weak` for `etag` only, and `on` is for booleans.
+        assert!(config.set_raw("authFor", "w", Layer::Cli).is_err());
+        assert!(config.set_raw("etag", "on", Layer::Cli).is_err());
+        assert!(config.set_raw("workers", "yes", Layer::Cli).is_err());
+        // Strings are taken as they are.
+        config.set_raw("authToken", "yes", Layer::Cli).unwrap();
+        assert_eq!(config.str("authToken"), "yes");
+    }
+
+    #[test]
+    fn values_of_the_wrong_kind_are_config_errors() {
+        assert!(matches!(parse_size("12q"), Err(Error::Config(_))));
+        let err = Config::default()
+            .set_raw("workers", "many", Layer::Cli)
+            .unwrap_err();
+        match err {
+            Error::Config(message) => assert_eq!(
+                message,
+                "option `workers`: expected a non-negative integer, got `many`"
+            ),
+            other => panic!("not a config error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_resolved_but_shown_as_written() {
+        let dir = dir("placeholders");
+        let secret = dir.join("token");
+        fs::write(&secret, "from-a-file\n").unwrap();
+        let path = env::var("PATH").unwrap();
+
+        let mut config = Config::default();
+        let raw = format!("${{file:{}}}", secret.display());
+        config.set_raw("authToken", &raw, Layer::Env).unwrap();
+        assert_eq!(config.str("authToken"), "from-a-file");
+        assert_eq!(config.shown("authToken"), &Value::from(raw.as_str()));
+        assert_eq!(config.to_json()["authToken"], Value::from(raw.as_str()));
+
+        let written = Value::from(vec!["PATH=${env:PATH}", "OTHER=${x}"]);
+        config.set_json("ssiVars", &written, Layer::File).unwrap();
+        assert_eq!(
+            config.list("ssiVars"),
+            [format!("PATH={path}"), "OTHER=${x}".to_owned()]
+        );
+        assert_eq!(config.to_json()["ssiVars"], written);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_variables_are_an_error() {
+        let mut config = Config::default();
+        let err = config
+            .set_raw("authToken", "${env:TINYSERVE_TEST_UNSET}", Layer::Cli)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("TINYSERVE_TEST_UNSET is not set"), "{err}");
+        assert_eq!(config.layer("authToken"), Layer::Default);
+        assert_eq!(expand("${unknown} stays").unwrap(), None);
+    }
+
+    #[test]
+    fn placeholder_files_may_start_at_the_home_directory() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            home_relative("~/.secrets/token").unwrap(),
+            home.join(".secrets/token")
+        );
+        assert_eq!(
+            home_relative("/run/secrets/token").unwrap(),
+            Path::new("/run/secrets/token")
+        );
+    }
+
+    #[test]
+    fn includes_that_loop_are_refused() {
+        let dir = dir("cycle");
+        fs::write(dir.join("a.json"), r#"{"include": "b.json"}"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{"include": "a.json"}"#).unwrap();
+        let err = load(&dir.join("a.json")).unwrap_err().to_string();
+        assert!(err.contains("include each other"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_included_twice_side_by_side_are_no_loop() {
+        let dir = dir("diamond");
+        fs::write(dir.join("base.json"), r#"{"workers": 3}"#).unwrap();
+        fs::write(dir.join("left.json"), r#"{"include": "base.json"}"#).unwrap();
+        fs::write(dir.join("right.json"), r#"{"include": "base.json"}"#).unwrap();
+        fs::write(
+            dir.join("top.json"),
+            r#"{"include": ["left.json", "right.json"]}"#,
+        )
+        .unwrap();
+        let config = load(&dir.join("top.json")).unwrap();
+        assert_eq!(config.int("workers"), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn later_includes_and_the_file_itself_take_precedence() {
+        let dir = dir("order");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(
+            dir.join("shared.json"),
+            r#"{"workers": 2, "showDir": false}"#,
+        )
+        .unwrap();
+        // Relative to the including file, not to the top one.
+        fs::write(
+            dir.join("sub/site.json"),
+            r#"{"include": "../shared.json", "workers": 4, "port": 9000}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("top.json"),
+            r#"{"include": ["shared.json", "sub/site.json"], "port": 8000}"#,
+        )
+        .unwrap();
+        let config = load(&dir.join("top.json")).unwrap();
+        assert_eq!(config.int("workers"), 4);
+        assert_eq!(config.int("port"), 8000);
+        assert!(!config.bool("showDir"));
+        assert_eq!(config.layer("port"), Layer::File);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn includes_may_start_at_the_home_directory() {
+        let base = Path::new("/etc/tinyserve");
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            include_path(base, "~/site.json").unwrap(),
+            home.join("site.json")
+        );
+        assert_eq!(
+            include_path(base, "conf.d/a.json").unwrap(),
+            base.join("conf.d/a.json")
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! Location of the per-user configs dir (`~/.tinyserve/configs`).
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result, anyhow};
+
+use super::aliases::ALIASES_FILE;
+
+/// Name of the default config file inside the configs dir.
+pub const CONFIG_FILE: &str = "config.json";
+
+/// Directory inside the configs dir searched for WebAssembly plugins.
+pub const PLUGINS_DIR: &str = "plugins";
+
+/// Hooks script inside the configs dir, used when present.
+pub const HOOKS_FILE: &str = "hooks.rhai";
+
+/// Directory inside the configs dir holding extra locale files.
+pub const LOCALES_DIR: &str = "locales";
+
+/// PID file inside the configs dir written by `--daemon`.
+pub const PID_FILE: &str = "tinyserve.pid";
+
+/// Directory inside the configs dir that `--daemon` logs to.
+pub const LOGS_DIR: &str = "logs";
+
+/// Directory inside the configs dir where `tinyserve up` records its sites.
+pub const SITES_DIR: &str = "sites";
+
+/// The user's home directory, from `HOME` or `USERPROFILE`.
+pub fn home_dir() -> Result<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("cannot locate the home directory (HOME is not set)"))
+}
+
+/// `~/.tinyserve/configs`, without touching the filesystem.
+pub fn default_configs_dir() -> Result<PathBuf> {
+    Ok(home_dir()?.join(".tinyserve").join("configs"))
+}
+
+/// Returns the configs dir, creating it and an empty alias table on first use.
+pub fn ensure_default_configs_dir() -> Result<PathBuf> {
+    let dir = default_configs_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let aliases = dir.join(ALIASES_FILE);
+    if !aliases.exists() {
+        fs::write(&aliases, "{}\n").with_context(|| format!("writing {}", aliases.display()))?;
+    }
+    Ok(dir)
+}
@@ -0,0 +1,8 @@
+//! Configuration: option schema, aliases and the configs dir.
+
+pub mod aliases;
+pub mod config;
+pub mod dirs;
+
+pub use aliases::{Aliases, KeyNormalizer, Separators, normalize_key};
+pub use config::{Config, Kind, Layer, OPTIONS, OptionSpec};
@@ -0,0 +1,282 @@
+//! Running in the background: `--daemon` detaches from the terminal and
+//! records the server's PID so that `tinyserve stop` and `tinyserve status`
+//! can find it later.
+//!
+//! The process forks before anything else starts. The parent waits until
+//! the child has bound its listener (or failed to), reports that, and
+//! exits; the child carries on with stdout appended to `access.log` and
+//! stderr to `error.log` in `logDir`. The working directory is kept, so a
+//! relative root still means the same directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde_json::json;
+
+use crate::core::Config;
+use crate::core::dirs::{LOGS_DIR, PID_FILE};
+use crate::log;
+
+/// How long `stop` waits for the server to exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The PID file: `pidFile`, or `tinyserve.pid` in the configs dir.
+pub fn pid_file(config: &Config, configs_dir: &Path) -> PathBuf {
+    match config.str("pidFile") {
+        "" => configs_dir.join(PID_FILE),
+        path => PathBuf::from(path),
+    }
+}
+
+/// Where a daemon writes its output: `logDir`, or `logs` in the configs dir.
+fn log_dir(config: &Config, configs_dir: &Path) -> PathBuf {
+    match config.str("logDir") {
+        "" => configs_dir.join(LOGS_DIR),
+        path => PathBuf::from(path),
+    }
+}
+
+/// The PID recorded in `path`, if the file exists.
+fn read_pid(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("{}: not a PID", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Whether the process `pid` exists; always `false` off Unix.
+pub fn alive(pid: u32) -> bool {
+    sys::alive(pid)
+}
+
+/// `tinyserve status`: whether the server in the PID file is running.
+pub fn status(config: &Config, configs_dir: &Path) -> Result<()> {
+    let path = pid_file(config, configs_dir);
+    match read_pid(&path)? {
+        Some(pid) if sys::alive(pid) => {
+            log::outcome(
+                json!({"event": "status", "running": true, "pid": pid}),
+                &format!("tinyserve is running (pid {pid})"),
+            );
+            Ok(())
+        }
+        Some(pid) => bail!(
+            "tinyserve is not running (stale PID file {} names {pid})",
+            path.display()
+        ),
+        None => bail!("tinyserve is not running (no {})", path.display()),
+    }
+}
+
+/// `tinyserve stop`: asks the server in the PID file to exit and waits.
+pub fn stop(config: &Config, configs_dir: &Path) -> Result<()> {
+    let path = pid_file(config, configs_dir);
+    let Some(pid) = read_pid(&path)? else {
+        bail!("tinyserve is not running (no {})", path.display());
+    };
+    if sys::alive(pid) {
+        sys::terminate(pid)?;
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while sys::alive(pid) {
+            if Instant::now() > deadline {
+                bail!("tinyserve (pid {pid}) did not exit within {STOP_TIMEOUT:?}");
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        log::outcome(
+            json!({"event": "stopped", "pid": pid}),
+            &format!("stopped tinyserve (pid {pid})"),
+        );
+    } else {
+        log::outcome(
+            json!({"event": "stale", "pid": pid, "pidFile": path}),
+            &format!(
+                "tinyserve was not running; removed stale {}",
+                path.display()
+            ),
+        );
+    }
+    fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))
+}
+
+/// A daemonized child, until it reports how startup went.
+pub struct Daemon {
+    #[cfg(unix)]
+    report: fs::File,
+    pid_file: PathBuf,
+}
+
+impl Daemon {
+    /// Forks into the background. Only the child returns; the parent exits
+    /// once the child calls [`ready`](Self::ready) or [`failed`](Self::failed).
+    /// Must run before any thread is started.
+    pub fn start(config: &Config, configs_dir: &Path) -> Result<Self> {
+        let pid_file = pid_file(config, configs_dir);
+        if let Some(pid) = read_pid(&pid_file)?
+            && sys::alive(pid)
+        {
+            bail!(
+                "tinyserve is already running (pid {pid}, {})",
+                pid_file.display()
+            );
+        }
+        let logs = log_dir(config, configs_dir);
+        fs::create_dir_all(&logs).with_context(|| format!("creating {}", logs.display()))?;
+        #[cfg(unix)]
+        {
+            let report = sys::detach(&logs)?;
+            Ok(Self { report, pid_file })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = pid_file;
+            bail!("--daemon is only available on Unix")
+        }
+    }
+
+    /// Writes the PID file and lets the parent exit successfully.
+    pub fn ready(self) -> Result<()> {
+        let pid = std::process::id();
+        if let Some(dir) = self.pid_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.pid_file, format!("{pid}\n"))
+            .with_context(|| format!("writing {}", self.pid_file.display()))?;
+        self.report(&format!("ok {pid}"));
+        Ok(())
+    }
+
+    /// Hands the startup error to the parent to print.
+    pub fn failed(self, error: &anyhow::Error) {
+        self.report(&format!("{error:#}"));
+    }
+
+    fn report(self, message: &str) {
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            let mut report = self.report;
+            let _ = report.write_all(message.as_bytes());
+        }
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::path::Path;
+    use std::process;
+
+    use anyhow::{Context as _, Result, bail};
+    use serde_json::json;
+
+    use crate::log;
+
+    pub fn alive(pid: u32) -> bool {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks that the process exists.
+        let found = unsafe { libc::kill(pid, 0) } == 0;
+        found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    pub fn terminate(pid: u32) -> Result<()> {
+        let pid = libc::pid_t::try_from(pid).context("bad PID")?;
+        // SAFETY: plain kill(2).
+        if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+            return Err(io::Error::last_os_error()).context("signalling tinyserve");
+        }
+        Ok(())
+    }
+
+    fn redirect(file: &File, fd: i32) -> io::Result<()> {
+        // SAFETY: both descriptors are open.
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Forks. The parent waits for the child's report and exits; the child
+    /// starts a new session, moves its output to `logs` and returns the
+    /// write end of the report pipe.
+    pub fn detach(logs: &Path) -> Result<File> {
+        let open = |name: &str| {
+            let path = logs.join(name);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("opening {}", path.display()))
+        };
+        let (access, errors) = (open("access.log")?, open("error.log")?);
+        let null = File::open("/dev/null")?;
+
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error()).context("creating a pipe");
+        }
+        // SAFETY: pipe(2) returned two fresh descriptors we now own.
+        let (mut reader, writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        // SAFETY: no other threads exist yet, so the child is consistent.
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()).context("forking"),
+            0 => {
+                drop(reader);
+                // SAFETY: setsid(2) in the fresh child.
+                unsafe { libc::setsid() };
+                redirect(&null, 0)?;
+                redirect(&access, 1)?;
+                redirect(&errors, 2)?;
+                Ok(writer)
+            }
+            _ => {
+                drop(writer);
+                let mut report = String::new();
+                reader.read_to_string(&mut report)?;
+                match report.strip_prefix("ok ") {
+                    Some(pid) => {
+                        log::outcome(
+                            json!({"event": "daemon", "pid": pid.parse::<u32>().ok(), "logs": logs}),
+                            &format!(
+                                "tinyserve running in the background (pid {pid}), logs in {}",
+                                logs.display()
+                            ),
+                        );
+                        process::exit(0);
+                    }
+                    None if report.is_empty() => bail!("the background server exited at startup"),
+                    None => bail!("{report}"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use anyhow::{Result, bail};
+
+    pub fn alive(_pid: u32) -> bool {
+        false
+    }
+
+    pub fn terminate(_pid: u32) -> Result<()> {
+        bail!("stopping a background server is only available on Unix")
+    }
+}
@@ -0,0 +1,105 @@
+//! [`Error`]: how the embedding API and the config loaders fail, by kind,
+//! so callers can match on failures instead of their messages.
+//!
+//! The CLI and the modules behind it use `anyhow`; [`Error`] converts into
+//! it with `?` like any other error.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Result of the embedding API and the config loaders.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An unknown option, a value of the wrong kind, or a config file or
+    /// rule that cannot be used.
+    Config(String),
+    /// A file or directory could not be read; `context` says which.
+    Io { context: String, source: io::Error },
+    /// The listener could not be bound to `addr`.
+    Bind { addr: String, source: io::Error },
+    /// TLS could not be set up for an `https://` origin, e.g. for a host
+    /// that is no valid server name.
+    Tls(String),
+    /// The server stopped before it was ready, or its thread panicked.
+    Stopped(String),
+    /// Requests were still in progress when a shutdown deadline passed.
+    Timeout { active: usize, deadline: Duration },
+}
+
+impl Error {
+    /// Adds `context` in front of the message, keeping the kind.
+    pub(crate) fn context(self, context: impl fmt::Display) -> Self {
+        match self {
+            Error::Config(message) => Error::Config(format!("{context}: {message}")),
+            Error::Tls(message) => Error::Tls(format!("{context}: {message}")),
+            Error::Io {
+                context: inner,
+                source,
+            } => Error::Io {
+                context: format!("{context}: {inner}"),
+                source,
+            },
+            other => other,
+        }
+    }
+
+    /// Sorts an `anyhow` error from the modules behind the API: a TLS
+    /// failure anywhere in its chain makes it [`Error::Tls`], an I/O failure
+    /// [`Error::Io`], anything else [`Error::Config`].
+    pub(crate) fn from_anyhow(e: anyhow::Error) -> Self {
+        if e.chain().any(is_tls) {
+            return Error::Tls(format!("{e:#}"));
+        }
+        let Some(io) = e.chain().find_map(|c| c.downcast_ref::<io::Error>()) else {
+            return Error::Config(format!("{e:#}"));
+        };
+        let context: Vec<String> = e
+            .chain()
+            .take_while(|c| c.downcast_ref::<io::Error>().is_none())
+            .map(ToString::to_string)
+            .collect();
+        Error::Io {
+            context: context.join(": "),
+            source: io::Error::new(io.kind(), io.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) | Error::Tls(message) | Error::Stopped(message) => {
+                f.write_str(message)
+            }
+            Error::Io { context, .. } if context.is_empty() => f.write_str("I/O error"),
+            Error::Io { context, .. } => f.write_str(context),
+            Error::Bind { addr, .. } => write!(f, "cannot listen on {addr}"),
+            Error::Timeout { active, deadline } => {
+                write!(f, "{active} requests still in progress after {deadline:?}")
+            }
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Bind { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `e` is an [`Error::Tls`], bare or carried by an `io::Error`.
+fn is_tls(e: &(dyn StdError + 'static)) -> bool {
+    let inner = e
+        .downcast_ref::<io::Error>()
+        .and_then(io::Error::get_ref)
+        .map_or(e, |inner| inner as &(dyn StdError + 'static));
+    matches!(inner.downcast_ref::<Error>(), Some(Error::Tls(_)))
+}
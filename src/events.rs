@@ -0,0 +1,91 @@
+//! `/__tinyserve/events`: a Server-Sent Events stream of changes to the
+//! served tree, for test runners and build scripts.
+//!
+//! Each change is one event named after its kind (`create`, `modify`,
+//! `delete`) carrying a JSON object:
+//!
+//! ```text
+//! event: modify
+//! data: {"dir":false,"kind":"modify","path":"/css/site.css"}
+//! ```
+
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::http::Response;
+use crate::http::sse::{self, Streams};
+use crate::watch::{Change, Watcher};
+
+/// Path of the endpoint below the internal prefix.
+pub const PATH: &str = "events";
+
+/// JSON payload of one event.
+pub fn payload(change: &Change) -> String {
+    json!({
+        "kind": change.kind.as_str(),
+        "path": change.url_path(),
+        "dir": change.is_dir,
+    })
+    .to_string()
+}
+
+/// Opens a stream that lasts until the client disconnects or the server
+/// shuts down.
+pub fn stream(streams: &Arc<Streams>, watcher: &Watcher) -> Response {
+    let slot = match streams.open() {
+        Ok(slot) => slot,
+        Err(resp) => return resp,
+    };
+    let changes = watcher.subscribe();
+    sse::response(move |w| {
+        sse::write_comment(w, "tinyserve events")?;
+        let mut last_write = Instant::now();
+        while !slot.closed() {
+            match changes.recv_timeout(sse::CHECK) {
+                Ok(change) => sse::write_event(w, Some(change.kind.as_str()), &payload(&change))?,
+                Err(RecvTimeoutError::Timeout) if last_write.elapsed() >= sse::KEEPALIVE => {
+                    sse::write_comment(w, "ping")?;
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            last_write = Instant::now();
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    use crate::test::TestServer;
+
+    #[test]
+    fn streams_are_limited_and_end_on_shutdown() {
+        let server =
+            TestServer::with(|b| b.option("events", true).option("maxStreams", 1)).unwrap();
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        write!(
+            stream,
+            "GET /__tinyserve/events HTTP/1.1\r\nHost: {}\r\n\r\n",
+            server.addr()
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while !line.contains("tinyserve events") {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        assert_eq!(server.get("/__tinyserve/events").unwrap().status, 503);
+
+        server.handle().shutdown(Duration::from_secs(5)).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+    }
+}
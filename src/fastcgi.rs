@@ -0,0 +1,494 @@
+//! FastCGI responder client, for handing scripts such as `*.php` to php-fpm.
+//!
+//! Requests whose path resolves to a file matching `fastcgiFiles` (directly,
+//! through a directory index, or followed by extra `PATH_INFO` segments) are
+//! forwarded to the `fastcgi` address: `host:port`, or `unix:/path/to.sock`.
+//! One backend connection is used per request.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::files::{self, resolve};
+use crate::glob;
+use crate::http::{Body, Headers, Request, Response};
+use crate::log;
+use crate::server::Context;
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+const REQUEST_ID: u16 = 1;
+/// Largest record payload.
+const MAX_CONTENT: usize = 65_535;
+const BACKEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A script to run and the request path split around it.
+#[derive(Debug)]
+pub struct Script {
+    pub filename: PathBuf,
+    /// URL path of the script itself.
+    pub name: String,
+    /// Remainder of the URL path after the script.
+    pub path_info: String,
+}
+
+/// Whether a file at `url_path` would be run by the FastCGI backend.
+pub fn runs(ctx: &Context, url_path: &str) -> bool {
+    !ctx.config.str("fastcgi").is_empty()
+        && glob::matches_any(&ctx.config.list("fastcgiFiles"), url_path)
+}
+
+/// Finds the script a request path maps to, if FastCGI is enabled.
+pub fn script_for(ctx: &Context, url_path: &str) -> Option<Script> {
+    if ctx.config.str("fastcgi").is_empty() {
+        return None;
+    }
+    let patterns = ctx.config.list("fastcgiFiles");
+    let show_hidden = ctx.config.bool("showHidden");
+    let segments: Vec<&str> = url_path.split('/').filter(|s| !s.is_empty()).collect();
+    for end in (0..=segments.len()).rev() {
+        let name = format!("/{}", segments[..end].join("/"));
+        let path = resolve::resolve(&ctx.root, &name, show_hidden)?;
+        let Ok(meta) = files::metadata(ctx, &path) else {
+            continue;
+        };
+        let path_info = segments[end..].iter().map(|s| format!("/{s}")).collect();
+        if meta.is_file {
+            return (glob::matches_any(&patterns, &name) && resolve::is_within(&ctx.root, &path))
+                .then_some(Script {
+                    filename: path,
+                    name,
+                    path_info,
+                });
+        }
+        // A directory request (`/blog/`) may be answered by an index script.
+        if end == segments.len() && url_path.ends_with('/') {
+            return ctx.config.list("index").iter().find_map(|index| {
+                let filename = path.join(index);
+                let name = format!("{}/{index}", name.trim_end_matches('/'));
+                (filename.is_file()
+                    && glob::matches_any(&patterns, &name)
+                    && resolve::is_within(&ctx.root, &filename))
+                .then_some(Script {
+                    filename,
+                    name,
+                    path_info: String::new(),
+                })
+            });
+        }
+        return None;
+    }
+    None
+}
+
+enum Conn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Conn {
+    fn connect(addr: &str) -> io::Result<Self> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(BACKEND_TIMEOUT))?;
+                return Ok(Conn::Unix(stream));
+            }
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unix sockets are not available here ({path})"),
+            ));
+        }
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(BACKEND_TIMEOUT))?;
+        Ok(Conn::Tcp(stream))
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Conn::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn write_record(w: &mut impl Write, kind: u8, content: &[u8]) -> io::Result<()> {
+    let len = content.len() as u16;
+    let padding = (8 - content.len() % 8) % 8;
+    let [id_hi, id_lo] = REQUEST_ID.to_be_bytes();
+    let [len_hi, len_lo] = len.to_be_bytes();
+    w.write_all(&[
+        VERSION,
+        kind,
+        id_hi,
+        id_lo,
+        len_hi,
+        len_lo,
+        padding as u8,
+        0,
+    ])?;
+    w.write_all(content)?;
+    w.write_all(&[0; 8][..padding])
+}
+
+fn encode_len(out: &mut Vec<u8>, len: usize) {
+    if len < 128 {
+        out.push(len as u8);
+    } else {
+        out.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+fn write_params(w: &mut impl Write, params: &[(String, String)]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for (name, value) in params {
+        let mut pair = Vec::with_capacity(name.len() + value.len() + 8);
+        encode_len(&mut pair, name.len());
+        encode_len(&mut pair, value.len());
+        pair.extend_from_slice(name.as_bytes());
+        pair.extend_from_slice(value.as_bytes());
+        if buf.len() + pair.len() > MAX_CONTENT {
+            write_record(w, PARAMS, &buf)?;
+            buf.clear();
+        }
+        buf.extend_from_slice(&pair);
+    }
+    if !buf.is_empty() {
+        write_record(w, PARAMS, &buf)?;
+    }
+    write_record(w, PARAMS, &[])
+}
+
+/// Reads the next record, returning its type and content.
+fn read_record(r: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 8];
+    r.read_exact(&mut header)?;
+    let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    let mut content = vec![0u8; len + usize::from(header[6])];
+    r.read_exact(&mut content)?;
+    content.truncate(len);
+    Ok((header[1], content))
+}
+
+/// Yields STDOUT bytes until END_REQUEST, logging STDERR along the way.
+struct Stdout<R> {
+    conn: R,
+    script: String,
+    pending: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Read for Stdout<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.pending.len() && !self.done {
+            let (kind, content) = read_record(&mut self.conn)?;
+            match kind {
+                STDOUT => {
+                    self.pending = content;
+                    self.pos = 0;
+                }
+                STDERR if !content.is_empty() => log::warn(&format!(
+                    "{}: {}",
+                    self.script,
+                    String::from_utf8_lossy(&content).trim_end()
+                )),
+                END_REQUEST => self.done = true,
+                _ => {}
+            }
+        }
+        let n = (self.pending.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn params(
+    ctx: &Context,
+    req: &Request,
+    script: &Script,
+    content_length: u64,
+) -> Vec<(String, String)> {
+    let mut params: Vec<(String, String)> = [
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        ("SERVER_SOFTWARE", crate::http::response::SERVER.to_owned()),
+        ("SERVER_PROTOCOL", req.version.to_string()),
+        ("REQUEST_METHOD", req.method.clone()),
+        ("REQUEST_URI", req.target.clone()),
+        ("DOCUMENT_URI", script.name.clone()),
+        ("DOCUMENT_ROOT", ctx.root.display().to_string()),
+        ("SCRIPT_NAME", script.name.clone()),
+        ("SCRIPT_FILENAME", script.filename.display().to_string()),
+        ("PATH_INFO", script.path_info.clone()),
+        ("QUERY_STRING", req.query.clone().unwrap_or_default()),
+        ("REMOTE_ADDR", req.peer.ip().to_string()),
+        ("REMOTE_PORT", req.peer.port().to_string()),
+        ("SERVER_ADDR", req.local.ip().to_string()),
+        ("SERVER_PORT", req.local.port().to_string()),
+        (
+            "SERVER_NAME",
+            req.headers
+                .get("host")
+                .map(|h| h.rsplit_once(':').map_or(h, |(name, _)| name).to_owned())
+                .unwrap_or_else(|| req.local.ip().to_string()),
+        ),
+        ("CONTENT_LENGTH", content_length.to_string()),
+        (
+            "CONTENT_TYPE",
+            req.headers
+                .get("content-type")
+                .unwrap_or_default()
+                .to_owned(),
+        ),
+        // php-cgi refuses to run without it (cgi.force_redirect).
+        ("REDIRECT_STATUS", "200".to_owned()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect();
+    for (name, value) in req.headers.iter() {
+        if name.eq_ignore_ascii_case("content-type") || name.eq_ignore_ascii_case("content-length")
+        {
+            continue;
+        }
+        // Underscored names would be indistinguishable from dashed ones.
+        if name.contains('_') {
+            continue;
+        }
+        // `HTTP_PROXY` would be taken as the script's own proxy (httpoxy).
+        if name.eq_ignore_ascii_case("proxy") {
+            continue;
+        }
+        let key = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match params.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            None => params.push((key, value.to_owned())),
+        }
+    }
+    params
+}
+
+/// Runs `script` for `req` on the configured backend.
+pub fn handle(ctx: &Context, req: &mut Request, script: &Script) -> Response {
+    let addr = ctx.config.str("fastcgi");
+    match forward(ctx, req, script, addr) {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::error(&format!("FastCGI backend {addr}: {e}"));
+            Response::error(502)
+        }
+    }
+}
+
+fn forward(ctx: &Context, req: &mut Request, script: &Script, addr: &str) -> io::Result<Response> {
+    // CGI needs CONTENT_LENGTH up front, so chunked bodies are buffered.
+    let limit = ctx.config.int("maxUploadSize");
+    let declared = req
+        .headers
+        .get("content-length")
+        .and_then(|v| v.parse::<u64>().ok());
+    let mut buffered = None;
+    let content_length = match declared {
+        Some(len) => len,
+        None => {
+            let mut buf = Vec::new();
+            (&mut req.body).take(limit + 1).read_to_end(&mut buf)?;
+            if buf.len() as u64 > limit {
+                return Ok(Response::error(413));
+            }
+            let len = buf.len() as u64;
+            buffered = Some(buf);
+            len
+        }
+    };
+    let params = params(ctx, req, script, content_length);
+    let mut body: Box<dyn Read + '_> = match buffered {
+        Some(buf) => Box::new(io::Cursor::new(buf)),
+        None => Box::new(&mut req.body),
+    };
+
+    let mut conn = Conn::connect(addr)?;
+    let mut begin = Vec::with_capacity(8);
+    begin.extend_from_slice(&RESPONDER.to_be_bytes());
+    begin.extend_from_slice(&[0; 6]);
+    write_record(&mut conn, BEGIN_REQUEST, &begin)?;
+    write_params(&mut conn, &params)?;
+    let mut chunk = vec![0u8; MAX_CONTENT];
+    loop {
+        let n = body.read(&mut chunk)?;
+        write_record(&mut conn, STDIN, &chunk[..n])?;
+        if n == 0 {
+            break;
+        }
+    }
+    conn.flush()?;
+
+    let stdout = Stdout {
+        conn,
+        script: script.name.clone(),
+        pending: Vec::new(),
+        pos: 0,
+        done: false,
+    };
+    let mut reader = BufReader::new(stdout);
+    let mut headers = Headers::new();
+    let mut status = 200;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("status") {
+            status = value
+                .split_whitespace()
+                .next()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(500);
+        } else {
+            if name.eq_ignore_ascii_case("location") && status == 200 {
+                status = 302;
+            }
+            headers.append(name.trim(), value);
+        }
+    }
+
+    let mut resp = Response::new(status);
+    resp.headers = headers;
+    resp.body = Body::Stream(Box::new(move |w| io::copy(&mut reader, w).map(drop)));
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestServer;
+
+    fn params_for(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        let server = TestServer::new().unwrap();
+        let ctx = server.handle().context();
+        let mut req = Request::new("GET", "/index.php/extra?x=1").unwrap();
+        for (name, value) in headers {
+            req.headers.append(*name, *value);
+        }
+        let script = Script {
+            filename: ctx.root.join("index.php"),
+            name: "/index.php".into(),
+            path_info: "/extra".into(),
+        };
+        params(ctx, &req, &script, 0)
+    }
+
+    fn get<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn passes_request_headers_as_http_variables() {
+        let params = params_for(&[("Accept", "text/html"), ("X-Thing", "a"), ("x-thing", "b")]);
+        assert_eq!(get(&params, "HTTP_ACCEPT"), Some("text/html"));
+        assert_eq!(get(&params, "HTTP_X_THING"), Some("a, b"));
+        assert_eq!(get(&params, "PATH_INFO"), Some("/extra"));
+        assert_eq!(get(&params, "QUERY_STRING"), Some("x=1"));
+    }
+
+    #[test]
+    fn drops_the_proxy_header() {
+        let params = params_for(&[("Proxy", "http://evil.test:8080"), ("X_Y", "z")]);
+        assert_eq!(get(&params, "HTTP_PROXY"), None);
+        assert_eq!(get(&params, "HTTP_X_Y"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn index_scripts_stay_inside_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let server = TestServer::with(|b| {
+            b.option("fastcgi", "127.0.0.1:9000")
+                .option("index", "index.php")
+        })
+        .unwrap();
+        let outside = TestServer::new().unwrap();
+        outside.write("index.php", "<?php").unwrap();
+        let root = server.root().unwrap();
+        server.write("blog/index.php", "<?php").unwrap();
+        std::fs::create_dir(root.join("shop")).unwrap();
+        symlink(
+            outside.root().unwrap().join("index.php"),
+            root.join("shop/index.php"),
+        )
+        .unwrap();
+
+        let ctx = server.handle().context();
+        let script = script_for(ctx, "/blog/").unwrap();
+        assert_eq!(script.name, "/blog/index.php");
+        assert!(script_for(ctx, "/shop/").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn static_indexes_stay_inside_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let server = TestServer::new().unwrap();
+        let outside = TestServer::new().unwrap();
+        outside.write("index.html", "outside").unwrap();
+        let root = server.root().unwrap();
+        std::fs::create_dir(root.join("d")).unwrap();
+        symlink(
+            outside.root().unwrap().join("index.html"),
+            root.join("d/index.html"),
+        )
+        .unwrap();
+        assert_eq!(server.get("/d/").unwrap().status, 404);
+        assert_eq!(server.get("/d/index.html").unwrap().status, 404);
+    }
+}
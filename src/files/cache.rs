@@ -0,0 +1,179 @@
+//! In-memory LRU cache of small file bodies.
+//!
+//! Entries are keyed by path and checked against the file's modification
+//! time and length on every use, so a stale body is never served even when
+//! no watcher is running; with one, changed files are dropped right away.
+//! Concurrent misses for the same version of a file are
+//! [coalesced](crate::coalesce): one request reads it, the rest wait.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::coalesce::Flights;
+use crate::vfs::Metadata;
+
+struct Cached {
+    modified: Option<SystemTime>,
+    data: Arc<[u8]>,
+    /// Position in the recency order.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<PathBuf, Cached>,
+    /// Least recently used first.
+    order: BTreeMap<u64, PathBuf>,
+    tick: u64,
+    bytes: u64,
+}
+
+impl Lru {
+    fn remove(&mut self, path: &Path) {
+        if let Some(old) = self.entries.remove(path) {
+            self.order.remove(&old.tick);
+            self.bytes -= old.data.len() as u64;
+        }
+    }
+
+    fn touch(&mut self, path: &Path) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.order.remove(&entry.tick);
+            entry.tick = tick;
+            self.order.insert(tick, path.to_path_buf());
+        }
+    }
+}
+
+/// Bounded by `cacheSize` in total and `cacheMaxFile` per entry.
+pub struct FileCache {
+    max_bytes: u64,
+    max_file: u64,
+    lru: Mutex<Lru>,
+    /// Reads in progress, by path and the version being read.
+    loads: Flights<(PathBuf, Option<SystemTime>, u64), Arc<[u8]>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl FileCache {
+    pub fn new(max_bytes: u64, max_file: u64) -> Self {
+        Self {
+            max_bytes,
+            max_file: max_file.min(max_bytes),
+            lru: Mutex::new(Lru::default()),
+            loads: Flights::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Lookups answered from memory and read from disk so far.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The contents of `path`, whose current metadata is `meta`, or `None`
+    /// if the file is too large to cache. Misses are read with `load`.
+    pub fn get(
+        &self,
+        path: &Path,
+        meta: &Metadata,
+        load: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Option<Arc<[u8]>>> {
+        if meta.len > self.max_file {
+            return Ok(None);
+        }
+        let modified = meta.modified;
+        {
+            let mut lru = self.lock();
+            match lru.entries.get(path) {
+                Some(hit) if hit.modified == modified && hit.data.len() as u64 == meta.len => {
+                    let data = Arc::clone(&hit.data);
+                    lru.touch(path);
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Some(data));
+                }
+                Some(_) => lru.remove(path),
+                None => {}
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // Read without holding the lock; whoever asks for the same version
+        // meanwhile waits for this read.
+        let key = (path.to_path_buf(), modified, meta.len);
+        self.loads
+            .run(&key, || self.load(path, modified, meta.len, load))
+            .map(Some)
+    }
+
+    /// Reads a missed file and keeps it, if it is still `len` bytes long.
+    fn load(
+        &self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        len: u64,
+        load: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<[u8]>> {
+        let data: Arc<[u8]> = load()?.into();
+        if data.len() as u64 != len {
+            // Changed while reading; serve it but don't keep it.
+            return Ok(data);
+        }
+        let mut lru = self.lock();
+        lru.remove(path);
+        while lru.bytes + data.len() as u64 > self.max_bytes {
+            let Some((_, oldest)) = lru.order.pop_first() else {
+                break;
+            };
+            if let Some(old) = lru.entries.remove(&oldest) {
+                lru.bytes -= old.data.len() as u64;
+            }
+        }
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.bytes += data.len() as u64;
+        lru.order.insert(tick, path.to_path_buf());
+        lru.entries.insert(
+            path.to_path_buf(),
+            Cached {
+                modified,
+                data: Arc::clone(&data),
+                tick,
+            },
+        );
+        Ok(data)
+    }
+
+    /// Drops `path`, or everything below it if it is a directory.
+    pub fn invalidate(&self, path: &Path) {
+        let mut lru = self.lock();
+        let stale: Vec<PathBuf> = lru
+            .entries
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in stale {
+            lru.remove(&p);
+        }
+    }
+
+    pub fn clear(&self) {
+        *self.lock() = Lru::default();
+    }
+}
@@ -0,0 +1,158 @@
+//! Content hashes for ETags in deterministic mode.
+//!
+//! Files are hashed as a stream through a fixed buffer, so a large file
+//! costs time but not memory, and each hash is remembered until the file's
+//! length or modification time changes. Concurrent requests for a file not
+//! hashed yet share one pass over it.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::coalesce::Flights;
+use crate::vfs::{Contents, Metadata};
+
+/// Bytes read at a time while hashing.
+const BUFFER: usize = 64 * 1024;
+/// Hashes kept before the table is emptied.
+const MAX_ENTRIES: usize = 16 * 1024;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The version of a file a hash was taken from.
+type Version = (u64, Option<SystemTime>);
+
+#[derive(Default)]
+pub struct Digests {
+    hashes: Mutex<HashMap<PathBuf, (Version, u64)>>,
+    hashing: Flights<(PathBuf, Version), u64>,
+}
+
+impl Digests {
+    /// The FNV-1a hash of the file at `path`, as described by `meta`;
+    /// `open` is called to read it when it is not known yet.
+    pub fn hash(
+        &self,
+        path: &Path,
+        meta: &Metadata,
+        open: impl FnOnce() -> io::Result<Contents>,
+    ) -> io::Result<u64> {
+        let version = (meta.len, meta.modified);
+        let known = self
+            .hashes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(path)
+            .filter(|(v, _)| *v == version)
+            .map(|(_, hash)| *hash);
+        if let Some(hash) = known {
+            return Ok(hash);
+        }
+        self.hashing.run(&(path.to_path_buf(), version), || {
+            let hash = stream(open()?)?;
+            let mut hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+            if hashes.len() >= MAX_ENTRIES {
+                hashes.clear();
+            }
+            hashes.insert(path.to_path_buf(), (version, hash));
+            Ok(hash)
+        })
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds, for content hashes.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    extend(FNV_OFFSET, data)
+}
+
+fn extend(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn stream(contents: Contents) -> io::Result<u64> {
+    let mut reader: Box<dyn Read> = match contents {
+        Contents::File(file) => Box::new(file),
+        Contents::Region {
+            mut file,
+            offset,
+            len,
+        } => {
+            file.seek(SeekFrom::Start(offset))?;
+            Box::new(file.take(len))
+        }
+        Contents::Reader { reader, len } => Box::new(reader.take(len)),
+        Contents::Bytes(bytes) => return Ok(fnv1a(&bytes)),
+        Contents::Static(bytes) => return Ok(fnv1a(bytes)),
+    };
+    let mut buf = vec![0; BUFFER];
+    let mut hash = FNV_OFFSET;
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(hash),
+            Ok(n) => hash = extend(hash, &buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test::TestServer;
+
+    fn meta(len: u64) -> Metadata {
+        Metadata {
+            is_dir: false,
+            is_file: true,
+            len,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn streaming_matches_the_one_shot_hash() {
+        let data: Vec<u8> = (0..BUFFER * 3 + 17).map(|i| i as u8).collect();
+        let path = std::env::temp_dir().join(format!("tinyserve-digest-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let streamed = stream(Contents::File(file)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(streamed, fnv1a(&data));
+        assert_eq!(fnv1a(b""), FNV_OFFSET);
+    }
+
+    #[test]
+    fn remembers_hashes_per_version() {
+        let digests = Digests::default();
+        let path = Path::new("/a");
+        let bytes = |b: &'static [u8]| move || Ok(Contents::Bytes(Arc::from(b)));
+        let first = digests.hash(path, &meta(3), bytes(b"abc")).unwrap();
+        assert_eq!(first, fnv1a(b"abc"));
+        let unread = || -> io::Result<Contents> { panic!("hashed again") };
+        assert_eq!(digests.hash(path, &meta(3), unread).unwrap(), first);
+        let changed = digests.hash(path, &meta(4), bytes(b"abcd")).unwrap();
+        assert_eq!(changed, fnv1a(b"abcd"));
+    }
+
+    #[test]
+    fn deterministic_etags_follow_the_contents() {
+        let server = TestServer::with(|b| b.option("deterministic", true)).unwrap();
+        server.write("a.txt", "same").unwrap();
+        server.write("b.txt", "same").unwrap();
+        let etag = |path| {
+            let resp = server.request("HEAD", path, &[], b"").unwrap();
+            resp.header("etag").unwrap().to_owned()
+        };
+        assert_eq!(etag("/a.txt"), format!("\"{:016x}-4\"", fnv1a(b"same")));
+        assert_eq!(etag("/a.txt"), etag("/b.txt"));
+        server.write("b.txt", "diff").unwrap();
+        assert_ne!(etag("/a.txt"), etag("/b.txt"));
+    }
+}
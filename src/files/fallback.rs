@@ -0,0 +1,57 @@
+//! Stand-ins for `/robots.txt` and `/favicon.ico` when the root has none,
+//! so crawlers and browsers get an answer instead of filling the log with
+//! 404s. `robots` is `allow`, `disallow` or a file to send; `favicon` is
+//! `builtin` or an image file. Both are `off` by default.
+
+use std::fs;
+use std::path::Path;
+
+use crate::http::{Request, Response, mime};
+use crate::log;
+use crate::server::{Context, debug};
+
+/// A 16x16 icon with the tinyserve "t".
+const FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+/// How long clients may keep a stand-in.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// The stand-in for `req`, which found no file, if one is configured.
+pub fn serve(ctx: &Context, req: &Request) -> Option<Response> {
+    let (setting, builtin) = match req.path.as_str() {
+        "/robots.txt" => (ctx.config.str("robots"), robots(ctx.config.str("robots"))),
+        "/favicon.ico" => (
+            ctx.config.str("favicon"),
+            (ctx.config.str("favicon") == "builtin").then_some(FAVICON),
+        ),
+        _ => return None,
+    };
+    if setting == "off" {
+        return None;
+    }
+    let resp = match builtin {
+        Some(body) => {
+            debug::note(|| format!("no {} in the root, built-in `{setting}` stand-in", req.path));
+            Response::bytes(200, mime::from_path(Path::new(&req.path)), body)
+        }
+        None => match fs::read(setting) {
+            Ok(body) => {
+                debug::note(|| format!("no {} in the root, stand-in {setting}", req.path));
+                Response::bytes(200, mime::from_path(Path::new(setting)), body)
+            }
+            Err(e) => {
+                log::warn(&format!("{}: {setting}: {e}", req.path));
+                return None;
+            }
+        },
+    };
+    Some(resp.header("Cache-Control", CACHE_CONTROL))
+}
+
+fn robots(setting: &str) -> Option<&'static [u8]> {
+    match setting {
+        "allow" => Some(b"User-agent: *\nDisallow:\n"),
+        "disallow" => Some(b"User-agent: *\nDisallow: /\n"),
+        _ => None,
+    }
+}
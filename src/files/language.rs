@@ -0,0 +1,104 @@
+//! `Accept-Language` negotiation between `name.<lang>.ext` variants.
+//!
+//! With `languages` enabled, a request for `/about.html` that has no
+//! `about.html` on disk looks for `about.en.html`, `about.de.html`, ... and
+//! serves the one that best fits the client's preferences, falling back to
+//! `defaultLanguage` and then to the first variant by name.
+
+use std::path::{Path, PathBuf};
+
+use crate::server::Context;
+
+/// A variant picked for a request.
+#[derive(Debug)]
+pub struct Variant {
+    pub path: PathBuf,
+    pub language: String,
+}
+
+/// Whether `s` looks like a language tag (`en`, `pt-BR`, `zh-Hant`).
+pub fn is_tag(s: &str) -> bool {
+    let mut subtags = s.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags
+            .all(|t| (1..=8).contains(&t.len()) && t.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// Language ranges from an `Accept-Language` header, most preferred first.
+/// Ranges with `q=0` are dropped.
+pub fn preferences(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let range = params.next()?.trim().to_ascii_lowercase();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!range.is_empty() && q > 0.0).then_some((range, q))
+        })
+        .collect();
+    // Stable, so equal weights keep the client's order.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+/// `en` matches `en-US` and vice versa; `*` matches anything.
+pub fn matches(range: &str, tag: &str) -> bool {
+    let tag = tag.to_ascii_lowercase();
+    range == "*"
+        || range == tag
+        || tag.strip_prefix(range).is_some_and(|r| r.starts_with('-'))
+        || range.strip_prefix(&tag).is_some_and(|r| r.starts_with('-'))
+}
+
+/// Lists `stem.<lang>.ext` siblings of the missing file `path`.
+fn variants(ctx: &Context, path: &Path) -> Vec<Variant> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Vec::new();
+    };
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) => (stem, Some(ext)),
+        None => (name, None),
+    };
+    let Ok(entries) = super::read_dir(ctx, dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<Variant> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let rest = entry.name.strip_prefix(stem)?.strip_prefix('.')?;
+            let language = match ext {
+                Some(ext) => rest.strip_suffix(ext)?.strip_suffix('.')?,
+                None => rest,
+            };
+            (is_tag(language) && entry.meta.is_file).then(|| Variant {
+                path: dir.join(&entry.name),
+                language: language.to_owned(),
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+/// Picks the variant of the missing file `path` to serve for `accept`.
+pub fn negotiate(ctx: &Context, path: &Path, accept: Option<&str>) -> Option<Variant> {
+    if !ctx.config.bool("languages") {
+        return None;
+    }
+    let mut variants = variants(ctx, path);
+    let default = ctx.config.str("defaultLanguage").to_ascii_lowercase();
+    let index = preferences(accept.unwrap_or_default())
+        .iter()
+        .find_map(|range| {
+            let range = if range == "*" { &default } else { range };
+            variants.iter().position(|v| matches(range, &v.language))
+        })
+        .or_else(|| variants.iter().position(|v| matches(&default, &v.language)))
+        .or((!variants.is_empty()).then_some(0))?;
+    Some(variants.swap_remove(index))
+}
@@ -0,0 +1,457 @@
+//! Directory listings, as HTML pages or JSON.
+//!
+//! A [`ListingRenderer`] turns the entries into a body; the first one that
+//! [applies](ListingRenderer::applies) to the request answers it. Renderers
+//! added with [`ServerBuilder::listing_renderer`](crate::server::ServerBuilder::listing_renderer)
+//! are asked before the built-in [`Json`] and [`Html`], so an embedder can
+//! answer `?format=rss` with a feed of the directory.
+
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::access;
+use crate::http::request::percent_encode_path;
+use crate::http::response::{Identity, escape_html};
+use serde_json::{Value, json};
+
+use crate::http::{Request, Response, date, mime};
+use crate::locale::Strings;
+use crate::server::{Context, debug};
+
+/// One row of a listing.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Reads `dir`, directories first, then by name. A directory with more
+/// than `listingMaxEntries` entries gets an error page instead, so one with
+/// millions of files cannot tie up the server sorting and rendering them.
+pub fn read_entries(ctx: &Context, dir: &Path, show_hidden: bool) -> Result<Vec<Entry>, Response> {
+    let raw = super::read_dir(ctx, dir).map_err(|e| super::io_error(&e))?;
+    let max = ctx.config.int("listingMaxEntries");
+    if max > 0 && raw.len() as u64 > max {
+        debug::note(|| format!("{} entries, over listingMaxEntries", raw.len()));
+        return Err(Response::error_with(
+            403,
+            &format!("This directory has more than {max} entries and is not listed."),
+        ));
+    }
+    let mut entries: Vec<Entry> = raw
+        .into_iter()
+        .filter(|entry| show_hidden || !entry.name.starts_with('.'))
+        .filter(|entry| !access::is_access_file(&entry.name))
+        .map(|entry| Entry {
+            name: entry.name,
+            is_dir: entry.meta.is_dir,
+            len: entry.meta.len,
+            modified: super::modified(ctx, &entry.meta),
+        })
+        .collect();
+    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => a.name.cmp(&b.name),
+    });
+    Ok(entries)
+}
+
+/// What a [`ListingRenderer`] gets to render.
+pub struct Listing<'a> {
+    /// URL path of the directory, ending in `/`.
+    pub url_path: &'a str,
+    /// The directory itself.
+    pub dir: &'a Path,
+    /// Directories first, then by name.
+    pub entries: &'a [Entry],
+}
+
+/// A rendered listing: its `Content-Type` and body.
+pub struct Rendered {
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Rendered {
+    pub fn new(content_type: impl Into<String>, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            content_type: content_type.into(),
+            body: body.into(),
+        }
+    }
+}
+
+/// Renders directory listings in one format.
+pub trait ListingRenderer: Send + Sync {
+    /// Short name of the format; it is part of the listing's ETag.
+    fn name(&self) -> &str;
+
+    /// Whether to answer `req`; by default when it asks for
+    /// `?format=<name>`.
+    fn applies(&self, req: &Request) -> bool {
+        let query = req.query.as_deref().unwrap_or_default();
+        query
+            .split('&')
+            .any(|pair| pair.strip_prefix("format=") == Some(self.name()))
+    }
+
+    fn render(&self, ctx: &Context, req: &Request, listing: &Listing<'_>) -> Rendered;
+}
+
+/// `{"path": ..., "entries": [{"name", "dir", "size", "modified"}]}`, for
+/// `?format=json` or `Accept: application/json`.
+pub struct Json;
+
+impl ListingRenderer for Json {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn applies(&self, req: &Request) -> bool {
+        wants_json(req)
+    }
+
+    fn render(&self, _ctx: &Context, _req: &Request, listing: &Listing<'_>) -> Rendered {
+        let value = to_json(listing.url_path, listing.entries);
+        Rendered::new("application/json", value.to_string())
+    }
+}
+
+/// The page in the `listingTheme` theme; answers every request.
+pub struct Html;
+
+impl ListingRenderer for Html {
+    fn name(&self) -> &str {
+        "html"
+    }
+
+    fn applies(&self, _req: &Request) -> bool {
+        true
+    }
+
+    fn render(&self, ctx: &Context, req: &Request, listing: &Listing<'_>) -> Rendered {
+        Rendered::new("text/html; charset=utf-8", page(ctx, req, listing))
+    }
+}
+
+/// The built-in renderers, in the order they are asked.
+pub fn builtin() -> Vec<Box<dyn ListingRenderer>> {
+    vec![Box::new(Json), Box::new(Html)]
+}
+
+/// Drop zone with per-file progress bars, posting to the listed directory.
+const UPLOAD_FORM: &str = include_str!("upload.html");
+
+/// Styles of the `modern` theme, light or dark with the system.
+const MODERN_CSS: &str = include_str!("listing.css");
+
+/// Search box filtering the rows as you type, through the JSON listing
+/// when the page holds only some of them.
+const FILTER_FORM: &str = include_str!("filter.html");
+
+/// Renders the listing for `url_path` (which ends in `/`) with the first of
+/// the context's renderers that applies. Unless `etag` is off, the listing
+/// carries an ETag and a matching `If-None-Match` gets a 304.
+pub fn render(
+    ctx: &Context,
+    req: &Request,
+    url_path: &str,
+    dir: &Path,
+    entries: &[Entry],
+) -> Response {
+    let renderer: &dyn ListingRenderer = ctx
+        .listing_renderers
+        .iter()
+        .map(Box::as_ref)
+        .find(|r| r.applies(req))
+        .unwrap_or(&Html);
+    let format = renderer.name();
+    let etag = (ctx.config.str("etag") != "off").then(|| etag(ctx, req, format, entries));
+    let finish = |mut resp: Response| {
+        if let Some(tag) = &etag {
+            resp.headers.set("ETag", tag.as_str());
+        }
+        if ctx.locales.varies() && format != "json" {
+            resp.headers.append("Vary", "Accept-Language");
+        }
+        resp
+    };
+    if super::not_modified(req, etag.as_deref(), None) {
+        debug::note(|| "the client's copy of the listing is current".to_owned());
+        return finish(Response::new(304));
+    }
+    let listing = Listing {
+        url_path,
+        dir,
+        entries,
+    };
+    let rendered = renderer.render(ctx, req, &listing);
+    finish(Response::bytes(200, &rendered.content_type, rendered.body))
+}
+
+/// The HTML page: the `listingTheme` theme with up to `listingRows` rows.
+/// Unless `readOnly` is on, it also offers drag-and-drop uploads into the
+/// directory. A [README](super::readme) of the directory goes above the
+/// table, and the strings come from the request's [locale](crate::locale).
+fn page(ctx: &Context, req: &Request, listing: &Listing<'_>) -> String {
+    let Listing {
+        url_path,
+        dir,
+        entries,
+    } = *listing;
+    let t = ctx.locales.strings(req);
+    let upload = if ctx.config.bool("readOnly") {
+        String::new()
+    } else {
+        t.html(UPLOAD_FORM)
+    };
+    let limit = usize::try_from(ctx.config.int("listingRows")).unwrap_or(usize::MAX);
+    let shown = match limit {
+        0 => entries,
+        n => &entries[..entries.len().min(n)],
+    };
+    let note = if shown.len() < entries.len() {
+        escape_html(&t.fill(
+            "listing.truncated",
+            &[
+                ("shown", &shown.len().to_string()),
+                ("total", &entries.len().to_string()),
+            ],
+        ))
+    } else {
+        String::new()
+    };
+    let readme = super::readme::render(ctx, dir, entries).unwrap_or_default();
+    let forms = format!(
+        "{readme}{upload}{}",
+        t.html(FILTER_FORM).replace("@NOTE@", &note)
+    );
+    let theme = ctx.config.str("listingTheme");
+    let dirs = entries.iter().filter(|e| e.is_dir).count();
+    let table = format!(
+        "<table class=\"ts-entries\" data-theme=\"{theme}\" data-total=\"{}\" data-shown=\"{}\">",
+        entries.len(),
+        shown.len()
+    );
+    match theme {
+        "modern" => modern(
+            t,
+            &ctx.identity,
+            url_path,
+            shown,
+            (dirs, entries.len() - dirs),
+            &table,
+            &forms,
+        ),
+        _ => plain(t, &ctx.identity, url_path, shown, &table, &forms),
+    }
+}
+
+/// A weak validator for the listing `req` gets: a hash of the entries'
+/// names, sizes and times, the format and the locale.
+fn etag(ctx: &Context, req: &Request, format: &str, entries: &[Entry]) -> String {
+    let mut key = format!("{format}\n{}\n", ctx.locales.strings(req).tag);
+    for e in entries {
+        let mtime = e
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        let _ = writeln!(key, "{}\t{}\t{}\t{mtime}", e.name, e.is_dir, e.len);
+    }
+    format!("W/\"{:016x}\"", super::fnv1a(key.as_bytes()))
+}
+
+/// Whether a listing request asks for JSON.
+fn wants_json(req: &Request) -> bool {
+    let query = req.query.as_deref().unwrap_or_default();
+    query.split('&').any(|pair| pair == "format=json")
+        || req
+            .headers
+            .get("accept")
+            .is_some_and(|accept| accept.starts_with("application/json"))
+}
+
+/// `{"path": ..., "entries": [{"name", "dir", "size", "modified"}]}`.
+fn to_json(url_path: &str, entries: &[Entry]) -> Value {
+    let entries: Vec<Value> = entries
+        .iter()
+        .map(|e| {
+            json!({
+                "name": e.name,
+                "dir": e.is_dir,
+                "size": e.len,
+                "modified": e.modified.map(date::format_iso),
+            })
+        })
+        .collect();
+    json!({"path": url_path, "entries": entries})
+}
+
+/// The bare table: names, byte sizes and HTTP dates.
+fn plain(
+    t: Strings,
+    identity: &Identity,
+    url_path: &str,
+    entries: &[Entry],
+    table: &str,
+    forms: &str,
+) -> String {
+    let title = escape_html(&t.fill("listing.title", &[("path", url_path)]));
+    let mut rows = String::new();
+    if url_path != "/" {
+        rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for e in entries {
+        let slash = if e.is_dir { "/" } else { "" };
+        let size = if e.is_dir {
+            "-".to_owned()
+        } else {
+            e.len.to_string()
+        };
+        let modified = e.modified.map(date::format).unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr data-name=\"{name}\"><td><a href=\"{href}{slash}\">{name}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            href = percent_encode_path(&e.name),
+            name = escape_html(&e.name),
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\"><title>{title}</title></head>\n\
+         <body><h1>{title}</h1>\n{forms}{table}\n<tr><th>{name}</th><th>{size}</th><th>{modified}</th></tr>\n\
+         {rows}</table>\n{signature}</body></html>\n",
+        name = escape_html(t.get("listing.name")),
+        size = escape_html(t.get("listing.size")),
+        modified = escape_html(t.get("listing.modified")),
+        signature = identity.signature(),
+    )
+}
+
+/// Breadcrumbs, file-type icons, human-readable sizes and dark mode.
+fn modern(
+    t: Strings,
+    identity: &Identity,
+    url_path: &str,
+    entries: &[Entry],
+    (dirs, files): (usize, usize),
+    table: &str,
+    forms: &str,
+) -> String {
+    let mut crumbs = String::from("<a href=\"/\">&#8962;</a>");
+    let segments: Vec<&str> = url_path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut href = String::from("/");
+    for (i, segment) in segments.iter().enumerate() {
+        href.push_str(&percent_encode_path(segment));
+        href.push('/');
+        crumbs.push_str("<span class=\"sep\">/</span>");
+        if i + 1 == segments.len() {
+            crumbs.push_str(&format!("<span>{}</span>", escape_html(segment)));
+        } else {
+            crumbs.push_str(&format!("<a href=\"{href}\">{}</a>", escape_html(segment)));
+        }
+    }
+
+    let mut rows = String::new();
+    if url_path != "/" {
+        rows.push_str(
+            "<tr><td><a href=\"../\"><span class=\"icon\">&#11025;</span>..</a></td>\
+             <td class=\"size\"></td><td class=\"time\"></td></tr>\n",
+        );
+    }
+    for e in entries {
+        let slash = if e.is_dir { "/" } else { "" };
+        let size = if e.is_dir {
+            "&mdash;".to_owned()
+        } else {
+            human_size(e.len)
+        };
+        let time = match e.modified {
+            Some(at) => {
+                let iso = date::format_iso(at);
+                format!(
+                    "<time datetime=\"{iso}\">{} UTC</time>",
+                    iso[..16].replace('T', " ")
+                )
+            }
+            None => String::new(),
+        };
+        rows.push_str(&format!(
+            "<tr data-name=\"{name}\"><td><a href=\"{href}{slash}\"><span class=\"icon\">{icon}</span>{name}{slash}</a></td>\
+             <td class=\"size\" title=\"{bytes}\">{size}</td><td class=\"time\">{time}</td></tr>\n",
+            href = percent_encode_path(&e.name),
+            icon = icon(e),
+            name = escape_html(&e.name),
+            bytes = escape_html(&t.fill("listing.bytes", &[("bytes", &e.len.to_string())])),
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\"><title>{title}</title>\
+         <style>{MODERN_CSS}</style></head>\n\
+         <body><nav>{crumbs}</nav>\n{forms}{table}\n<thead><tr><th>{name}</th><th class=\"size\">{size}</th>\
+         <th class=\"time\">{modified}</th></tr></thead>\n<tbody>\n{rows}</tbody></table>\n\
+         <footer>{summary}{server}</footer></body></html>\n",
+        title = escape_html(&t.fill("listing.title", &[("path", url_path)])),
+        name = escape_html(t.get("listing.name")),
+        size = escape_html(t.get("listing.size")),
+        modified = escape_html(t.get("listing.modified")),
+        summary = escape_html(&t.fill(
+            "listing.summary",
+            &[("dirs", &dirs.to_string()), ("files", &files.to_string())]
+        )),
+        server = match identity.name() {
+            name if name.is_empty() => String::new(),
+            name => format!(" &middot; {}", escape_html(&name)),
+        },
+    )
+}
+
+/// An icon for the kind of file, from its MIME type.
+fn icon(entry: &Entry) -> &'static str {
+    if entry.is_dir {
+        return "&#128193;";
+    }
+    let ext = Path::new(&entry.name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if matches!(
+        ext.as_str(),
+        "zip" | "gz" | "tgz" | "tar" | "bz2" | "xz" | "zst" | "7z" | "rar"
+    ) {
+        return "&#128230;";
+    }
+    let content_type = mime::from_path(Path::new(&entry.name));
+    match content_type.split(['/', ';']).next().unwrap_or_default() {
+        "image" => "&#128444;",
+        "audio" => "&#127925;",
+        "video" => "&#127902;",
+        "font" => "&#128289;",
+        _ if content_type.starts_with("application/pdf") => "&#128213;",
+        _ if content_type.starts_with("text/html") => "&#127760;",
+        _ if mime::is_text(content_type) => "&#128196;",
+        _ => "&#128462;",
+    }
+}
+
+/// `1.5 KB` style sizes.
+pub fn human_size(len: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = len as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{len} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
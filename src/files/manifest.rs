@@ -0,0 +1,124 @@
+//! Asset fingerprint manifests: a JSON object mapping logical names to
+//! hashed file names, as written by most bundlers.
+//!
+//! ```json
+//! { "app.js": "app.3f9ab2.js", "main.css": { "file": "main.81c0de.css" } }
+//! ```
+//!
+//! Names are relative to the manifest's directory unless they start with
+//! `/`. A request for `app.js` is answered with `app.3f9ab2.js`, revalidated
+//! on every use so a new build takes effect at once; the hashed name itself
+//! never changes content and is cached for a year.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde_json::Value;
+
+use crate::log;
+
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+pub const REVALIDATE: &str = "no-cache";
+
+/// What a request path maps to.
+#[derive(Debug)]
+pub struct Fingerprint {
+    /// URL path of the hashed file to serve.
+    pub target: String,
+    /// `Cache-Control` for the response.
+    pub cache_control: &'static str,
+}
+
+#[derive(Default)]
+struct Entries {
+    logical: HashMap<String, String>,
+    hashed: HashSet<String>,
+}
+
+/// A manifest file, re-read whenever its modification time changes.
+pub struct Manifest {
+    file: PathBuf,
+    /// URL path of the manifest's directory, with a trailing slash.
+    base: String,
+    loaded: Mutex<Option<(Option<SystemTime>, Entries)>>,
+}
+
+impl Manifest {
+    /// `url_path` locates the manifest below `root`.
+    pub fn new(root: &Path, url_path: &str) -> Self {
+        let rel = url_path.trim_start_matches('/');
+        let base = match rel.rfind('/') {
+            Some(i) => format!("/{}", &rel[..=i]),
+            None => "/".to_owned(),
+        };
+        Self {
+            file: root.join(rel),
+            base,
+            loaded: Mutex::new(None),
+        }
+    }
+
+    /// Maps a logical or hashed URL path, or `None` if the manifest does not
+    /// mention it.
+    pub fn lookup(&self, url_path: &str) -> Option<Fingerprint> {
+        let modified = fs::metadata(&self.file).and_then(|m| m.modified()).ok();
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        if loaded.as_ref().is_none_or(|(at, _)| *at != modified) {
+            *loaded = Some((modified, self.read()));
+        }
+        let (_, entries) = loaded.as_ref()?;
+        if let Some(target) = entries.logical.get(url_path) {
+            return Some(Fingerprint {
+                target: target.clone(),
+                cache_control: REVALIDATE,
+            });
+        }
+        entries.hashed.contains(url_path).then(|| Fingerprint {
+            target: url_path.to_owned(),
+            cache_control: IMMUTABLE,
+        })
+    }
+
+    fn read(&self) -> Entries {
+        let parsed = fs::read(&self.file)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()));
+        let object = match parsed {
+            Ok(Value::Object(object)) => object,
+            Ok(_) => {
+                log::warn(&format!("{}: expected a JSON object", self.file.display()));
+                return Entries::default();
+            }
+            Err(e) => {
+                log::warn(&format!("{}: {e}", self.file.display()));
+                return Entries::default();
+            }
+        };
+        let mut entries = Entries::default();
+        for (name, value) in object {
+            let hashed = match &value {
+                Value::String(s) => s.as_str(),
+                Value::Object(o) => match o.get("file") {
+                    Some(Value::String(s)) => s.as_str(),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let hashed = self.url_path(hashed);
+            entries.hashed.insert(hashed.clone());
+            entries.logical.insert(self.url_path(&name), hashed);
+        }
+        entries
+    }
+
+    fn url_path(&self, name: &str) -> String {
+        if name.starts_with('/') {
+            name.to_owned()
+        } else {
+            format!("{}{}", self.base, name.trim_start_matches("./"))
+        }
+    }
+}
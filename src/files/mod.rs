@@ -0,0 +1,499 @@
+//! Static file serving.
+
+pub mod cache;
+pub mod digest;
+pub mod fallback;
+pub mod language;
+pub mod listing;
+pub mod manifest;
+pub mod precompressed;
+pub mod preload;
+pub mod readme;
+pub mod resolve;
+pub mod stat;
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::glob;
+use crate::http::mmap::Mmap;
+use crate::http::range::{self, Byteranges, RangeRequest};
+use crate::http::request::percent_encode_query;
+use crate::http::{Body, Request, Response, date, mime};
+use crate::server::{Context, debug};
+use crate::ssi;
+use crate::vfs::{Contents, DirEntry, Forward, Metadata, Window};
+
+pub use digest::fnv1a;
+
+/// Bytes of a mapped body paged in ahead of sending.
+const READ_AHEAD: usize = 8 << 20;
+
+/// Serves `req.path` from the root: a file, a directory index, or a listing.
+pub fn serve(ctx: &Context, req: &Request) -> Response {
+    if let Some(fingerprint) = ctx.manifest.as_ref().and_then(|m| m.lookup(&req.path)) {
+        debug::note(|| format!("fingerprinted name for {}", fingerprint.target));
+        let resp = serve_path(ctx, req, &fingerprint.target);
+        return if resp.status < 400 {
+            resp.header("Cache-Control", fingerprint.cache_control)
+        } else {
+            resp
+        };
+    }
+    let resp = serve_path(ctx, req, &req.path);
+    if resp.status == 404
+        && let Some(fallback) = fallback::serve(ctx, req)
+    {
+        return fallback;
+    }
+    resp
+}
+
+fn serve_path(ctx: &Context, req: &Request, url_path: &str) -> Response {
+    let Some(path) = resolve::resolve(&ctx.root, url_path, ctx.config.bool("showHidden")) else {
+        debug::note(|| format!("{url_path} refused: `..`, hidden or invalid segment"));
+        return Response::error(404);
+    };
+    debug::note(|| format!("resolved to {}", path.display()));
+    let meta = match metadata(ctx, &path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            debug::note(|| "no such file".to_owned());
+            return match language::negotiate(ctx, &path, req.headers.get("accept-language")) {
+                Some(variant) => serve_variant(ctx, req, variant),
+                None => io_error(&e),
+            };
+        }
+        Err(e) => {
+            debug::note(|| format!("stat failed: {e}"));
+            return io_error(&e);
+        }
+    };
+    if !contains(ctx, &path) {
+        debug::note(|| "lies outside the served tree".to_owned());
+        return Response::error(404);
+    }
+    if !meta.is_dir {
+        return serve_file(ctx, req, &path, &meta);
+    }
+
+    if !url_path.ends_with('/') {
+        let mut location = format!("{}/", crate::http::request::percent_encode_path(url_path));
+        if let Some(q) = &req.query {
+            location.push('?');
+            location.push_str(q);
+        }
+        debug::note(|| "directory without a trailing slash".to_owned());
+        return Response::redirect(301, &location);
+    }
+    for index in ctx.config.list("index") {
+        let candidate = path.join(&index);
+        if let Ok(meta) = metadata(ctx, &candidate)
+            && meta.is_file
+            && contains(ctx, &candidate)
+        {
+            debug::note(|| format!("directory index {index}"));
+            return serve_file(ctx, req, &candidate, &meta);
+        }
+    }
+    let accept = req.headers.get("accept-language");
+    for index in ctx.config.list("index") {
+        if let Some(variant) = language::negotiate(ctx, &path.join(&index), accept) {
+            return serve_variant(ctx, req, variant);
+        }
+    }
+    if !ctx.config.bool("showDir") {
+        debug::note(|| "directory without an index, and showDir is off".to_owned());
+        return Response::error(404);
+    }
+    debug::note(|| "directory listing".to_owned());
+    match listing::read_entries(ctx, &path, ctx.config.bool("showHidden")) {
+        Ok(entries) => listing::render(ctx, req, url_path, &path, &entries),
+        Err(resp) => resp,
+    }
+}
+
+/// Serves a file picked by language negotiation.
+fn serve_variant(ctx: &Context, req: &Request, variant: language::Variant) -> Response {
+    debug::note(|| {
+        format!(
+            "language variant {} ({})",
+            variant.path.display(),
+            variant.language
+        )
+    });
+    let meta = match metadata(ctx, &variant.path) {
+        Ok(meta) => meta,
+        Err(e) => return io_error(&e),
+    };
+    if !contains(ctx, &variant.path) {
+        return Response::error(404);
+    }
+    let mut resp =
+        serve_file(ctx, req, &variant.path, &meta).header("Content-Language", variant.language);
+    resp.headers.append("Vary", "Accept-Language");
+    resp
+}
+
+/// `path`, which lies under the root, as the VFS names it.
+fn vfs_path<'a>(ctx: &Context, path: &'a Path) -> io::Result<&'a Path> {
+    path.strip_prefix(&ctx.root)
+        .map_err(|_| io::ErrorKind::NotFound.into())
+}
+
+/// Metadata from the VFS, through the stat cache when there is one.
+pub fn metadata(ctx: &Context, path: &Path) -> io::Result<Metadata> {
+    let stat = || ctx.vfs.metadata(vfs_path(ctx, path)?);
+    match &ctx.stat_cache {
+        Some(cache) => cache.metadata(path, stat),
+        None => stat(),
+    }
+}
+
+pub fn read_dir(ctx: &Context, path: &Path) -> io::Result<Vec<DirEntry>> {
+    ctx.vfs.read_dir(vfs_path(ctx, path)?)
+}
+
+pub fn read(ctx: &Context, path: &Path) -> io::Result<Vec<u8>> {
+    ctx.vfs.read(vfs_path(ctx, path)?)
+}
+
+fn open(ctx: &Context, path: &Path) -> io::Result<Contents> {
+    ctx.vfs.open(vfs_path(ctx, path)?)
+}
+
+/// Whether the existing `path` really lies inside the served tree.
+pub fn contains(ctx: &Context, path: &Path) -> bool {
+    vfs_path(ctx, path).is_ok_and(|p| ctx.vfs.contains(p))
+}
+
+/// Maps a filesystem error to an error response.
+pub fn io_error(e: &io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => Response::error(404),
+        io::ErrorKind::PermissionDenied => Response::error(403),
+        _ => Response::error(500),
+    }
+}
+
+/// Validator for a file, per the `etag` option: the VFS's own tag if it has
+/// one, else derived from modification time and length, or in deterministic
+/// mode from the contents.
+pub fn etag(ctx: &Context, path: &Path, meta: &Metadata) -> Option<String> {
+    let mode = ctx.config.str("etag");
+    if mode != "strong" && mode != "weak" {
+        return None;
+    }
+    let hint = vfs_path(ctx, path).ok().and_then(|p| ctx.vfs.etag(p, meta));
+    let tag = hint.unwrap_or_else(|| {
+        if let Some(digests) = &ctx.digests
+            && let Ok(hash) = digests.hash(path, meta, || open(ctx, path))
+        {
+            return format!("\"{hash:016x}-{:x}\"", meta.len);
+        }
+        let mtime = meta
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        format!("\"{mtime:x}-{:x}\"", meta.len)
+    });
+    match mode {
+        "strong" => Some(tag),
+        "weak" => Some(format!("W/{tag}")),
+        _ => None,
+    }
+}
+
+/// Modification time to tell clients: `deterministicDate` in deterministic
+/// mode, so nothing depends on when the files were copied.
+pub fn modified(ctx: &Context, meta: &Metadata) -> Option<SystemTime> {
+    if ctx.config.bool("deterministic") {
+        return Some(UNIX_EPOCH + Duration::from_secs(ctx.config.int("deterministicDate")));
+    }
+    meta.modified
+}
+
+/// Weak comparison of an `If-None-Match` list against `etag`.
+pub fn etag_matches(list: &str, etag: &str) -> bool {
+    let bare = |t: &str| t.trim().trim_start_matches("W/").to_owned();
+    list.split(',')
+        .any(|t| t.trim() == "*" || bare(t) == bare(etag))
+}
+
+/// Whether the request's validators show the client's copy is current.
+pub fn not_modified(req: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    if let Some(inm) = req.headers.get("if-none-match") {
+        return etag.is_some_and(|tag| etag_matches(inm, tag));
+    }
+    match (
+        req.headers.get("if-modified-since").and_then(date::parse),
+        modified,
+    ) {
+        (Some(since), Some(modified)) => date::truncate(modified) <= since,
+        _ => false,
+    }
+}
+
+/// Whether an `If-Range` precondition allows serving a partial response.
+fn if_range_ok(req: &Request, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+    let Some(cond) = req.headers.get("if-range") else {
+        return true;
+    };
+    if cond.starts_with('"') {
+        return etag.is_some_and(|tag| tag == cond);
+    }
+    match (date::parse(cond), modified) {
+        (Some(at), Some(modified)) => date::truncate(modified) == at,
+        _ => false,
+    }
+}
+
+/// Serves one regular file with validators and range support, as an
+/// attachment when [`is_download`] says so.
+pub fn serve_file(ctx: &Context, req: &Request, path: &Path, meta: &Metadata) -> Response {
+    let mut resp = file_response(ctx, req, path, meta);
+    if resp.status < 300 && is_download(ctx, req) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        debug::note(|| "sent as an attachment".to_owned());
+        resp.headers
+            .set("Content-Disposition", content_disposition(&name));
+    }
+    resp
+}
+
+/// Whether `req` is for a download: `?download`, or a path matching the
+/// `download` globs without `?download=0`.
+fn is_download(ctx: &Context, req: &Request) -> bool {
+    match req.query_param("download").as_deref() {
+        Some("0" | "false") => false,
+        Some(_) => true,
+        None => glob::matches_any(&ctx.config.list("download"), &req.path),
+    }
+}
+
+/// `attachment` with `name` as the file name, spelled out in ASCII for old
+/// clients and in UTF-8 for the rest.
+fn content_disposition(name: &str) -> String {
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{ascii}\"; filename*=UTF-8''{}",
+        percent_encode_query(name)
+    )
+}
+
+fn file_response(ctx: &Context, req: &Request, path: &Path, meta: &Metadata) -> Response {
+    if ssi::applies(ctx, path) {
+        debug::note(|| "server-side includes".to_owned());
+        return ssi::serve(ctx, req, path);
+    }
+    let content_type = mime::from_path(path);
+    if !ctx.config.bool("precompressed") {
+        return send_file(ctx, req, path, meta, content_type);
+    }
+    let sidecars = precompressed::sidecars(ctx, path, meta);
+    if sidecars.is_empty() {
+        return send_file(ctx, req, path, meta, content_type);
+    }
+    let mut resp = match precompressed::pick(req, sidecars) {
+        Some(sidecar) => {
+            debug::note(|| format!("{} sidecar {}", sidecar.coding, sidecar.path.display()));
+            let resp = send_file(ctx, req, &sidecar.path, &sidecar.meta, content_type);
+            resp.header("Content-Encoding", sidecar.coding)
+        }
+        None => {
+            debug::note(|| "no sidecar in an accepted encoding".to_owned());
+            send_file(ctx, req, path, meta, content_type)
+        }
+    };
+    resp.headers.append("Vary", "Accept-Encoding");
+    resp
+}
+
+/// Sends the file at `path` as `content_type`, with validators and range
+/// support.
+fn send_file(
+    ctx: &Context,
+    req: &Request,
+    path: &Path,
+    meta: &Metadata,
+    content_type: &str,
+) -> Response {
+    let len = meta.len;
+    let modified = modified(ctx, meta);
+    let etag = etag(ctx, path, meta);
+    debug::note(|| {
+        format!(
+            "{len} bytes, validators: ETag {} (etag={}), Last-Modified {}",
+            etag.as_deref().unwrap_or("none"),
+            ctx.config.str("etag"),
+            modified.map_or("none".to_owned(), date::format)
+        )
+    });
+
+    let mut resp = Response::new(200)
+        .header("Content-Type", content_type)
+        .header("Accept-Ranges", "bytes");
+    if let Some(tag) = &etag {
+        resp.headers.set("ETag", tag.as_str());
+    }
+    if let Some(modified) = modified {
+        resp.headers.set("Last-Modified", date::format(modified));
+    }
+    if not_modified(req, etag.as_deref(), modified) {
+        debug::note(|| "the client's copy is current".to_owned());
+        resp.status = 304;
+        return resp;
+    }
+    if req.headers.contains("if-none-match") || req.headers.contains("if-modified-since") {
+        debug::note(|| "the client's copy is stale".to_owned());
+    }
+
+    let ranges = match req.headers.get("range") {
+        Some(header) if if_range_ok(req, etag.as_deref(), modified) => range::parse(header, len),
+        Some(_) => {
+            debug::note(|| "If-Range does not match, sending everything".to_owned());
+            RangeRequest::Full
+        }
+        None => RangeRequest::Full,
+    };
+    match &ranges {
+        RangeRequest::Partial(parts) => debug::note(|| format!("range of {} part(s)", parts.len())),
+        RangeRequest::Unsatisfiable => debug::note(|| "range cannot be satisfied".to_owned()),
+        RangeRequest::Full => {}
+    }
+    if ranges == RangeRequest::Full
+        && let Some(cache) = &ctx.file_cache
+    {
+        match cache.get(path, meta, || read(ctx, path)) {
+            Ok(Some(data)) => {
+                debug::note(|| "body from the file cache".to_owned());
+                return resp.with_body(Body::Shared(data));
+            }
+            Ok(None) => debug::note(|| "not kept by the file cache".to_owned()),
+            Err(e) => return io_error(&e),
+        }
+    }
+    let contents = match open(ctx, path) {
+        Ok(contents) => contents,
+        Err(e) => return io_error(&e),
+    };
+    match ranges {
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let r = ranges[0];
+            resp.status = 206;
+            resp.headers.set("Content-Range", r.content_range(len));
+            match contents_body(ctx, contents, len, r.start, r.len()) {
+                Ok(body) => resp.with_body(body),
+                Err(e) => io_error(&e),
+            }
+        }
+        RangeRequest::Partial(ranges) => {
+            let parts = match contents {
+                Contents::File(file) => Byteranges::new(file, &ranges, content_type, len),
+                Contents::Bytes(data) => {
+                    Byteranges::new(io::Cursor::new(data), &ranges, content_type, len)
+                }
+                Contents::Static(data) => {
+                    Byteranges::new(io::Cursor::new(data), &ranges, content_type, len)
+                }
+                Contents::Region {
+                    file,
+                    offset,
+                    len: region,
+                } => match Window::new(file, offset, region) {
+                    Ok(window) => Byteranges::new(window, &ranges, content_type, len),
+                    Err(e) => return io_error(&e),
+                },
+                Contents::Reader { reader, .. } => {
+                    Byteranges::new(Forward::new(reader), &ranges, content_type, len)
+                }
+            };
+            resp.status = 206;
+            resp.headers.set("Content-Type", parts.content_type());
+            resp.with_body(Body::Ranges(parts))
+        }
+        RangeRequest::Unsatisfiable => {
+            Response::error(416).header("Content-Range", format!("bytes */{len}"))
+        }
+        RangeRequest::Full => match contents_body(ctx, contents, len, 0, len) {
+            Ok(body) => resp.with_body(body),
+            Err(e) => io_error(&e),
+        },
+    }
+}
+
+/// A body for `len` bytes of a `total`-byte file. Open files are mapped into
+/// memory when `mmap` is on and the file reaches `mmapThreshold`; regions of
+/// files are always sent from the file.
+fn contents_body(
+    ctx: &Context,
+    contents: Contents,
+    total: u64,
+    offset: u64,
+    len: u64,
+) -> io::Result<Body> {
+    let file = match contents {
+        Contents::File(file) => file,
+        Contents::Region {
+            file,
+            offset: base,
+            len: region,
+        } => {
+            let len = len.min(region.saturating_sub(offset));
+            return Ok(Body::File {
+                file,
+                offset: base + offset,
+                len,
+            });
+        }
+        Contents::Bytes(data) if offset == 0 && len == data.len() as u64 => {
+            return Ok(Body::Shared(data));
+        }
+        Contents::Bytes(data) => {
+            let end = (offset.saturating_add(len) as usize).min(data.len());
+            let start = (offset as usize).min(end);
+            return Ok(Body::Bytes(data[start..end].to_vec()));
+        }
+        Contents::Static(data) => {
+            let end = (offset.saturating_add(len) as usize).min(data.len());
+            let start = (offset as usize).min(end);
+            return Ok(Body::Static(&data[start..end]));
+        }
+        Contents::Reader {
+            mut reader,
+            len: available,
+        } => {
+            let skipped = io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+            if skipped < offset {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let len = len.min(available.saturating_sub(offset));
+            return Ok(Body::Reader { reader, len });
+        }
+    };
+    if ctx.config.bool("mmap")
+        && total >= ctx.config.int("mmapThreshold")
+        && let Ok(map) = Mmap::map(&file, total)
+    {
+        let (offset, len) = (offset as usize, len as usize);
+        map.will_need(offset, len.min(READ_AHEAD));
+        debug::note(|| "body memory-mapped".to_owned());
+        return Ok(Body::Mapped { map, offset, len });
+    }
+    debug::note(|| {
+        let how = if ctx.config.bool("sendfile") {
+            "with sendfile"
+        } else {
+            "by copying"
+        };
+        format!("body sent from the file {how}")
+    });
+    Ok(Body::File { file, offset, len })
+}
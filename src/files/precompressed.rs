@@ -0,0 +1,126 @@
+//! Precompressed sidecars: with `precompressed` on, `app.js` is answered
+//! from `app.js.br`, `app.js.zst` or `app.js.gz` when the client accepts
+//! that encoding and the sidecar is at least as new as the file.
+//! `tinyserve precompress` writes them.
+
+use std::path::{Path, PathBuf};
+
+use crate::http::Request;
+use crate::server::Context;
+use crate::vfs::Metadata;
+
+use super::{contains, metadata};
+
+/// Content codings with their sidecar extensions, in order of preference.
+pub const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("zstd", "zst"), ("gzip", "gz")];
+
+/// A sidecar of a file.
+pub struct Sidecar {
+    pub coding: &'static str,
+    pub path: PathBuf,
+    pub meta: Metadata,
+}
+
+/// `path` with `.ext` appended.
+pub fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// Whether `name` is a sidecar by its extension.
+pub fn is_sidecar(name: &str) -> bool {
+    ENCODINGS
+        .iter()
+        .any(|(_, ext)| name.strip_suffix(ext).is_some_and(|s| s.ends_with('.')))
+}
+
+/// The up-to-date sidecars of `path`, in order of preference.
+pub fn sidecars(ctx: &Context, path: &Path, meta: &Metadata) -> Vec<Sidecar> {
+    ENCODINGS
+        .iter()
+        .filter_map(|(coding, ext)| {
+            let path = sidecar_path(path, ext);
+            let side = metadata(ctx, &path)
+                .ok()
+                .filter(|m| m.is_file && contains(ctx, &path))?;
+            let fresh = match (side.modified, meta.modified) {
+                (Some(side), Some(orig)) => side >= orig,
+                _ => false,
+            };
+            fresh.then_some(Sidecar {
+                coding,
+                path,
+                meta: side,
+            })
+        })
+        .collect()
+}
+
+/// The first of `sidecars` whose coding `req` accepts.
+pub fn pick(req: &Request, sidecars: Vec<Sidecar>) -> Option<Sidecar> {
+    let accept = req.headers.get("accept-encoding")?;
+    sidecars.into_iter().find(|s| accepts(accept, s.coding))
+}
+
+/// Whether an `Accept-Encoding` value allows `coding`: named or matched by
+/// `*`, with a non-zero quality.
+fn accepts(header: &str, coding: &str) -> bool {
+    let mut wildcard = None;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q > 0.0;
+        }
+        if name == "*" {
+            wildcard = Some(q > 0.0);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::TestServer;
+
+    #[test]
+    fn serves_fresh_sidecars() {
+        let server = TestServer::with(|b| b.option("precompressed", true)).unwrap();
+        server.write("app.js", "plain").unwrap();
+        server.write("app.js.gz", "packed").unwrap();
+        let resp = server
+            .request("GET", "/app.js", &[("Accept-Encoding", "gzip")], b"")
+            .unwrap();
+        assert_eq!(resp.header("content-encoding"), Some("gzip"));
+        assert_eq!(resp.text(), "packed");
+        assert_eq!(server.get("/app.js").unwrap().text(), "plain");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignores_sidecars_leading_outside_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let server = TestServer::with(|b| b.option("precompressed", true)).unwrap();
+        let outside = TestServer::new().unwrap();
+        server.write("app.js", "plain").unwrap();
+        outside.write("secret.gz", "secret").unwrap();
+        let root = server.root().unwrap();
+        symlink(
+            outside.root().unwrap().join("secret.gz"),
+            root.join("app.js.gz"),
+        )
+        .unwrap();
+        let resp = server
+            .request("GET", "/app.js", &[("Accept-Encoding", "gzip")], b"")
+            .unwrap();
+        assert_eq!(resp.header("content-encoding"), None);
+        assert_eq!(resp.text(), "plain");
+    }
+}
@@ -0,0 +1,110 @@
+//! Warm-up at startup (`preload`): files below the root matching the globs
+//! are read before the server starts accepting, so the first requests
+//! after a deploy find them in the caches rather than on disk.
+//!
+//! Each file's metadata goes into the stat cache and its body into the
+//! file cache (when they are on), its ETag is computed, and with
+//! `precompressed` on its up-to-date sidecars are loaded the same way.
+//! Without `cacheSize` the reads still leave the files in the OS page cache.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::glob;
+use crate::log;
+use crate::server::Context;
+use crate::vfs::{Contents, Metadata};
+
+use super::{etag, metadata, open, precompressed, read, read_dir};
+
+#[derive(Default)]
+struct Tally {
+    files: u64,
+    bytes: u64,
+}
+
+/// Loads the files matching `preload`, if any.
+pub fn run(ctx: &Context) {
+    let patterns = ctx.config.list("preload");
+    if patterns.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    let mut tally = Tally::default();
+    walk(ctx, &ctx.root, "", &patterns, &mut tally);
+    log::info(&format!(
+        "preloaded {} file(s), {} bytes, in {} ms",
+        tally.files,
+        tally.bytes,
+        started.elapsed().as_millis()
+    ));
+}
+
+/// Loads the matching files below `dir`, whose path relative to the root
+/// is `rel`. Hidden entries and sidecars are left out.
+fn walk(ctx: &Context, dir: &Path, rel: &str, patterns: &[String], tally: &mut Tally) {
+    let entries = match read_dir(ctx, dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn(&format!("preload: cannot list {}: {e}", dir.display()));
+            return;
+        }
+    };
+    for entry in entries {
+        if entry.name.starts_with('.') || precompressed::is_sidecar(&entry.name) {
+            continue;
+        }
+        let path = dir.join(&entry.name);
+        let rel = format!("{rel}/{}", entry.name);
+        if entry.meta.is_dir {
+            walk(ctx, &path, &rel, patterns, tally);
+        } else if entry.meta.is_file
+            && glob::matches_any(patterns, &rel)
+            && let Err(e) = load(ctx, &path, tally)
+        {
+            log::warn(&format!("preload: cannot read {}: {e}", path.display()));
+        }
+    }
+}
+
+/// Loads `path` and, with `precompressed` on, its sidecars.
+fn load(ctx: &Context, path: &Path, tally: &mut Tally) -> io::Result<()> {
+    let meta = metadata(ctx, path)?;
+    warm(ctx, path, &meta, tally)?;
+    if ctx.config.bool("precompressed") {
+        for sidecar in precompressed::sidecars(ctx, path, &meta) {
+            warm(ctx, &sidecar.path, &sidecar.meta, tally)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads `path` into the file cache, or through to nothing when the cache
+/// does not keep it, and computes its ETag.
+fn warm(ctx: &Context, path: &Path, meta: &Metadata, tally: &mut Tally) -> io::Result<()> {
+    let cached = match &ctx.file_cache {
+        Some(cache) => cache.get(path, meta, || read(ctx, path))?.is_some(),
+        None => false,
+    };
+    if !cached {
+        match open(ctx, path)? {
+            Contents::File(mut file) => {
+                io::copy(&mut file, &mut io::sink())?;
+            }
+            Contents::Region {
+                mut file,
+                offset,
+                len,
+            } => {
+                file.seek(SeekFrom::Start(offset))?;
+                io::copy(&mut file.take(len), &mut io::sink())?;
+            }
+            Contents::Bytes(_) | Contents::Static(_) | Contents::Reader { .. } => {}
+        }
+    }
+    etag(ctx, path, meta);
+    tally.files += 1;
+    tally.bytes += meta.len;
+    Ok(())
+}
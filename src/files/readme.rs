@@ -0,0 +1,110 @@
+//! READMEs shown above directory listings, like code forges do.
+//!
+//! The first entry named in `readme` (case aside) is rendered: Markdown
+//! files as HTML with the `markdown` feature, anything else as plain text.
+//! Raw HTML in Markdown is shown as text, so a README in a shared folder
+//! cannot run scripts on the listing page.
+
+use std::path::Path;
+
+use crate::http::response::escape_html;
+use crate::log;
+use crate::server::Context;
+
+use super::listing::Entry;
+
+/// READMEs larger than this are left to the file table.
+const MAX_LEN: u64 = 256 << 10;
+
+/// The README of `dir` as an HTML fragment, if it has one.
+pub fn render(ctx: &Context, dir: &Path, entries: &[Entry]) -> Option<String> {
+    let names = ctx.config.list("readme");
+    let entry = names.iter().find_map(|name| {
+        entries
+            .iter()
+            .find(|e| !e.is_dir && e.name.eq_ignore_ascii_case(name))
+    })?;
+    if entry.len > MAX_LEN {
+        return None;
+    }
+    let path = dir.join(&entry.name);
+    if !super::contains(ctx, &path) {
+        return None;
+    }
+    let text = match super::read(ctx, &path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            log::warn(&format!("{}: {e}", path.display()));
+            return None;
+        }
+    };
+    let markdown = Path::new(&entry.name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"));
+    let body = if markdown {
+        to_html(&text)
+    } else {
+        format!("<pre>{}</pre>", escape_html(&text))
+    };
+    Some(format!("<article class=\"readme\">\n{body}</article>\n"))
+}
+
+#[cfg(feature = "markdown")]
+fn to_html(text: &str) -> String {
+    use pulldown_cmark::{Event, Options, Parser, Tag, html};
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    out
+}
+
+/// `url`, or `#` for `javascript:` and other script-running URLs.
+#[cfg(feature = "markdown")]
+fn safe_url(url: pulldown_cmark::CowStr<'_>) -> pulldown_cmark::CowStr<'_> {
+    let scheme = url.split(':').next().unwrap_or_default().trim();
+    if url.contains(':')
+        && ["javascript", "vbscript", "data"]
+            .iter()
+            .any(|s| scheme.eq_ignore_ascii_case(s))
+    {
+        pulldown_cmark::CowStr::Borrowed("#")
+    } else {
+        url
+    }
+}
+
+/// Without the `markdown` feature, Markdown is shown as it is written.
+#[cfg(not(feature = "markdown"))]
+fn to_html(text: &str) -> String {
+    format!("<pre>{}</pre>", escape_html(text))
+}
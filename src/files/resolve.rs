@@ -0,0 +1,29 @@
+//! Mapping request paths onto the served root.
+
+use std::path::{Path, PathBuf};
+
+use crate::access;
+
+/// Joins a decoded URL path onto `root`. Returns `None` for paths that must
+/// never reach the filesystem: parent-directory segments, NUL bytes,
+/// backslashes, access files, and (unless `show_hidden`) dotfile segments.
+pub fn resolve(root: &Path, url_path: &str, show_hidden: bool) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in url_path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains(['\0', '\\']) => return None,
+            s if s.starts_with('.') && !show_hidden => return None,
+            s if access::is_access_file(s) => return None,
+            s => path.push(s),
+        }
+    }
+    Some(path)
+}
+
+/// Whether `path`, after resolving symlinks, still lies under `root`
+/// (which must already be canonical).
+pub fn is_within(root: &Path, path: &Path) -> bool {
+    path.canonicalize().is_ok_and(|p| p.starts_with(root))
+}
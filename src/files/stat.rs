@@ -0,0 +1,107 @@
+//! Short-lived cache of metadata lookups, including failed lookups, so bursts
+//! of requests for the same paths touch the filesystem once per `statTtl`.
+//! "Not found" results may be kept for `notFoundTtl` instead, which alone
+//! spares the filesystem when clients keep asking for paths that do not
+//! exist.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::vfs::Metadata;
+
+/// Entries kept before expired ones are swept out.
+const MAX_ENTRIES: usize = 10_000;
+
+type Lookup = Result<Metadata, io::ErrorKind>;
+
+pub struct StatCache {
+    ttl: Duration,
+    /// How long "not found" is kept, if longer than `ttl`.
+    miss_ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, (Instant, Lookup)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration, miss_ttl: Duration) -> Self {
+        Self {
+            ttl,
+            miss_ttl: miss_ttl.max(ttl),
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Lookups answered from the cache and from `stat` so far.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, (Instant, Lookup)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The metadata of `path`, answered from the cache while the entry is
+    /// fresh and from `stat` otherwise.
+    pub fn metadata(
+        &self,
+        path: &Path,
+        stat: impl FnOnce() -> io::Result<Metadata>,
+    ) -> io::Result<Metadata> {
+        let now = Instant::now();
+        if let Some((at, lookup)) = self.lock().get(path)
+            && now.duration_since(*at) < self.ttl_of(lookup)
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return lookup.map_err(io::Error::from);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let result = stat();
+        let lookup = match &result {
+            Ok(meta) => Ok(*meta),
+            Err(e) => Err(e.kind()),
+        };
+        if self.ttl_of(&lookup).is_zero() {
+            return result;
+        }
+        let mut entries = self.lock();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (at, lookup)| now.duration_since(*at) < self.ttl_of(lookup));
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(path.to_path_buf(), (now, lookup));
+        result
+    }
+
+    fn ttl_of(&self, lookup: &Lookup) -> Duration {
+        match lookup {
+            Err(io::ErrorKind::NotFound) => self.miss_ttl,
+            _ => self.ttl,
+        }
+    }
+
+    /// Forgets `path` and everything below it.
+    pub fn invalidate(&self, path: &Path) {
+        self.lock().retain(|p, _| !p.starts_with(path));
+    }
+
+    /// Forgets `path` alone, keeping what is below it.
+    pub fn forget(&self, path: &Path) {
+        self.lock().remove(path);
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+}
@@ -0,0 +1,371 @@
+//! Forward authentication: before a gated request is served, it is put to
+//! an auth service, the way nginx `auth_request` and Traefik `forwardAuth`
+//! do. Sign-in itself (an OIDC authorization code flow, say) runs in that
+//! service, such as oauth2-proxy or Authelia; tinyserve only relays the
+//! session cookie and acts on the verdict.
+//!
+//! The service gets a `GET` of `forwardAuth` carrying the client's
+//! `Cookie` and `Authorization` plus `X-Forwarded-Method`, `-Proto`,
+//! `-Host`, `-Uri` and `-For`. Then:
+//!
+//! - 2xx: the request goes on, with the `forwardAuthHeaders` of the answer
+//!   (the signed-in user, say) set on it. The verdict is reused for
+//!   `forwardAuthTtl` milliseconds, for the same method, host and target
+//!   with the same cookies and credentials; a request carrying neither is
+//!   put to the service every time.
+//! - 3xx: the client is sent where the service points.
+//! - 401: browsers are sent to `forwardAuthLogin`, where `{url}` stands for
+//!   the page they asked for; other clients get the 401.
+//! - anything else: 403, or 502 when the service can't be reached.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+
+use crate::core::Config;
+use crate::glob;
+use crate::http::client;
+use crate::http::request::percent_encode_query;
+use crate::http::response::SERVER;
+use crate::http::{Request, Response};
+use crate::log;
+use crate::server::{Context, debug};
+
+/// How long to wait on the auth service.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Remembered sessions at most; the table is cleared beyond that.
+const MAX_SESSIONS: usize = 4096;
+
+/// A verdict of the auth service.
+struct Answer {
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl Answer {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// An accepted session: when it lapses and the headers to set.
+type Session = (Instant, Vec<(String, String)>);
+
+#[derive(Debug)]
+pub struct ForwardAuth {
+    /// `host:port` to connect to.
+    addr: String,
+    /// Value of the `Host` header.
+    host: String,
+    /// Path and query to ask for.
+    target: String,
+    login: String,
+    paths: Vec<String>,
+    copy: Vec<String>,
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// The forward auth configured by `forwardAuth`, if any.
+pub fn load(config: &Config) -> Result<Option<ForwardAuth>> {
+    let url = config.str("forwardAuth");
+    if url.is_empty() {
+        return Ok(None);
+    }
+    let Some(rest) = url.strip_prefix("http://") else {
+        bail!("forwardAuth: only http:// auth services are supported");
+    };
+    let (authority, target) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if authority.is_empty() {
+        bail!("forwardAuth: the URL has no host");
+    }
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, p)| !p.contains(']'));
+    Ok(Some(ForwardAuth {
+        addr: if has_port {
+            authority.to_owned()
+        } else {
+            format!("{authority}:80")
+        },
+        host: authority.to_owned(),
+        target: if target.is_empty() { "/" } else { target }.to_owned(),
+        login: config.str("forwardAuthLogin").to_owned(),
+        paths: config.list("forwardAuthPaths"),
+        copy: config.list("forwardAuthHeaders"),
+        ttl: Duration::from_millis(config.int("forwardAuthTtl")),
+        sessions: Mutex::new(HashMap::new()),
+    }))
+}
+
+/// Lets `req` through with the service's headers set on it, or returns the
+/// response turning it away.
+pub fn handle(ctx: &Context, req: &mut Request) -> Option<Response> {
+    let auth = ctx.forward_auth.as_ref()?;
+    if !auth.paths.is_empty() && !glob::matches_any(&auth.paths, &req.path) {
+        return None;
+    }
+    // Only the auth service may vouch for these.
+    for name in &auth.copy {
+        req.headers.remove(name);
+    }
+    let cookie = req.headers.get("cookie").unwrap_or_default();
+    let authorization = req.headers.get("authorization").unwrap_or_default();
+    // The service judges the request as a whole, so its verdict only holds
+    // for the same one; without credentials there is no session to trust.
+    let key = (!cookie.is_empty() || !authorization.is_empty()).then(|| {
+        format!(
+            "{}\n{}\n{}\n{cookie}\n{authorization}",
+            req.method,
+            req.headers.get("host").unwrap_or_default(),
+            req.target
+        )
+    });
+    if let Some(headers) = key.as_deref().and_then(|key| auth.remembered(key)) {
+        debug::note(|| "forward auth: session still trusted".to_owned());
+        set_headers(req, headers);
+        return None;
+    }
+    let answer = match auth.ask(req) {
+        Ok(answer) => answer,
+        Err(e) => {
+            log::warn(&format!("forward auth: {}: {e}", auth.addr));
+            return Some(Response::error_with(
+                502,
+                "the auth service could not be reached",
+            ));
+        }
+    };
+    debug::note(|| format!("forward auth answered {}", answer.status));
+    match answer.status {
+        200..=299 => {
+            let headers: Vec<(String, String)> = auth
+                .copy
+                .iter()
+                .filter_map(|name| Some((name.clone(), answer.header(name)?.to_owned())))
+                .collect();
+            if let Some(key) = key {
+                auth.remember(key, &headers);
+            }
+            set_headers(req, headers);
+            None
+        }
+        300..=399 if answer.header("location").is_some() => {
+            let location = answer.header("location").unwrap_or_default();
+            Some(with_cookies(Response::redirect(302, location), &answer))
+        }
+        401 if !auth.login.is_empty() && is_browser(req) => {
+            let host = req.headers.get("host").unwrap_or_default();
+            let url = format!("http://{host}{}", req.target);
+            let location = auth.login.replace("{url}", &percent_encode_query(&url));
+            Some(with_cookies(
+                Response::redirect(302, location.as_str()),
+                &answer,
+            ))
+        }
+        401 => {
+            let mut resp = Response::error(401);
+            for (_, value) in answer
+                .headers
+                .iter()
+                .filter(|(n, _)| n.eq_ignore_ascii_case("www-authenticate"))
+            {
+                resp.headers.append("WWW-Authenticate", value.as_str());
+            }
+            Some(resp)
+        }
+        _ => Some(Response::error(403)),
+    }
+}
+
+fn set_headers(req: &mut Request, headers: Vec<(String, String)>) {
+    for (name, value) in headers {
+        req.headers.set(name, value);
+    }
+}
+
+/// Passes on cookies the service sets, such as a sign-in state.
+fn with_cookies(mut resp: Response, answer: &Answer) -> Response {
+    for (_, value) in answer
+        .headers
+        .iter()
+        .filter(|(n, _)| n.eq_ignore_ascii_case("set-cookie"))
+    {
+        resp.headers.append("Set-Cookie", value.as_str());
+    }
+    resp
+}
+
+/// Whether a person is likely behind `req`, to be sent to a login page
+/// rather than given a bare 401.
+fn is_browser(req: &Request) -> bool {
+    req.method == "GET"
+        && req
+            .headers
+            .get("accept")
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+impl ForwardAuth {
+    fn remembered(&self, key: &str) -> Option<Vec<(String, String)>> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let (until, headers) = sessions.get(key)?;
+        (Instant::now() < *until).then(|| headers.clone())
+    }
+
+    fn remember(&self, key: String, headers: &[(String, String)]) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        if sessions.len() >= MAX_SESSIONS {
+            sessions.clear();
+        }
+        sessions.insert(key, (Instant::now() + self.ttl, headers.to_vec()));
+    }
+
+    /// Puts `req` to the auth service.
+    fn ask(&self, req: &Request) -> io::Result<Answer> {
+        let stream = client::connect(&self.addr, TIMEOUT)?;
+        let mut head = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {SERVER}\r\nConnection: close\r\n\
+             X-Forwarded-Method: {}\r\nX-Forwarded-Proto: http\r\nX-Forwarded-Uri: {}\r\n\
+             X-Forwarded-For: {}\r\n",
+            self.target,
+            self.host,
+            req.method,
+            req.target,
+            req.peer.ip()
+        );
+        for (name, header) in [
+            ("X-Forwarded-Host", "host"),
+            ("Cookie", "cookie"),
+            ("Authorization", "authorization"),
+        ] {
+            if let Some(value) = req.headers.get(header) {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        head.push_str("\r\n");
+        (&stream).write_all(head.as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad auth response"))?;
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        Ok(Answer { status, headers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use crate::test::TestServer;
+
+    /// An auth service that lets anyone into `/public/` and the holder of
+    /// `session=alice` into `/open/`, counting the requests it judges.
+    fn service() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/auth", listener.local_addr().unwrap());
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&asked);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (mut uri, mut cookie) = (String::new(), String::new());
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(v) = line.strip_prefix("X-Forwarded-Uri: ") {
+                        uri = v.to_owned();
+                    } else if let Some(v) = line.strip_prefix("Cookie: ") {
+                        cookie = v.to_owned();
+                    }
+                }
+                let allowed = uri.starts_with("/public/")
+                    || (uri.starts_with("/open/") && cookie == "session=alice");
+                let status = if allowed {
+                    "200 OK"
+                } else {
+                    "401 Unauthorized"
+                };
+                let _ = stream.write_all(
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes(),
+                );
+            }
+        });
+        (url, asked)
+    }
+
+    fn server(url: &str) -> TestServer {
+        let server = TestServer::with(|b| b.option("forwardAuth", url)).unwrap();
+        for path in ["public/a.txt", "open/a.txt", "secret/a.txt"] {
+            server.write(path, "a").unwrap();
+        }
+        server
+    }
+
+    #[test]
+    fn anonymous_verdicts_are_not_reused() {
+        let (url, asked) = service();
+        let server = server(&url);
+        assert_eq!(server.get("/public/a.txt").unwrap().status, 200);
+        assert_eq!(server.get("/secret/a.txt").unwrap().status, 401);
+        assert_eq!(server.get("/public/a.txt").unwrap().status, 200);
+        assert_eq!(asked.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn verdicts_hold_for_the_same_request_only() {
+        let (url, asked) = service();
+        let server = server(&url);
+        let cookie = [("Cookie", "session=alice")];
+        let status =
+            |method: &str, path: &str| server.request(method, path, &cookie, b"").unwrap().status;
+        assert_eq!(status("GET", "/open/a.txt"), 200);
+        assert_eq!(status("GET", "/open/a.txt"), 200);
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+        assert_eq!(status("GET", "/secret/a.txt"), 401);
+        assert_eq!(status("GET", "/secret/a.txt"), 401);
+        assert_eq!(status("HEAD", "/open/a.txt"), 200);
+        assert_eq!(asked.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,92 @@
+//! Shell-style glob matching for option values.
+//!
+//! `*` matches within one path segment, `**` across segments and `?` one
+//! character. A pattern without `/` is matched against the file name alone,
+//! so `*.log` matches at any depth; otherwise it is matched against the whole
+//! path relative to the root (leading `/` ignored on both sides).
+
+/// Whether `path` matches `pattern`.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let pattern = pattern.trim_start_matches('/');
+    if pattern.contains('/') {
+        match_bytes(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        match_bytes(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Whether any pattern in `patterns` matches `path`.
+pub fn matches_any<S: AsRef<str>>(patterns: &[S], path: &str) -> bool {
+    patterns.iter().any(|p| matches(p.as_ref(), path))
+}
+
+fn match_bytes(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => (0..=text.len())
+            .filter(|&i| i == 0 || text[i - 1] == b'/')
+            .any(|i| match_bytes(rest, &text[i..])),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| match_bytes(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            for i in 0..=text.len() {
+                if match_bytes(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        [b'?', rest @ ..] => {
+            matches!(text, [c, tail @ ..] if *c != b'/' && match_bytes(rest, tail))
+        }
+        [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && match_bytes(rest, tail)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_match_at_any_depth() {
+        assert!(matches("*.log", "app.log"));
+        assert!(matches("*.log", "/var/app.log"));
+        assert!(matches("*.log", "a/b/c.log"));
+        assert!(!matches("*.log", "app.log.1"));
+        assert!(!matches("*.log", "logs/app.txt"));
+        assert!(matches("app-?.log", "app-1.log"));
+        assert!(!matches("app-?.log", "app-12.log"));
+    }
+
+    #[test]
+    fn paths_match_from_the_root() {
+        assert!(matches("/logs/*.log", "logs/a.log"));
+        assert!(matches("logs/*.log", "/logs/a.log"));
+        assert!(!matches("logs/*.log", "logs/old/a.log"));
+        assert!(!matches("logs/*.log", "x/logs/a.log"));
+        assert!(!matches("logs/?/a", "logs///a"));
+    }
+
+    #[test]
+    fn double_stars_cross_segments() {
+        assert!(matches("logs/**/*.log", "logs/a.log"));
+        assert!(matches("logs/**/*.log", "logs/x/y/a.log"));
+        assert!(!matches("logs/**/*.log", "logsx/a.log"));
+        assert!(matches("**/secret", "secret"));
+        assert!(matches("**/secret", "a/b/secret"));
+        assert!(!matches("**/secret", "a/notsecret"));
+        assert!(matches("assets/**", "assets/css/site.css"));
+    }
+
+    #[test]
+    fn any_pattern_will_do() {
+        let patterns = ["*.log", "*.txt"];
+        assert!(matches_any(&patterns, "a.txt"));
+        assert!(!matches_any(&patterns, "a.md"));
+        assert!(!matches_any::<&str>(&[], "a.txt"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Scripted request hooks: a Rhai script (`hooks`, by default `hooks.rhai`
+//! in the configs dir) whose functions run on every request, when built
+//! with the `rhai` feature.
+//!
+//! ```text
+//! fn on_request() {
+//!     if this.path.starts_with("/admin/") && this.headers["x-token"] != "s3cret" {
+//!         return deny(403, "admins only");
+//!     }
+//!     if this.path == "/old" {
+//!         this.path = "/new";
+//!     }
+//! }
+//!
+//! fn on_response(req) {
+//!     this.headers["x-frame-options"] = "DENY";
+//! }
+//! ```
+//!
+//! In `on_request`, `this` is the request: `method`, `path`, `query`, `peer`
+//! and `headers` (by lowercase name). Changes to `path` and `headers` are
+//! applied, with `()` removing a header; a new `path` is normalized and
+//! must pass `schedule`, `policy` and the access rules again, as they ran
+//! on the old one. Returning `deny(status, body)`
+//! answers the request instead. In `on_response`, `this` holds the response
+//! `status` and `headers`, and the request is passed read-only. Either
+//! function may be left out. Each call is limited in the operations it may
+//! run; an `on_request` that fails answers `500`.
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::access;
+use crate::core::Config;
+use crate::http::{Request, Response, request};
+use crate::log;
+use crate::server::{Context, policy, schedule, unicode};
+
+/// Compiles the script named by the `hooks` option.
+pub fn load(config: &Config) -> Result<Option<Hooks>> {
+    let path = match config.str("hooks") {
+        "" => return Ok(None),
+        path => Path::new(path),
+    };
+    #[cfg(feature = "rhai")]
+    {
+        Hooks::load(path).map(Some)
+    }
+    #[cfg(not(feature = "rhai"))]
+    anyhow::bail!(
+        "{}: scripted hooks need a build with the `rhai` feature",
+        path.display()
+    )
+}
+
+/// Runs `on_request`, returning a response if the script denied the request.
+pub fn before(ctx: &Context, req: &mut Request) -> Option<Response> {
+    let hooks = ctx.hooks.as_ref()?;
+    let path = req.path.clone();
+    match hooks.on_request(req) {
+        Ok(None) if req.path != path => recheck(ctx, req),
+        Ok(resp) => resp,
+        Err(e) => {
+            log::warn(&format!("hooks: {e:#}"));
+            Some(Response::error_with(500, "a request hook failed"))
+        }
+    }
+}
+
+/// Puts a rewritten path in the form the path rules expect and holds it to
+/// them.
+fn recheck(ctx: &Context, req: &mut Request) -> Option<Response> {
+    req.path = request::normalize_path(&req.path);
+    if let Some(resp) = unicode::apply(&ctx.config, req) {
+        return Some(resp);
+    }
+    let req = &*req;
+    schedule::check_path(ctx, &req.path)
+        .or_else(|| policy::check_path(ctx, req, &req.path))
+        .or_else(|| access::check_path(ctx, req, &req.path))
+}
+
+/// Runs `on_response`; a failing script leaves the response as it was.
+pub fn after(ctx: &Context, req: &Request, resp: &mut Response) {
+    if let Some(hooks) = &ctx.hooks
+        && let Err(e) = hooks.on_response(req, resp)
+    {
+        log::warn(&format!("hooks: {e:#}"));
+    }
+}
+
+#[cfg(feature = "rhai")]
+pub use script::Hooks;
+
+/// Without the `rhai` feature no script can be loaded.
+#[cfg(not(feature = "rhai"))]
+pub enum Hooks {}
+
+#[cfg(not(feature = "rhai"))]
+impl Hooks {
+    pub fn on_request(&self, _: &mut Request) -> Result<Option<Response>> {
+        match *self {}
+    }
+
+    pub fn on_response(&self, _: &Request, _: &mut Response) -> Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "rhai")]
+mod script {
+    use std::path::Path;
+
+    use anyhow::{Context as _, Result, anyhow, bail};
+    use rhai::{AST, CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope};
+
+    use crate::http::{Headers, Request, Response};
+
+    /// Operations one call may run before it is aborted.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// What `deny(status, body)` returns.
+    #[derive(Clone)]
+    struct Denial {
+        status: i64,
+        body: String,
+    }
+
+    /// A compiled hooks script.
+    pub struct Hooks {
+        engine: Engine,
+        ast: AST,
+        on_request: bool,
+        on_response: bool,
+    }
+
+    impl Hooks {
+        pub(super) fn load(path: &Path) -> Result<Self> {
+            let mut engine = Engine::new();
+            engine
+                .set_max_operations(MAX_OPERATIONS)
+                .set_max_call_levels(32)
+                .set_max_string_size(1 << 20)
+                .set_max_array_size(10_000)
+                .set_max_map_size(10_000);
+            engine.register_type_with_name::<Denial>("Denial");
+            engine.register_fn("deny", |status: i64, body: &str| Denial {
+                status,
+                body: body.to_owned(),
+            });
+            engine.register_fn("deny", |status: i64| Denial {
+                status,
+                body: String::new(),
+            });
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|e| anyhow!("{e}"))
+                .with_context(|| format!("hooks {}", path.display()))?;
+            let defines = |name: &str| ast.iter_functions().any(|f| f.name == name);
+            Ok(Self {
+                on_request: defines("on_request"),
+                on_response: defines("on_response"),
+                engine,
+                ast,
+            })
+        }
+
+        fn call(&self, name: &str, this: &mut Dynamic, args: impl FuncArgs) -> Result<Dynamic> {
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+            self.engine
+                .call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args)
+                .map_err(|e| anyhow!("{name}: {e}"))
+        }
+
+        pub fn on_request(&self, req: &mut Request) -> Result<Option<Response>> {
+            if !self.on_request {
+                return Ok(None);
+            }
+            let mut this = Dynamic::from_map(request_map(req));
+            let out = self.call("on_request", &mut this, ())?;
+            if let Some(denial) = out.try_cast::<Denial>() {
+                let status = u16::try_from(denial.status)
+                    .ok()
+                    .filter(|s| (100..600).contains(s))
+                    .ok_or_else(|| anyhow!("deny: invalid status {}", denial.status))?;
+                return Ok(Some(Response::text(status, denial.body)));
+            }
+            let this = this
+                .try_cast::<Map>()
+                .ok_or_else(|| anyhow!("on_request replaced `this`"))?;
+            if let Some(path) = this.get("path") {
+                match path.clone().into_string() {
+                    Ok(path) if path.starts_with('/') => req.path = path,
+                    _ => bail!("on_request: `path` must be a string starting with /"),
+                }
+            }
+            apply_headers(&mut req.headers, &this)?;
+            Ok(None)
+        }
+
+        pub fn on_response(&self, req: &Request, resp: &mut Response) -> Result<()> {
+            if !self.on_response {
+                return Ok(());
+            }
+            let mut this = Map::new();
+            this.insert("status".into(), Dynamic::from_int(i64::from(resp.status)));
+            this.insert("headers".into(), header_map(&resp.headers).into());
+            let mut this = Dynamic::from_map(this);
+            let _ = self.call("on_response", &mut this, (request_map(req),))?;
+            let this = this
+                .try_cast::<Map>()
+                .ok_or_else(|| anyhow!("on_response replaced `this`"))?;
+            apply_headers(&mut resp.headers, &this)
+        }
+    }
+
+    fn header_map(headers: &Headers) -> Map {
+        let mut map = Map::new();
+        for (name, value) in headers.iter() {
+            let name = name.to_ascii_lowercase();
+            let value = match map.remove(name.as_str()) {
+                Some(prev) => format!("{prev}, {value}"),
+                None => value.to_owned(),
+            };
+            map.insert(name.into(), value.into());
+        }
+        map
+    }
+
+    fn request_map(req: &Request) -> Map {
+        let mut map = Map::new();
+        map.insert("method".into(), req.method.clone().into());
+        map.insert("path".into(), req.path.clone().into());
+        let query = req.query.clone().map_or(Dynamic::UNIT, Dynamic::from);
+        map.insert("query".into(), query);
+        map.insert("peer".into(), req.peer.ip().to_string().into());
+        map.insert("headers".into(), header_map(&req.headers).into());
+        map
+    }
+
+    /// Brings `headers` in line with `this.headers`.
+    fn apply_headers(headers: &mut Headers, this: &Map) -> Result<()> {
+        let Some(map) = this.get("headers") else {
+            return Ok(());
+        };
+        let map = map
+            .read_lock::<Map>()
+            .ok_or_else(|| anyhow!("`headers` must be a map"))?;
+        // Untouched entries are left alone, so repeated headers such as
+        // `Set-Cookie` survive.
+        let original = header_map(headers);
+        for name in original.keys() {
+            if !map.contains_key(name) {
+                headers.remove(name);
+            }
+        }
+        for (name, value) in map.iter() {
+            if value.is_unit() {
+                headers.remove(name);
+                continue;
+            }
+            let value = value.to_string();
+            if value.contains(['\r', '\n']) {
+                bail!("header `{name}` must be a single line");
+            }
+            if original.get(name).is_none_or(|v| v.to_string() != value) {
+                headers.set(name.as_str(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rhai"))]
+mod tests {
+    use crate::test::TestServer;
+
+    #[test]
+    fn rewritten_paths_are_held_to_the_path_rules() {
+        let scripts = TestServer::new().unwrap();
+        scripts
+            .write(
+                "hooks.rhai",
+                r#"fn on_request() {
+                    if this.path == "/old" { this.path = "//open/./x"; }
+                    if this.path == "/sneaky" { this.path = "//secret/./x"; }
+                }"#,
+            )
+            .unwrap();
+        let hooks = scripts.root().unwrap().join("hooks.rhai");
+        let server = TestServer::with(|b| {
+            b.option("hooks", hooks.to_str().unwrap())
+                .option("policy", "/secret/**=deny")
+        })
+        .unwrap();
+        server.write("open/x", "shown").unwrap();
+        server.write("secret/x", "hidden").unwrap();
+
+        let moved = server.get("/old").unwrap();
+        assert_eq!(moved.status, 200);
+        assert_eq!(moved.text(), "shown");
+        assert_eq!(server.get("/sneaky").unwrap().status, 403);
+    }
+}
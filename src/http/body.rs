@@ -0,0 +1,229 @@
+//! Message body framing: Content-Length and chunked transfer coding.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+use super::request::{MAX_HEAD_BYTES, MAX_HEADERS};
+
+/// How the remaining bytes of a request body are delimited on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// No body, or the body has been read completely.
+    Done,
+    /// This many bytes remain.
+    Length(u64),
+    /// Chunked; `remaining` bytes are left in the current chunk.
+    Chunked { remaining: u64 },
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// The trailer section of a chunked body went past the limits of a request
+/// head; see [`trailers_too_large`].
+#[derive(Debug)]
+struct TrailersTooLarge;
+
+impl fmt::Display for TrailersTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("trailer section too large")
+    }
+}
+
+impl Error for TrailersTooLarge {}
+
+/// Whether reading a body failed on a trailer section with more fields or
+/// bytes than a request head may have, which deserves a `431`.
+pub fn trailers_too_large(e: &io::Error) -> bool {
+    e.get_ref()
+        .is_some_and(|inner| inner.is::<TrailersTooLarge>())
+}
+
+/// Reads and drops the trailer section, held to [`MAX_HEADERS`] fields and
+/// [`MAX_HEAD_BYTES`] in all.
+fn skip_trailers(src: &mut impl BufRead) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidData, TrailersTooLarge);
+    let mut budget = MAX_HEAD_BYTES;
+    let mut line = Vec::new();
+    // The fields, then the empty line ending them.
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        let n = Read::take(&mut *src, budget as u64 + 1).read_until(b'\n', &mut line)?;
+        if n > budget {
+            return Err(too_large());
+        }
+        budget -= n;
+        if !line.ends_with(b"\n") {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let content = line.strip_suffix(b"\r\n").unwrap_or(&line[..n - 1]);
+        if content.contains(&b'\r') {
+            return Err(invalid("bare CR in trailer field"));
+        }
+        if content.is_empty() {
+            return Ok(());
+        }
+    }
+    Err(too_large())
+}
+
+fn read_line(src: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if Read::take(&mut *src, 4096).read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid("chunk line too long"));
+    }
+    line.pop();
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    if line.contains('\r') {
+        return Err(invalid("bare CR in chunk line"));
+    }
+    Ok(line)
+}
+
+impl Framing {
+    /// Reads decoded body bytes from `src` into `buf`, returning 0 at the end.
+    pub fn read(&mut self, src: &mut impl BufRead, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match *self {
+                Framing::Done => return Ok(0),
+                Framing::Length(0) => *self = Framing::Done,
+                Framing::Length(left) => {
+                    let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                    let n = src.read(&mut buf[..max])?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *self = Framing::Length(left - n as u64);
+                    return Ok(n);
+                }
+                Framing::Chunked { remaining: 0 } => {
+                    let line = read_line(src)?;
+                    let size = line.split(';').next().unwrap_or_default();
+                    let size = size.trim_end_matches([' ', '\t']);
+                    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(invalid("bad chunk size"));
+                    }
+                    let size =
+                        u64::from_str_radix(size, 16).map_err(|_| invalid("bad chunk size"))?;
+                    if size == 0 {
+                        skip_trailers(src)?;
+                        *self = Framing::Done;
+                    } else {
+                        *self = Framing::Chunked { remaining: size };
+                    }
+                }
+                Framing::Chunked { remaining } => {
+                    let max = buf
+                        .len()
+                        .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                    let n = src.read(&mut buf[..max])?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let remaining = remaining - n as u64;
+                    if remaining == 0 && !read_line(src)?.is_empty() {
+                        return Err(invalid("missing CRLF after chunk"));
+                    }
+                    // A zero here means "read the next size line".
+                    *self = Framing::Chunked { remaining };
+                    return Ok(n);
+                }
+            }
+        }
+    }
+
+    /// Discards up to `limit` body bytes. Returns false if the body was longer
+    /// or malformed, in which case the connection cannot be reused.
+    pub fn drain(&mut self, src: &mut impl BufRead, limit: u64) -> bool {
+        let mut buf = [0u8; 8192];
+        let mut total = 0u64;
+        loop {
+            match self.read(src, &mut buf) {
+                Ok(0) => return true,
+                Ok(n) => total += n as u64,
+                Err(_) => return false,
+            }
+            if total > limit {
+                return false;
+            }
+        }
+    }
+}
+
+/// Writes each `write` call as one chunk; [`ChunkedWriter::finish`] sends the
+/// terminating chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_chunked(wire: &[u8]) -> io::Result<Vec<u8>> {
+        let mut framing = Framing::Chunked { remaining: 0 };
+        let mut src = wire;
+        let mut body = Vec::new();
+        let mut buf = [0u8; 16];
+        loop {
+            match framing.read(&mut src, &mut buf)? {
+                0 => return Ok(body),
+                n => body.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn trailers_are_held_to_the_head_limits() {
+        let body = |trailers: &str| format!("5\r\nhello\r\n0\r\n{trailers}\r\n").into_bytes();
+        let fields = |n: usize| "X-Sum: 1\r\n".repeat(n);
+
+        assert_eq!(read_chunked(&body(&fields(MAX_HEADERS))).unwrap(), b"hello");
+        let err = read_chunked(&body(&fields(MAX_HEADERS + 1))).unwrap_err();
+        assert!(trailers_too_large(&err), "{err}");
+        let long = format!("X-Pad: {}\r\n", "a".repeat(MAX_HEAD_BYTES));
+        let err = read_chunked(&body(&long)).unwrap_err();
+        assert!(trailers_too_large(&err), "{err}");
+
+        let err = read_chunked(&body("X-Sum: 1\rX\r\n")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!trailers_too_large(&err));
+        let cut = read_chunked(b"5\r\nhello\r\n0\r\nX-Sum: 1\r\n").unwrap_err();
+        assert_eq!(cut.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,44 @@
+//! Outgoing connections, to origins and auth services.
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Connects to `addr` (`host:port`), trying each address it resolves to in
+/// turn. Each attempt, and every read and write on the connection, gives
+/// up after `timeout`, so an unreachable peer can't hold a worker for the
+/// system's connect timeout.
+pub fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{addr} has no addresses"))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn sets_timeouts_on_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let stream = connect(&addr, Duration::from_secs(3)).unwrap();
+        assert_eq!(stream.read_timeout().unwrap(), Some(Duration::from_secs(3)));
+
+        drop(listener);
+        assert!(connect(&addr, Duration::from_secs(3)).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Read-only memory maps for serving very large files.
+//!
+//! A mapped file that shrinks while mapped faults on access (`SIGBUS`),
+//! which ends the whole process. [`Mmap::map`] therefore refuses network
+//! filesystems, where that is more likely, files whose length no longer
+//! matches what the caller saw, and every non-Unix platform, with
+//! `Unsupported` so callers fall back to reading. Readers go through
+//! [`Mmap::read`], which checks the file's length again before each chunk.
+//! That narrows the risk to a file truncated in place in the moment
+//! between a check and its chunk being copied; files that are replaced by
+//! renaming a new version over them are always safe.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+
+/// Bytes of a mapping read between checks of the file's length.
+const CHUNK: usize = 1 << 20;
+
+/// A whole file mapped read-only.
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+    /// The mapped file, to check that it has not shrunk.
+    file: File,
+}
+
+// SAFETY: the mapping is read-only and owned exclusively by this value.
+unsafe impl Send for Mmap {}
+// SAFETY: as above; shared access never writes.
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps `file`, which should be `len` bytes long, and advises sequential
+    /// access.
+    /// Fails with `Unsupported` if the open file is not `len` bytes long.
+    #[cfg(unix)]
+    pub fn map(file: &File, len: u64) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        if file.metadata()?.len() != len {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        let len = usize::try_from(len).map_err(|_| io::ErrorKind::Unsupported)?;
+        if len == 0 || !local_filesystem(file) {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        let file = file.try_clone()?;
+        // SAFETY: a fresh shared read-only mapping of an open descriptor; the
+        // result is checked before use.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let map = Self {
+            ptr: ptr.cast(),
+            len,
+            file,
+        };
+        map.advise(0, len, libc::MADV_SEQUENTIAL);
+        Ok(map)
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File, _len: u64) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Passes `len` bytes from `offset` to `f` a chunk at a time, checking
+    /// before each chunk that the file still covers it.
+    pub fn read(
+        &self,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let end = offset + len;
+        let mut at = offset;
+        while at < end {
+            let next = (at + CHUNK).min(end);
+            if self.file.metadata()?.len() < next as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file shrank while being sent",
+                ));
+            }
+            f(&self[at..next])?;
+            at = next;
+        }
+        Ok(())
+    }
+
+    /// Hints that `len` bytes from `offset` will be read soon.
+    pub fn will_need(&self, offset: usize, len: usize) {
+        #[cfg(unix)]
+        self.advise(offset, len, libc::MADV_WILLNEED);
+        #[cfg(not(unix))]
+        let _ = (offset, len);
+    }
+
+    #[cfg(unix)]
+    fn advise(&self, offset: usize, len: usize, advice: libc::c_int) {
+        // madvise wants a page-aligned start.
+        // SAFETY: sysconf has no preconditions.
+        let page = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).unwrap_or(4096);
+        let start = offset - offset % page;
+        let end = (offset + len).min(self.len);
+        // SAFETY: the range lies within the mapping; advice never changes
+        // its contents. Failures are harmless and ignored.
+        unsafe {
+            libc::madvise(self.ptr.add(start).cast(), end - start, advice);
+        }
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` maps `len` readable bytes for the life of `self`.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        // SAFETY: unmaps exactly the region mapped in `map`.
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// Whether `file` lives on a filesystem where mapping is considered safe.
+#[cfg(target_os = "linux")]
+fn local_filesystem(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+
+    const NFS: i64 = 0x6969;
+    const SMB: i64 = 0x517b;
+    const SMB2: i64 = 0xfe53_4d42;
+    const CIFS: i64 = 0xff53_4d42;
+    const FUSE: i64 = 0x6573_5546;
+    const AFS: i64 = 0x5346_414f;
+
+    // SAFETY: `buf` is a valid out-pointer for fstatfs.
+    let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut buf) } != 0 {
+        return false;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let kind = buf.f_type as i64;
+    ![NFS, SMB, SMB2, CIFS, FUSE, AFS].contains(&kind)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn local_filesystem(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: `buf` is a valid out-pointer for fstatfs.
+    let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut buf) } != 0 {
+        return false;
+    }
+    (buf.f_flags as u64 & libc::MNT_LOCAL as u64) != 0
+}
+
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))
+))]
+fn local_filesystem(_file: &File) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_file(name: &str, data: &[u8]) -> (PathBuf, File) {
+        let path =
+            std::env::temp_dir().join(format!("tinyserve-mmap-{name}-{}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+        (path, file)
+    }
+
+    fn collect(map: &Mmap, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        map.read(offset, len, |chunk| {
+            out.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(out)
+    }
+
+    #[test]
+    fn reads_ranges_across_chunks() {
+        let data: Vec<u8> = (0..CHUNK * 2 + 5).map(|i| (i % 251) as u8).collect();
+        let (path, file) = temp_file("ranges", &data);
+        let map = Mmap::map(&file, data.len() as u64).unwrap();
+        assert_eq!(collect(&map, 0, data.len()).unwrap(), data);
+        assert_eq!(collect(&map, 7, CHUNK + 3).unwrap(), &data[7..CHUNK + 10]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_length_the_file_no_longer_has() {
+        let (path, file) = temp_file("length", b"0123456789");
+        let err = Mmap::map(&file, 20).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stops_when_the_file_shrinks() {
+        let data = vec![1; CHUNK * 2];
+        let (path, file) = temp_file("shrink", &data);
+        let map = Mmap::map(&file, data.len() as u64).unwrap();
+        file.set_len(CHUNK as u64).unwrap();
+        let err = collect(&map, 0, data.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,18 @@
+//! Minimal HTTP/1.1 building blocks.
+
+pub mod body;
+pub mod client;
+pub mod date;
+pub mod headers;
+pub mod mime;
+pub mod mmap;
+pub mod multipart;
+pub mod range;
+pub mod request;
+pub mod response;
+pub mod sendfile;
+pub mod sse;
+
+pub use headers::Headers;
+pub use request::{ParseError, Request, RequestBody, Version};
+pub use response::{Body, Response};
@@ -0,0 +1,176 @@
+//! Streaming `multipart/form-data` reader.
+
+use std::io::{self, Read, Write};
+
+use super::headers::Headers;
+
+const BUF_SIZE: usize = 64 * 1024;
+/// Upper bound on the header block of a single part.
+const MAX_PART_HEAD: usize = 16 * 1024;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Extracts the boundary parameter from a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let essence = params.next()?.trim();
+    if !essence.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|p| {
+        let (name, value) = p.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_owned())
+            .filter(|b| !b.is_empty() && b.len() <= 70)
+    })
+}
+
+/// The head of one part.
+#[derive(Debug)]
+pub struct Part {
+    pub headers: Headers,
+    /// `name` from Content-Disposition.
+    pub name: Option<String>,
+    /// `filename` from Content-Disposition; present for file fields.
+    pub filename: Option<String>,
+}
+
+/// Reads parts one after another; each part's content must be consumed with
+/// [`Multipart::copy_part`] (or skipped by calling `next_part` again).
+pub struct Multipart<R> {
+    reader: R,
+    buf: Vec<u8>,
+    /// `\r\n--boundary`.
+    delimiter: Vec<u8>,
+    in_part: bool,
+    done: bool,
+    started: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(BUF_SIZE),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            in_part: false,
+            done: false,
+            started: false,
+        }
+    }
+
+    /// Reads more input; returns false at end of input.
+    fn fill(&mut self) -> io::Result<bool> {
+        let start = self.buf.len();
+        self.buf.resize(start + BUF_SIZE, 0);
+        let n = self.reader.read(&mut self.buf[start..])?;
+        self.buf.truncate(start + n);
+        Ok(n > 0)
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buf.windows(needle.len()).position(|w| w == needle)
+    }
+
+    /// Advances to the next part, skipping any unread content of the current one.
+    pub fn next_part(&mut self) -> io::Result<Option<Part>> {
+        if self.in_part {
+            self.copy_part(&mut io::sink(), u64::MAX)?;
+        }
+        if self.done {
+            return Ok(None);
+        }
+        if !self.started {
+            // The first delimiter has no leading CRLF.
+            self.buf.extend_from_slice(b"\r\n");
+            self.started = true;
+        }
+        // Expect a delimiter, then either `--` (end) or CRLF and part headers.
+        while self.buf.len() < self.delimiter.len() + 2 {
+            if !self.fill()? {
+                return Err(invalid("truncated multipart body"));
+            }
+        }
+        if !self.buf.starts_with(&self.delimiter) {
+            return Err(invalid("missing multipart boundary"));
+        }
+        let after = self.delimiter.len();
+        if &self.buf[after..after + 2] == b"--" {
+            self.done = true;
+            return Ok(None);
+        }
+        if &self.buf[after..after + 2] != b"\r\n" {
+            return Err(invalid("malformed multipart boundary"));
+        }
+        self.buf.drain(..after + 2);
+
+        let head_end = loop {
+            if let Some(i) = self.find(b"\r\n\r\n") {
+                break i;
+            }
+            if self.buf.len() > MAX_PART_HEAD {
+                return Err(invalid("multipart headers too large"));
+            }
+            if !self.fill()? {
+                return Err(invalid("truncated multipart headers"));
+            }
+        };
+        let head = String::from_utf8_lossy(&self.buf[..head_end]).into_owned();
+        self.buf.drain(..head_end + 4);
+
+        let mut headers = Headers::new();
+        for line in head.split("\r\n") {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.append(name.trim(), value.trim());
+            }
+        }
+        let disposition = headers.get("content-disposition").unwrap_or_default();
+        let name = disposition_param(disposition, "name");
+        let filename = disposition_param(disposition, "filename");
+        self.in_part = true;
+        Ok(Some(Part {
+            headers,
+            name,
+            filename,
+        }))
+    }
+
+    /// Copies the current part's content to `out`. Fails with
+    /// `ErrorKind::FileTooLarge` once more than `limit` bytes have been seen.
+    pub fn copy_part(&mut self, out: &mut dyn Write, limit: u64) -> io::Result<u64> {
+        let mut total = 0u64;
+        let keep = self.delimiter.len() - 1;
+        loop {
+            let (chunk, finished) = match self.find(&self.delimiter) {
+                Some(i) => (i, true),
+                None => (self.buf.len().saturating_sub(keep), false),
+            };
+            total += chunk as u64;
+            if total > limit {
+                return Err(io::ErrorKind::FileTooLarge.into());
+            }
+            out.write_all(&self.buf[..chunk])?;
+            self.buf.drain(..chunk);
+            if finished {
+                self.in_part = false;
+                return Ok(total);
+            }
+            if !self.fill()? {
+                return Err(invalid("truncated multipart body"));
+            }
+        }
+    }
+}
+
+/// A parameter of a Content-Disposition value, e.g. `filename="a.txt"`.
+fn disposition_param(value: &str, param: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|p| {
+        let (name, v) = p.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case(param)
+            .then(|| v.trim().trim_matches('"').to_owned())
+    })
+}
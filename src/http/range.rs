@@ -0,0 +1,301 @@
+//! `Range: bytes=...` parsing and `multipart/byteranges` bodies.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Range sets with more specs than this are answered with the full
+/// representation instead.
+pub const MAX_RANGES: usize = 64;
+
+/// Ranges separated by fewer bytes than this are merged, since a part
+/// header costs about as much as the gap would.
+const COALESCE_GAP: u64 = 80;
+
+/// An inclusive byte range within a representation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// `Content-Range` value for this range of a `total`-byte representation.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable Range header: send the whole representation.
+    Full,
+    Partial(Vec<ByteRange>),
+    /// Every range lies outside the representation (416).
+    Unsatisfiable,
+}
+
+/// Interprets a Range header against a representation of `len` bytes.
+/// Syntactically invalid headers are ignored, as RFC 9110 §14.2 permits, and
+/// so are sets of more than [`MAX_RANGES`] specs. Overlapping and nearly
+/// adjacent ranges are merged, in ascending order.
+pub fn parse(header: &str, len: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = match (first.trim(), last.trim()) {
+            ("", "") => return RangeRequest::Full,
+            ("", suffix) => {
+                let Ok(n) = suffix.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                if n == 0 || len == 0 {
+                    continue;
+                }
+                ByteRange {
+                    start: len.saturating_sub(n),
+                    end: len - 1,
+                }
+            }
+            (first, last) => {
+                let Ok(start) = first.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match last {
+                    "" => u64::MAX,
+                    last => match last.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                if start >= len {
+                    continue;
+                }
+                ByteRange {
+                    start,
+                    end: end.min(len - 1),
+                }
+            }
+        };
+        ranges.push(range);
+    }
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(coalesce(ranges))
+    }
+}
+
+/// Sorts `ranges` and merges those that overlap or nearly touch.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end.saturating_add(COALESCE_GAP) => {
+                last.end = last.end.max(r.end);
+            }
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+/// Anything ranges can be read from.
+trait Source: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Source for T {}
+
+/// A `multipart/byteranges` body carrying several ranges of one file.
+pub struct Byteranges {
+    file: Box<dyn Source>,
+    boundary: String,
+    /// Head of each part, paired with its range.
+    parts: Vec<(String, ByteRange)>,
+}
+
+impl Byteranges {
+    /// `content_type` is that of the file; `total` its length.
+    pub fn new(
+        file: impl Read + Seek + Send + 'static,
+        ranges: &[ByteRange],
+        content_type: &str,
+        total: u64,
+    ) -> Self {
+        static SEQ: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let boundary = format!(
+            "tinyserve-{:08x}{:08x}",
+            nanos,
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        );
+        let parts = ranges
+            .iter()
+            .map(|r| {
+                let head = format!(
+                    "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+                    r.content_range(total)
+                );
+                (head, *r)
+            })
+            .collect();
+        Self {
+            file: Box::new(file),
+            boundary,
+            parts,
+        }
+    }
+
+    /// Value for the response's `Content-Type`.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    fn trailer(&self) -> String {
+        format!("\r\n--{}--\r\n", self.boundary)
+    }
+
+    pub fn len(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(head, r)| head.len() as u64 + r.len())
+            .sum();
+        parts + self.trailer().len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Writes the whole body, returning the number of bytes sent.
+    pub fn write_to(mut self, w: &mut dyn Write) -> io::Result<u64> {
+        let mut bytes = 0;
+        for (head, r) in &self.parts {
+            w.write_all(head.as_bytes())?;
+            self.file.seek(SeekFrom::Start(r.start))?;
+            let n = io::copy(&mut (&mut self.file).take(r.len()), w)?;
+            if n < r.len() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            bytes += head.len() as u64 + n;
+        }
+        let trailer = self.trailer();
+        w.write_all(trailer.as_bytes())?;
+        Ok(bytes + trailer.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(
+            ranges
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse("bytes=0-9", 100), partial(&[(0, 9)]));
+        assert_eq!(parse("bytes=90-", 100), partial(&[(90, 99)]));
+        assert_eq!(parse("bytes=-10", 100), partial(&[(90, 99)]));
+        assert_eq!(parse("bytes=-500", 100), partial(&[(0, 99)]));
+        assert_eq!(parse("bytes=50-500", 100), partial(&[(50, 99)]));
+        assert_eq!(parse(" bytes= 5 - 6 ", 100), partial(&[(5, 6)]));
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        for header in [
+            "",
+            "items=0-1",
+            "bytes=",
+            "bytes=-",
+            "bytes=5",
+            "bytes=a-b",
+            "bytes=9-5",
+            "bytes=0-1,x",
+        ] {
+            assert_eq!(parse(header, 100), RangeRequest::Full, "{header}");
+        }
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(&many, 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn reports_unsatisfiable_sets() {
+        assert_eq!(parse("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=200-300,0-0", 100), partial(&[(0, 0)]));
+    }
+
+    #[test]
+    fn sorts_and_merges_ranges() {
+        assert_eq!(
+            parse("bytes=500-599,0-9,5-20", 1000),
+            partial(&[(0, 20), (500, 599)])
+        );
+        // A gap under COALESCE_GAP is cheaper to send than a part header.
+        assert_eq!(parse("bytes=0-9,50-59", 1000), partial(&[(0, 59)]));
+        assert_eq!(
+            parse("bytes=0-9,200-209", 1000),
+            partial(&[(0, 9), (200, 209)])
+        );
+    }
+
+    #[test]
+    fn writes_multipart_byteranges() {
+        let data: Vec<u8> = (b'a'..=b'z').collect();
+        let ranges = [
+            ByteRange { start: 0, end: 1 },
+            ByteRange { start: 24, end: 25 },
+        ];
+        let body = Byteranges::new(Cursor::new(data), &ranges, "text/plain", 26);
+        let boundary = body
+            .content_type()
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_owned();
+        let len = body.len();
+        let mut out = Vec::new();
+        assert_eq!(body.write_to(&mut out).unwrap(), len);
+        assert_eq!(out.len() as u64, len);
+        let expected = format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/26\r\n\r\nab\
+             \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 24-25/26\r\n\r\nyz\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
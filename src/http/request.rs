@@ -0,0 +1,501 @@
+//! Request head parsing and the request type handed to handlers.
+//!
+//! The parser is strict where leniency lets two parties disagree about
+//! where a request ends (RFC 9112 §11.2): a bare CR, obsolete line folding,
+//! both `Content-Length` and `Transfer-Encoding`, conflicting lengths and
+//! transfer codings other than a final `chunked` are rejected with `400`,
+//! and the connection is closed after answering. The `fuzz` directory has a
+//! target that feeds arbitrary bytes through [`Request::read_head`] and the
+//! body framing.
+
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use super::body::Framing;
+use super::headers::Headers;
+
+/// Upper bound on the request line plus all header fields.
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+/// Upper bound on the number of header fields.
+pub const MAX_HEADERS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        })
+    }
+}
+
+/// A request that could not be parsed, with the status to answer it with.
+#[derive(Debug)]
+pub struct ParseError {
+    pub status: u16,
+    pub reason: &'static str,
+}
+
+impl ParseError {
+    fn new(status: u16, reason: &'static str) -> Self {
+        Self { status, reason }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.reason, self.status)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Streaming request body. Reads return 0 once the body is exhausted.
+pub struct RequestBody(Box<dyn Read + Send>);
+
+impl RequestBody {
+    pub fn empty() -> Self {
+        Self(Box::new(io::empty()))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(Box::new(io::Cursor::new(bytes)))
+    }
+
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        Self(Box::new(reader))
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RequestBody")
+    }
+}
+
+/// A parsed request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// The request-target exactly as received.
+    pub target: String,
+    /// Percent-decoded path component of the target, without empty or `.`
+    /// segments (see [`normalize_path`]). Bytes that are not UTF-8 are
+    /// replaced with U+FFFD; see [`path_is_utf8`](Self::path_is_utf8).
+    pub path: String,
+    /// Raw query string, without the `?`.
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub peer: SocketAddr,
+    /// The server address the request arrived on.
+    pub local: SocketAddr,
+    pub body: RequestBody,
+}
+
+impl Request {
+    /// Builds a request without a connection, e.g. for internal dispatch.
+    pub fn new(method: &str, target: &str) -> Result<Self, ParseError> {
+        let (path, query) = split_target(target)?;
+        Ok(Self {
+            method: method.to_owned(),
+            target: target.to_owned(),
+            path,
+            query,
+            version: Version::Http11,
+            headers: Headers::new(),
+            peer: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            local: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            body: RequestBody::empty(),
+        })
+    }
+
+    /// Reads a request head. Returns `Ok(None)` if the peer closed the
+    /// connection before sending anything.
+    pub fn read_head(src: &mut impl BufRead, peer: SocketAddr) -> Result<Option<Self>, ParseError> {
+        let mut budget = MAX_HEAD_BYTES;
+        let mut line = String::new();
+        // Tolerate empty lines before the request line (RFC 9112 §2.2).
+        loop {
+            line.clear();
+            match read_line(src, &mut line, &mut budget)? {
+                0 => return Ok(None),
+                _ if line.is_empty() => continue,
+                _ => break,
+            }
+        }
+
+        let mut parts = line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::new(400, "malformed request line"));
+        };
+        if method.is_empty() || !method.bytes().all(is_tchar) {
+            return Err(ParseError::new(400, "invalid method"));
+        }
+        if target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+            return Err(ParseError::new(400, "malformed request line"));
+        }
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => {
+                return Err(ParseError::new(505, "unsupported HTTP version"));
+            }
+            _ => return Err(ParseError::new(400, "malformed request line")),
+        };
+        let mut req = Self::new(method, target)?;
+        req.version = version;
+        req.peer = peer;
+
+        loop {
+            line.clear();
+            if read_line(src, &mut line, &mut budget)? == 0 {
+                return Err(ParseError::new(400, "unexpected end of headers"));
+            }
+            if line.is_empty() {
+                break;
+            }
+            if req.headers.len() == MAX_HEADERS {
+                return Err(ParseError::new(431, "too many header fields"));
+            }
+            if line.starts_with([' ', '\t']) {
+                return Err(ParseError::new(400, "obsolete line folding"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseError::new(400, "malformed header field"))?;
+            if name.is_empty() || !name.bytes().all(is_tchar) {
+                return Err(ParseError::new(400, "malformed header field"));
+            }
+            let value = value.trim_matches([' ', '\t']);
+            if value.bytes().any(|b| b == 0 || b == b'\r') {
+                return Err(ParseError::new(400, "invalid header field value"));
+            }
+            req.headers.append(name, value);
+        }
+        if req.headers.get_all("host").count() > 1 {
+            return Err(ParseError::new(400, "more than one Host field"));
+        }
+        Ok(Some(req))
+    }
+
+    /// How the body following the head is delimited.
+    pub fn framing(&self) -> Result<Framing, ParseError> {
+        let has_length = self.headers.contains("content-length");
+        if self.headers.contains("transfer-encoding") {
+            if has_length {
+                return Err(ParseError::new(
+                    400,
+                    "both Content-Length and Transfer-Encoding",
+                ));
+            }
+            if self.version == Version::Http10 {
+                return Err(ParseError::new(400, "Transfer-Encoding in HTTP/1.0"));
+            }
+            let codings: Vec<&str> = self.headers.tokens("transfer-encoding").collect();
+            return match codings.as_slice() {
+                [coding] if coding.eq_ignore_ascii_case("chunked") => {
+                    Ok(Framing::Chunked { remaining: 0 })
+                }
+                [.., last] if last.eq_ignore_ascii_case("chunked") => {
+                    Err(ParseError::new(501, "unsupported transfer coding"))
+                }
+                // Without a final `chunked` the body has no length at all.
+                _ => Err(ParseError::new(400, "invalid Transfer-Encoding")),
+            };
+        }
+        if !has_length {
+            return Ok(Framing::Done);
+        }
+        // Repeated fields, or a list, are accepted only when they agree.
+        let mut lengths = self
+            .headers
+            .get_all("content-length")
+            .flat_map(|v| v.split(','));
+        let invalid = || ParseError::new(400, "invalid Content-Length");
+        let first = lengths.next().map(str::trim).ok_or_else(invalid)?;
+        if first.is_empty()
+            || !first.bytes().all(|b| b.is_ascii_digit())
+            || lengths.any(|v| v.trim() != first)
+        {
+            return Err(invalid());
+        }
+        first.parse().map(Framing::Length).map_err(|_| invalid())
+    }
+
+    /// Whether the client asked for, or defaults to, a persistent connection.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("connection", "close"),
+            Version::Http10 => self.headers.has_token("connection", "keep-alive"),
+        }
+    }
+
+    /// Whether the target's path decodes to UTF-8 as sent, i.e. `path` had
+    /// nothing replaced.
+    pub fn path_is_utf8(&self) -> bool {
+        let raw = self.target.split('?').next().unwrap_or_default();
+        percent_decode(raw).is_some_and(|bytes| std::str::from_utf8(&bytes).is_ok())
+    }
+
+    pub fn is_head(&self) -> bool {
+        self.method == "HEAD"
+    }
+
+    /// Value of `name` in the query string, percent-decoded. A bare `name`
+    /// yields an empty string.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.as_deref()?.split('&').find_map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (k == name).then(|| {
+                let v = v.replace('+', " ");
+                String::from_utf8_lossy(&percent_decode(&v).unwrap_or_else(|| v.into_bytes()))
+                    .into_owned()
+            })
+        })
+    }
+}
+
+fn read_line(
+    src: &mut impl BufRead,
+    line: &mut String,
+    budget: &mut usize,
+) -> Result<usize, ParseError> {
+    let mut raw = Vec::new();
+    let n = src
+        .by_ref()
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut raw)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                ParseError::new(408, "request timeout")
+            }
+            _ => ParseError::new(400, "read error"),
+        })?;
+    if n > *budget {
+        return Err(ParseError::new(431, "request head too large"));
+    }
+    *budget -= n;
+    if n > 0 && !raw.ends_with(b"\n") {
+        return Err(ParseError::new(400, "unexpected end of request head"));
+    }
+    // CRLF, or a bare LF (RFC 9112 §2.2); a CR anywhere else is an error.
+    raw.pop();
+    if raw.last() == Some(&b'\r') {
+        raw.pop();
+    }
+    if raw.contains(&b'\r') {
+        return Err(ParseError::new(400, "bare CR in request head"));
+    }
+    *line = String::from_utf8(raw).map_err(|_| ParseError::new(400, "non-UTF-8 request head"))?;
+    Ok(n)
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn split_target(target: &str) -> Result<(String, Option<String>), ParseError> {
+    let (raw_path, query) = match target.split_once('?') {
+        Some((p, q)) => (p, Some(q.to_owned())),
+        None => (target, None),
+    };
+    // Absolute-form targets (`http://host/path`) are reduced to their path;
+    // a `://` inside an origin-form path is just part of it.
+    let raw_path = match raw_path.split_once("://") {
+        Some((_, rest)) if !raw_path.starts_with('/') => rest.find('/').map_or("/", |i| &rest[i..]),
+        _ => raw_path,
+    };
+    if !raw_path.starts_with('/') && raw_path != "*" {
+        return Err(ParseError::new(400, "invalid request target"));
+    }
+    let bytes = percent_decode(raw_path).ok_or(ParseError::new(400, "invalid percent-encoding"))?;
+    let path = String::from_utf8(bytes)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+    Ok((normalize_path(&path), query))
+}
+
+/// Drops the empty and `.` segments of a decoded path, which name the same
+/// file as the path without them: `//a/./b` is `/a/b`. A trailing slash is
+/// kept, and `..` is left for the file lookup to refuse. Path rules
+/// (`policy`, `schedule`, ...) see this form, so spelling a path another
+/// way cannot get around them.
+pub fn normalize_path(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_owned();
+    }
+    let mut out = String::with_capacity(path.len());
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        out.push('/');
+        out.push_str(segment);
+    }
+    if out.is_empty() || path.ends_with('/') || path.ends_with("/.") {
+        out.push('/');
+    }
+    out
+}
+
+/// Decodes `%XX` escapes. Returns `None` on a malformed escape.
+pub fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            out.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+/// Percent-encodes everything but unreserved characters and `/`.
+pub fn percent_encode_path(s: &str) -> String {
+    percent_encode(s, b"-._~/")
+}
+
+/// Percent-encodes all but unreserved characters, for a query value.
+pub fn percent_encode_query(s: &str) -> String {
+    percent_encode(s, b"-._~")
+}
+
+fn percent_encode(s: &str, keep: &[u8]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || keep.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_empty_and_dot_segments() {
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path("/."), "/");
+        assert_eq!(normalize_path("/./a//b/"), "/a/b/");
+        assert_eq!(normalize_path("/a/."), "/a/");
+        assert_eq!(normalize_path("/a/../b"), "/a/../b");
+        assert_eq!(normalize_path("*"), "*");
+    }
+
+    #[test]
+    fn decodes_then_normalizes_the_target() {
+        let req = Request::new("GET", "/%2e/a%2f%2fb?q=1").unwrap();
+        assert_eq!(req.path, "/a/b");
+        assert_eq!(req.query.as_deref(), Some("q=1"));
+        let req = Request::new("GET", "http://example.test//a/").unwrap();
+        assert_eq!(req.path, "/a/");
+    }
+
+    #[test]
+    fn only_absolute_form_loses_its_authority() {
+        let req = Request::new("GET", "/a://b/c").unwrap();
+        assert_eq!(req.path, "/a:/b/c");
+        let req = Request::new("GET", "/s.txt?next=http://example.test/x").unwrap();
+        assert_eq!(req.path, "/s.txt");
+        let req = Request::new("GET", "http://example.test").unwrap();
+        assert_eq!(req.path, "/");
+        assert!(Request::new("GET", "example.test/a").is_err());
+    }
+
+    /// Parses `head` and its body framing, as a connection would.
+    fn parse(head: &str) -> Result<Framing, ParseError> {
+        let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let req = Request::read_head(&mut head.as_bytes(), peer)?.expect("a request");
+        req.framing()
+    }
+
+    fn rejection(head: &str) -> (u16, &'static str) {
+        let e = parse(head).expect_err("rejected");
+        (e.status, e.reason)
+    }
+
+    #[test]
+    fn accepts_plain_framing() {
+        let get = "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert_eq!(parse(get).unwrap(), Framing::Done);
+        let put = "PUT /a HTTP/1.1\nHost: a\nContent-Length: 5, 5\n\n";
+        assert_eq!(parse(put).unwrap(), Framing::Length(5));
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(parse(chunked).unwrap(), Framing::Chunked { remaining: 0 });
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        let head = "POST / HTTP/1.1\r\nContent-Length: 3\r\n\
+                    Transfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(
+            rejection(head),
+            (400, "both Content-Length and Transfer-Encoding")
+        );
+    }
+
+    #[test]
+    fn rejects_obsolete_line_folding() {
+        let head = "GET / HTTP/1.1\r\nX-A: 1\r\n 2\r\n\r\n";
+        assert_eq!(rejection(head), (400, "obsolete line folding"));
+        let head = "GET / HTTP/1.1\r\nX-A: 1\r\n\t2\r\n\r\n";
+        assert_eq!(rejection(head), (400, "obsolete line folding"));
+    }
+
+    #[test]
+    fn rejects_a_bare_cr() {
+        let head = "GET / HTTP/1.1\r\nX-A: 1\rX-B: 2\r\n\r\n";
+        assert_eq!(rejection(head), (400, "bare CR in request head"));
+        let head = "GET /\r HTTP/1.1\r\n\r\n";
+        assert_eq!(rejection(head), (400, "bare CR in request head"));
+    }
+
+    #[test]
+    fn rejects_a_duplicate_host() {
+        let head = "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n";
+        assert_eq!(rejection(head), (400, "more than one Host field"));
+    }
+
+    #[test]
+    fn rejects_transfer_encoding_in_http_1_0() {
+        let head = "POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(rejection(head), (400, "Transfer-Encoding in HTTP/1.0"));
+    }
+
+    #[test]
+    fn rejects_unframed_and_conflicting_lengths() {
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n";
+        assert_eq!(rejection(head), (400, "invalid Transfer-Encoding"));
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert_eq!(rejection(head), (501, "unsupported transfer coding"));
+        let head = "POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n";
+        assert_eq!(rejection(head), (400, "invalid Content-Length"));
+        let head = "POST / HTTP/1.1\r\nContent-Length: +3\r\n\r\n";
+        assert_eq!(rejection(head), (400, "invalid Content-Length"));
+    }
+}
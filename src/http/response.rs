@@ -0,0 +1,532 @@
+//! Responses and their serialization.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde_json::Value;
+
+use super::body::ChunkedWriter;
+use super::date;
+use super::headers::Headers;
+use super::mmap::Mmap;
+use super::range::Byteranges;
+use super::request::Version;
+use super::sendfile;
+
+/// Product token sent in the `Server` header and on built-in error pages.
+pub const SERVER: &str = concat!("tinyserve/", env!("CARGO_PKG_VERSION"));
+
+/// How a server names itself and dates its responses. Each server keeps
+/// its own, from `serverHeader` and `deterministic`, and hands it to
+/// [`Response::write_to`] through [`WriteOptions`].
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    /// Replaces the default name; empty leaves it out.
+    name: Option<String>,
+    /// What `Date` is pinned to.
+    frozen: Option<SystemTime>,
+}
+
+impl Identity {
+    /// Sends `name` instead of the default in `Server` and page footers (an
+    /// empty name leaves both out), and with `frozen` pins `Date` to it and
+    /// leaves the version out of the default name, so responses come out
+    /// byte for byte the same.
+    pub fn new(name: Option<String>, frozen: Option<SystemTime>) -> Self {
+        Self { name, frozen }
+    }
+
+    /// The configured name, else [`SERVER`], or just `tinyserve` when
+    /// frozen.
+    pub fn name(&self) -> Cow<'_, str> {
+        match &self.name {
+            Some(name) => Cow::Borrowed(name),
+            None if self.frozen.is_some() => Cow::Borrowed("tinyserve"),
+            None => Cow::Borrowed(SERVER),
+        }
+    }
+
+    /// The `<hr><address>` footer of built-in pages, or nothing when the
+    /// name is suppressed.
+    pub fn signature(&self) -> String {
+        match self.name() {
+            name if name.is_empty() => String::new(),
+            name => format!("<hr><address>{}</address>", escape_html(&name)),
+        }
+    }
+
+    /// The time for `Date`: now, or the frozen one.
+    pub fn now(&self) -> SystemTime {
+        self.frozen.unwrap_or_else(SystemTime::now)
+    }
+}
+
+/// A body produced incrementally by writing to the connection.
+pub type StreamFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    /// Bytes shared with a cache.
+    Shared(Arc<[u8]>),
+    /// Bytes compiled into the binary.
+    Static(&'static [u8]),
+    /// `len` bytes of `file` starting at `offset`.
+    File {
+        file: File,
+        offset: u64,
+        len: u64,
+    },
+    /// `len` bytes of a mapped file starting at `offset`.
+    Mapped {
+        map: Mmap,
+        offset: usize,
+        len: usize,
+    },
+    /// `len` bytes from `reader`, such as a zip entry inflated as it is sent.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        len: u64,
+    },
+    /// Several ranges of a file as `multipart/byteranges`.
+    Ranges(Byteranges),
+    /// Unknown length; sent chunked to HTTP/1.1 clients and close-delimited otherwise.
+    Stream(StreamFn),
+}
+
+impl Body {
+    /// Length in bytes, if known up front.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(b) => Some(b.len() as u64),
+            Body::Shared(b) => Some(b.len() as u64),
+            Body::Static(b) => Some(b.len() as u64),
+            Body::File { len, .. } => Some(*len),
+            Body::Mapped { len, .. } => Some(*len as u64),
+            Body::Reader { len, .. } => Some(*len),
+            Body::Ranges(r) => Some(r.len()),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => f.write_str("Empty"),
+            Body::Bytes(b) => write!(f, "Bytes({})", b.len()),
+            Body::Shared(b) => write!(f, "Shared({})", b.len()),
+            Body::Static(b) => write!(f, "Static({})", b.len()),
+            Body::File { offset, len, .. } => write!(f, "File({offset}+{len})"),
+            Body::Mapped { offset, len, .. } => write!(f, "Mapped({offset}+{len})"),
+            Body::Reader { len, .. } => write!(f, "Reader({len})"),
+            Body::Ranges(r) => write!(f, "Ranges({})", r.len()),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+    /// Detail line of a built-in error page, kept so the page can be
+    /// rendered again in the client's language.
+    pub error_detail: Option<String>,
+}
+
+/// How the connection is to be used when writing a response.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteOptions<'a> {
+    pub version: Version,
+    /// Send the head only (HEAD requests).
+    pub head_only: bool,
+    pub keep_alive: bool,
+    /// The socket behind the writer, for sending file bodies with
+    /// [`sendfile`](super::sendfile). `None` always copies through `w`.
+    pub socket: Option<&'a TcpStream>,
+    /// The server's name and clock.
+    pub identity: &'a Identity,
+}
+
+/// Outcome of [`Response::write_to`].
+#[derive(Clone, Copy, Debug)]
+pub struct Written {
+    /// Body bytes sent.
+    pub bytes: u64,
+    /// Whether the connection may carry another request.
+    pub keep_alive: bool,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+            error_detail: None,
+        }
+    }
+
+    pub fn bytes(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self::new(status)
+            .header("Content-Type", content_type)
+            .with_body(Body::Bytes(body.into()))
+    }
+
+    pub fn text(status: u16, text: impl Into<String>) -> Self {
+        Self::bytes(status, "text/plain; charset=utf-8", text.into())
+    }
+
+    pub fn html(status: u16, html: impl Into<String>) -> Self {
+        Self::bytes(status, "text/html; charset=utf-8", html.into())
+    }
+
+    pub fn json(status: u16, value: &Value) -> Self {
+        Self::bytes(status, "application/json", value.to_string())
+    }
+
+    /// A streamed body of unknown length.
+    pub fn stream(
+        status: u16,
+        content_type: &str,
+        f: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        Self::new(status)
+            .header("Content-Type", content_type)
+            .with_body(Body::Stream(Box::new(f)))
+    }
+
+    /// The built-in HTML error page for `status`.
+    pub fn error(status: u16) -> Self {
+        Self::error_with(status, "")
+    }
+
+    /// Error page with an extra explanatory line.
+    pub fn error_with(status: u16, detail: &str) -> Self {
+        let mut resp = Self::html(status, error_page(status, reason(status), detail));
+        resp.error_detail = Some(detail.to_owned());
+        resp
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::new(status).header("Location", location)
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: Body) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Whether the status forbids a body (1xx, 204, 304).
+    pub fn is_bodiless(&self) -> bool {
+        matches!(self.status, 100..=199 | 204 | 304)
+    }
+
+    /// Built-in error pages are made before the server is known, so they
+    /// carry the default footer; it is swapped for `identity`'s here.
+    fn sign_error_page(&mut self, identity: &Identity) {
+        if self.error_detail.is_none() {
+            return;
+        }
+        let Body::Bytes(body) = &mut self.body else {
+            return;
+        };
+        let default = Identity::default().signature();
+        let tail = format!("{default}</body></html>\n");
+        let signature = identity.signature();
+        if signature != default && body.ends_with(tail.as_bytes()) {
+            body.truncate(body.len() - tail.len());
+            body.extend_from_slice(format!("{signature}</body></html>\n").as_bytes());
+        }
+    }
+
+    /// Serializes the response. Date, Server, framing and Connection headers
+    /// are filled in here.
+    pub fn write_to(mut self, w: &mut dyn Write, opts: WriteOptions<'_>) -> io::Result<Written> {
+        let mut keep_alive = opts.keep_alive;
+        let bodiless = self.is_bodiless();
+        let mut chunked = false;
+        if !self.headers.contains("date") {
+            self.headers.set("Date", date::format(opts.identity.now()));
+        }
+        if !self.headers.contains("server") {
+            let name = opts.identity.name();
+            if !name.is_empty() {
+                self.headers.set("Server", name.into_owned());
+            }
+        }
+        self.sign_error_page(opts.identity);
+        match self.body.len() {
+            _ if bodiless => {}
+            Some(len) => {
+                if !self.headers.contains("content-length") {
+                    self.headers.set("Content-Length", len.to_string());
+                }
+            }
+            None if opts.version == Version::Http11 => {
+                self.headers.set("Transfer-Encoding", "chunked");
+                chunked = true;
+            }
+            None => keep_alive = false,
+        }
+        if !keep_alive {
+            self.headers.set("Connection", "close");
+        } else if opts.version == Version::Http10 {
+            self.headers.set("Connection", "keep-alive");
+        }
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in self.headers.iter() {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        w.write_all(head.as_bytes())?;
+
+        let mut bytes = 0;
+        if !opts.head_only && !bodiless {
+            match self.body {
+                Body::Empty => {}
+                Body::Bytes(b) => {
+                    w.write_all(&b)?;
+                    bytes = b.len() as u64;
+                }
+                Body::Shared(b) => {
+                    w.write_all(&b)?;
+                    bytes = b.len() as u64;
+                }
+                Body::Static(b) => {
+                    w.write_all(b)?;
+                    bytes = b.len() as u64;
+                }
+                Body::File {
+                    mut file,
+                    offset,
+                    len,
+                } => {
+                    bytes = match opts.socket {
+                        Some(socket) => {
+                            w.flush()?;
+                            match sendfile::copy(&file, offset, len, socket) {
+                                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                                    file.seek(SeekFrom::Start(offset))?;
+                                    io::copy(&mut file.take(len), w)?
+                                }
+                                sent => sent?,
+                            }
+                        }
+                        None => {
+                            file.seek(SeekFrom::Start(offset))?;
+                            io::copy(&mut file.take(len), w)?
+                        }
+                    };
+                    if bytes < len {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+                Body::Mapped { map, offset, len } => {
+                    map.read(offset, len, |chunk| w.write_all(chunk))?;
+                    bytes = len as u64;
+                }
+                Body::Reader { reader, len } => {
+                    bytes = io::copy(&mut reader.take(len), w)?;
+                    if bytes < len {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+                Body::Ranges(r) => bytes = r.write_to(w)?,
+                Body::Stream(f) => {
+                    let mut counter = CountingWriter {
+                        inner: &mut *w,
+                        count: 0,
+                    };
+                    if chunked {
+                        let mut chunks = ChunkedWriter::new(&mut counter);
+                        f(&mut chunks)?;
+                        chunks.finish()?;
+                    } else {
+                        f(&mut counter)?;
+                    }
+                    bytes = counter.count;
+                }
+            }
+        }
+        w.flush()?;
+        Ok(Written { bytes, keep_alive })
+    }
+}
+
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: u64,
+}
+
+impl Write for CountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Escapes text for inclusion in HTML content or attribute values.
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The built-in error page, with `reason` and `detail` as given and the
+/// default footer, which [`Response::write_to`] adapts to the server.
+pub fn error_page(status: u16, reason: &str, detail: &str) -> String {
+    let title = format!("{status} {}", escape_html(reason));
+    let detail = if detail.is_empty() {
+        String::new()
+    } else {
+        format!("<p>{}</p>", escape_html(detail))
+    };
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body><h1>{title}</h1>{detail}{}</body></html>\n",
+        Identity::default().signature()
+    )
+}
+
+/// Standard reason phrase for a status code.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        421 => "Misdirected Request",
+        423 => "Locked",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        507 => "Insufficient Storage",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::test::TestServer;
+
+    fn written(resp: Response, identity: &Identity) -> String {
+        let mut out = Vec::new();
+        let opts = WriteOptions {
+            version: Version::Http11,
+            head_only: false,
+            keep_alive: false,
+            socket: None,
+            identity,
+        };
+        resp.write_to(&mut out, opts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn identity_names_and_dates_responses() {
+        let frozen = Identity::new(None, Some(UNIX_EPOCH + Duration::from_secs(86_400)));
+        let text = written(Response::new(204), &frozen);
+        assert!(text.contains("Date: Fri, 02 Jan 1970 00:00:00 GMT\r\n"));
+        assert!(text.contains("Server: tinyserve\r\n"));
+
+        let named = Identity::new(Some("edge".into()), None);
+        let text = written(Response::error(404), &named);
+        assert!(text.contains("Server: edge\r\n"));
+        assert!(text.ends_with("<hr><address>edge</address></body></html>\n"));
+
+        let hidden = Identity::new(Some(String::new()), None);
+        let text = written(Response::error(404), &hidden);
+        assert!(!text.contains("Server:"));
+        assert!(!text.contains("<address>"));
+        assert!(text.ends_with("</h1></body></html>\n"));
+    }
+
+    #[test]
+    fn servers_in_one_process_keep_their_own_identity() {
+        let plain = TestServer::new().unwrap();
+        let named = TestServer::with(|b| {
+            b.option("serverHeader", "edge")
+                .option("deterministic", true)
+        })
+        .unwrap();
+        let resp = plain.get("/missing").unwrap();
+        assert_eq!(resp.header("server"), Some(SERVER));
+        assert!(
+            resp.text()
+                .contains(&format!("<address>{SERVER}</address>"))
+        );
+        assert_ne!(resp.header("date"), named.get("/").unwrap().header("date"));
+
+        let resp = named.get("/missing").unwrap();
+        assert_eq!(resp.header("server"), Some("edge"));
+        assert!(resp.text().contains("<address>edge</address>"));
+        assert_eq!(plain.get("/x").unwrap().header("server"), Some(SERVER));
+    }
+}
@@ -0,0 +1,102 @@
+//! Server-Sent Events (`text/event-stream`) framing.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use super::response::Response;
+
+/// How often an idle stream sends a comment line, so that dead clients are
+/// noticed and intermediaries keep the connection open.
+pub const KEEPALIVE: Duration = Duration::from_secs(15);
+/// How often a waiting stream checks whether it should end.
+pub const CHECK: Duration = Duration::from_millis(250);
+
+/// The streams a server has open: at most `max` at once, each holding a
+/// worker, and all told to end when the server shuts down.
+#[derive(Debug)]
+pub struct Streams {
+    open: AtomicUsize,
+    max: usize,
+    closed: AtomicBool,
+}
+
+impl Streams {
+    /// Allows `max` streams at once; 0 allows any number.
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            open: AtomicUsize::new(0),
+            max: if max == 0 { usize::MAX } else { max },
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Room for one more stream, or `503` when `max` are open.
+    pub fn open(self: &Arc<Self>) -> Result<Slot, Response> {
+        let taken = self
+            .open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max).then_some(open + 1)
+            });
+        match taken {
+            Ok(_) => Ok(Slot(Arc::clone(self))),
+            Err(_) => Err(Response::error_with(503, "too many event streams are open")
+                .header("Retry-After", "5")),
+        }
+    }
+
+    /// Tells every stream, open or yet to open, to end.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// One open stream, counted until dropped.
+#[derive(Debug)]
+pub struct Slot(Arc<Streams>);
+
+impl Slot {
+    /// Whether the stream should end because the server is shutting down.
+    pub fn closed(&self) -> bool {
+        self.0.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A streaming `200 text/event-stream` response driven by `f`.
+pub fn response(f: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static) -> Response {
+    Response::stream(200, "text/event-stream", f)
+        .header("Cache-Control", "no-cache")
+        .header("X-Accel-Buffering", "no")
+}
+
+/// Writes one event and flushes it. Multi-line `data` is split into several
+/// `data:` fields as the format requires.
+pub fn write_event(w: &mut dyn Write, event: Option<&str>, data: &str) -> io::Result<()> {
+    let mut frame = String::new();
+    if let Some(event) = event {
+        frame.push_str("event: ");
+        frame.push_str(event);
+        frame.push('\n');
+    }
+    for line in data.split('\n') {
+        frame.push_str("data: ");
+        frame.push_str(line.strip_suffix('\r').unwrap_or(line));
+        frame.push('\n');
+    }
+    frame.push('\n');
+    w.write_all(frame.as_bytes())?;
+    w.flush()
+}
+
+/// Writes a comment line, used as a heartbeat.
+pub fn write_comment(w: &mut dyn Write, text: &str) -> io::Result<()> {
+    w.write_all(format!(": {text}\n\n").as_bytes())?;
+    w.flush()
+}
@@ -0,0 +1,118 @@
+//! The machine's addresses and name, for the startup banner's URLs.
+
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::server::listen::Stack;
+
+/// Addresses of the interfaces that are up, sorted, without IPv6
+/// link-local ones (their URLs would need a zone).
+pub fn addrs() -> Vec<IpAddr> {
+    let mut addrs = platform_addrs();
+    addrs.retain(|ip| match ip {
+        IpAddr::V6(v6) => !v6.is_unicast_link_local(),
+        IpAddr::V4(_) => true,
+    });
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+#[cfg(unix)]
+fn platform_addrs() -> Vec<IpAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::ptr;
+
+    let mut head: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return routed_addr().into_iter().collect();
+    }
+    let mut out = Vec::new();
+    let mut cur = head;
+    while !cur.is_null() {
+        // SAFETY: `cur` walks the list `getifaddrs` returned, which stays
+        // valid until `freeifaddrs`.
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_flags & libc::IFF_UP as libc::c_uint == 0 {
+            continue;
+        }
+        // SAFETY: the family says which `sockaddr` variant `ifa_addr` is.
+        match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*ifa.ifa_addr.cast::<libc::sockaddr_in>() };
+                out.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.s_addr,
+                ))));
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*ifa.ifa_addr.cast::<libc::sockaddr_in6>() };
+                out.push(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)));
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(head) };
+    out
+}
+
+#[cfg(not(unix))]
+fn platform_addrs() -> Vec<IpAddr> {
+    routed_addr().into_iter().collect()
+}
+
+/// The address outgoing traffic would leave from. Connecting a UDP socket
+/// sends nothing; it only picks a route.
+fn routed_addr() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect(SocketAddr::from(([192, 0, 2, 1], 9))).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// The machine's host name, if it has one.
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]).into_owned();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The machine's host name, if it has one.
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok().filter(|n| !n.is_empty())
+}
+
+/// URLs reaching a server bound to `addr` as `stack`, labelled `local`,
+/// `network` or `hostname`. A wildcard address gets one per interface.
+pub fn urls(addr: SocketAddr, stack: Stack) -> Vec<(&'static str, String)> {
+    let port = addr.port();
+    let url = |ip: IpAddr| format!("http://{}/", SocketAddr::new(ip, port));
+    let ip = addr.ip();
+    if ip.is_loopback() {
+        return vec![("local", format!("http://localhost:{port}/"))];
+    }
+    if !ip.is_unspecified() {
+        return vec![("network", url(ip))];
+    }
+    let mut urls = vec![("local", format!("http://localhost:{port}/"))];
+    urls.extend(
+        addrs()
+            .into_iter()
+            .filter(|a| {
+                !a.is_loopback()
+                    && match a {
+                        IpAddr::V4(_) => stack.takes_ipv4(ip),
+                        IpAddr::V6(_) => ip.is_ipv6(),
+                    }
+            })
+            .map(|a| ("network", url(a))),
+    );
+    if let Some(name) = hostname() {
+        urls.push(("hostname", format!("http://{name}:{port}/")));
+    }
+    urls
+}
@@ -0,0 +1,41 @@
+//! tinyserve: a small HTTP static file server.
+
+pub mod access;
+pub mod audit;
+pub mod auth;
+pub mod bench;
+pub mod cli;
+pub mod coalesce;
+pub mod completions;
+pub mod core;
+pub mod daemon;
+pub mod error;
+pub mod events;
+pub mod fastcgi;
+pub mod files;
+pub mod forward_auth;
+pub mod glob;
+pub mod hooks;
+pub mod http;
+pub mod interfaces;
+pub mod locale;
+pub mod log;
+pub mod mock;
+pub mod plugin;
+pub mod precompress;
+pub mod resumable;
+pub mod sandbox;
+pub mod server;
+pub mod service;
+pub mod sites;
+pub mod ssi;
+pub mod tail;
+pub mod test;
+pub mod tui;
+pub mod vfs;
+pub mod watch;
+pub mod webdav;
+pub mod writable;
+
+pub use error::{Error, Result};
+pub use server::{Server, ServerBuilder, ServerHandle};
@@ -0,0 +1,250 @@
+//! Strings of directory listings and built-in error pages, by language.
+//!
+//! English is built in, with German, French and Spanish tables beside it.
+//! `<localeDir>/<locale>.json` files add languages or override strings of
+//! the built-in ones. Each holds a flat object from message id to text:
+//!
+//! ```json
+//! {"listing.title": "Inhalt von {path}", "404": "Nicht gefunden",
+//!  "invalid path": "ungültiger Pfad"}
+//! ```
+//!
+//! The ids are those of [`ENGLISH`]; a status code names the heading of its
+//! error page, and the English text of an error detail translates that
+//! detail. Whatever a table lacks is shown in English. With `locale` at
+//! `auto`, the table is picked by the request's `Accept-Language`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as _, Result, bail};
+use serde_json::Value;
+
+use crate::core::Config;
+use crate::files::language;
+use crate::http::response::{error_page, escape_html, reason};
+use crate::http::{Body, Request, Response};
+use crate::server::Context;
+
+/// Message ids with their English text; `{name}` marks a value filled in.
+pub const ENGLISH: &[(&str, &str)] = &[
+    ("listing.title", "Index of {path}"),
+    ("listing.name", "Name"),
+    ("listing.size", "Size"),
+    ("listing.modified", "Modified"),
+    ("listing.summary", "{dirs} folder(s), {files} file(s)"),
+    ("listing.bytes", "{bytes} bytes"),
+    (
+        "listing.truncated",
+        "first {shown} of {total} entries shown; filter to search them all",
+    ),
+    ("filter.placeholder", "Filter"),
+    ("filter.label", "Filter entries"),
+    ("filter.searching", "searching…"),
+    ("filter.failed", "search failed"),
+    ("filter.hits", "{hits} of {total}"),
+    ("filter.more", "{hits} of {total}, first {shown} shown"),
+    ("upload.drop", "Drop files here or"),
+    ("upload.button", "Upload"),
+    ("upload.failed", "failed ({reason})"),
+    ("upload.network", "network error"),
+];
+
+/// Tables shipped in the binary.
+const BUILTIN: &[(&str, &str)] = &[
+    ("de", include_str!("de.json")),
+    ("es", include_str!("es.json")),
+    ("fr", include_str!("fr.json")),
+];
+
+type Table = HashMap<String, String>;
+
+/// The string tables other than English, and the `locale` setting.
+#[derive(Debug, Default)]
+pub struct Locales {
+    tables: BTreeMap<String, Table>,
+    /// The table `locale` names, or `None` for `auto`.
+    fixed: Option<String>,
+}
+
+/// The built-in tables merged with those in `localeDir`.
+pub fn load(config: &Config) -> Result<Locales> {
+    let mut tables = BTreeMap::new();
+    for (tag, json) in BUILTIN {
+        let table = parse(json).with_context(|| format!("built-in locale {tag}"))?;
+        tables.insert((*tag).to_owned(), table);
+    }
+    match config.str("localeDir") {
+        "" => {}
+        dir => read_dir(Path::new(dir), &mut tables)?,
+    }
+    let fixed = match config.str("locale").to_ascii_lowercase() {
+        tag if tag == "auto" => None,
+        tag if tag == "en" || tables.contains_key(&tag) => Some(tag),
+        tag => {
+            let known: Vec<&str> = tables.keys().map(String::as_str).collect();
+            bail!(
+                "locale `{tag}` is not known (auto, en, {})",
+                known.join(", ")
+            );
+        }
+    };
+    Ok(Locales { tables, fixed })
+}
+
+/// Adds the `<locale>.json` files of `dir` to `tables`.
+fn read_dir(dir: &Path, tables: &mut BTreeMap<String, Table>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("localeDir {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let Some(tag) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        if !language::is_tag(tag) {
+            continue;
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("{}", path.display()))?;
+        let table = parse(&text).with_context(|| format!("{}", path.display()))?;
+        tables
+            .entry(tag.to_ascii_lowercase())
+            .or_default()
+            .extend(table);
+    }
+    Ok(())
+}
+
+fn parse(json: &str) -> Result<Table> {
+    let Value::Object(map) = serde_json::from_str(json)? else {
+        bail!("expected an object of strings");
+    };
+    map.into_iter()
+        .map(|(id, text)| match text {
+            Value::String(text) => Ok((id, text)),
+            _ => bail!("`{id}` is not a string"),
+        })
+        .collect()
+}
+
+impl Locales {
+    /// The strings to answer `req` with.
+    pub fn strings(&self, req: &Request) -> Strings<'_> {
+        let tag = match &self.fixed {
+            Some(tag) => Some(tag.as_str()),
+            None => self.negotiate(req.headers.get("accept-language").unwrap_or_default()),
+        };
+        match tag.and_then(|tag| self.tables.get_key_value(tag)) {
+            Some((tag, table)) => Strings {
+                tag,
+                table: Some(table),
+            },
+            None => Strings::default(),
+        }
+    }
+
+    /// The most preferred table of `accept`, or `None` for English.
+    fn negotiate(&self, accept: &str) -> Option<&str> {
+        for range in language::preferences(accept) {
+            if range == "*" || language::matches(&range, "en") {
+                return None;
+            }
+            if let Some((tag, _)) = self.tables.get_key_value(&range) {
+                return Some(tag);
+            }
+            if let Some(tag) = self.tables.keys().find(|t| language::matches(&range, t)) {
+                return Some(tag);
+            }
+        }
+        None
+    }
+
+    /// Whether pages differ by `Accept-Language`.
+    pub fn varies(&self) -> bool {
+        self.fixed.is_none() && !self.tables.is_empty()
+    }
+}
+
+/// One language's strings, falling back to English.
+#[derive(Clone, Copy, Debug)]
+pub struct Strings<'a> {
+    /// The locale tag, `en` for English.
+    pub tag: &'a str,
+    table: Option<&'a Table>,
+}
+
+impl Default for Strings<'_> {
+    fn default() -> Self {
+        Self {
+            tag: "en",
+            table: None,
+        }
+    }
+}
+
+impl<'a> Strings<'a> {
+    /// The translation of `id`, if this table has one.
+    pub fn lookup(&self, id: &str) -> Option<&'a str> {
+        self.table?.get(id).map(String::as_str)
+    }
+
+    /// The text of `id`; empty for an id that is not in [`ENGLISH`].
+    pub fn get(&self, id: &str) -> &'a str {
+        self.lookup(id)
+            .or_else(|| ENGLISH.iter().find(|(i, _)| *i == id).map(|(_, t)| *t))
+            .unwrap_or_default()
+    }
+
+    /// The text of `id` with its `{name}` values filled in.
+    pub fn fill(&self, id: &str, values: &[(&str, &str)]) -> String {
+        values
+            .iter()
+            .fold(self.get(id).to_owned(), |text, (name, value)| {
+                text.replace(&format!("{{{name}}}"), value)
+            })
+    }
+
+    /// `template` with each `{{id}}` replaced by its HTML-escaped text.
+    pub fn html(&self, template: &str) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            out.push_str(&rest[..start]);
+            out.push_str(&escape_html(self.get(&rest[start + 2..start + end])));
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Renders a built-in error page again in the client's language, unless a
+/// later stage has replaced its body.
+pub fn localize_error(ctx: &Context, req: &Request, resp: &mut Response) {
+    let Some(detail) = resp.error_detail.as_deref() else {
+        return;
+    };
+    if ctx.locales.varies() {
+        resp.headers.append("Vary", "Accept-Language");
+    }
+    let strings = ctx.locales.strings(req);
+    if strings.table.is_none() {
+        return;
+    }
+    let status = resp.status;
+    let original = error_page(status, reason(status), detail);
+    if !matches!(&resp.body, Body::Bytes(body) if *body == original.as_bytes()) {
+        return;
+    }
+    let heading = strings
+        .lookup(&status.to_string())
+        .unwrap_or(reason(status));
+    let detail = strings.lookup(detail).unwrap_or(detail);
+    resp.body = Body::Bytes(error_page(status, heading, detail).into_bytes());
+}
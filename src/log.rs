@@ -0,0 +1,235 @@
+//! Console output: startup messages, access lines and errors.
+//!
+//! Output is plain text until [`set_style`] is called with the configured
+//! [`Style`]: `quiet` keeps only warnings and errors, `color` highlights
+//! them on terminals, and `json` prints one object per line for scripts.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+use crate::core::Config;
+use crate::server::listen::Stack;
+
+/// Takes console output in place of the terminal; see [`redirect`].
+pub type Sink = Box<dyn Fn(&str) + Send + Sync>;
+
+static SINK: RwLock<Option<Sink>> = RwLock::new(None);
+
+/// How console output looks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Style {
+    /// Only warnings and errors.
+    pub quiet: bool,
+    /// ANSI colors, for the streams that are terminals.
+    pub color: bool,
+    /// One JSON object per line instead of text.
+    pub json: bool,
+}
+
+impl Style {
+    /// The style set by `quiet`, `color` and `output`. Colors also need
+    /// `NO_COLOR` to be unset or empty.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            quiet: config.bool("quiet"),
+            color: config.bool("color") && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+            json: config.str("output") == "json",
+        }
+    }
+}
+
+/// The style in use, with colors resolved per stream.
+#[derive(Clone, Copy)]
+struct Current {
+    style: Style,
+    color_out: bool,
+    color_err: bool,
+}
+
+static STYLE: RwLock<Current> = RwLock::new(Current {
+    style: Style {
+        quiet: false,
+        color: false,
+        json: false,
+    },
+    color_out: false,
+    color_err: false,
+});
+
+/// Switches console output to `style`.
+pub fn set_style(style: Style) {
+    *STYLE.write().unwrap_or_else(|e| e.into_inner()) = Current {
+        style,
+        color_out: style.color && !style.json && io::stdout().is_terminal(),
+        color_err: style.color && !style.json && io::stderr().is_terminal(),
+    };
+}
+
+fn current() -> Current {
+    *STYLE.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Hands messages to `sink`, and drops access lines, while a full-screen
+/// display owns the terminal. `None` goes back to printing.
+pub fn redirect(sink: Option<Sink>) {
+    *SINK.write().unwrap_or_else(|e| e.into_inner()) = sink;
+}
+
+/// Prints a message on stderr, or passes it to the sink. `color` is the
+/// escape for the `level:` prefix of text output.
+fn emit(level: &str, color: &str, msg: &str) {
+    if let Some(sink) = &*SINK.read().unwrap_or_else(|e| e.into_inner()) {
+        match level {
+            "info" => sink(msg),
+            _ => sink(&format!("{level}: {msg}")),
+        }
+        return;
+    }
+    let current = current();
+    if current.style.json {
+        eprintln!("{}", json!({"level": level, "message": msg}));
+    } else if level == "info" {
+        eprintln!("{msg}");
+    } else if current.color_err {
+        eprintln!("{color}{level}:\x1b[0m {msg}");
+    } else {
+        eprintln!("{level}: {msg}");
+    }
+}
+
+/// Informational message on stderr.
+pub fn info(msg: &str) {
+    if !current().style.quiet {
+        emit("info", "", msg);
+    }
+}
+
+pub fn warn(msg: &str) {
+    emit("warning", "\x1b[1;33m", msg);
+}
+
+pub fn error(msg: &str) {
+    emit("error", "\x1b[1;31m", msg);
+}
+
+/// Prints `value` as a JSON line, or `text`, on stderr or stdout.
+fn print(value: Value, text: &str, stderr: bool) {
+    let current = current();
+    if current.style.quiet {
+        return;
+    }
+    let line = if current.style.json {
+        value.to_string()
+    } else {
+        text.to_owned()
+    };
+    match &*SINK.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(sink) => sink(text),
+        None if stderr => eprintln!("{line}"),
+        None => println!("{line}"),
+    }
+}
+
+/// The outcome of a command such as `status`, on stdout: `value` for JSON
+/// output, `text` otherwise.
+pub fn outcome(value: Value, text: &str) {
+    print(value, text, false);
+}
+
+/// The startup banner on stderr: what is served, every URL that reaches it
+/// (`urls`, labelled as by [`interfaces::urls`](crate::interfaces::urls)),
+/// how IPv4 and IPv6 are taken on a wildcard address, and the options set
+/// away from their defaults, as `(key, JSON text)`.
+pub fn serving(
+    root: &Path,
+    url: &str,
+    urls: &[(&str, String)],
+    stack: Stack,
+    options: &[(&str, String)],
+) {
+    let color = current().color_err;
+    let mut text = format!("Serving {}", root.display());
+    for (label, url) in urls {
+        let label = match *label {
+            "local" => "Local:",
+            "network" => "Network:",
+            _ => "Host:",
+        };
+        if color {
+            text.push_str(&format!("\n  {label:<10}\x1b[1;36m{url}\x1b[0m"));
+        } else {
+            text.push_str(&format!("\n  {label:<10}{url}"));
+        }
+    }
+    if let Some(stack) = stack.describe() {
+        text.push_str(&format!("\n  {:<10}{stack}", "Stack:"));
+    }
+    if !options.is_empty() {
+        let options: Vec<String> = options.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        text.push_str(&format!("\n  {:<10}{}", "Options:", options.join(", ")));
+    }
+    let urls: Vec<Value> = urls
+        .iter()
+        .map(|(kind, url)| json!({"kind": kind, "url": url}))
+        .collect();
+    let options: serde_json::Map<String, Value> = options
+        .iter()
+        .map(|(k, v)| {
+            let value = serde_json::from_str(v).unwrap_or_else(|_| Value::from(v.as_str()));
+            (k.to_string(), value)
+        })
+        .collect();
+    let mut event =
+        json!({"event": "serving", "root": root, "url": url, "urls": urls, "options": options});
+    if let Some(stack) = stack.name() {
+        event["stack"] = Value::from(stack);
+    }
+    print(event, &text, true);
+}
+
+/// One line per completed request on stdout.
+pub fn access(peer: SocketAddr, request_line: &str, status: u16, bytes: u64, elapsed: Duration) {
+    if SINK.read().unwrap_or_else(|e| e.into_inner()).is_some() {
+        return;
+    }
+    let current = current();
+    if current.style.quiet {
+        return;
+    }
+    let ms = elapsed.as_secs_f64() * 1000.0;
+    if current.style.json {
+        println!(
+            "{}",
+            json!({
+                "event": "access",
+                "peer": peer.ip().to_string(),
+                "request": request_line,
+                "status": status,
+                "bytes": bytes,
+                "ms": (ms * 10.0).round() / 10.0,
+            })
+        );
+        return;
+    }
+    let status = if current.color_out {
+        let color = match status {
+            200..=299 => "32",
+            300..=399 => "36",
+            400..=499 => "33",
+            _ => "31",
+        };
+        format!("\x1b[{color}m{status}\x1b[0m")
+    } else {
+        status.to_string()
+    };
+    println!(
+        "{} \"{request_line}\" {status} {bytes} {ms:.1}ms",
+        peer.ip()
+    );
+}
@@ -1,3 +1,11 @@
-fn main() {
-    println!("Hello, world!");
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match tinyserve::cli::run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tinyserve::log::error(&format!("{e:#}"));
+            ExitCode::FAILURE
+        }
+    }
 }
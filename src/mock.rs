@@ -0,0 +1,262 @@
+//! Canned API responses from fixture files, answered before the filesystem
+//! so a static frontend can be developed against a fake backend.
+//!
+//! `mocks` names a JSON file, or a directory whose top-level `*.json` files
+//! are read in name order. Each file holds one mock or an array of them:
+//!
+//! ```json
+//! [
+//!   {"method": "GET", "path": "/api/users", "query": {"page": "2"},
+//!    "body": [{"id": 1, "name": "Ann"}], "delay": 150},
+//!   {"path": "/api/users/*", "bodyFile": "bodies/user.json"},
+//!   {"method": "POST", "path": "/api/login", "status": 401,
+//!    "headers": {"WWW-Authenticate": "Bearer"}, "body": "bad credentials"}
+//! ]
+//! ```
+//!
+//! `path` is a glob; `method` (any by default, `GET` also answering `HEAD`)
+//! and `query` (parameters that must be present with these values) narrow
+//! the match. The first match answers. A JSON `body` is sent as
+//! `application/json` and a string as text; `bodyFile` is read, relative to
+//! the mock file, on every request. `status` defaults to 200 and `delay` is
+//! in milliseconds. The files are re-read whenever they change.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde_json::Value;
+
+use crate::core::Config;
+use crate::glob;
+use crate::http::{Request, Response, mime};
+use crate::log;
+use crate::server::Context;
+
+/// The mocks configured by `mocks`, if any.
+pub fn load(config: &Config) -> Result<Option<Mocks>> {
+    match config.str("mocks") {
+        "" => Ok(None),
+        path => Mocks::new(path).map(Some),
+    }
+}
+
+/// The canned response for `req`, if a mock matches.
+pub fn handle(ctx: &Context, req: &Request) -> Option<Response> {
+    ctx.mocks.as_ref()?.answer(req)
+}
+
+#[derive(Debug)]
+enum MockBody {
+    Empty,
+    Text(String),
+    Json(String),
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+struct Mock {
+    method: Option<String>,
+    path: String,
+    query: Vec<(String, String)>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: MockBody,
+    delay: Duration,
+}
+
+/// Modification times of the files a set of mocks was read from.
+type Stamp = Vec<(PathBuf, Option<SystemTime>)>;
+
+pub struct Mocks {
+    source: PathBuf,
+    loaded: Mutex<(Stamp, Arc<Vec<Mock>>)>,
+}
+
+impl Mocks {
+    /// Reads the mocks at `source`, failing on any malformed file.
+    pub fn new(source: impl Into<PathBuf>) -> Result<Self> {
+        let source = source.into();
+        let stamp = stamp(&source)?;
+        let mocks = read(&stamp)?;
+        Ok(Self {
+            source,
+            loaded: Mutex::new((stamp, Arc::new(mocks))),
+        })
+    }
+
+    /// The current mocks, re-read if a file changed. A broken edit keeps
+    /// the previous set.
+    fn current(&self) -> Arc<Vec<Mock>> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        match stamp(&self.source) {
+            Ok(now) if now != loaded.0 => match read(&now) {
+                Ok(mocks) => *loaded = (now, Arc::new(mocks)),
+                Err(e) => {
+                    log::warn(&format!("mocks: {e:#}"));
+                    loaded.0 = now;
+                }
+            },
+            Ok(_) => {}
+            Err(e) => log::warn(&format!("mocks: {e:#}")),
+        }
+        Arc::clone(&loaded.1)
+    }
+
+    /// The canned response for `req`, if a mock matches.
+    pub fn answer(&self, req: &Request) -> Option<Response> {
+        let mocks = self.current();
+        let mock = mocks.iter().find(|mock| mock.matches(req))?;
+        if !mock.delay.is_zero() {
+            thread::sleep(mock.delay);
+        }
+        Some(mock.respond())
+    }
+}
+
+impl Mock {
+    fn matches(&self, req: &Request) -> bool {
+        let method_ok = self.method.as_deref().is_none_or(|m| {
+            m.eq_ignore_ascii_case(&req.method) || (m == "GET" && req.method == "HEAD")
+        });
+        method_ok
+            && glob::matches(&self.path, &req.path)
+            && self
+                .query
+                .iter()
+                .all(|(name, value)| req.query_param(name).as_deref() == Some(value))
+    }
+
+    fn respond(&self) -> Response {
+        let mut resp = match &self.body {
+            MockBody::Empty => Response::new(self.status),
+            MockBody::Text(text) => Response::text(self.status, text.clone()),
+            MockBody::Json(json) => Response::bytes(self.status, "application/json", json.clone()),
+            MockBody::File(path) => match fs::read(path) {
+                Ok(bytes) => Response::bytes(self.status, mime::from_path(path), bytes),
+                Err(e) => {
+                    log::warn(&format!("mocks: {}: {e}", path.display()));
+                    return Response::error_with(500, "mock body file is unreadable");
+                }
+            },
+        };
+        for (name, value) in &self.headers {
+            resp.headers.set(name, value);
+        }
+        resp
+    }
+}
+
+/// The files making up `source`, with their modification times.
+fn stamp(source: &Path) -> Result<Stamp> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if !source.is_dir() {
+        return Ok(vec![(source.to_path_buf(), modified(source))]);
+    }
+    let entries = fs::read_dir(source).with_context(|| format!("{}", source.display()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files
+        .into_iter()
+        .map(|path| {
+            let at = modified(&path);
+            (path, at)
+        })
+        .collect())
+}
+
+fn read(stamp: &Stamp) -> Result<Vec<Mock>> {
+    let mut mocks = Vec::new();
+    for (file, _) in stamp {
+        read_file(file, &mut mocks).with_context(|| format!("{}", file.display()))?;
+    }
+    Ok(mocks)
+}
+
+fn read_file(file: &Path, mocks: &mut Vec<Mock>) -> Result<()> {
+    let json: Value = serde_json::from_slice(&fs::read(file)?)?;
+    let dir = file.parent().unwrap_or(Path::new("."));
+    match json {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                mocks.push(parse(item, dir).with_context(|| format!("mock #{}", i + 1))?);
+            }
+        }
+        item => mocks.push(parse(&item, dir)?),
+    }
+    Ok(())
+}
+
+fn strings(value: Option<&Value>, field: &str) -> Result<Vec<(String, String)>> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let object = value
+        .as_object()
+        .ok_or_else(|| anyhow!("`{field}` must be an object"))?;
+    object
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(s) => Ok((name.clone(), s.clone())),
+            Value::Number(n) => Ok((name.clone(), n.to_string())),
+            Value::Bool(b) => Ok((name.clone(), b.to_string())),
+            _ => bail!("`{field}.{name}` must be a string"),
+        })
+        .collect()
+}
+
+fn parse(item: &Value, dir: &Path) -> Result<Mock> {
+    let object = item
+        .as_object()
+        .ok_or_else(|| anyhow!("expected an object"))?;
+    let path = match object.get("path") {
+        Some(Value::String(path)) if path.starts_with('/') => path.clone(),
+        _ => bail!("`path` must be a string starting with /"),
+    };
+    let method = match object.get("method") {
+        None => None,
+        Some(Value::String(m)) => Some(m.to_ascii_uppercase()),
+        Some(_) => bail!("`method` must be a string"),
+    };
+    let status = match object.get("status") {
+        None => 200,
+        Some(v) => v
+            .as_u64()
+            .and_then(|s| u16::try_from(s).ok())
+            .filter(|s| (100..600).contains(s))
+            .ok_or_else(|| anyhow!("`status` must be an HTTP status code"))?,
+    };
+    let delay = match object.get("delay") {
+        None => Duration::ZERO,
+        Some(v) => Duration::from_millis(
+            v.as_u64()
+                .ok_or_else(|| anyhow!("`delay` must be milliseconds"))?,
+        ),
+    };
+    let body = match (object.get("body"), object.get("bodyFile")) {
+        (Some(_), Some(_)) => bail!("give either `body` or `bodyFile`, not both"),
+        (Some(Value::String(text)), None) => MockBody::Text(text.clone()),
+        (Some(json), None) => MockBody::Json(json.to_string()),
+        (None, Some(Value::String(file))) => MockBody::File(dir.join(file)),
+        (None, Some(_)) => bail!("`bodyFile` must be a string"),
+        (None, None) => MockBody::Empty,
+    };
+    Ok(Mock {
+        method,
+        path,
+        query: strings(object.get("query"), "query")?,
+        status,
+        headers: strings(object.get("headers"), "headers")?,
+        body,
+        delay,
+    })
+}
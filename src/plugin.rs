@@ -0,0 +1,303 @@
+//! WebAssembly request plugins, loaded from the `plugins` directory (by
+//! default `plugins/` in the configs dir) when built with the `wasm` feature.
+//!
+//! Each `*.wasm` file is a module exporting its `memory`, an
+//! `alloc(len: i32) -> i32` the server uses to pass data in, and
+//! `on_request(ptr: i32, len: i32) -> i64`. The request arrives as JSON:
+//!
+//! ```json
+//! {"method": "GET", "path": "/docs/", "query": "q=1", "headers": {"host": "..."}, "peer": "127.0.0.1"}
+//! ```
+//!
+//! `on_request` returns 0 to let the request through unchanged, or
+//! `ptr << 32 | len` of a JSON reply in its memory. `{"path": "/other",
+//! "headers": {"x-user": "ann", "cookie": null}}` rewrites the request (a
+//! `null` header is removed); `{"respond": {"status": 403, "headers": {},
+//! "body": "denied"}}` answers it instead.
+//!
+//! Plugins run in file name order, each in a fresh instance per request.
+//! The only import offered is `env.log(ptr: i32, len: i32)`. `pluginFuel`
+//! bounds the instructions run per request and `pluginMemory` the memory; a
+//! plugin that traps, runs out of either, or replies with anything else
+//! fails the request with `500`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+
+use crate::core::Config;
+use crate::http::{Request, Response};
+use crate::log;
+use crate::server::Context;
+
+/// The `*.wasm` files in `dir`, by name.
+fn find(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("plugins {}", dir.display()))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "wasm") && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Compiles the plugins named by the `plugins` option.
+pub fn load(config: &Config) -> Result<Vec<Plugin>> {
+    let dir = match config.str("plugins") {
+        "" => return Ok(Vec::new()),
+        dir => Path::new(dir),
+    };
+    let paths = find(dir)?;
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    #[cfg(feature = "wasm")]
+    {
+        let engine = wasm::engine();
+        paths
+            .iter()
+            .map(|path| Plugin::load(&engine, path, config))
+            .collect()
+    }
+    #[cfg(not(feature = "wasm"))]
+    anyhow::bail!(
+        "{} holds WebAssembly plugins, which need a build with the `wasm` feature",
+        dir.display()
+    )
+}
+
+/// Runs the plugins on `req` in order; the first to answer wins.
+pub fn handle(ctx: &Context, req: &mut Request) -> Option<Response> {
+    for plugin in &ctx.plugins {
+        match plugin.on_request(req) {
+            Ok(None) => {}
+            Ok(Some(resp)) => return Some(resp),
+            Err(e) => {
+                log::warn(&format!("plugin {}: {e:#}", plugin.name()));
+                return Some(Response::error_with(500, "a request plugin failed"));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(feature = "wasm")]
+pub use wasm::Plugin;
+
+/// Without the `wasm` feature no plugin can be loaded.
+#[cfg(not(feature = "wasm"))]
+pub enum Plugin {}
+
+#[cfg(not(feature = "wasm"))]
+impl Plugin {
+    pub fn name(&self) -> &str {
+        match *self {}
+    }
+
+    pub fn on_request(&self, _: &mut Request) -> Result<Option<Response>> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    use std::fs;
+    use std::path::Path;
+
+    use anyhow::{Context as _, Result, anyhow, bail};
+    use serde_json::{Map, Value};
+    use wasmi::{
+        Caller, Engine, Extern, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    };
+
+    use crate::core::Config;
+    use crate::http::{Headers, Request, Response};
+    use crate::log;
+
+    pub(super) fn engine() -> Engine {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    }
+
+    /// A compiled plugin.
+    pub struct Plugin {
+        name: String,
+        module: Module,
+        linker: Linker<State>,
+        fuel: u64,
+        memory: usize,
+    }
+
+    /// Per-instance state.
+    struct State {
+        plugin: String,
+        limits: StoreLimits,
+    }
+
+    impl Plugin {
+        pub(super) fn load(engine: &Engine, path: &Path, config: &Config) -> Result<Self> {
+            let name = path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+            let load = || -> Result<Self> {
+                let wasm = fs::read(path)?;
+                let module = Module::new(engine, &wasm)?;
+                let mut linker = Linker::new(engine);
+                linker.func_wrap("env", "log", guest_log)?;
+                let plugin = Self {
+                    name: name.clone(),
+                    module,
+                    linker,
+                    fuel: config.int("pluginFuel"),
+                    memory: usize::try_from(config.int("pluginMemory")).unwrap_or(usize::MAX),
+                };
+                // Catch missing exports and disallowed imports at startup.
+                let mut store = plugin.store()?;
+                plugin.instantiate(&mut store)?;
+                Ok(plugin)
+            };
+            load().with_context(|| format!("plugin {}", path.display()))
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn store(&self) -> Result<Store<State>> {
+            let limits = StoreLimitsBuilder::new()
+                .memory_size(self.memory)
+                .instances(1)
+                .build();
+            let state = State {
+                plugin: self.name.clone(),
+                limits,
+            };
+            let mut store = Store::new(self.module.engine(), state);
+            store.limiter(|state| &mut state.limits);
+            store.set_fuel(self.fuel).map_err(|e| anyhow!("{e}"))?;
+            Ok(store)
+        }
+
+        fn instantiate(&self, store: &mut Store<State>) -> Result<Instance> {
+            let instance = self
+                .linker
+                .instantiate(&mut *store, &self.module)?
+                .start(&mut *store)?;
+            instance
+                .get_memory(&*store, "memory")
+                .ok_or_else(|| anyhow!("no exported `memory`"))?;
+            instance.get_typed_func::<i32, i32>(&*store, "alloc")?;
+            instance.get_typed_func::<(i32, i32), i64>(&*store, "on_request")?;
+            Ok(instance)
+        }
+
+        pub fn on_request(&self, req: &mut Request) -> Result<Option<Response>> {
+            let input = describe(req);
+            let mut store = self.store()?;
+            let instance = self.instantiate(&mut store)?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| anyhow!("no exported `memory`"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+            let on_request = instance.get_typed_func::<(i32, i32), i64>(&store, "on_request")?;
+
+            let len = i32::try_from(input.len()).context("request too large")?;
+            let ptr = alloc.call(&mut store, len)?;
+            memory
+                .write(&mut store, ptr as u32 as usize, &input)
+                .map_err(|e| anyhow!("alloc returned a bad pointer: {e}"))?;
+            let packed = on_request.call(&mut store, (ptr, len))? as u64;
+            if packed == 0 {
+                return Ok(None);
+            }
+            let start = (packed >> 32) as usize;
+            let end = start + (packed & 0xffff_ffff) as usize;
+            let reply = memory
+                .data(&store)
+                .get(start..end)
+                .ok_or_else(|| anyhow!("reply lies outside memory"))?;
+            apply(reply, req)
+        }
+    }
+
+    /// The request as handed to `on_request`.
+    fn describe(req: &Request) -> Vec<u8> {
+        let mut headers = Map::new();
+        for (name, value) in req.headers.iter() {
+            let name = name.to_ascii_lowercase();
+            let value = match headers.remove(&name) {
+                Some(Value::String(prev)) => format!("{prev}, {value}"),
+                _ => value.to_owned(),
+            };
+            headers.insert(name, Value::from(value));
+        }
+        let json = serde_json::json!({
+            "method": req.method,
+            "path": req.path,
+            "query": req.query,
+            "headers": headers,
+            "peer": req.peer.ip().to_string(),
+        });
+        json.to_string().into_bytes()
+    }
+
+    /// Applies a reply from `on_request`.
+    fn apply(reply: &[u8], req: &mut Request) -> Result<Option<Response>> {
+        let reply: Value = serde_json::from_slice(reply).context("reply is not JSON")?;
+        if let Some(respond) = reply.get("respond") {
+            let status = respond.get("status").and_then(Value::as_u64).unwrap_or(200);
+            let status = u16::try_from(status)
+                .ok()
+                .filter(|s| (100..600).contains(s))
+                .ok_or_else(|| anyhow!("invalid status {status}"))?;
+            let body = respond.get("body").and_then(Value::as_str).unwrap_or("");
+            let mut resp = Response::text(status, body);
+            set_headers(&mut resp.headers, respond.get("headers"))?;
+            return Ok(Some(resp));
+        }
+        if let Some(path) = reply.get("path") {
+            match path.as_str() {
+                Some(path) if path.starts_with('/') => req.path = path.to_owned(),
+                _ => bail!("`path` must be a string starting with /"),
+            }
+        }
+        set_headers(&mut req.headers, reply.get("headers"))?;
+        Ok(None)
+    }
+
+    fn set_headers(headers: &mut Headers, rules: Option<&Value>) -> Result<()> {
+        let Some(rules) = rules else { return Ok(()) };
+        let rules = rules
+            .as_object()
+            .ok_or_else(|| anyhow!("`headers` must be an object"))?;
+        for (name, value) in rules {
+            match value {
+                Value::Null => headers.remove(name),
+                Value::String(value) if !value.contains(['\r', '\n']) => headers.set(name, value),
+                _ => bail!("header `{name}` must be a single-line string or null"),
+            }
+        }
+        Ok(())
+    }
+
+    /// `env.log(ptr, len)`: prints a line from the guest.
+    fn guest_log(caller: Caller<'_, State>, ptr: i32, len: i32) {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+            return;
+        };
+        let start = ptr as u32 as usize;
+        let end = start.saturating_add(len as u32 as usize);
+        if let Some(bytes) = memory.data(&caller).get(start..end) {
+            let plugin = &caller.data().plugin;
+            log::info(&format!(
+                "plugin {plugin}: {}",
+                String::from_utf8_lossy(bytes)
+            ));
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! `tinyserve precompress`: writes compressed sidecars ahead of a deploy.
+//!
+//! ```text
+//! tinyserve precompress [DIR] [--formats gz,br,zst] [--min-size BYTES]
+//!                       [--types TYPE,...] [--exclude GLOB]...
+//!                       [--manifest FILE [--fingerprint GLOB]...] [--force]
+//! ```
+//!
+//! Walks `DIR` (default `.`) and writes `name.gz`, `name.br` and `name.zst`
+//! next to every compressible file of at least `--min-size` bytes, which
+//! `--precompressed` then serves. `--types` replaces the built-in idea of
+//! compressible with a list of content types (`text/*` covers a whole
+//! family), and files whose path below `DIR` matches an `--exclude` glob
+//! are skipped. Sidecars newer than their file are left
+//! alone unless `--force` is given, and a sidecar that would not be smaller
+//! than the file is not written. Hidden entries and symlinks are skipped.
+//!
+//! With `--manifest`, files matching the `--fingerprint` globs (default
+//! `*.js` and `*.css`) below the manifest's directory are first copied to
+//! content-hashed names (`app.3f9ab2c1.js`) and listed in the manifest, in
+//! the format `--manifest` reads when serving.
+//!
+//! Gzip is always available; brotli and zstd need the `brotli` and `zstd`
+//! features.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde_json::{Map, Value};
+
+use crate::files::fnv1a;
+use crate::files::precompressed::{is_sidecar, sidecar_path};
+use crate::glob;
+use crate::http::mime;
+
+/// Formats built into this binary.
+const AVAILABLE: &[&str] = &[
+    "gz",
+    #[cfg(feature = "brotli")]
+    "br",
+    #[cfg(feature = "zstd")]
+    "zst",
+];
+
+struct Options {
+    dir: PathBuf,
+    formats: Vec<String>,
+    min_size: u64,
+    /// Content types to compress; empty for the built-in choice.
+    types: Vec<String>,
+    exclude: Vec<String>,
+    manifest: Option<PathBuf>,
+    fingerprint: Vec<String>,
+    force: bool,
+}
+
+fn parse(args: &[String]) -> Result<Options> {
+    let mut opts = Options {
+        dir: PathBuf::from("."),
+        formats: AVAILABLE.iter().map(|f| (*f).to_owned()).collect(),
+        min_size: 1024,
+        types: Vec::new(),
+        exclude: Vec::new(),
+        manifest: None,
+        fingerprint: Vec::new(),
+        force: false,
+    };
+    let mut args = args.iter();
+    let mut dir = None;
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| anyhow!("`{name}` needs a value"))
+        };
+        match name {
+            "--formats" => {
+                opts.formats = value()?
+                    .split(',')
+                    .map(|f| f.trim().to_owned())
+                    .filter(|f| !f.is_empty())
+                    .collect();
+            }
+            "--min-size" => opts.min_size = value()?.parse().context("--min-size")?,
+            "--types" => {
+                opts.types = value()?
+                    .split(',')
+                    .map(|t| t.trim().to_ascii_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect();
+            }
+            "--exclude" => opts.exclude.push(value()?),
+            "--manifest" => opts.manifest = Some(PathBuf::from(value()?)),
+            "--fingerprint" => opts.fingerprint.push(value()?),
+            "--force" => opts.force = true,
+            _ if name.starts_with('-') => bail!("unknown precompress option `{arg}`"),
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument `{arg}`"),
+        }
+    }
+    if let Some(dir) = dir {
+        opts.dir = dir;
+    }
+    for format in &opts.formats {
+        match format.as_str() {
+            "gz" => {}
+            "br" if cfg!(feature = "brotli") => {}
+            "zst" if cfg!(feature = "zstd") => {}
+            "br" => bail!("brotli needs tinyserve built with the `brotli` feature"),
+            "zst" => bail!("zstd needs tinyserve built with the `zstd` feature"),
+            other => bail!("unknown format `{other}` (gz, br, zst)"),
+        }
+    }
+    if opts.formats.is_empty() {
+        bail!("--formats names no format");
+    }
+    if !opts.fingerprint.is_empty() && opts.manifest.is_none() {
+        bail!("--fingerprint needs --manifest");
+    }
+    if opts.fingerprint.is_empty() {
+        opts.fingerprint = vec!["*.js".to_owned(), "*.css".to_owned()];
+    }
+    Ok(opts)
+}
+
+#[derive(Default)]
+struct Tally {
+    written: usize,
+    fresh: usize,
+    not_smaller: usize,
+    before: u64,
+    after: u64,
+}
+
+/// Runs `tinyserve precompress` with the arguments after it.
+pub fn run(args: &[String]) -> Result<()> {
+    let opts = parse(args)?;
+    if !opts.dir.is_dir() {
+        bail!("{} is not a directory", opts.dir.display());
+    }
+    let mut files = Vec::new();
+    walk(&opts.dir, &mut files)?;
+    files.sort();
+
+    if let Some(manifest) = &opts.manifest {
+        let added = fingerprint(&opts, &opts.dir.join(manifest), &files)?;
+        files.extend(added);
+        files.sort();
+        files.dedup();
+    }
+
+    let mut tally = Tally::default();
+    for file in &files {
+        let meta = fs::metadata(file).with_context(|| format!("{}", file.display()))?;
+        if meta.len() < opts.min_size || !eligible(&opts, file) {
+            continue;
+        }
+        let modified = meta.modified().ok();
+        let mut data = None;
+        let mut done = Vec::new();
+        for format in &opts.formats {
+            let target = sidecar_path(file, format);
+            if !opts.force && up_to_date(&target, modified) {
+                tally.fresh += 1;
+                continue;
+            }
+            let data = match &mut data {
+                Some(data) => data,
+                None => data
+                    .insert(fs::read(file).with_context(|| format!("reading {}", file.display()))?),
+            };
+            let packed = compress(format, data)
+                .with_context(|| format!("compressing {}", file.display()))?;
+            if packed.len() >= data.len() {
+                tally.not_smaller += 1;
+                continue;
+            }
+            fs::write(&target, &packed).with_context(|| format!("writing {}", target.display()))?;
+            tally.written += 1;
+            tally.before += data.len() as u64;
+            tally.after += packed.len() as u64;
+            done.push(format!(
+                "{format} {}%",
+                packed.len() as u64 * 100 / (data.len() as u64).max(1)
+            ));
+        }
+        if !done.is_empty() {
+            let name = file.strip_prefix(&opts.dir).unwrap_or(file);
+            println!("{}: {}", name.display(), done.join(", "));
+        }
+    }
+    println!(
+        "{} sidecar(s) written ({} -> {} bytes), {} up to date, {} not smaller",
+        tally.written, tally.before, tally.after, tally.fresh, tally.not_smaller
+    );
+    Ok(())
+}
+
+/// Regular files below `dir`, leaving out hidden entries, symlinks and
+/// existing sidecars.
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            walk(&entry.path(), out)?;
+        } else if kind.is_file() && !is_sidecar(&name) {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `file` passes `--exclude` and `--types`.
+fn eligible(opts: &Options, file: &Path) -> bool {
+    let rel = file.strip_prefix(&opts.dir).unwrap_or(file);
+    let rel = rel.to_string_lossy().replace('\\', "/");
+    if glob::matches_any(&opts.exclude, &rel) {
+        return false;
+    }
+    let content_type = mime::from_path(file);
+    if opts.types.is_empty() {
+        return compressible(content_type);
+    }
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    opts.types.iter().any(|t| match t.strip_suffix("/*") {
+        Some(family) => essence
+            .split_once('/')
+            .is_some_and(|(top, _)| top == family),
+        None => essence == t,
+    })
+}
+
+/// Whether a response of this type is worth compressing.
+fn compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    mime::is_text(content_type)
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/wasm" | "font/ttf" | "font/otf" | "application/vnd.ms-fontobject"
+        )
+}
+
+fn up_to_date(target: &Path, source: Option<SystemTime>) -> bool {
+    let built = fs::metadata(target).and_then(|m| m.modified()).ok();
+    matches!((built, source), (Some(built), Some(source)) if built >= source)
+}
+
+fn compress(format: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        "gz" => Ok(gzip(data)),
+        #[cfg(feature = "brotli")]
+        "br" => {
+            use std::io::Write;
+            let mut writer = brotli::CompressorWriter::new(Vec::new(), 1 << 16, 11, 22);
+            writer.write_all(data)?;
+            Ok(writer.into_inner())
+        }
+        #[cfg(feature = "zstd")]
+        "zst" => Ok(zstd::encode_all(data, 19)?),
+        other => bail!("unknown format `{other}`"),
+    }
+}
+
+/// `data` as a gzip member at the highest compression level.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags, no mtime, best compression, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 255];
+    out.extend(miniz_oxide::deflate::compress_to_vec(data, 9));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// CRC-32 (IEEE), as gzip's trailer wants.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Whether `stem` ends in a `.` and eight hex digits, as fingerprinted
+/// names do.
+fn looks_hashed(stem: &str) -> bool {
+    stem.rsplit_once('.')
+        .is_some_and(|(_, hash)| hash.len() == 8 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Copies the files matching `--fingerprint` below the manifest's directory
+/// to hashed names and writes the manifest. Returns the new copies.
+fn fingerprint(opts: &Options, manifest: &Path, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let mut entries = Map::new();
+    let mut added = Vec::new();
+    for file in files {
+        let Ok(rel) = file.strip_prefix(base) else {
+            continue;
+        };
+        let rel = rel.to_string_lossy().replace('\\', "/");
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        if file == manifest || looks_hashed(&stem) || !glob::matches_any(&opts.fingerprint, &rel) {
+            continue;
+        }
+        let data = fs::read(file).with_context(|| format!("reading {}", file.display()))?;
+        let hash = format!("{:016x}", fnv1a(&data));
+        let hashed_name = match file.extension() {
+            Some(ext) => format!("{stem}.{}.{}", &hash[..8], ext.to_string_lossy()),
+            None => format!("{stem}.{}", &hash[..8]),
+        };
+        let hashed = file.with_file_name(&hashed_name);
+        if !hashed.exists() {
+            fs::write(&hashed, &data).with_context(|| format!("writing {}", hashed.display()))?;
+            added.push(hashed);
+        }
+        let dir = rel
+            .rsplit_once('/')
+            .map_or(String::new(), |(d, _)| format!("{d}/"));
+        entries.insert(rel.clone(), Value::String(format!("{dir}{hashed_name}")));
+    }
+    let count = entries.len();
+    let text = serde_json::to_string_pretty(&Value::Object(entries))? + "\n";
+    fs::write(manifest, text).with_context(|| format!("writing {}", manifest.display()))?;
+    println!("{}: {count} fingerprinted file(s)", manifest.display());
+    Ok(added)
+}
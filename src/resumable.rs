@@ -0,0 +1,283 @@
+//! Resumable `PUT` uploads (`resumableUploads`), in writable mode.
+//!
+//! The body is written to a hidden `.<name>.tinyserve.part` beside the
+//! destination and renamed into place once all of it has arrived, so the
+//! destination keeps its previous version until then. If the connection
+//! drops first, the partial file stays. The client asks
+//! `GET /__tinyserve/upload-offset?path=/dir/name` how many bytes arrived
+//! and sends the rest with `Content-Range: bytes <offset>-<last>/<total>`.
+//! A chunk that does not finish the file is answered `202` with the new
+//! `Upload-Offset`.
+//!
+//! One upload per destination runs at a time; a second gets `409`. Partial
+//! files left untouched for `partialTtl` seconds are deleted by a
+//! background sweep.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+
+use crate::auth;
+use crate::http::request::percent_encode_path;
+use crate::http::{Request, Response};
+use crate::log;
+use crate::server::Context;
+use crate::writable;
+
+/// Path of the offset endpoint under `/__tinyserve/`.
+pub const OFFSET_PATH: &str = "upload-offset";
+
+/// Ending of partial files, after the destination's name.
+const SUFFIX: &str = ".tinyserve.part";
+
+/// Destinations with an upload in progress.
+#[derive(Default)]
+pub struct Uploads {
+    active: Mutex<HashSet<PathBuf>>,
+}
+
+/// Marks `dest` busy until dropped.
+struct Claim<'a> {
+    uploads: &'a Uploads,
+    dest: PathBuf,
+}
+
+impl Uploads {
+    fn claim(&self, dest: &Path) -> Option<Claim<'_>> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.insert(dest.to_path_buf()).then(|| Claim {
+            uploads: self,
+            dest: dest.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut active = self
+            .uploads
+            .active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        active.remove(&self.dest);
+    }
+}
+
+/// Where the partial upload for `dest` is kept.
+fn part_path(dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{name}{SUFFIX}"))
+}
+
+/// `bytes <first>-<last>/<total>` from `Content-Range`.
+fn content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last, total) = (
+        first.trim().parse().ok()?,
+        last.trim().parse().ok()?,
+        total.trim().parse().ok()?,
+    );
+    (first <= last && last < total).then_some((first, last, total))
+}
+
+/// `PUT /path` with `resumableUploads` on; `dest` has passed the checks of
+/// [`writable::put`].
+pub fn put(ctx: &Context, req: &mut Request, uploads: &Uploads, dest: &Path) -> Response {
+    let limit = ctx.config.int("maxUploadSize");
+    let length = req
+        .headers
+        .get("content-length")
+        .and_then(|v| v.parse::<u64>().ok());
+    let (offset, total) = match req.headers.get("content-range") {
+        Some(value) => match content_range(value) {
+            Some((first, last, total)) if length.is_none_or(|n| n == last - first + 1) => {
+                (first, Some(total))
+            }
+            _ => return Response::error_with(400, "invalid Content-Range"),
+        },
+        None => (0, length),
+    };
+    if total.is_some_and(|n| n > limit) {
+        return Response::error(413);
+    }
+    let Some(_claim) = uploads.claim(dest) else {
+        return Response::error_with(409, "an upload to this path is in progress");
+    };
+
+    let part = part_path(dest);
+    let opened = if offset == 0 {
+        File::create(&part)
+    } else {
+        OpenOptions::new().append(true).open(&part)
+    };
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return mismatch(0),
+        Err(e) => return writable::write_error(&e),
+    };
+    let have = file.metadata().map_or(0, |m| m.len());
+    if have != offset {
+        return mismatch(have);
+    }
+    let copied = writable::copy_limited(&mut req.body, &mut file, limit - offset);
+    // Whatever arrived is kept for a later resume, even if the rest did not.
+    let synced = file.sync_all();
+    let received = match copied.and_then(|n| synced.map(|()| n)) {
+        Ok(n) => offset + n,
+        Err(e) => return writable::write_error(&e),
+    };
+    match total {
+        Some(total) if received < total => Response::new(202)
+            .header("Upload-Offset", received.to_string())
+            .header("Cache-Control", "no-store"),
+        Some(total) if received > total => {
+            let _ = fs::remove_file(&part);
+            Response::error_with(400, "body is longer than the declared total")
+        }
+        _ => finish(ctx, req, &part, dest),
+    }
+}
+
+/// Moves the complete upload into place, unless its preconditions no
+/// longer hold, in which case it is dropped.
+fn finish(ctx: &Context, req: &Request, part: &Path, dest: &Path) -> Response {
+    let _guard = writable::lock();
+    if writable::conditional(&req.headers)
+        && let Err(resp) = writable::precondition(ctx, &req.headers, dest)
+    {
+        let _ = fs::remove_file(part);
+        return resp;
+    }
+    let existed = dest.exists();
+    if let Err(e) = fs::rename(part, dest) {
+        return writable::write_error(&e);
+    }
+    if existed {
+        Response::new(204)
+    } else {
+        Response::new(201).header("Location", percent_encode_path(&req.path))
+    }
+}
+
+/// `409` telling the client where to resume from.
+fn mismatch(have: u64) -> Response {
+    Response::error_with(409, &format!("upload offset is {have}"))
+        .header("Upload-Offset", have.to_string())
+}
+
+/// `GET /__tinyserve/upload-offset?path=/dir/name`: how many bytes of an
+/// interrupted upload arrived, `0` if none did.
+pub fn offset(ctx: &Context, req: &Request) -> Response {
+    if auth::enabled(ctx)
+        && let Err(resp) = auth::authenticate(ctx, req)
+    {
+        return resp;
+    }
+    let Some(path) = req.query_param("path") else {
+        return Response::error_with(400, "expected ?path=");
+    };
+    let dest = match writable::target(ctx, &path) {
+        Ok(dest) => dest,
+        Err(resp) => return resp,
+    };
+    let have = fs::metadata(part_path(&dest)).map_or(0, |m| m.len());
+    Response::json(200, &json!({ "path": path, "offset": have }))
+        .header("Upload-Offset", have.to_string())
+        .header("Cache-Control", "no-store")
+}
+
+/// Deletes partial uploads older than `partialTtl` under the root, every
+/// quarter of that (at least a minute), while the server is up.
+pub fn sweep(ctx: &Arc<Context>) {
+    let ttl = Duration::from_secs(ctx.config.int("partialTtl").max(1));
+    let every = (ttl / 4).max(Duration::from_secs(60));
+    let ctx = Arc::downgrade(ctx);
+    let spawned = thread::Builder::new()
+        .name("tinyserve-partials".into())
+        .spawn(move || {
+            loop {
+                thread::sleep(every);
+                let Some(ctx) = ctx.upgrade() else { break };
+                remove_stale(&ctx.root, ttl);
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn(&format!("cannot spawn partial upload sweep: {e}"));
+    }
+}
+
+fn remove_stale(dir: &Path, ttl: Duration) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if kind.is_dir() {
+            remove_stale(&entry.path(), ttl);
+        } else if kind.is_file() && name.starts_with('.') && name.ends_with(SUFFIX) {
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|at| SystemTime::now().duration_since(at).ok())
+                .is_some_and(|age| age > ttl);
+            if stale {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{AUTH, TestServer};
+
+    #[test]
+    fn uploads_resume_from_the_reported_offset() {
+        let server =
+            TestServer::with_auth(|b| b.option("readOnly", false).option("resumableUploads", true))
+                .unwrap();
+        let put = |range: &str, body: &[u8]| {
+            server
+                .request("PUT", "/f.txt", &[AUTH, ("Content-Range", range)], body)
+                .unwrap()
+        };
+        let offset = || {
+            let resp = server
+                .request(
+                    "GET",
+                    "/__tinyserve/upload-offset?path=/f.txt",
+                    &[AUTH],
+                    b"",
+                )
+                .unwrap();
+            assert_eq!(resp.status, 200);
+            resp.header("upload-offset").unwrap().to_owned()
+        };
+
+        let first = put("bytes 0-4/10", b"hello");
+        assert_eq!(first.status, 202);
+        assert_eq!(first.header("upload-offset"), Some("5"));
+        assert_eq!(offset(), "5");
+        assert_eq!(server.get("/f.txt").unwrap().status, 404);
+
+        let wrong = put("bytes 3-9/10", b"loworld");
+        assert_eq!(wrong.status, 409);
+        assert_eq!(wrong.header("upload-offset"), Some("5"));
+
+        assert_eq!(put("bytes 5-9/10", b"world").status, 201);
+        assert_eq!(server.get("/f.txt").unwrap().text(), "helloworld");
+        assert_eq!(offset(), "0");
+    }
+}
@@ -0,0 +1,481 @@
+//! Hardening applied once the listener is bound and the root is open, so a
+//! bug in path handling cannot reach the rest of the machine.
+//!
+//! - `sandboxUser` (`user` or `user:group`, names or ids): a server started
+//!   as root switches to that user, dropping supplementary groups.
+//! - `sandbox` (Linux 5.13+): Landlock confines file access to the root
+//!   (read-only unless `readOnly` is off) and the few other paths options
+//!   name, such as `tailDir` or `mocks`. Shared libraries and resolver
+//!   files stay readable, so host names still resolve. Files opened before,
+//!   like `record` and `auditLog`, keep working.
+//! - `sandboxSeccomp`, with `sandbox`: `execve`, `ptrace`, `mount`, module
+//!   loading, further uid changes and similar calls fail with `EPERM`.
+//!
+//! Both apply to the thread that bound the server and every thread it
+//! starts; the file watcher of `events`, started earlier, only reads the
+//! root. A kernel without Landlock is an error, not a silent downgrade.
+
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+
+use crate::server::Context;
+use crate::vfs::Remote;
+
+/// Applies the configured hardening to the current process.
+pub fn apply(ctx: &Context) -> Result<()> {
+    let config = &ctx.config;
+    let confine = config.bool("sandbox");
+    if confine && !cfg!(target_os = "linux") {
+        bail!("sandbox needs Linux (Landlock)");
+    }
+    let ruleset = if confine {
+        Some(imp::Ruleset::new(&paths(ctx))?)
+    } else {
+        None
+    };
+    match config.str("sandboxUser") {
+        "" => {}
+        spec => imp::switch_user(spec)?,
+    }
+    if let Some(ruleset) = ruleset {
+        ruleset.restrict()?;
+        if config.bool("sandboxSeccomp") {
+            imp::seccomp()?;
+        }
+    }
+    Ok(())
+}
+
+/// What stays reachable: `(path, writable)`. Paths that do not exist are
+/// left out.
+fn paths(ctx: &Context) -> Vec<(PathBuf, bool)> {
+    let config = &ctx.config;
+    let root = config.str("root");
+    let mut paths = if root.contains("://") {
+        let cache = match config.str("originCache") {
+            "" => Remote::default_cache_dir(root),
+            dir => PathBuf::from(dir),
+        };
+        vec![(cache, true)]
+    } else {
+        vec![(ctx.root.clone(), !config.bool("readOnly"))]
+    };
+    let named = [
+        "tailDir",
+        "localeDir",
+        "mocks",
+        "plugins",
+        "robots",
+        "favicon",
+    ]
+    .into_iter()
+    .map(|key| config.str(key))
+    .filter(|v| !matches!(*v, "" | "off" | "allow" | "disallow" | "builtin"));
+    paths.extend(named.map(|p| (PathBuf::from(p), false)));
+    paths.extend(SYSTEM_PATHS.iter().map(|p| (PathBuf::from(p), false)));
+    paths.retain(|(p, _)| p.exists());
+    paths
+}
+
+/// Read by the C library to resolve host names.
+const SYSTEM_PATHS: &[&str] = &[
+    "/etc/hosts",
+    "/etc/resolv.conf",
+    "/etc/nsswitch.conf",
+    "/etc/gai.conf",
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+];
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{Context as _, Result, bail};
+
+    // Landlock ABI, from linux/landlock.h.
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const REMOVE_DIR: u64 = 1 << 4;
+    const REMOVE_FILE: u64 = 1 << 5;
+    const MAKE_DIR: u64 = 1 << 7;
+    const MAKE_REG: u64 = 1 << 8;
+    /// Every right of ABI 1.
+    const ABI_1: u64 = (1 << 13) - 1;
+    const REFER: u64 = 1 << 13;
+    const TRUNCATE: u64 = 1 << 14;
+    const IOCTL_DEV: u64 = 1 << 15;
+    /// Rights that make sense on a file rather than a directory.
+    const FILE_RIGHTS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE | IOCTL_DEV;
+    const WRITE: u64 =
+        WRITE_FILE | REMOVE_DIR | REMOVE_FILE | MAKE_DIR | MAKE_REG | REFER | TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// A Landlock ruleset built while every path can still be opened.
+    pub struct Ruleset {
+        fd: OwnedFd,
+    }
+
+    impl Ruleset {
+        pub fn new(paths: &[(PathBuf, bool)]) -> Result<Self> {
+            // SAFETY: asking for the ABI version takes no attribute.
+            let abi = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    std::ptr::null::<RulesetAttr>(),
+                    0usize,
+                    CREATE_RULESET_VERSION,
+                )
+            };
+            if abi < 1 {
+                bail!(
+                    "sandbox: Landlock is not available ({}); it needs Linux 5.13+ with Landlock enabled",
+                    io::Error::last_os_error()
+                );
+            }
+            let mut handled = ABI_1;
+            if abi >= 2 {
+                handled |= REFER;
+            }
+            if abi >= 3 {
+                handled |= TRUNCATE;
+            }
+            if abi >= 5 {
+                handled |= IOCTL_DEV;
+            }
+            let attr = RulesetAttr {
+                handled_access_fs: handled,
+            };
+            // SAFETY: `attr` is a valid ruleset attribute of the given size.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    &attr,
+                    size_of::<RulesetAttr>(),
+                    0,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error()).context("sandbox: creating the ruleset");
+            }
+            // SAFETY: the kernel just returned this descriptor to us.
+            let ruleset = Self {
+                fd: unsafe { OwnedFd::from_raw_fd(fd as i32) },
+            };
+            for (path, writable) in paths {
+                let mut access = READ_FILE | READ_DIR;
+                if *writable {
+                    access |= WRITE;
+                }
+                ruleset
+                    .allow(path, access & handled)
+                    .with_context(|| format!("sandbox: allowing {}", path.display()))?;
+            }
+            Ok(ruleset)
+        }
+
+        fn allow(&self, path: &Path, mut access: u64) -> io::Result<()> {
+            let file = File::open(path)?;
+            if !file.metadata()?.is_dir() {
+                access &= FILE_RIGHTS;
+            }
+            let attr = PathBeneathAttr {
+                allowed_access: access,
+                parent_fd: file.as_raw_fd(),
+            };
+            // SAFETY: `attr` is a valid path-beneath rule for an open file.
+            let rc = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    self.fd.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &attr,
+                    0,
+                )
+            };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// Confines the calling thread and the threads it starts.
+        pub fn restrict(self) -> Result<()> {
+            no_new_privs()?;
+            // SAFETY: `self.fd` is a ruleset descriptor.
+            let rc =
+                unsafe { libc::syscall(libc::SYS_landlock_restrict_self, self.fd.as_raw_fd(), 0) };
+            if rc < 0 {
+                return Err(io::Error::last_os_error()).context("sandbox: restricting");
+            }
+            Ok(())
+        }
+    }
+
+    fn no_new_privs() -> Result<()> {
+        // SAFETY: plain prctl with integer arguments.
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error()).context("sandbox: no_new_privs");
+        }
+        Ok(())
+    }
+
+    /// Switches to `spec` (`user` or `user:group`) and drops supplementary
+    /// groups.
+    pub fn switch_user(spec: &str) -> Result<()> {
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        let (uid, user_gid) = lookup_user(user)?;
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => user_gid,
+        };
+        // SAFETY: plain id queries and changes; glibc applies them to every
+        // thread.
+        unsafe {
+            if libc::geteuid() == uid && libc::getegid() == gid {
+                return Ok(());
+            }
+            if libc::geteuid() != 0 {
+                bail!("sandboxUser: switching to {spec} needs tinyserve to start as root");
+            }
+            if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("sandboxUser: switching to {spec}"));
+            }
+            if uid != 0 && libc::setuid(0) == 0 {
+                bail!("sandboxUser: root privileges could be regained");
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+        let c_name = CString::new(name)?;
+        // SAFETY: `getpwnam` returns null or a valid entry, read at once.
+        let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+        if !pw.is_null() {
+            return Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) });
+        }
+        match name.parse() {
+            Ok(uid) => Ok((uid, uid)),
+            Err(_) => bail!("sandboxUser: no user `{name}`"),
+        }
+    }
+
+    fn lookup_group(name: &str) -> Result<libc::gid_t> {
+        let c_name = CString::new(name)?;
+        // SAFETY: `getgrnam` returns null or a valid entry, read at once.
+        let gr = unsafe { libc::getgrnam(c_name.as_ptr()) };
+        if !gr.is_null() {
+            return Ok(unsafe { (*gr).gr_gid });
+        }
+        name.parse()
+            .map_err(|_| anyhow::anyhow!("sandboxUser: no group `{name}`"))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// System calls a file server never needs, refused with `EPERM`.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_setuid,
+        libc::SYS_setgid,
+        libc::SYS_setreuid,
+        libc::SYS_setregid,
+        libc::SYS_setresuid,
+        libc::SYS_setresgid,
+        libc::SYS_setgroups,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_kexec_load,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+    ];
+
+    /// Installs the filter on every thread of the process.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub fn seccomp() -> Result<()> {
+        const LOAD: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+        const JEQ: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+        const RET: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+        let stmt = |code, k| libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jeq = |k, jt, jf| libc::sock_filter {
+            code: JEQ,
+            jt,
+            jf,
+            k,
+        };
+        let refuse = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        // `arch` follows the 4-byte `nr` in `seccomp_data`.
+        let mut filter = vec![
+            stmt(LOAD, 4),
+            jeq(AUDIT_ARCH, 1, 0),
+            stmt(RET, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(LOAD, 0),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            // x32 system calls reach the same kernel code under other numbers.
+            const JGE: u16 = (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16;
+            filter.push(libc::sock_filter {
+                code: JGE,
+                jt: 0,
+                jf: 1,
+                k: 0x4000_0000,
+            });
+            filter.push(stmt(RET, refuse));
+        }
+        for &nr in DENIED {
+            filter.push(jeq(nr as u32, 0, 1));
+            filter.push(stmt(RET, refuse));
+        }
+        filter.push(stmt(RET, libc::SECCOMP_RET_ALLOW));
+        let prog = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        no_new_privs()?;
+        // SAFETY: `prog` points at `filter`, which outlives the call.
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &prog,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error())
+                .context("sandboxSeccomp: installing the filter");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn seccomp() -> Result<()> {
+        bail!("sandboxSeccomp is not supported on this architecture; turn it off")
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{Result, bail};
+
+    pub struct Ruleset;
+
+    impl Ruleset {
+        pub fn new(_paths: &[(PathBuf, bool)]) -> Result<Self> {
+            bail!("sandbox needs Linux (Landlock)")
+        }
+
+        pub fn restrict(self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn switch_user(_spec: &str) -> Result<()> {
+        bail!("sandboxUser is only supported on Linux")
+    }
+
+    pub fn seccomp() -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use std::thread;
+
+    use crate::test::TestServer;
+
+    #[test]
+    fn confines_the_server_to_its_root() {
+        let outside = TestServer::new().unwrap();
+        outside.write("secret.txt", "secret").unwrap();
+        let secret = outside.root().unwrap().join("secret.txt");
+        // The sandbox holds the thread that starts the server and every
+        // thread it starts; the test thread stays free to clean up.
+        let started = thread::spawn(move || {
+            let server = TestServer::with(|b| {
+                b.option("readOnly", false)
+                    .option("sandbox", true)
+                    .option("sandboxSeccomp", true)
+            })?;
+            let read = fs::read(&secret).map_err(|e| e.kind());
+            let exec = Command::new("/bin/true").status().map_err(|e| e.kind());
+            anyhow::Ok((server, read, exec))
+        })
+        .join()
+        .unwrap();
+        let (server, read, exec) = match started {
+            Ok(started) => started,
+            Err(e) if format!("{e:#}").contains("Landlock is not available") => return,
+            Err(e) => panic!("{e:#}"),
+        };
+        assert_eq!(read, Err(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(exec, Err(std::io::ErrorKind::PermissionDenied));
+
+        server.write("page.txt", "inside").unwrap();
+        assert_eq!(server.get("/page.txt").unwrap().text(), "inside");
+        let put = server.request("PUT", "/new.txt", &[], b"new").unwrap();
+        assert_eq!(put.status, 201);
+        assert_eq!(
+            fs::read(server.root().unwrap().join("new.txt")).unwrap(),
+            b"new"
+        );
+    }
+}
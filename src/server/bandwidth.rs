@@ -0,0 +1,155 @@
+//! Bandwidth caps, so one big download cannot take the whole uplink of the
+//! box tinyserve runs on.
+//!
+//! `bandwidth` caps what all connections together send, e.g. `20Mbps`.
+//! `bandwidthLimits` caps single responses by path, as `[GLOB=]RATE` rules
+//! such as `*.iso=2MB/s`; the first matching rule applies. Rates take the
+//! units of `throttle`. Both are token buckets drawn from as the response
+//! is written, head included, so a capped response is not sent with
+//! `sendfile`.
+
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+
+use crate::core::Config;
+use crate::glob;
+
+use super::throttle;
+
+/// Largest slice written at once, so buckets are drawn from evenly.
+const MAX_SLICE: u64 = 64 * 1024;
+
+/// A token bucket of `rate` bytes per second. Draws may overdraw it; the
+/// drawer then waits until the debt is paid, so concurrent writers queue
+/// in turn instead of spinning.
+pub struct Bucket {
+    rate: u64,
+    /// Most tokens that build up while idle: a tenth of a second's worth.
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    pub fn new(rate: u64) -> Self {
+        let burst = (rate as f64 / 10.0).max(1024.0);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Takes `n` tokens, sleeping until they are available.
+    pub fn take(&self, n: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            let refill = now.duration_since(*last).as_secs_f64() * self.rate as f64;
+            *tokens = (*tokens + refill).min(self.burst) - n as f64;
+            *last = now;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / self.rate as f64))
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    /// A slice small enough to draw smoothly: about 1/20 s of traffic.
+    fn slice(&self) -> u64 {
+        (self.rate / 20).clamp(512, MAX_SLICE)
+    }
+}
+
+/// The configured caps.
+#[derive(Default)]
+pub struct Bandwidth {
+    /// Shared by every connection, from `bandwidth`.
+    pub global: Option<Bucket>,
+    /// `(glob, rate)` from `bandwidthLimits`; `None` matches every path.
+    pub rules: Vec<(Option<String>, u64)>,
+}
+
+impl Bandwidth {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let global = match config.str("bandwidth").trim() {
+            "" => None,
+            rate => Some(Bucket::new(
+                throttle::parse_rate(rate).with_context(|| format!("bandwidth `{rate}`"))?,
+            )),
+        };
+        let rules = config
+            .list("bandwidthLimits")
+            .iter()
+            .map(|rule| {
+                let (glob, rate) = match rule.split_once('=') {
+                    Some((glob, rate)) => (Some(glob.trim().to_owned()), rate.trim()),
+                    None => (None, rule.trim()),
+                };
+                let rate = throttle::parse_rate(rate)
+                    .with_context(|| format!("bandwidth limit `{rule}`"))?;
+                Ok((glob, rate))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { global, rules })
+    }
+
+    /// The per-response rate for `path`, if a rule matches.
+    pub fn limit(&self, path: &str) -> Option<u64> {
+        self.rules
+            .iter()
+            .find(|(g, _)| g.as_deref().is_none_or(|g| glob::matches(g, path)))
+            .map(|(_, rate)| *rate)
+    }
+
+    /// Whether a response for `path` is capped at all.
+    pub fn applies(&self, path: &str) -> bool {
+        self.global.is_some() || self.limit(path).is_some()
+    }
+}
+
+/// Writes through to `inner`, drawing every byte from the global bucket and
+/// the response's own.
+pub struct Capped<'a> {
+    inner: &'a mut dyn Write,
+    global: Option<&'a Bucket>,
+    own: Option<Bucket>,
+}
+
+impl<'a> Capped<'a> {
+    pub fn new(inner: &'a mut dyn Write, bandwidth: &'a Bandwidth, path: &str) -> Self {
+        Self {
+            inner,
+            global: bandwidth.global.as_ref(),
+            own: bandwidth.limit(path).map(Bucket::new),
+        }
+    }
+}
+
+impl Write for Capped<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let slice = [self.global, self.own.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(Bucket::slice)
+            .min()
+            .unwrap_or(MAX_SLICE);
+        let n = buf.len().min(usize::try_from(slice).unwrap_or(usize::MAX));
+        if let Some(own) = &self.own {
+            own.take(n as u64);
+        }
+        if let Some(global) = self.global {
+            global.take(n as u64);
+        }
+        self.inner.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
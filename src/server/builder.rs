@@ -0,0 +1,353 @@
+//! Embedding tinyserve: [`ServerBuilder`] and the [`ServerHandle`] of a
+//! server running on a background thread.
+//!
+//! ```no_run
+//! # fn main() -> tinyserve::Result<()> {
+//! let handle = tinyserve::Server::builder()
+//!     .root("public")
+//!     .bind("127.0.0.1:0")
+//!     .option("showDir", true)
+//!     .route("/api/version", |_, _| {
+//!         tinyserve::http::Response::text(200, env!("CARGO_PKG_VERSION"))
+//!     })
+//!     .start()?;
+//! println!("serving at {}", handle.url());
+//! handle.stop()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::auth::AuthProvider;
+use crate::core::{Aliases, Config, Layer};
+use crate::error::{Error, Result};
+use crate::files::listing::ListingRenderer;
+use crate::http::{Request, Response};
+use crate::vfs::Vfs;
+
+use super::callbacks::{Callbacks, ErrorEvent, RequestEvent};
+use super::lifecycle::Ready;
+use super::middleware::{Completed, Middleware};
+use super::routes::Routes;
+use super::transform::Transform;
+use super::{Context, Server};
+
+/// Configures a [`Server`]. Starts from the built-in defaults; no config
+/// file or environment variables are read.
+#[must_use]
+pub struct ServerBuilder {
+    config: Config,
+    aliases: Aliases,
+    middleware: Vec<Box<dyn Middleware>>,
+    callbacks: Callbacks,
+    routes: Routes,
+    transforms: Vec<Box<dyn Transform>>,
+    listing_renderers: Vec<Box<dyn ListingRenderer>>,
+    auth: Vec<Box<dyn AuthProvider>>,
+    vfs: Option<Arc<dyn Vfs>>,
+    /// First error from a setter, reported by `build`.
+    error: Option<Error>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new(Config::default())
+    }
+}
+
+impl ServerBuilder {
+    /// Starts from an existing configuration, e.g. one loaded by the CLI
+    /// layers.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            aliases: Aliases::builtin(),
+            middleware: Vec::new(),
+            callbacks: Callbacks::default(),
+            routes: Routes::default(),
+            transforms: Vec::new(),
+            listing_renderers: Vec::new(),
+            auth: Vec::new(),
+            vfs: None,
+            error: None,
+        }
+    }
+
+    /// Resolves option names against `aliases` instead of the built-in set.
+    pub fn aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    fn record(&mut self, result: Result<()>) {
+        if let Err(e) = result
+            && self.error.is_none()
+        {
+            self.error = Some(e);
+        }
+    }
+
+    /// Directory to serve.
+    pub fn root(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.option("root", path)
+    }
+
+    /// Address to listen on, as `host:port`; port 0 picks a free one.
+    pub fn bind(mut self, addr: impl AsRef<str>) -> Self {
+        let addr = addr.as_ref();
+        let result = match addr.rsplit_once(':') {
+            Some((host, port)) => {
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                self.config
+                    .set_raw("host", host, Layer::Cli)
+                    .and_then(|()| self.config.set_raw("port", port, Layer::Cli))
+            }
+            None => Err(Error::Config(format!("bind address `{addr}` needs a port"))),
+        };
+        self.record(result);
+        self
+    }
+
+    /// Sets any option by name or alias, as a config file would.
+    pub fn option(mut self, key: &str, value: impl Into<Value>) -> Self {
+        let value = value.into();
+        let result = match self.aliases.resolve(key) {
+            Some(key) => self.config.set_json(key, &value, Layer::Cli),
+            None => Err(Error::Config(format!("unknown option `{key}`"))),
+        };
+        self.record(result);
+        self
+    }
+
+    /// Serves files from `vfs` instead of the `root` directory. The server
+    /// is then read-only.
+    pub fn vfs(mut self, vfs: impl Vfs + 'static) -> Self {
+        self.vfs = Some(Arc::new(vfs));
+        self
+    }
+
+    /// Appends a stage to the request pipeline. Stages run in the order
+    /// added, after the built-in ones (authentication, internal endpoints,
+    /// FastCGI) and before routes.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Calls `f` for every request as it arrives, before authentication or
+    /// any other stage.
+    pub fn on_request(mut self, f: impl Fn(&RequestEvent<'_>) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_request(f);
+        self
+    }
+
+    /// Calls `f` once each response has been sent, or sending failed.
+    pub fn on_response(mut self, f: impl Fn(&Completed<'_>) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_response(f);
+        self
+    }
+
+    /// Calls `f` for responses with a `5xx` status and responses that could
+    /// not be sent.
+    pub fn on_error(mut self, f: impl Fn(&ErrorEvent<'_>) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_error(f);
+        self
+    }
+
+    /// Rewrites the bodies of successful text responses (HTML unless
+    /// [`Transform::applies`] says otherwise), after any `injectHtml`.
+    pub fn transform(mut self, transform: impl Transform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Renders directory listings with `renderer` for the requests it
+    /// [applies](ListingRenderer::applies) to, ahead of the built-in JSON
+    /// and HTML renderers.
+    pub fn listing_renderer(mut self, renderer: impl ListingRenderer + 'static) -> Self {
+        self.listing_renderers.push(Box::new(renderer));
+        self
+    }
+
+    /// Checks credentials with `provider` as well, after the built-in
+    /// `authUsers` and `authToken`. Wherever credentials are demanded
+    /// (`authFor`, `require-auth` policy rules, access files, writes), a
+    /// request signed in through any provider gets in.
+    pub fn auth_provider(mut self, provider: impl AuthProvider + 'static) -> Self {
+        self.auth.push(Box::new(provider));
+        self
+    }
+
+    /// Answers requests for exactly `path` with `handler`, ahead of the
+    /// filesystem.
+    pub fn route<F>(mut self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Context, &mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.add(path, Box::new(handler));
+        self
+    }
+
+    /// Answers requests for `prefix` and every path below it with `handler`.
+    /// The longest matching prefix wins; exact routes win over prefixes.
+    pub fn route_prefix<F>(mut self, prefix: &str, handler: F) -> Self
+    where
+        F: Fn(&Context, &mut Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.add_prefix(prefix, Box::new(handler));
+        self
+    }
+
+    /// Binds the listener without serving yet.
+    pub fn build(self) -> Result<Server> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut ctx = match self.vfs {
+            Some(vfs) => Context::with_vfs(self.config, vfs),
+            None => Context::new(self.config),
+        }
+        .map_err(Error::from_anyhow)?;
+        if !self.callbacks.is_empty() {
+            ctx.middleware.insert(0, Box::new(self.callbacks));
+        }
+        ctx.middleware.extend(self.middleware);
+        ctx.transforms.extend(self.transforms);
+        ctx.listing_renderers.splice(0..0, self.listing_renderers);
+        ctx.auth.extend(self.auth);
+        if !self.routes.is_empty() {
+            ctx.middleware.push(Box::new(self.routes));
+        }
+        Server::from_context(ctx)
+    }
+
+    /// Binds and serves on a background thread.
+    pub fn start(self) -> Result<ServerHandle> {
+        self.build()?.start()
+    }
+}
+
+/// A server running on a background thread. Dropping the handle leaves the
+/// server running; call [`stop`](Self::stop) or
+/// [`shutdown`](Self::shutdown) to end it.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    /// Every listener's address, to wake each accept loop.
+    addrs: Vec<SocketAddr>,
+    ctx: Arc<Context>,
+    /// Taken by the first [`join`](Self::join).
+    thread: Mutex<Option<JoinHandle<Result<()>>>>,
+}
+
+impl Server {
+    /// Serves on a background thread.
+    pub fn start(self) -> Result<ServerHandle> {
+        let addr = self.local_addr();
+        let addrs = self.local_addrs();
+        let ctx = Arc::clone(&self.ctx);
+        let thread = thread::Builder::new()
+            .name("tinyserve-accept".into())
+            .spawn(move || self.run())
+            .map_err(|source| Error::Io {
+                context: "cannot start the server thread".into(),
+                source,
+            })?;
+        Ok(ServerHandle {
+            addr,
+            addrs,
+            ctx,
+            thread: Mutex::new(Some(thread)),
+        })
+    }
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `http://addr/`, ready to request.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    pub fn context(&self) -> &Arc<Context> {
+        &self.ctx
+    }
+
+    /// Resolves once the server accepts connections; fails if it stopped
+    /// first. The listener is bound before [`start`](ServerBuilder::start)
+    /// returns, so connecting earlier is safe, just not yet answered.
+    pub fn ready(&self) -> Ready<'_> {
+        self.ctx.lifecycle.ready()
+    }
+
+    /// Blocking form of [`ready`](Self::ready).
+    pub fn wait_ready(&self) -> Result<()> {
+        self.ctx.lifecycle.wait_ready()
+    }
+
+    /// Stops accepting connections and waits for the accept loop to exit.
+    /// Requests already in progress run to completion.
+    pub fn stop(self) -> Result<()> {
+        self.close();
+        self.join()
+    }
+
+    /// Stops accepting connections, then waits up to `deadline` for
+    /// requests in progress to finish. Fails if some are still running.
+    pub fn shutdown(&self, deadline: Duration) -> Result<()> {
+        let until = Instant::now() + deadline;
+        self.close();
+        self.join()?;
+        if !self.ctx.lifecycle.wait_idle(until) {
+            return Err(Error::Timeout {
+                active: self.ctx.lifecycle.active(),
+                deadline,
+            });
+        }
+        Ok(())
+    }
+
+    /// Waits for the server to stop, returning how its accept loop ended.
+    /// Later calls return at once.
+    pub fn join(&self) -> Result<()> {
+        let mut thread = self.thread.lock().unwrap_or_else(|e| e.into_inner());
+        match thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| Error::Stopped("server thread panicked".into()))?,
+            None => Ok(()),
+        }
+    }
+
+    /// Tells the accept loop to exit.
+    fn close(&self) {
+        self.ctx.shutdown.store(true, Ordering::SeqCst);
+        self.ctx.streams.close();
+        // Wake the accept loops; they check the flag before serving.
+        for addr in &self.addrs {
+            let _ = TcpStream::connect(wake_addr(*addr));
+        }
+    }
+}
+
+/// The listener's address, with an unspecified IP replaced by loopback.
+fn wake_addr(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        match addr {
+            SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+            SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    addr
+}
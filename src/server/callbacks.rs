@@ -0,0 +1,106 @@
+//! Observers registered with [`ServerBuilder::on_request`],
+//! [`on_response`](ServerBuilder::on_response) and
+//! [`on_error`](ServerBuilder::on_error): plain closures for metrics and
+//! audit trails that don't need a full [`Middleware`].
+//!
+//! They run ahead of every other stage, so `on_request` also sees requests
+//! that authentication or a plugin turns away.
+//!
+//! [`ServerBuilder::on_request`]: super::ServerBuilder::on_request
+//! [`ServerBuilder::on_response`]: super::ServerBuilder::on_response
+//! [`ServerBuilder::on_error`]: super::ServerBuilder::on_error
+
+use std::io;
+use std::net::SocketAddr;
+
+use crate::http::{Headers, Request, Response};
+
+use super::Context;
+use super::middleware::{Completed, Middleware};
+
+/// A request as it arrived, before any stage has seen it.
+#[derive(Debug)]
+pub struct RequestEvent<'a> {
+    pub peer: SocketAddr,
+    pub method: &'a str,
+    /// Percent-decoded path.
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub headers: &'a Headers,
+}
+
+/// A request that ended badly: the server answered `5xx`, or the response
+/// could not be sent.
+#[derive(Debug)]
+pub struct ErrorEvent<'a> {
+    pub peer: SocketAddr,
+    /// `METHOD target VERSION`.
+    pub request_line: &'a str,
+    pub status: u16,
+    /// Why sending failed; `None` for a `5xx` that was sent.
+    pub error: Option<&'a io::Error>,
+}
+
+type OnRequest = Box<dyn Fn(&RequestEvent<'_>) + Send + Sync>;
+type OnResponse = Box<dyn Fn(&Completed<'_>) + Send + Sync>;
+type OnError = Box<dyn Fn(&ErrorEvent<'_>) + Send + Sync>;
+
+#[derive(Default)]
+pub struct Callbacks {
+    request: Vec<OnRequest>,
+    response: Vec<OnResponse>,
+    error: Vec<OnError>,
+}
+
+impl Callbacks {
+    pub fn is_empty(&self) -> bool {
+        self.request.is_empty() && self.response.is_empty() && self.error.is_empty()
+    }
+
+    pub fn on_request(&mut self, f: impl Fn(&RequestEvent<'_>) + Send + Sync + 'static) {
+        self.request.push(Box::new(f));
+    }
+
+    pub fn on_response(&mut self, f: impl Fn(&Completed<'_>) + Send + Sync + 'static) {
+        self.response.push(Box::new(f));
+    }
+
+    pub fn on_error(&mut self, f: impl Fn(&ErrorEvent<'_>) + Send + Sync + 'static) {
+        self.error.push(Box::new(f));
+    }
+}
+
+impl Middleware for Callbacks {
+    fn before_request(&self, _: &Context, req: &mut Request) -> Option<Response> {
+        if !self.request.is_empty() {
+            let event = RequestEvent {
+                peer: req.peer,
+                method: &req.method,
+                path: &req.path,
+                query: req.query.as_deref(),
+                headers: &req.headers,
+            };
+            for f in &self.request {
+                f(&event);
+            }
+        }
+        None
+    }
+
+    fn on_complete(&self, _: &Context, done: &Completed<'_>) {
+        for f in &self.response {
+            f(done);
+        }
+        if done.status >= 500 || done.error.is_some() {
+            let event = ErrorEvent {
+                peer: done.peer,
+                request_line: done.request_line,
+                status: done.status,
+                error: done.error,
+            };
+            for f in &self.error {
+                f(&event);
+            }
+        }
+    }
+}
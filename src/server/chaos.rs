@@ -0,0 +1,172 @@
+//! Chaos mode: `chaos` rules make a share of responses fail on purpose, so
+//! client retry and error handling can be exercised locally.
+//!
+//! Each rule reads `[GLOB=]FAULT:PERCENT`, e.g. `error:5` for every path or
+//! `/api/**=drop:10`. Matching rules are rolled in order and the first hit
+//! picks the fault:
+//!
+//! - `error` answers `500` instead of the real response;
+//! - `truncate` sends the head and about half the body, then closes;
+//! - `stall` holds the response for `chaosStall` milliseconds first;
+//! - `drop` closes the connection without answering.
+//!
+//! Built-in endpoints under `internalPrefix` are left alone.
+
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::core::Config;
+use crate::glob;
+use crate::http::Response;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    Error,
+    Truncate,
+    Stall,
+    Drop,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: Option<String>,
+    fault: Fault,
+    percent: f64,
+}
+
+pub struct Chaos {
+    rules: Vec<Rule>,
+    /// How long `stall` holds a response.
+    pub stall: Duration,
+    /// xorshift state.
+    state: AtomicU64,
+}
+
+impl Chaos {
+    /// The rules configured by `chaos`; `None` when there are none.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let rules = config
+            .list("chaos")
+            .iter()
+            .map(|rule| parse(rule).with_context(|| format!("chaos rule `{rule}`")))
+            .collect::<Result<Vec<_>>>()?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            rules,
+            stall: Duration::from_millis(config.int("chaosStall")),
+            state: AtomicU64::new(RandomState::new().hash_one(0u64) | 1),
+        }))
+    }
+
+    /// A uniform number in `[0, 100)`.
+    fn roll(&self) -> f64 {
+        let mut x = self.state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        // Racing updates only make the sequence less predictable.
+        self.state.store(x, Ordering::Relaxed);
+        (x >> 11) as f64 / (1u64 << 53) as f64 * 100.0
+    }
+
+    /// The fault to inject for a request to `path`, if any.
+    pub fn pick(&self, path: &str) -> Option<Fault> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.glob.as_deref().is_none_or(|g| glob::matches(g, path)))
+            .find(|rule| self.roll() < rule.percent)
+            .map(|rule| rule.fault)
+    }
+}
+
+fn parse(rule: &str) -> Result<Rule> {
+    let (glob, spec) = match rule.split_once('=') {
+        Some((glob, spec)) => (Some(glob.trim().to_owned()), spec.trim()),
+        None => (None, rule.trim()),
+    };
+    let (fault, percent) = spec
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected FAULT:PERCENT"))?;
+    let fault = match fault.trim() {
+        "error" => Fault::Error,
+        "truncate" => Fault::Truncate,
+        "stall" => Fault::Stall,
+        "drop" => Fault::Drop,
+        other => bail!("unknown fault `{other}` (error, truncate, stall, drop)"),
+    };
+    let percent: f64 = percent
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| anyhow!("bad percentage `{percent}`"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        bail!("percentage `{percent}` is out of range");
+    }
+    Ok(Rule {
+        glob,
+        fault,
+        percent,
+    })
+}
+
+/// The response sent in place of the real one for [`Fault::Error`].
+pub fn error() -> Response {
+    Response::error_with(500, "failure injected by chaos mode")
+}
+
+/// Passes the response head and `budget` body bytes to `inner`, then fails
+/// as if the connection broke.
+pub struct Cut<'a> {
+    inner: &'a mut dyn Write,
+    budget: u64,
+    /// Bytes of the `\r\n\r\n` that ends the head seen so far; 4 once in
+    /// the body.
+    head_end: usize,
+}
+
+impl<'a> Cut<'a> {
+    pub fn new(inner: &'a mut dyn Write, budget: u64) -> Self {
+        Self {
+            inner,
+            budget,
+            head_end: 0,
+        }
+    }
+}
+
+impl Write for Cut<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut allowed = 0;
+        while allowed < buf.len() && self.head_end < 4 {
+            let want = b"\r\n\r\n"[self.head_end];
+            self.head_end = match buf[allowed] {
+                b if b == want => self.head_end + 1,
+                b'\r' => 1,
+                _ => 0,
+            };
+            allowed += 1;
+        }
+        let body = (buf.len() - allowed).min(usize::try_from(self.budget).unwrap_or(usize::MAX));
+        allowed += body;
+        self.budget -= body as u64;
+        if allowed == 0 {
+            self.inner.flush()?;
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "response truncated by chaos mode",
+            ));
+        }
+        self.inner.write_all(&buf[..allowed])?;
+        Ok(allowed)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
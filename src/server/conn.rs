@@ -0,0 +1,297 @@
+//! One client connection: parse requests, dispatch, write responses, repeat
+//! while the connection stays persistent.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http::body::Framing;
+use crate::http::response::{WriteOptions, Written};
+use crate::http::{Request, RequestBody, Response, Version};
+
+use super::bandwidth::Capped;
+use super::chaos::{self, Cut, Fault};
+use super::middleware::{self, Completed};
+use super::shed::{Pressure, Shed};
+use super::throttle::{self, Paced};
+use super::{Context, early_hints, handler, pool};
+
+/// How long an idle persistent connection is kept open.
+pub(super) const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Unread request body bytes discarded to keep a connection reusable.
+const MAX_DRAIN: u64 = 1024 * 1024;
+
+/// The read half of a connection, shared between the loop and the body
+/// reader handed to the handler.
+struct Inbound {
+    reader: BufReader<TcpStream>,
+    framing: Framing,
+    /// Set while a `100 Continue` is owed to a client that sent
+    /// `Expect: 100-continue`; sent on the first body read.
+    continue_to: Option<TcpStream>,
+}
+
+struct BodyReader(Arc<Mutex<Inbound>>);
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inbound = self.0.lock().unwrap();
+        if let Some(mut stream) = inbound.continue_to.take() {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        let Inbound {
+            reader, framing, ..
+        } = &mut *inbound;
+        framing.read(reader, buf)
+    }
+}
+
+/// One client connection between requests.
+pub(super) struct Connection {
+    inbound: Arc<Mutex<Inbound>>,
+    out: BufWriter<TcpStream>,
+    /// A handle on the socket for `sendfile`, when enabled.
+    zero_copy: Option<TcpStream>,
+    peer: SocketAddr,
+    local: SocketAddr,
+}
+
+impl Connection {
+    pub(super) fn new(ctx: &Context, stream: TcpStream) -> Option<Self> {
+        let (Ok(peer), Ok(local)) = (stream.peer_addr(), stream.local_addr()) else {
+            return None;
+        };
+        // IPv4 clients of a dual-stack socket arrive v4-mapped.
+        let canonical = |addr: SocketAddr| SocketAddr::new(addr.ip().to_canonical(), addr.port());
+        let (peer, local) = (canonical(peer), canonical(local));
+        let _ = stream.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT));
+        let _ = stream.set_nodelay(true);
+        let read_half = stream.try_clone().ok()?;
+        let zero_copy = ctx
+            .config
+            .bool("sendfile")
+            .then(|| stream.try_clone().ok())
+            .flatten();
+        Some(Self {
+            inbound: Arc::new(Mutex::new(Inbound {
+                reader: BufReader::new(read_half),
+                framing: Framing::Done,
+                continue_to: None,
+            })),
+            out: BufWriter::new(stream),
+            zero_copy,
+            peer,
+            local,
+        })
+    }
+
+    #[cfg(all(feature = "tokio", unix))]
+    pub(super) fn socket(&self) -> &TcpStream {
+        self.out.get_ref()
+    }
+
+    /// Whether a (pipelined) request is already buffered.
+    #[cfg(all(feature = "tokio", unix))]
+    pub(super) fn has_buffered(&self) -> bool {
+        !self.inbound.lock().unwrap().reader.buffer().is_empty()
+    }
+
+    /// Reads, handles and answers one request. Returns whether the
+    /// connection can carry another.
+    pub(super) fn serve_next(&mut self, ctx: &Context) -> bool {
+        let head = Request::read_head(&mut self.inbound.lock().unwrap().reader, self.peer);
+        let mut req = match head {
+            Ok(Some(req)) => req,
+            Ok(None) => return false,
+            Err(e) => {
+                if e.status != 408 {
+                    let opts = WriteOptions {
+                        version: Version::Http11,
+                        head_only: false,
+                        keep_alive: false,
+                        socket: None,
+                        identity: &ctx.identity,
+                    };
+                    let _ = Response::error_with(e.status, e.reason).write_to(&mut self.out, opts);
+                }
+                return false;
+            }
+        };
+        let framing = match req.framing() {
+            Ok(framing) => framing,
+            Err(e) => {
+                let opts = WriteOptions {
+                    version: req.version,
+                    head_only: false,
+                    keep_alive: false,
+                    socket: None,
+                    identity: &ctx.identity,
+                };
+                let _ = Response::error_with(e.status, e.reason).write_to(&mut self.out, opts);
+                return false;
+            }
+        };
+        {
+            let mut inbound = self.inbound.lock().unwrap();
+            inbound.framing = framing;
+            inbound.continue_to = (framing != Framing::Done
+                && req.headers.has_token("expect", "100-continue"))
+            .then(|| self.out.get_ref().try_clone().ok())
+            .flatten();
+        }
+        req.local = self.local;
+        req.body = RequestBody::from_reader(BodyReader(Arc::clone(&self.inbound)));
+
+        let exchange = ctx.recorder.as_ref().map(|r| r.start(&mut req));
+        let started = Instant::now();
+        let request_line = format!("{} {} {}", req.method, req.target, req.version);
+        let pressure = ctx.shed.as_ref().map_or(Pressure::Normal, Shed::pressure);
+        let _in_flight = ctx.shed.as_ref().map(Shed::enter);
+        let _active = ctx.lifecycle.enter();
+        let mut hints = Vec::new();
+        let resp = if pressure == Pressure::Overloaded {
+            pool::busy()
+        } else {
+            let out = &mut self.out;
+            // Hints wait for the middleware, so a request turned away
+            // learns nothing about the page.
+            handler::handle(ctx, &mut req, |req| {
+                if req.method == "GET" && pressure == Pressure::Normal && !ctx.is_internal(req) {
+                    hints = early_hints::links(&ctx.early_hints, &req.path);
+                }
+                if !hints.is_empty() && req.version == Version::Http11 {
+                    let interim = early_hints::interim(&hints);
+                    let _ = out.write_all(&interim).and_then(|()| out.flush());
+                }
+            })
+        };
+        let fault = ctx
+            .chaos
+            .as_ref()
+            .filter(|_| !ctx.is_internal(&req))
+            .and_then(|c| c.pick(&req.path));
+        let mut resp = if fault == Some(Fault::Error) {
+            chaos::error()
+        } else {
+            resp
+        };
+        if !hints.is_empty() && (200..300).contains(&resp.status) {
+            resp.headers.append("Link", hints.join(", "));
+        }
+
+        if let Some(exchange) = &exchange {
+            let waiting = self.inbound.lock().unwrap().continue_to.is_some();
+            if !waiting {
+                exchange.read_rest(&mut req.body);
+            }
+        }
+        let drained = {
+            let mut inbound = self.inbound.lock().unwrap();
+            // A client still waiting for 100 Continue may never send the
+            // body, so the connection cannot be reused.
+            let waiting = inbound.continue_to.take().is_some();
+            let Inbound {
+                reader, framing, ..
+            } = &mut *inbound;
+            !waiting && framing.drain(reader, MAX_DRAIN)
+        };
+        let opts = WriteOptions {
+            version: req.version,
+            head_only: req.is_head(),
+            keep_alive: drained
+                && req.keep_alive()
+                && pressure == Pressure::Normal
+                && !ctx.shutdown.load(Ordering::SeqCst),
+            socket: self.zero_copy.as_ref(),
+            identity: &ctx.identity,
+        };
+        let status = resp.status;
+        let written = match exchange {
+            Some(exchange) => {
+                let wait = started.elapsed();
+                let mut tap = exchange.tap(&mut self.out);
+                let opts = WriteOptions {
+                    socket: None,
+                    ..opts
+                };
+                let written = send(ctx, &mut tap, resp, opts, &req.path, fault);
+                exchange.finish(tap, wait, &written);
+                written
+            }
+            None => send(ctx, &mut self.out, resp, opts, &req.path, fault),
+        };
+        let bytes = written.as_ref().map_or(0, |w| w.bytes);
+        middleware::complete(
+            ctx,
+            &Completed {
+                peer: self.peer,
+                request_line: &request_line,
+                status,
+                bytes,
+                elapsed: started.elapsed(),
+                error: written.as_ref().err(),
+            },
+        );
+        // A stream may have ended because the server is shutting down.
+        matches!(written, Ok(w) if w.keep_alive) && !ctx.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// Writes `resp` to `out`, applying any throttle, bandwidth cap and injected
+/// fault.
+fn send(
+    ctx: &Context,
+    out: &mut dyn Write,
+    resp: Response,
+    mut opts: WriteOptions<'_>,
+    path: &str,
+    fault: Option<Fault>,
+) -> io::Result<Written> {
+    match (fault, &ctx.chaos) {
+        (Some(Fault::Drop), _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection dropped by chaos mode",
+            ));
+        }
+        (Some(Fault::Stall), Some(chaos)) => thread::sleep(chaos.stall),
+        _ => {}
+    }
+    let throttle = throttle::find(&ctx.throttle, path);
+    if let Some(throttle) = throttle {
+        thread::sleep(throttle.latency);
+    }
+    let mut paced;
+    let mut capped;
+    let mut cut;
+    let mut w: &mut dyn Write = &mut *out;
+    if let Some(rate) = throttle.and_then(|t| t.rate) {
+        opts.socket = None;
+        paced = Paced::new(w, rate);
+        w = &mut paced;
+    }
+    if ctx.bandwidth.applies(path) {
+        opts.socket = None;
+        capped = Capped::new(w, &ctx.bandwidth, path);
+        w = &mut capped;
+    }
+    if fault == Some(Fault::Truncate) {
+        opts.socket = None;
+        opts.keep_alive = false;
+        cut = Cut::new(w, resp.body.len().map_or(512, |len| len / 2));
+        w = &mut cut;
+    }
+    resp.write_to(w, opts)
+}
+
+/// Serves requests on `stream` until the connection ends, on this thread.
+pub(super) fn serve(ctx: &Context, stream: TcpStream) {
+    let _open = ctx.lifecycle.connect();
+    let Some(mut conn) = Connection::new(ctx, stream) else {
+        return;
+    };
+    while conn.serve_next(ctx) {}
+}
@@ -0,0 +1,68 @@
+//! Permissive CORS for development: with `cors` on, every response may be
+//! read by any origin, and preflights are answered for any method and
+//! header. `corsCredentials` reflects the request's `Origin` and allows
+//! cookies and `Authorization` instead of answering `*`.
+//!
+//! Headers already set by a plugin, hook or mock are left alone.
+
+use crate::http::{Request, Response};
+
+use super::Context;
+
+/// Methods offered to preflights besides the one asked about.
+const METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Headers exposed with credentials, where `*` would be taken literally.
+const EXPOSE: &str =
+    "Content-Length, Content-Range, Content-Disposition, ETag, Last-Modified, Location";
+
+/// How long browsers may cache a preflight, in seconds.
+const MAX_AGE: &str = "86400";
+
+/// Answers a preflight request.
+pub fn preflight(ctx: &Context, req: &Request) -> Option<Response> {
+    if !ctx.config.bool("cors") || req.method != "OPTIONS" || !req.headers.contains("origin") {
+        return None;
+    }
+    let method = req.headers.get("access-control-request-method")?;
+    let mut resp = Response::new(204).header("Access-Control-Max-Age", MAX_AGE);
+    resp.headers.set(
+        "Access-Control-Allow-Methods",
+        if METHODS.split(", ").any(|m| m == method) {
+            METHODS.to_owned()
+        } else {
+            format!("{METHODS}, {method}")
+        },
+    );
+    resp.headers.set(
+        "Access-Control-Allow-Headers",
+        req.headers
+            .get("access-control-request-headers")
+            .filter(|h| !h.trim().is_empty())
+            .unwrap_or("*"),
+    );
+    if req.headers.get("access-control-request-private-network") == Some("true") {
+        resp.headers
+            .set("Access-Control-Allow-Private-Network", "true");
+    }
+    Some(resp)
+}
+
+/// Adds the CORS headers to `resp`.
+pub fn decorate(ctx: &Context, req: &Request, resp: &mut Response) {
+    if !ctx.config.bool("cors") || resp.headers.contains("access-control-allow-origin") {
+        return;
+    }
+    match req.headers.get("origin") {
+        Some(origin) if ctx.config.bool("corsCredentials") => {
+            resp.headers.set("Access-Control-Allow-Origin", origin);
+            resp.headers.set("Access-Control-Allow-Credentials", "true");
+            resp.headers.set("Access-Control-Expose-Headers", EXPOSE);
+            resp.headers.append("Vary", "Origin");
+        }
+        _ => {
+            resp.headers.set("Access-Control-Allow-Origin", "*");
+            resp.headers.set("Access-Control-Expose-Headers", "*");
+        }
+    }
+}
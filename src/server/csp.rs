@@ -0,0 +1,238 @@
+//! `Content-Security-Policy` from the `csp` option, one entry per directive
+//! such as `script-src 'self' https://cdn.example.com`. Entries naming the
+//! same directive are merged.
+//!
+//! With `cspNonce` on, every HTML page gets a fresh nonce: each `<script>`
+//! and `<style>` tag in it gains `nonce="..."`, and `'nonce-...'` is added
+//! to `script-src` and `style-src`, which are copied from `default-src`
+//! when not given. Inline code then runs without `'unsafe-inline'` or a
+//! hash per snippet. A nonce must not be reused, so such pages are sent
+//! without `ETag` or `Last-Modified` and with `Cache-Control: no-store`;
+//! pages too big to rewrite get the policy without a nonce.
+
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Result, bail};
+
+use crate::core::Config;
+use crate::http::{Body, Request, Response, mime};
+
+use super::transform::take_body;
+
+/// Directives a nonce is added to.
+const NONCE_DIRECTIVES: [&str; 2] = ["script-src", "style-src"];
+
+/// Tags given the nonce.
+const NONCE_TAGS: [&[u8]; 2] = [b"<script", b"<style"];
+
+/// The parsed policy.
+pub struct Csp {
+    /// `(directive, sources)`, in the order first given.
+    directives: Vec<(String, Vec<String>)>,
+    nonce: bool,
+    report_only: bool,
+    keys: RandomState,
+    counter: AtomicU64,
+}
+
+impl Csp {
+    /// `None` when `csp` is empty and `cspNonce` off.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let mut directives: Vec<(String, Vec<String>)> = Vec::new();
+        for entry in config.list("csp") {
+            if entry.contains(';') {
+                bail!("csp: `{entry}`: give one directive per entry");
+            }
+            let mut words = entry.split_whitespace();
+            let Some(name) = words.next() else { continue };
+            let name = name.to_ascii_lowercase();
+            if !name.bytes().all(|b| b.is_ascii_lowercase() || b == b'-') {
+                bail!("csp: `{name}` is not a directive name");
+            }
+            let sources = words.map(str::to_owned);
+            match directives.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, existing)) => existing.extend(sources),
+                None => directives.push((name, sources.collect())),
+            }
+        }
+        let nonce = config.bool("cspNonce");
+        if directives.is_empty() && !nonce {
+            return Ok(None);
+        }
+        if nonce {
+            let fallback = directives
+                .iter()
+                .find(|(n, _)| n == "default-src")
+                .map(|(_, s)| s.clone())
+                .unwrap_or_default();
+            for name in NONCE_DIRECTIVES {
+                if !directives.iter().any(|(n, _)| n == name) {
+                    directives.push((name.to_owned(), fallback.clone()));
+                }
+            }
+        }
+        Ok(Some(Self {
+            directives,
+            nonce,
+            report_only: config.bool("cspReportOnly"),
+            keys: RandomState::new(),
+            counter: AtomicU64::new(0),
+        }))
+    }
+
+    fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    /// The header value, allowing `nonce` where it applies.
+    fn policy(&self, nonce: Option<&str>) -> String {
+        let mut out = String::new();
+        for (name, sources) in &self.directives {
+            if !out.is_empty() {
+                out.push_str("; ");
+            }
+            out.push_str(name);
+            for source in sources {
+                out.push(' ');
+                out.push_str(source);
+            }
+            if let Some(nonce) = nonce
+                && NONCE_DIRECTIVES.contains(&name.as_str())
+            {
+                out.push_str(&format!(" 'nonce-{nonce}'"));
+            }
+        }
+        out
+    }
+
+    /// 128 unpredictable bits, in hex (a subset of the base64 CSP expects).
+    fn fresh_nonce(&self) -> String {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!(
+            "{:016x}{:016x}",
+            self.keys.hash_one((n, 0u8)),
+            self.keys.hash_one((n, 1u8))
+        )
+    }
+
+    /// Drops validators and ranges from requests for HTML pages, which are
+    /// rewritten with a new nonce every time.
+    pub fn prepare(&self, req: &mut Request) {
+        if self.nonce && mime::from_path(Path::new(&req.path)).starts_with("text/html") {
+            for name in ["range", "if-none-match", "if-modified-since", "if-range"] {
+                req.headers.remove(name);
+            }
+        }
+    }
+
+    /// Sets the header, adding a nonce to HTML pages with `cspNonce` on.
+    pub fn apply(&self, resp: &mut Response) {
+        let html = resp
+            .headers
+            .get("content-type")
+            .is_some_and(|t| t.starts_with("text/html"));
+        if !(self.nonce && html && resp.status == 200) {
+            resp.headers.set(self.header_name(), self.policy(None));
+            return;
+        }
+        let Some(body) = take_body(resp) else {
+            resp.headers.set(self.header_name(), self.policy(None));
+            return;
+        };
+        let nonce = self.fresh_nonce();
+        resp.body = Body::Bytes(add_nonce(&body, &nonce));
+        resp.headers.remove("content-length");
+        resp.headers.remove("etag");
+        resp.headers.remove("last-modified");
+        resp.headers.set("Cache-Control", "no-store");
+        resp.headers
+            .set(self.header_name(), self.policy(Some(&nonce)));
+    }
+}
+
+/// `body` with ` nonce="<nonce>"` after the name of every `<script>` and
+/// `<style>` tag.
+fn add_nonce(body: &[u8], nonce: &str) -> Vec<u8> {
+    let attr = format!(" nonce=\"{nonce}\"");
+    let mut out = Vec::with_capacity(body.len() + 4 * attr.len());
+    let mut i = 0;
+    while i < body.len() {
+        out.push(body[i]);
+        if body[i] == b'<' {
+            let rest = &body[i..];
+            let tag = NONCE_TAGS.iter().find(|tag| {
+                rest.len() > tag.len()
+                    && rest[..tag.len()].eq_ignore_ascii_case(tag)
+                    && matches!(rest[tag.len()], b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r')
+            });
+            if let Some(tag) = tag {
+                out.extend_from_slice(&rest[1..tag.len()]);
+                out.extend_from_slice(attr.as_bytes());
+                i += tag.len();
+                continue;
+            }
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::TestServer;
+
+    #[test]
+    fn pages_get_a_fresh_nonce_each_time() {
+        let server = TestServer::with(|b| {
+            b.option("csp", "default-src 'self'")
+                .option("cspNonce", true)
+        })
+        .unwrap();
+        server
+            .write("page.html", "<script>go()</script><STYLE>p{}</STYLE>")
+            .unwrap();
+        server.write("app.js", "go()").unwrap();
+
+        let nonce = || {
+            let resp = server.get("/page.html").unwrap();
+            assert_eq!(resp.status, 200);
+            assert_eq!(resp.header("cache-control"), Some("no-store"));
+            assert_eq!(resp.header("etag"), None);
+            let policy = resp.header("content-security-policy").unwrap();
+            let nonce = policy
+                .split("'nonce-")
+                .nth(1)
+                .and_then(|rest| rest.split('\'').next())
+                .unwrap()
+                .to_owned();
+            assert_eq!(
+                policy,
+                format!(
+                    "default-src 'self'; script-src 'self' 'nonce-{nonce}'; \
+                     style-src 'self' 'nonce-{nonce}'"
+                )
+            );
+            assert_eq!(
+                resp.text(),
+                format!(
+                    "<script nonce=\"{nonce}\">go()</script><STYLE nonce=\"{nonce}\">p{{}}</STYLE>"
+                )
+            );
+            nonce
+        };
+        assert_ne!(nonce(), nonce());
+
+        let script = server.get("/app.js").unwrap();
+        assert_eq!(
+            script.header("content-security-policy"),
+            Some("default-src 'self'; script-src 'self'; style-src 'self'")
+        );
+        assert_eq!(script.text(), "go()");
+    }
+}
@@ -0,0 +1,80 @@
+//! Per-request dumps for `debugRequests`: the request head, the steps taken
+//! to answer it (which stage answered, the file it resolved to, validators
+//! and how the body is sent) and the response head, printed as one block.
+//!
+//! Steps are collected with [`note`] on the handling thread, and cost a
+//! thread-local check when dumping is off.
+
+use std::cell::RefCell;
+
+use crate::glob;
+use crate::http::response::reason;
+use crate::http::{Request, Response};
+use crate::log;
+
+use super::Context;
+
+thread_local! {
+    /// Steps noted for the request being handled on this thread, if it is
+    /// being dumped.
+    static STEPS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Whether requests to `path` are dumped: with `debugRequests` on, or the
+/// path matching `debugPaths`.
+fn wanted(ctx: &Context, path: &str) -> bool {
+    let globs = ctx.config.list("debugPaths");
+    if globs.is_empty() {
+        ctx.config.bool("debugRequests")
+    } else {
+        globs.iter().any(|g| glob::matches(g, path))
+    }
+}
+
+/// Starts collecting steps for `req` if it is dumped, returning its head
+/// as received.
+pub(super) fn begin(ctx: &Context, req: &Request) -> Option<String> {
+    let on = wanted(ctx, &req.path);
+    STEPS.with(|steps| *steps.borrow_mut() = on.then(Vec::new));
+    if !on {
+        return None;
+    }
+    let mut head = format!(
+        "--- {} {} {} from {}",
+        req.method, req.target, req.version, req.peer
+    );
+    for (name, value) in req.headers.iter() {
+        head.push_str(&format!("\n    {name}: {value}"));
+    }
+    Some(head)
+}
+
+/// Records a step of the request being dumped, if any.
+pub fn note(step: impl FnOnce() -> String) {
+    STEPS.with(|steps| {
+        if let Some(steps) = steps.borrow_mut().as_mut() {
+            steps.push(step());
+        }
+    });
+}
+
+/// Prints the dump of a request with the head from [`begin`], answered
+/// with `resp`.
+pub(super) fn finish(mut out: String, resp: &Response) {
+    let steps = STEPS
+        .with(|steps| steps.borrow_mut().take())
+        .unwrap_or_default();
+    for step in steps {
+        out.push_str(&format!("\n  * {step}"));
+    }
+    out.push_str(&format!(
+        "\n--> {} {} ({:?} body)",
+        resp.status,
+        reason(resp.status),
+        resp.body
+    ));
+    for (name, value) in resp.headers.iter() {
+        out.push_str(&format!("\n    {name}: {value}"));
+    }
+    log::info(&out);
+}
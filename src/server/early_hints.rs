@@ -0,0 +1,329 @@
+//! Early hints: resources a page will need, announced before the page.
+//!
+//! `earlyHints` rules read `GLOB=URL[ URL...]`, e.g. `/=/app.css /app.js`
+//! or `/docs/*.html=/docs.css`. With `earlyHintsAuto` the HTML files below
+//! the root are read at startup and the stylesheets, scripts and preloads
+//! in their `<head>` become rules for their URL (and their directory's,
+//! for index files). Rules from the option come first; every matching rule
+//! contributes.
+//!
+//! For a `GET` of a matching path that the middleware (hosts, policy,
+//! authentication and the like) lets through, an HTTP/1.1 client is sent
+//! `103 Early Hints` with a `Link: <url>; rel=preload` field per resource
+//! while the response is prepared, and a successful final response carries
+//! the same links, for clients and proxies that skip interim responses.
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+use crate::core::Config;
+use crate::files::{self, precompressed};
+use crate::glob;
+use crate::log;
+
+use super::Context;
+
+/// Bytes of a page searched for its `<head>`.
+const HEAD_LIMIT: usize = 64 * 1024;
+/// Pages larger than this are not read by `earlyHintsAuto`.
+const MAX_PAGE: u64 = 4 << 20;
+
+/// The links for one path pattern.
+#[derive(Debug)]
+pub struct Rule {
+    pub glob: String,
+    /// `Link` field values.
+    pub links: Vec<String>,
+}
+
+/// The rules configured by `earlyHints`.
+pub fn rules(config: &Config) -> Result<Vec<Rule>> {
+    config
+        .list("earlyHints")
+        .iter()
+        .map(|rule| {
+            let Some((glob, urls)) = rule.split_once('=') else {
+                bail!("earlyHints: expected `GLOB=URL`, got `{rule}`");
+            };
+            let links: Vec<String> = urls.split_whitespace().map(link_for).collect();
+            if glob.trim().is_empty() || links.is_empty() {
+                bail!("earlyHints: expected `GLOB=URL`, got `{rule}`");
+            }
+            Ok(Rule {
+                glob: glob.trim().to_owned(),
+                links,
+            })
+        })
+        .collect()
+}
+
+/// The links announced for `path`, in rule order without repeats.
+pub fn links<'a>(rules: &'a [Rule], path: &str) -> Vec<&'a str> {
+    let mut out: Vec<&str> = Vec::new();
+    for rule in rules.iter().filter(|r| glob::matches(&r.glob, path)) {
+        for link in &rule.links {
+            if !out.contains(&link.as_str()) {
+                out.push(link);
+            }
+        }
+    }
+    out
+}
+
+/// The `103 Early Hints` response head carrying `links`.
+pub fn interim(links: &[&str]) -> Vec<u8> {
+    let mut head = String::from("HTTP/1.1 103 Early Hints\r\n");
+    for link in links {
+        head.push_str(&format!("Link: {link}\r\n"));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+/// A preload link for `url`, its destination guessed from the extension.
+fn link_for(url: &str) -> String {
+    let ext = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|p| p.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    let dest = match ext.as_str() {
+        "css" => Some("style"),
+        "js" | "mjs" => Some("script"),
+        "woff" | "woff2" | "ttf" | "otf" => Some("font"),
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico" => Some("image"),
+        "json" => Some("fetch"),
+        _ => None,
+    };
+    preload(url, dest, matches!(dest, Some("font" | "fetch")))
+}
+
+fn preload(url: &str, dest: Option<&str>, cors: bool) -> String {
+    let mut link = format!("<{url}>; rel=preload");
+    if let Some(dest) = dest {
+        link.push_str(&format!("; as={dest}"));
+    }
+    if cors {
+        link.push_str("; crossorigin");
+    }
+    link
+}
+
+/// Rules for the HTML files below the root, from the resources in their
+/// `<head>`; empty unless `earlyHintsAuto` is on.
+pub fn derive(ctx: &Context) -> Vec<Rule> {
+    if !ctx.config.bool("earlyHintsAuto") {
+        return Vec::new();
+    }
+    let mut rules = Vec::new();
+    walk(ctx, &ctx.root, "", &mut rules);
+    log::info(&format!("early hints for {} path(s)", rules.len()));
+    rules
+}
+
+fn walk(ctx: &Context, dir: &Path, rel: &str, rules: &mut Vec<Rule>) {
+    let entries = match files::read_dir(ctx, dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn(&format!(
+                "earlyHintsAuto: cannot list {}: {e}",
+                dir.display()
+            ));
+            return;
+        }
+    };
+    let index = ctx.config.list("index");
+    for entry in entries {
+        if entry.name.starts_with('.') || precompressed::is_sidecar(&entry.name) {
+            continue;
+        }
+        let path = dir.join(&entry.name);
+        let url = format!("{rel}/{}", entry.name);
+        let html = entry.name.ends_with(".html") || entry.name.ends_with(".htm");
+        if entry.meta.is_dir {
+            walk(ctx, &path, &url, rules);
+        } else if entry.meta.is_file && html && entry.meta.len <= MAX_PAGE {
+            let Ok(page) = files::read(ctx, &path) else {
+                continue;
+            };
+            let links = head_links(&page[..page.len().min(HEAD_LIMIT)], &format!("{rel}/"));
+            if links.is_empty() {
+                continue;
+            }
+            if index.contains(&entry.name) {
+                rules.push(Rule {
+                    glob: format!("{rel}/"),
+                    links: links.clone(),
+                });
+            }
+            rules.push(Rule { glob: url, links });
+        }
+    }
+}
+
+/// Links for the stylesheets, scripts and preloads in a page's `<head>`,
+/// relative URLs resolved against `base`. Other origins are left out.
+fn head_links(page: &[u8], base: &str) -> Vec<String> {
+    let text = String::from_utf8_lossy(page);
+    let lower = text.to_ascii_lowercase();
+    let head = &text[..lower.find("</head").unwrap_or(text.len())];
+    let mut links = Vec::new();
+    let mut rest = head;
+    while let Some(at) = rest.find('<') {
+        rest = &rest[at + 1..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        let name = tag
+            .split(|c: char| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let link = match name.as_str() {
+            "link" => {
+                let rel = attr(tag, "rel").unwrap_or_default().to_ascii_lowercase();
+                let Some(href) = attr(tag, "href").and_then(|h| resolve(h, base)) else {
+                    continue;
+                };
+                match rel.as_str() {
+                    "stylesheet" => preload(&href, Some("style"), false),
+                    "modulepreload" => format!("<{href}>; rel=modulepreload"),
+                    "preload" => {
+                        let dest = attr(tag, "as").filter(|d| {
+                            !d.is_empty() && d.bytes().all(|b| b.is_ascii_alphabetic())
+                        });
+                        preload(&href, dest, attr(tag, "crossorigin").is_some())
+                    }
+                    _ => continue,
+                }
+            }
+            "script" => {
+                let Some(src) = attr(tag, "src").and_then(|s| resolve(s, base)) else {
+                    continue;
+                };
+                if attr(tag, "type").is_some_and(|t| t.eq_ignore_ascii_case("module")) {
+                    format!("<{src}>; rel=modulepreload")
+                } else {
+                    preload(&src, Some("script"), false)
+                }
+            }
+            _ => continue,
+        };
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+/// The value of attribute `name` in the inside of a tag, quoted or not.
+/// A bare attribute yields an empty string.
+fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag.split_once(|c: char| c.is_ascii_whitespace())?.1;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            return None;
+        }
+        let key_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, tail) = match after.chars().next() {
+                    Some(q @ ('"' | '\'')) => {
+                        let close = after[1..].find(q).map_or(after.len(), |i| i + 1);
+                        (&after[1..close], after.get(close + 1..).unwrap_or_default())
+                    }
+                    _ => after.split_at(
+                        after
+                            .find(|c: char| c.is_ascii_whitespace())
+                            .unwrap_or(after.len()),
+                    ),
+                };
+                rest = tail;
+                value
+            }
+            None => "",
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+    }
+}
+
+/// `url` as a path on this server, `None` for other origins and schemes.
+fn resolve(url: &str, base: &str) -> Option<String> {
+    let url = url.trim();
+    if url.is_empty()
+        || url.starts_with("//")
+        || url.contains([':', '<', '>'])
+        || url.chars().any(|c| c.is_control() || c.is_whitespace())
+    {
+        return None;
+    }
+    if url.starts_with('/') {
+        return Some(url.to_owned());
+    }
+    let mut segments: Vec<&str> = base.split('/').filter(|s| !s.is_empty()).collect();
+    let (path, suffix) = match url.find(['?', '#']) {
+        Some(at) => url.split_at(at),
+        None => (url, ""),
+    };
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}{suffix}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use crate::test::{AUTH, TestServer};
+
+    /// The raw response to a `GET` of `path` with `headers` (lines ending in
+    /// CRLF).
+    fn raw_get(server: &TestServer, path: &str, headers: &str) -> String {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{headers}\r\n",
+            server.addr()
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn hints_only_requests_the_middleware_accepts() {
+        let server = TestServer::with_auth(|b| {
+            b.option("earlyHints", "*.html=/app.css")
+                .option("authFor", "all")
+        })
+        .unwrap();
+        server.write("page.html", "<p>hi</p>").unwrap();
+
+        let refused = raw_get(&server, "/page.html", "");
+        assert!(refused.starts_with("HTTP/1.1 401"), "{refused}");
+        assert!(!refused.contains("app.css"), "{refused}");
+
+        let (name, value) = AUTH;
+        let accepted = raw_get(&server, "/page.html", &format!("{name}: {value}\r\n"));
+        assert!(accepted.starts_with("HTTP/1.1 103"), "{accepted}");
+        assert!(accepted.contains("\r\n\r\nHTTP/1.1 200"), "{accepted}");
+    }
+}
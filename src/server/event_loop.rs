@@ -0,0 +1,122 @@
+//! The `runtime=tokio` backend: idle connections wait on a tokio reactor
+//! instead of holding a thread, and each request is handled on tokio's
+//! blocking pool by the same code the thread-per-connection model uses.
+
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use anyhow::{Context as _, Result};
+use tokio::io::unix::AsyncFd;
+use tokio::runtime::Builder;
+use tokio::task;
+use tokio::time::timeout;
+
+use crate::log;
+
+use super::conn::{Connection, KEEP_ALIVE_TIMEOUT};
+use super::{Context, pool};
+
+/// Accepts connections on a multi-threaded tokio runtime until shut down.
+pub(super) fn run(ctx: Arc<Context>, listeners: Vec<TcpListener>) -> Result<()> {
+    let runtime = Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("tinyserve-rt")
+        .max_blocking_threads(match ctx.config.int("workers") {
+            0 => pool::default_workers(),
+            n => n as usize,
+        })
+        .build()
+        .context("cannot start the tokio runtime")?;
+    let result = runtime.block_on(async move {
+        let mut listeners = listeners
+            .into_iter()
+            .map(|listener| {
+                listener.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(listener)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let main = listeners.remove(0);
+        for listener in listeners {
+            tokio::spawn(accept(Arc::clone(&ctx), listener));
+        }
+        ctx.lifecycle.set_ready();
+        accept(ctx, main).await;
+        Ok(())
+    });
+    // Requests in progress finish on their own threads.
+    runtime.shutdown_background();
+    result
+}
+
+/// Serves the connections `listener` accepts until shut down.
+async fn accept(ctx: Arc<Context>, listener: tokio::net::TcpListener) {
+    loop {
+        let accepted = listener.accept().await;
+        if ctx.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn(&format!("accept failed: {e}"));
+                continue;
+            }
+        };
+        let ctx = Arc::clone(&ctx);
+        tokio::spawn(async move {
+            let Ok(stream) = stream.into_std() else {
+                return;
+            };
+            // Like the threaded model, a failed connection just ends.
+            let _ = serve(ctx, stream).await;
+        });
+    }
+}
+
+async fn serve(ctx: Arc<Context>, stream: std::net::TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let ready = AsyncFd::new(stream.try_clone()?)?;
+    let Some(mut conn) = Connection::new(&ctx, stream) else {
+        return Ok(());
+    };
+    let _open = ctx.lifecycle.connect();
+    loop {
+        if !conn.has_buffered() {
+            // Readiness is edge-triggered: clear it before checking for
+            // data, so bytes arriving afterwards wake us up again.
+            loop {
+                let Ok(guard) = timeout(KEEP_ALIVE_TIMEOUT, ready.readable()).await else {
+                    return Ok(());
+                };
+                guard?.clear_ready();
+                if has_input(conn.socket())? {
+                    break;
+                }
+            }
+        }
+        let ctx = Arc::clone(&ctx);
+        let (next, more) = task::spawn_blocking(move || {
+            let more = conn.serve_next(&ctx);
+            (conn, more)
+        })
+        .await?;
+        if !more {
+            return Ok(());
+        }
+        conn = next;
+    }
+}
+
+/// Whether reading `socket` would not block: data or end of stream waits.
+fn has_input(socket: &std::net::TcpStream) -> io::Result<bool> {
+    socket.set_nonblocking(true)?;
+    let peeked = socket.peek(&mut [0u8]);
+    socket.set_nonblocking(false)?;
+    match peeked {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
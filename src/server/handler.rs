@@ -0,0 +1,199 @@
+//! The request flow: middleware, method checks, then the filesystem.
+
+use crate::auth;
+use crate::events;
+use crate::files::{self, resolve};
+use crate::http::{Request, Response};
+use crate::resumable;
+use crate::tail;
+use crate::webdav;
+use crate::writable;
+
+use super::{Context, INTERNAL_ESCAPE, debug, middleware, recent, status, unicode};
+
+/// Produces the response for one request, through the middleware pipeline.
+/// `accepted` is called once every middleware stage has let the request
+/// through, just before it is dispatched.
+pub fn handle(ctx: &Context, req: &mut Request, accepted: impl FnOnce(&Request)) -> Response {
+    if let Some(resp) = unicode::apply(&ctx.config, req) {
+        return resp;
+    }
+    unescape(ctx, req);
+    let audited = ctx.audit.as_ref().and_then(|a| Some((a, a.watch(req)?)));
+    let resp = match debug::begin(ctx, req) {
+        Some(head) => {
+            let resp = middleware::run(ctx, req, accepted);
+            debug::finish(head, &resp);
+            resp
+        }
+        None => middleware::run(ctx, req, accepted),
+    };
+    if let Some((audit, read)) = audited {
+        audit.record(ctx, req, resp.status, &read);
+    }
+    resp
+}
+
+/// Turns `<prefix>-/rest` into `<prefix>rest` before any stage looks at the
+/// path, so policy and access rules see the file that will be served. The
+/// target keeps the escape, which tells [`Internal`](middleware::Internal)
+/// to stay out of it.
+fn unescape(ctx: &Context, req: &mut Request) {
+    if let Some(rest) = req
+        .path
+        .strip_prefix(&ctx.internal_prefix)
+        .and_then(|rest| rest.strip_prefix(INTERNAL_ESCAPE))
+    {
+        req.path = format!("{}{rest}", ctx.internal_prefix);
+    }
+}
+
+/// Answers by method, once no middleware has.
+pub(super) fn dispatch(ctx: &Context, req: &mut Request) -> Response {
+    let writable = !ctx.config.bool("readOnly");
+    let dav = ctx.config.bool("webdav");
+    match req.method.as_str() {
+        "GET" | "HEAD" => files::serve(ctx, req),
+        "OPTIONS" => options(ctx),
+        "PROPFIND" if dav => webdav::propfind(ctx, req),
+        "PROPPATCH" if dav => webdav::proppatch(ctx, req),
+        "PUT" if writable => writable::put(ctx, req),
+        "POST" if writable => writable::post(ctx, req),
+        "DELETE" | "MKCOL" | "COPY" | "MOVE" if writable && !auth::enabled(ctx) => {
+            Response::error_with(
+                403,
+                "deleting, creating and moving require authentication to be configured",
+            )
+        }
+        "DELETE" if writable => writable::delete(ctx, req),
+        "MKCOL" if writable => writable::mkcol(ctx, req),
+        "COPY" | "MOVE" if writable && dav => webdav::copy_or_move(ctx, req),
+        _ => Response::error(405).header("Allow", allow(ctx)),
+    }
+}
+
+/// Methods accepted by the request flow, for `Allow`.
+fn allow(ctx: &Context) -> String {
+    let mut methods = String::from("GET, HEAD, OPTIONS");
+    if !ctx.config.bool("readOnly") {
+        methods.push_str(", PUT, POST, DELETE, MKCOL");
+    }
+    if ctx.config.bool("webdav") {
+        methods.push_str(", ");
+        methods.push_str(webdav::METHODS);
+    }
+    methods
+}
+
+fn options(ctx: &Context) -> Response {
+    let resp = Response::new(204).header("Allow", allow(ctx));
+    if ctx.config.bool("webdav") {
+        resp.header("DAV", "1").header("MS-Author-Via", "DAV")
+    } else {
+        resp
+    }
+}
+
+/// `POST /__tinyserve/cache/invalidate[?path=/dir]` drops cached metadata
+/// and file bodies, and copies pulled from an origin, for everything or
+/// below one URL path. Like deleting, it needs credentials, so it is
+/// refused while none are configured.
+const INVALIDATE_PATH: &str = "cache/invalidate";
+
+fn invalidate(ctx: &Context, req: &Request) -> Response {
+    if !auth::enabled(ctx) {
+        return Response::error_with(
+            403,
+            "invalidating the cache requires authentication to be configured",
+        );
+    }
+    if let Err(resp) = auth::authenticate(ctx, req) {
+        return resp;
+    }
+    let path = req.query_param("path");
+    let path = path.as_deref().unwrap_or("/");
+    match resolve::resolve(&ctx.root, path, true) {
+        Some(target) => {
+            ctx.invalidate(&target);
+            Response::new(204)
+        }
+        None => Response::error_with(400, "invalid path"),
+    }
+}
+
+/// Built-in endpoints. `None` lets the path fall through to the filesystem.
+pub(super) fn internal(ctx: &Context, req: &Request, name: &str) -> Option<Response> {
+    if name == INVALIDATE_PATH && req.method == "POST" {
+        return Some(invalidate(ctx, req));
+    }
+    if req.method != "GET" && !req.is_head() {
+        return None;
+    }
+    match name {
+        events::PATH if ctx.config.bool("events") => ctx
+            .watcher
+            .as_deref()
+            .map(|watcher| events::stream(&ctx.streams, watcher)),
+        status::PATH => ctx.status.as_ref().map(|s| status::page(ctx, s)),
+        recent::PATH => ctx.recent.as_ref().map(recent::json),
+        resumable::OFFSET_PATH => ctx.uploads.as_ref().map(|_| resumable::offset(ctx, req)),
+        _ => {
+            let rel = name.strip_prefix(tail::PREFIX)?;
+            let dir = ctx.tail_dir.as_deref()?;
+            let patterns = ctx.config.list("tailFiles");
+            Some(tail::handle(&ctx.streams, dir, &patterns, rel, req))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{AUTH, TestServer};
+
+    #[test]
+    fn cache_invalidation_needs_credentials() {
+        let open = TestServer::new().unwrap();
+        let resp = open
+            .request("POST", "/__tinyserve/cache/invalidate", &[], b"")
+            .unwrap();
+        assert_eq!(resp.status, 403);
+
+        let server = TestServer::with_auth(|b| b.option("statTtl", 60_000)).unwrap();
+        let invalidate = |headers: &[(&str, &str)]| {
+            server
+                .request("POST", "/__tinyserve/cache/invalidate", headers, b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(invalidate(&[]), 401);
+        assert_eq!(invalidate(&[("Authorization", "Bearer wrong")]), 401);
+        assert_eq!(server.get("/a.txt").unwrap().status, 404);
+        // Behind the server's back, unlike `TestServer::write`.
+        std::fs::write(server.root().unwrap().join("a.txt"), "a").unwrap();
+        assert_eq!(server.get("/a.txt").unwrap().status, 404);
+        assert_eq!(invalidate(&[AUTH]), 204);
+        assert_eq!(server.get("/a.txt").unwrap().status, 200);
+    }
+
+    #[test]
+    fn writes_invalidate_what_they_touch() {
+        let server =
+            TestServer::with_auth(|b| b.option("readOnly", false).option("statTtl", 60_000))
+                .unwrap();
+        server.write("dir/b.txt", "b").unwrap();
+        server.write("other.txt", "old").unwrap();
+        assert_eq!(server.get("/dir/a.txt").unwrap().status, 404);
+        assert_eq!(server.get("/other.txt").unwrap().text(), "old");
+
+        let put = server
+            .request("PUT", "/dir/a.txt", &[AUTH], b"new")
+            .unwrap();
+        assert!(put.status < 300, "PUT answered {}", put.status);
+        assert_eq!(server.get("/dir/a.txt").unwrap().text(), "new");
+
+        // Not written through the server: the cached metadata still stands.
+        std::fs::write(server.root().unwrap().join("other.txt"), "changed").unwrap();
+        let other = server.get("/other.txt").unwrap();
+        assert_eq!(other.header("content-length"), Some("3"));
+    }
+}
@@ -0,0 +1,153 @@
+//! `allowedHosts`: refuses requests for host names the server does not
+//! answer to, so a page on another site cannot reach a dev server through
+//! DNS rebinding (pointing its own name at `127.0.0.1` or a LAN address).
+//!
+//! Entries are host names without a port, and may use globs:
+//! `*.example.test` matches every subdomain. `localhost` and IP addresses
+//! are always allowed, since rebinding needs a name. A request naming a
+//! host that is not allowed gets `421 Misdirected Request`; one with no
+//! usable `Host` (missing on HTTP/1.1, repeated, or malformed) gets `400`.
+//! An absolute-form target (`GET http://name/ HTTP/1.1`) names its host in
+//! the target, which then counts instead of the header; a URL further on,
+//! as in `GET /?next=http://name/`, does not.
+//!
+//! With no entries, every host is allowed.
+
+use std::net::IpAddr;
+
+use crate::core::Config;
+use crate::glob;
+use crate::http::{Request, Response, Version};
+
+use super::Context;
+
+/// The lowercased `allowedHosts` entries.
+pub fn from_config(config: &Config) -> Vec<String> {
+    config
+        .list("allowedHosts")
+        .into_iter()
+        .map(|h| h.trim_end_matches('.').to_ascii_lowercase())
+        .collect()
+}
+
+/// Answers requests for a host outside `allowedHosts`.
+pub fn check(ctx: &Context, req: &Request) -> Option<Response> {
+    if ctx.allowed_hosts.is_empty() {
+        return None;
+    }
+    let absolute = (!req.target.starts_with('/'))
+        .then(|| req.target.split(['?', '#']).next().unwrap_or_default())
+        .and_then(|target| target.split_once("://"));
+    let authority = match absolute {
+        Some((_, rest)) => Some(rest.split('/').next().unwrap_or_default()),
+        None => {
+            let mut values = req.headers.get_all("host");
+            let host = values.next();
+            if values.next().is_some() {
+                return Some(Response::error_with(400, "repeated Host"));
+            }
+            host
+        }
+    };
+    let Some(authority) = authority else {
+        return (req.version == Version::Http11).then(|| Response::error_with(400, "missing Host"));
+    };
+    let Some(host) = host_name(authority) else {
+        return Some(Response::error_with(400, "malformed Host"));
+    };
+    if allowed(&ctx.allowed_hosts, &host) {
+        return None;
+    }
+    Some(Response::error_with(
+        421,
+        &format!("this server does not answer for {host}"),
+    ))
+}
+
+/// The lowercased host of `host[:port]`, without brackets or a trailing
+/// dot; `None` if it is empty or holds characters no host name has.
+fn host_name(authority: &str) -> Option<String> {
+    let authority = authority.trim();
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.0,
+        None => authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host),
+    };
+    let host = host.trim_end_matches('.');
+    let valid = host
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-._:".contains(&b));
+    (!host.is_empty() && valid).then(|| host.to_ascii_lowercase())
+}
+
+fn allowed(entries: &[String], host: &str) -> bool {
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host.parse::<IpAddr>().is_ok()
+        || glob::matches_any(entries, host)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+
+    use crate::test::TestServer;
+
+    fn server() -> TestServer {
+        let server = TestServer::with(|b| b.option("allowedHosts", "example.test")).unwrap();
+        server.write("s.txt", "s").unwrap();
+        server
+    }
+
+    /// The status of `GET target`, sent with `host` as the only `Host`.
+    fn status(server: &TestServer, target: &str, host: Option<&str>) -> u16 {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        let host = host.map_or(String::new(), |h| format!("Host: {h}\r\n"));
+        write!(
+            stream,
+            "GET {target} HTTP/1.1\r\n{host}Connection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line.split(' ').nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn checks_the_host_header() {
+        let server = server();
+        assert_eq!(status(&server, "/s.txt", Some("example.test")), 200);
+        assert_eq!(status(&server, "/s.txt", Some("example.test:8080")), 200);
+        assert_eq!(status(&server, "/s.txt", Some("localhost")), 200);
+        assert_eq!(status(&server, "/s.txt", Some("127.0.0.1:80")), 200);
+        assert_eq!(status(&server, "/s.txt", Some("evil.rebind")), 421);
+        assert_eq!(status(&server, "/s.txt", Some("a b")), 400);
+    }
+
+    #[test]
+    fn urls_in_the_query_do_not_count() {
+        let server = server();
+        let target = "/s.txt?x=http://localhost/";
+        assert_eq!(status(&server, target, Some("evil.rebind")), 421);
+        assert_eq!(status(&server, target, Some("example.test")), 200);
+        let target = "/a://localhost/s.txt";
+        assert_eq!(status(&server, target, Some("evil.rebind")), 421);
+    }
+
+    #[test]
+    fn absolute_form_names_the_host() {
+        let server = server();
+        let target = "http://example.test/s.txt";
+        assert_eq!(status(&server, target, Some("evil.rebind")), 200);
+        let target = "http://evil.rebind/s.txt?x=http://example.test/";
+        assert_eq!(status(&server, target, Some("example.test")), 421);
+    }
+
+    #[test]
+    fn a_missing_host_is_refused() {
+        let server = server();
+        assert_eq!(status(&server, "/s.txt", None), 400);
+    }
+}
@@ -0,0 +1,162 @@
+//! Startup and shutdown milestones of a server, for sequencing tests and
+//! supervisors: when it starts accepting, when it stops, and how many
+//! connections are open and requests still in progress.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+use std::time::Instant;
+
+use crate::error::{Error, Result};
+
+#[derive(Default)]
+struct State {
+    ready: bool,
+    stopped: bool,
+    connections: usize,
+    active: usize,
+    /// Tasks awaiting [`Ready`].
+    wakers: Vec<Waker>,
+}
+
+#[derive(Default)]
+pub struct Lifecycle {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// Counts a request as in progress until dropped.
+pub struct Active<'a>(&'a Lifecycle);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        self.0.update(|state| state.active -= 1);
+    }
+}
+
+/// Counts a connection as open until dropped.
+pub struct Open<'a>(&'a Lifecycle);
+
+impl Drop for Open<'_> {
+    fn drop(&mut self) {
+        self.0.update(|state| state.connections -= 1);
+    }
+}
+
+impl Lifecycle {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.lock();
+        f(&mut state);
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        self.changed.notify_all();
+    }
+
+    /// Marks the accept loop as running.
+    pub(super) fn set_ready(&self) {
+        self.update(|state| state.ready = true);
+    }
+
+    /// Marks the accept loop as finished.
+    pub(super) fn set_stopped(&self) {
+        self.update(|state| state.stopped = true);
+    }
+
+    pub(super) fn enter(&self) -> Active<'_> {
+        self.update(|state| state.active += 1);
+        Active(self)
+    }
+
+    pub(super) fn connect(&self) -> Open<'_> {
+        self.update(|state| state.connections += 1);
+        Open(self)
+    }
+
+    /// Whether the server has started accepting connections.
+    pub fn is_ready(&self) -> bool {
+        self.lock().ready
+    }
+
+    /// Client connections open right now, idle ones included.
+    pub fn connections(&self) -> usize {
+        self.lock().connections
+    }
+
+    /// Requests being handled right now.
+    pub fn active(&self) -> usize {
+        self.lock().active
+    }
+
+    /// Blocks until the server accepts connections. Fails if it stopped
+    /// before getting there.
+    pub fn wait_ready(&self) -> Result<()> {
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = readiness(&state) {
+                return result;
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Resolves once the server accepts connections, like
+    /// [`wait_ready`](Self::wait_ready). Works on any executor.
+    pub fn ready(&self) -> Ready<'_> {
+        Ready(self)
+    }
+
+    /// Blocks until no request is in progress, or `deadline` passes.
+    /// Returns whether the server went idle.
+    pub fn wait_idle(&self, deadline: Instant) -> bool {
+        let mut state = self.lock();
+        while state.active > 0 {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            state = self
+                .changed
+                .wait_timeout(state, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+}
+
+fn readiness(state: &State) -> Option<Result<()>> {
+    if state.ready {
+        Some(Ok(()))
+    } else if state.stopped {
+        Some(Err(Error::Stopped(
+            "server stopped before it was ready".into(),
+        )))
+    } else {
+        None
+    }
+}
+
+/// Future returned by [`Lifecycle::ready`].
+pub struct Ready<'a>(&'a Lifecycle);
+
+impl Future for Ready<'_> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock();
+        match readiness(&state) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Binding the listening sockets.
+//!
+//! A specific address or host name gets one socket. The IPv6 wildcard `::`
+//! is bound per `dualStack`:
+//!
+//! - `mapped`: one socket that also takes IPv4 clients, which the kernel
+//!   presents as v4-mapped addresses (`::ffff:192.0.2.1`); connections
+//!   record them as plain IPv4.
+//! - `separate`: an IPv6-only socket on `::` and an IPv4 one on `0.0.0.0`.
+//! - `off`: IPv6 only.
+//! - `auto` (the default): `mapped`, else `separate` where the platform
+//!   refuses mapped addresses, else IPv4 alone if the machine has no IPv6.
+//!
+//! Which one applied is shown in the startup banner.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener};
+
+use crate::core::Config;
+use crate::error::Error;
+use crate::log;
+
+/// How the listening sockets cover IPv4 and IPv6.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stack {
+    /// One socket on the configured address.
+    Single,
+    /// `::` taking both, IPv4 as mapped addresses.
+    Mapped,
+    /// `::` IPv6-only and `0.0.0.0`.
+    Separate,
+    /// `::` IPv6-only.
+    Ipv6Only,
+    /// `0.0.0.0`, for `::` on a machine without IPv6.
+    Ipv4Only,
+}
+
+impl Stack {
+    /// Name in JSON output, `None` for [`Single`](Self::Single).
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Stack::Single => None,
+            Stack::Mapped => Some("mapped"),
+            Stack::Separate => Some("separate"),
+            Stack::Ipv6Only => Some("ipv6-only"),
+            Stack::Ipv4Only => Some("ipv4-only"),
+        }
+    }
+
+    /// Line for the startup banner, `None` for [`Single`](Self::Single).
+    pub fn describe(self) -> Option<&'static str> {
+        match self {
+            Stack::Single => None,
+            Stack::Mapped => Some("IPv4 and IPv6 on one socket (IPv4 clients v4-mapped)"),
+            Stack::Separate => Some("IPv4 and IPv6 on separate sockets"),
+            Stack::Ipv6Only => Some("IPv6 only"),
+            Stack::Ipv4Only => Some("IPv4 only (no IPv6 on this machine)"),
+        }
+    }
+
+    /// Whether IPv4 clients reach a server listening on `ip`.
+    pub fn takes_ipv4(self, ip: IpAddr) -> bool {
+        match self {
+            Stack::Single => ip.is_ipv4(),
+            Stack::Mapped | Stack::Separate | Stack::Ipv4Only => true,
+            Stack::Ipv6Only => false,
+        }
+    }
+}
+
+/// Binds `host` and `port`, the first listener being the main one.
+pub(super) fn bind(config: &Config) -> crate::Result<(Vec<TcpListener>, Stack)> {
+    let host = config.str("host");
+    let port = config.int("port") as u16;
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>();
+    let ip = match ip {
+        Ok(ip) => ip,
+        Err(_) => {
+            let addr = format!("{host}:{port}");
+            let listener = TcpListener::bind((host, port)).map_err(|source| Error::Bind {
+                addr: addr.clone(),
+                source,
+            })?;
+            return Ok((vec![listener], Stack::Single));
+        }
+    };
+    let addr = SocketAddr::new(ip, port);
+    let failed = |addr: SocketAddr| {
+        move |source| Error::Bind {
+            addr: addr.to_string(),
+            source,
+        }
+    };
+    if ip != IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+        let listener = TcpListener::bind(addr).map_err(failed(addr))?;
+        return Ok((vec![listener], Stack::Single));
+    }
+    let v6 = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0);
+    let v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    let mode = config.str("dualStack");
+    if mode == "mapped" || mode == "auto" {
+        match sys::bind_v6(v6, false) {
+            Ok(listener) => return Ok((vec![listener], Stack::Mapped)),
+            Err(e) if mode == "mapped" || !recoverable(&e) => return Err(failed(addr)(e)),
+            Err(_) => {}
+        }
+    }
+    let listener = match sys::bind_v6(v6, true) {
+        Ok(listener) => listener,
+        Err(e) if mode == "auto" && recoverable(&e) => {
+            log::warn(&format!("cannot listen on IPv6 ({e}); using IPv4 only"));
+            let listener = TcpListener::bind(v4).map_err(failed(v4))?;
+            return Ok((vec![listener], Stack::Ipv4Only));
+        }
+        Err(e) => return Err(failed(addr)(e)),
+    };
+    if mode == "off" {
+        return Ok((vec![listener], Stack::Ipv6Only));
+    }
+    // With the IPv6 socket on an ephemeral port, IPv4 follows it.
+    let v4 = SocketAddr::new(v4.ip(), listener.local_addr().map_or(port, |a| a.port()));
+    let second = TcpListener::bind(v4).map_err(failed(v4))?;
+    Ok((vec![listener, second], Stack::Separate))
+}
+
+/// Whether `auto` may try the next way after `e`, rather than failing as
+/// every way would, e.g. on a port in use.
+fn recoverable(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::AddrInUse | io::ErrorKind::PermissionDenied
+    )
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::{SocketAddrV6, TcpListener};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// Listens on `addr` with `IPV6_V6ONLY` set to `v6only`.
+    pub fn bind_v6(addr: SocketAddrV6, v6only: bool) -> io::Result<TcpListener> {
+        let check = |ret: libc::c_int| {
+            if ret < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(ret)
+            }
+        };
+        // SAFETY: plain socket(2); the descriptor is owned right away.
+        let fd = check(unsafe { libc::socket(libc::AF_INET6, libc::SOCK_STREAM, 0) })?;
+        // SAFETY: `fd` is a fresh descriptor nobody else owns.
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let fd = socket.as_raw_fd();
+        let set = |level, name, value: libc::c_int| {
+            // SAFETY: `value` outlives the call and its size is passed.
+            check(unsafe {
+                libc::setsockopt(
+                    fd,
+                    level,
+                    name,
+                    (&raw const value).cast(),
+                    mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            })
+        };
+        // SAFETY: fcntl(2) on our descriptor.
+        check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+        set(libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+        set(
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            libc::c_int::from(v6only),
+        )?;
+        // SAFETY: all-zero is a valid `sockaddr_in6`.
+        let mut sa: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+        sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        sa.sin6_port = addr.port().to_be();
+        sa.sin6_addr.s6_addr = addr.ip().octets();
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ))]
+        {
+            sa.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+        }
+        // SAFETY: `sa` is a complete `sockaddr_in6` of the given size.
+        check(unsafe {
+            libc::bind(
+                fd,
+                (&raw const sa).cast(),
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            )
+        })?;
+        // SAFETY: listen(2) on our bound socket.
+        check(unsafe { libc::listen(fd, libc::SOMAXCONN) })?;
+        Ok(TcpListener::from(socket))
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+    use std::net::{SocketAddrV6, TcpListener};
+
+    /// Listens on `addr`. Sockets here are IPv6-only, so mapped addresses
+    /// are unsupported and `auto` binds IPv4 separately.
+    pub fn bind_v6(addr: SocketAddrV6, v6only: bool) -> io::Result<TcpListener> {
+        if !v6only {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        TcpListener::bind(addr)
+    }
+}
@@ -0,0 +1,325 @@
+//! The request pipeline: an ordered list of [`Middleware`] around the
+//! method dispatch in [`handler`](super::handler).
+//!
+//! For each request, `before_request` runs in order until one returns a
+//! response; otherwise the request is dispatched. `after_response` then runs
+//! in reverse order for every middleware whose `before_request` ran, and
+//! `on_complete` for all of them once the response has been written.
+//!
+//! The built-in stages come first, so authentication also guards anything
+//! registered with [`ServerBuilder::middleware`](super::ServerBuilder::middleware).
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::access;
+use crate::auth;
+use crate::fastcgi;
+use crate::files::resolve;
+use crate::forward_auth;
+use crate::hooks;
+use crate::http::{Request, Response};
+use crate::locale;
+use crate::log;
+use crate::mock;
+use crate::plugin;
+use crate::webdav;
+
+use super::transform::Transforms;
+use super::{Context, cors, debug, handler, hosts, policy, schedule};
+
+/// A stage of the request pipeline. Every method defaults to doing nothing.
+pub trait Middleware: Send + Sync {
+    /// Runs before dispatch; returning a response skips later stages and the
+    /// dispatch itself.
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        let _ = (ctx, req);
+        None
+    }
+
+    /// Runs on the way out, and may rewrite the response.
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        let _ = (ctx, req, resp);
+    }
+
+    /// Runs after the response has been sent (or sending failed).
+    fn on_complete(&self, ctx: &Context, done: &Completed<'_>) {
+        let _ = (ctx, done);
+    }
+
+    /// Name shown in request dumps; the type name by default.
+    fn name(&self) -> &str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+/// What was sent for one request.
+#[derive(Debug)]
+pub struct Completed<'a> {
+    pub peer: SocketAddr,
+    /// `METHOD target VERSION`.
+    pub request_line: &'a str,
+    pub status: u16,
+    /// Body bytes written.
+    pub bytes: u64,
+    /// Time from the parsed request head to the last byte written.
+    pub elapsed: Duration,
+    /// Why writing the response failed, if it did.
+    pub error: Option<&'a io::Error>,
+}
+
+/// The built-in stages, in order.
+pub fn builtin() -> Vec<Box<dyn Middleware>> {
+    vec![
+        Box::new(AccessLog),
+        Box::new(Stats),
+        Box::new(Csp),
+        Box::new(Transforms),
+        Box::new(Localize),
+        Box::new(Hosts),
+        Box::new(Schedule),
+        Box::new(Cors),
+        Box::new(Policy),
+        Box::new(Auth),
+        Box::new(ForwardAuth),
+        Box::new(Plugins),
+        Box::new(ScriptHooks),
+        Box::new(Internal),
+        Box::new(Mocks),
+        Box::new(FastCgi),
+        Box::new(Invalidate),
+    ]
+}
+
+/// Runs the pipeline for one request.
+pub fn run(ctx: &Context, req: &mut Request, accepted: impl FnOnce(&Request)) -> Response {
+    let stages = &ctx.middleware;
+    let mut ran = 0;
+    let mut resp = None;
+    for stage in stages {
+        ran += 1;
+        resp = stage.before_request(ctx, req);
+        if resp.is_some() {
+            debug::note(|| format!("answered by the {} stage", stage.name()));
+            break;
+        }
+    }
+    let mut resp = resp.unwrap_or_else(|| {
+        debug::note(|| format!("no stage answered, dispatched on {}", req.method));
+        accepted(req);
+        handler::dispatch(ctx, req)
+    });
+    for stage in stages[..ran].iter().rev() {
+        stage.after_response(ctx, req, &mut resp);
+    }
+    resp
+}
+
+/// Reports a finished request to every stage.
+pub fn complete(ctx: &Context, done: &Completed<'_>) {
+    for stage in &ctx.middleware {
+        stage.on_complete(ctx, done);
+    }
+}
+
+/// One line per request on stdout, unless `accessLog` is off.
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn on_complete(&self, ctx: &Context, done: &Completed<'_>) {
+        if ctx.config.bool("accessLog") {
+            log::access(
+                done.peer,
+                done.request_line,
+                done.status,
+                done.bytes,
+                done.elapsed,
+            );
+        }
+    }
+}
+
+/// Counts requests for the status page, when `statusPage` is on, and keeps
+/// the last ones with `lastRequests`.
+pub struct Stats;
+
+impl Middleware for Stats {
+    fn on_complete(&self, ctx: &Context, done: &Completed<'_>) {
+        if let Some(status) = &ctx.status {
+            status.record(done);
+        }
+        if let Some(recent) = &ctx.recent {
+            recent.record(done);
+        }
+    }
+}
+
+/// Sends the `csp` policy and adds nonces to HTML pages. It comes before
+/// [`Transforms`] so that injected snippets get nonces too.
+pub struct Csp;
+
+impl Middleware for Csp {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        if let Some(csp) = &ctx.csp {
+            csp.prepare(req);
+        }
+        None
+    }
+
+    fn after_response(&self, ctx: &Context, _req: &Request, resp: &mut Response) {
+        if let Some(csp) = &ctx.csp {
+            csp.apply(resp);
+        }
+    }
+}
+
+/// Renders built-in error pages in the client's language.
+pub struct Localize;
+
+impl Middleware for Localize {
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        locale::localize_error(ctx, req, resp);
+    }
+}
+
+/// Refuses requests for hosts outside `allowedHosts`.
+pub struct Hosts;
+
+impl Middleware for Hosts {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        hosts::check(ctx, req)
+    }
+}
+
+/// Turns requests away outside the `schedule` windows.
+pub struct Schedule;
+
+impl Middleware for Schedule {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        schedule::check(ctx, req)
+    }
+}
+
+/// Answers preflights and lets any origin read responses, with `cors` on.
+pub struct Cors;
+
+impl Middleware for Cors {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        cors::preflight(ctx, req)
+    }
+
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        cors::decorate(ctx, req, resp);
+    }
+}
+
+/// Applies the `policy` rules.
+pub struct Policy;
+
+impl Middleware for Policy {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        policy::check(ctx, req)
+    }
+}
+
+/// Demands credentials per `authFor`, or per the access files when
+/// `accessFiles` is on.
+pub struct Auth;
+
+impl Middleware for Auth {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        if ctx.access.is_some() {
+            return access::check(ctx, req);
+        }
+        if !auth::required(ctx, req) {
+            return None;
+        }
+        auth::authenticate(ctx, req).err()
+    }
+}
+
+/// Asks the `forwardAuth` service whether the request may go on.
+pub struct ForwardAuth;
+
+impl Middleware for ForwardAuth {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        forward_auth::handle(ctx, req)
+    }
+}
+
+/// Runs the WebAssembly plugins from `plugins`.
+pub struct Plugins;
+
+impl Middleware for Plugins {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        plugin::handle(ctx, req)
+    }
+}
+
+/// Calls the `on_request` and `on_response` functions of the `hooks` script.
+pub struct ScriptHooks;
+
+impl Middleware for ScriptHooks {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        hooks::before(ctx, req)
+    }
+
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        hooks::after(ctx, req, resp);
+    }
+}
+
+/// Built-in endpoints under `internalPrefix`, except for escaped requests
+/// meant for files.
+pub struct Internal;
+
+impl Middleware for Internal {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        if !ctx.is_internal(req) {
+            return None;
+        }
+        let name = req.path.strip_prefix(&ctx.internal_prefix)?;
+        handler::internal(ctx, req, name)
+    }
+}
+
+/// Answers requests matched by `mocks`.
+pub struct Mocks;
+
+impl Middleware for Mocks {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        mock::handle(ctx, req)
+    }
+}
+
+/// Hands scripts to the FastCGI backend.
+pub struct FastCgi;
+
+impl Middleware for FastCgi {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        let script = fastcgi::script_for(ctx, &req.path)?;
+        Some(fastcgi::handle(ctx, req, &script))
+    }
+}
+
+/// Drops cached metadata and bodies for what a successful write touched:
+/// the request path and, for `COPY` and `MOVE`, the destination.
+pub struct Invalidate;
+
+impl Middleware for Invalidate {
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        if !auth::is_write_method(&req.method) || resp.status >= 400 {
+            return;
+        }
+        let destination = matches!(req.method.as_str(), "COPY" | "MOVE")
+            .then(|| webdav::destination(req))
+            .flatten();
+        for path in std::iter::once(req.path.as_str()).chain(destination.as_deref()) {
+            if let Some(written) = resolve::resolve(&ctx.root, path, true) {
+                ctx.invalidate_written(&written);
+            }
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! Listener, per-connection loop and request dispatch.
+
+pub mod bandwidth;
+mod builder;
+pub mod callbacks;
+pub mod chaos;
+mod conn;
+pub mod cors;
+pub mod csp;
+pub mod debug;
+pub mod early_hints;
+#[cfg(all(feature = "tokio", unix))]
+mod event_loop;
+pub mod handler;
+pub mod hosts;
+pub mod lifecycle;
+pub mod listen;
+pub mod middleware;
+mod notify;
+pub mod policy;
+pub mod pool;
+pub mod recent;
+pub mod record;
+pub mod routes;
+pub mod schedule;
+pub mod shed;
+pub mod status;
+pub mod throttle;
+pub mod transform;
+pub mod unicode;
+
+use std::hash::{BuildHasher, RandomState};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result};
+
+use crate::access::AccessFiles;
+use crate::audit::Audit;
+use crate::auth::{self, AuthProvider};
+use crate::core::{Config, Layer};
+use crate::error::Error;
+use crate::files;
+use crate::files::cache::FileCache;
+use crate::files::digest::Digests;
+use crate::files::listing::{self, ListingRenderer};
+use crate::files::manifest::Manifest;
+use crate::files::stat::StatCache;
+use crate::forward_auth::{self, ForwardAuth};
+use crate::hooks::{self, Hooks};
+use crate::http::Request;
+use crate::http::response::Identity;
+use crate::http::sse::Streams;
+use crate::locale::{self, Locales};
+use crate::log;
+use crate::mock::{self, Mocks};
+use crate::plugin::{self, Plugin};
+use crate::resumable::{self, Uploads};
+use crate::sandbox;
+use crate::vfs::{Archive, ArchiveLimits, LocalFs, Remote, Vfs};
+use crate::watch::Watcher;
+use bandwidth::Bandwidth;
+pub use builder::{ServerBuilder, ServerHandle};
+use chaos::Chaos;
+use csp::Csp;
+use lifecycle::Lifecycle;
+use listen::Stack;
+use middleware::Middleware;
+use policy::Policy;
+use recent::Recent;
+use record::Recorder;
+use schedule::Schedule;
+use shed::Shed;
+use status::Status;
+use throttle::Throttle;
+use transform::{InjectHtml, Transform};
+
+/// Segment after the internal prefix that reaches files instead:
+/// `/__tinyserve/-/status` is the file `__tinyserve/status` in the root.
+pub const INTERNAL_ESCAPE: &str = "-/";
+
+/// State shared by every connection.
+pub struct Context {
+    pub config: Config,
+    /// Canonical path of the served directory; for other backends, the
+    /// `root` option as given, which request paths are resolved against.
+    pub root: PathBuf,
+    /// Where files are read from.
+    pub vfs: Arc<dyn Vfs>,
+    /// Present when a feature that needs change notifications is enabled.
+    pub watcher: Option<Arc<Watcher>>,
+    /// Canonical `tailDir`, when log tailing is enabled.
+    pub tail_dir: Option<PathBuf>,
+    /// Asset manifest, when `manifest` is set.
+    pub manifest: Option<Manifest>,
+    /// Bodies of small, frequently requested files, when `cacheSize` > 0.
+    pub file_cache: Option<Arc<FileCache>>,
+    /// Content hashes for ETags, in `deterministic` mode.
+    pub digests: Option<Digests>,
+    /// Recent `stat` results, when `statTtl` or `notFoundTtl` > 0.
+    pub stat_cache: Option<StatCache>,
+    /// Load-shedding guard, when `shed` is on.
+    pub shed: Option<Shed>,
+    /// Fault injection, when `chaos` has rules.
+    pub chaos: Option<Chaos>,
+    /// Path rules checked before file lookup, from `policy`.
+    pub policy: Option<Policy>,
+    /// When paths may be served, from `schedule`.
+    pub schedule: Option<Schedule>,
+    /// HAR capture of the traffic, when `record` is set.
+    pub recorder: Option<Recorder>,
+    /// Log of write requests, when `auditLog` is set and `readOnly` off.
+    pub audit: Option<Audit>,
+    /// Simulated network conditions, from `throttle`.
+    pub throttle: Vec<Throttle>,
+    /// WebAssembly request plugins, from `plugins`.
+    pub plugins: Vec<Plugin>,
+    /// Scripted request hooks, from `hooks`.
+    pub hooks: Option<Hooks>,
+    /// Canned API responses, from `mocks`.
+    pub mocks: Option<Mocks>,
+    /// Parsed `.tinyserve-access` files, when `accessFiles` is on.
+    pub access: Option<AccessFiles>,
+    /// Who may sign in: the `authUsers` and `authToken` credentials, then
+    /// providers added by an embedder.
+    pub auth: Vec<Box<dyn AuthProvider>>,
+    /// The auth service asked about requests, from `forwardAuth`.
+    pub forward_auth: Option<ForwardAuth>,
+    /// Strings of listings and error pages, from `locale` and `localeDir`.
+    pub locales: Locales,
+    /// Counters for the status page, when `statusPage` is on.
+    pub status: Option<Status>,
+    /// The last requests, when `lastRequests` > 0.
+    pub recent: Option<Recent>,
+    /// Uploads in progress, when `resumableUploads` is on in writable mode.
+    pub uploads: Option<Uploads>,
+    /// Caps from `bandwidth` and `bandwidthLimits`.
+    pub bandwidth: Bandwidth,
+    /// Request pipeline: the built-in stages, then any added by an embedder.
+    pub middleware: Vec<Box<dyn Middleware>>,
+    /// Added to every response, from `headers`.
+    pub header_rules: Vec<(String, String)>,
+    /// Resources announced ahead of pages, from `earlyHints` and
+    /// `earlyHintsAuto`.
+    pub early_hints: Vec<early_hints::Rule>,
+    /// Body rewrites for text responses.
+    pub transforms: Vec<Box<dyn Transform>>,
+    /// Directory listing formats: any added by an embedder, then JSON and
+    /// HTML.
+    pub listing_renderers: Vec<Box<dyn ListingRenderer>>,
+    /// The policy from `csp`, `cspNonce` and `cspReportOnly`.
+    pub csp: Option<Csp>,
+    /// Set to make the accept loop exit.
+    pub shutdown: AtomicBool,
+    /// Open event and tail streams, limited by `maxStreams`.
+    pub streams: Arc<Streams>,
+    /// Readiness and requests in progress.
+    pub lifecycle: Lifecycle,
+    /// Where built-in endpoints live, from `internalPrefix`; starts and ends
+    /// with `/`.
+    pub internal_prefix: String,
+    /// Lowercased `allowedHosts`; empty allows every host.
+    pub allowed_hosts: Vec<String>,
+    /// The `Server` name and `Date` clock, from `serverHeader` and
+    /// `deterministic`.
+    pub identity: Identity,
+}
+
+impl Context {
+    pub fn new(config: Config) -> Result<Self> {
+        let root = config.str("root");
+        if root.contains("://") {
+            let cache = match config.str("originCache") {
+                "" => None,
+                dir => Some(PathBuf::from(dir)),
+            };
+            let ttl = Duration::from_millis(config.int("originTtl"));
+            let remote = Remote::new(root, cache, ttl)
+                .with_context(|| format!("cannot serve {root}"))?
+                .honor_cache_control(config.bool("originCacheControl"));
+            return Self::with_vfs(config, Arc::new(remote));
+        }
+        if Archive::is_archive(Path::new(root)) && Path::new(root).is_file() {
+            let limits = ArchiveLimits {
+                max_bytes: config.int("archiveMaxBytes"),
+                max_depth: config.int("archiveMaxDepth") as usize,
+            };
+            let archive =
+                Archive::open_with(root, limits).with_context(|| format!("cannot serve {root}"))?;
+            return Self::with_vfs(config, Arc::new(archive));
+        }
+        let local = LocalFs::new(root).with_context(|| format!("cannot serve {root}"))?;
+        let root = local.root().to_path_buf();
+        Self::build(config, root, Arc::new(local))
+    }
+
+    /// Serves files from `vfs` rather than the local filesystem. Uploads and
+    /// other writes need a local root, so the server is read-only.
+    pub fn with_vfs(mut config: Config, vfs: Arc<dyn Vfs>) -> Result<Self> {
+        config.set_raw("readOnly", "true", Layer::Cli)?;
+        let root = PathBuf::from(config.str("root"));
+        Self::build(config, root, vfs)
+    }
+
+    fn build(config: Config, root: PathBuf, vfs: Arc<dyn Vfs>) -> Result<Self> {
+        let watcher = config.bool("events").then(|| {
+            Watcher::spawn(
+                root.clone(),
+                Duration::from_millis(config.int("watchInterval").max(50)),
+                config.bool("showHidden"),
+            )
+        });
+        let tail_dir = match config.str("tailDir") {
+            "" => None,
+            dir => Some(
+                PathBuf::from(dir)
+                    .canonicalize()
+                    .with_context(|| format!("tailDir {dir}"))?,
+            ),
+        };
+        let manifest = match config.str("manifest") {
+            "" => None,
+            path => Some(Manifest::new(&root, path)),
+        };
+        let file_cache = match config.int("cacheSize") {
+            0 => None,
+            size => Some(Arc::new(FileCache::new(size, config.int("cacheMaxFile")))),
+        };
+        let stat_cache = match (config.int("statTtl"), config.int("notFoundTtl")) {
+            (0, 0) => None,
+            (ms, miss_ms) => Some(StatCache::new(
+                Duration::from_millis(ms),
+                Duration::from_millis(miss_ms),
+            )),
+        };
+        let digests = config.bool("deterministic").then(Digests::default);
+        let shed = config.bool("shed").then(|| Shed::new(&config));
+        unicode::check(&config)?;
+        let throttle = throttle::rules(&config)?;
+        let bandwidth = Bandwidth::from_config(&config)?;
+        let chaos = Chaos::from_config(&config)?;
+        let policy = Policy::from_config(&config)?;
+        let schedule = Schedule::from_config(&config)?;
+        let recorder = Recorder::from_config(&config)?;
+        let audit = Audit::from_config(&config)?;
+        let plugins = plugin::load(&config)?;
+        let hooks = hooks::load(&config)?;
+        let mocks = mock::load(&config)?;
+        let locales = locale::load(&config)?;
+        let frozen = config
+            .bool("deterministic")
+            .then(|| UNIX_EPOCH + Duration::from_secs(config.int("deterministicDate")));
+        let identity = Identity::new(server_name(config.str("serverHeader")), frozen);
+        let access = config.bool("accessFiles").then(AccessFiles::default);
+        let forward_auth = forward_auth::load(&config)?;
+        let status = config.bool("statusPage").then(Status::default);
+        let uploads =
+            (config.bool("resumableUploads") && !config.bool("readOnly")).then(Uploads::default);
+        let recent = match config.int("lastRequests") {
+            0 => None,
+            n => Some(Recent::new(
+                usize::try_from(n).unwrap_or(usize::MAX).min(10_000),
+            )),
+        };
+        let internal_prefix = match config.str("internalPrefix").trim_matches('/') {
+            "" => anyhow::bail!("internalPrefix cannot be the root"),
+            prefix => format!("/{prefix}/"),
+        };
+        let allowed_hosts = hosts::from_config(&config);
+        let header_rules = transform::header_rules(&config)?;
+        let early_hints = early_hints::rules(&config)?;
+        let mut transforms: Vec<Box<dyn Transform>> = Vec::new();
+        if let Some(inject) = InjectHtml::from_config(&config)? {
+            transforms.push(Box::new(inject));
+        }
+        let csp = Csp::from_config(&config)?;
+        let auth = auth::from_config(&config).into_iter().collect();
+        let streams = Streams::new(config.int("maxStreams") as usize);
+        Ok(Self {
+            config,
+            root,
+            vfs,
+            watcher,
+            tail_dir,
+            manifest,
+            file_cache,
+            digests,
+            stat_cache,
+            shed,
+            chaos,
+            policy,
+            schedule,
+            recorder,
+            audit,
+            throttle,
+            plugins,
+            hooks,
+            mocks,
+            access,
+            auth,
+            forward_auth,
+            locales,
+            status,
+            recent,
+            uploads,
+            bandwidth,
+            middleware: middleware::builtin(),
+            header_rules,
+            early_hints,
+            transforms,
+            listing_renderers: listing::builtin(),
+            csp,
+            shutdown: AtomicBool::new(false),
+            streams,
+            lifecycle: Lifecycle::default(),
+            internal_prefix,
+            allowed_hosts,
+            identity,
+        })
+    }
+
+    /// Whether `req` is for a built-in endpoint rather than an escaped file.
+    pub fn is_internal(&self, req: &Request) -> bool {
+        let escaped = req
+            .target
+            .strip_prefix(&self.internal_prefix)
+            .is_some_and(|rest| rest.starts_with(INTERNAL_ESCAPE));
+        req.path.starts_with(&self.internal_prefix) && !escaped
+    }
+
+    /// Drops cached state for `path` and everything below it.
+    pub fn invalidate(&self, path: &Path) {
+        if let Some(cache) = &self.stat_cache {
+            cache.invalidate(path);
+        }
+        if let Some(cache) = &self.file_cache {
+            cache.invalidate(path);
+        }
+        if let Ok(rel) = path.strip_prefix(&self.root) {
+            self.vfs.invalidate(rel);
+        }
+    }
+
+    /// Drops cached state after a write to `path`: the path and everything
+    /// below it, and what was known about the directory it is listed in.
+    pub fn invalidate_written(&self, path: &Path) {
+        self.invalidate(path);
+        if let (Some(cache), Some(parent)) = (&self.stat_cache, path.parent()) {
+            cache.forget(parent);
+        }
+    }
+}
+
+/// Drops cached state for files the watcher reports as changed.
+fn invalidate_on_change(ctx: &Arc<Context>, watcher: &Watcher) {
+    let changes = watcher.subscribe();
+    let ctx = Arc::downgrade(ctx);
+    let spawned = thread::Builder::new()
+        .name("tinyserve-cache".into())
+        .spawn(move || {
+            for change in changes {
+                let Some(ctx) = ctx.upgrade() else { break };
+                ctx.invalidate(&ctx.root.join(&change.path));
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn(&format!("cannot spawn cache invalidation thread: {e}"));
+    }
+}
+
+/// Names `serverHeader=random` picks from, once per start.
+const DECOY_SERVERS: &[&str] = &["nginx", "Apache", "Caddy", "lighttpd", "openresty"];
+
+/// The name `serverHeader` asks for; `None` keeps the default.
+fn server_name(setting: &str) -> Option<String> {
+    match setting {
+        "" | "full" => None,
+        "name" => Some("tinyserve".into()),
+        "off" => Some(String::new()),
+        "random" => {
+            let i = RandomState::new().hash_one(0u64) as usize % DECOY_SERVERS.len();
+            Some(DECOY_SERVERS[i].into())
+        }
+        name => Some(name.trim().chars().filter(|c| !c.is_control()).collect()),
+    }
+}
+
+/// A bound server, ready to [`run`](Server::run).
+pub struct Server {
+    ctx: Arc<Context>,
+    /// The main listener first; a second one with `dualStack=separate`.
+    listeners: Vec<TcpListener>,
+    stack: Stack,
+}
+
+impl Server {
+    pub fn bind(config: Config) -> crate::Result<Self> {
+        Self::from_context(Context::new(config).map_err(Error::from_anyhow)?)
+    }
+
+    /// Binds with a context the caller has prepared, e.g. with extra
+    /// middleware.
+    fn from_context(mut ctx: Context) -> crate::Result<Self> {
+        let config = &ctx.config;
+        if config.str("runtime") == "tokio" && !cfg!(all(feature = "tokio", unix)) {
+            return Err(Error::Config(
+                "runtime=tokio needs a Unix build with the `tokio` feature".into(),
+            ));
+        }
+        if config.bool("cors") {
+            log::warn(
+                "cors is on: any website may read what this server sends; for development only",
+            );
+        }
+        if ctx.policy.as_ref().is_some_and(Policy::requires_auth) && !auth::enabled(&ctx) {
+            return Err(Error::Config(
+                "policy: `require-auth` needs authUsers, authToken or an auth provider".into(),
+            ));
+        }
+        notify::starting(&ctx);
+        let (listeners, stack) = listen::bind(config)?;
+        sandbox::apply(&ctx).map_err(Error::from_anyhow)?;
+        files::preload::run(&ctx);
+        let derived = early_hints::derive(&ctx);
+        ctx.early_hints.extend(derived);
+        let ctx = Arc::new(ctx);
+        if let Some(watcher) = &ctx.watcher
+            && (ctx.file_cache.is_some() || ctx.stat_cache.is_some())
+        {
+            invalidate_on_change(&ctx, watcher);
+        }
+        if ctx.uploads.is_some() {
+            resumable::sweep(&ctx);
+        }
+        Ok(Self {
+            ctx,
+            listeners,
+            stack,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listeners[0]
+            .local_addr()
+            .expect("bound listener has an address")
+    }
+
+    /// The addresses of every listener, the main one first.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|l| l.local_addr().ok())
+            .collect()
+    }
+
+    /// How the listeners cover IPv4 and IPv6.
+    pub fn stack(&self) -> Stack {
+        self.stack
+    }
+
+    pub fn context(&self) -> &Arc<Context> {
+        &self.ctx
+    }
+
+    /// Accepts connections until shut down through a
+    /// [`ServerHandle`], handing them to the worker pool, or to the tokio
+    /// runtime with `runtime=tokio`.
+    pub fn run(self) -> crate::Result<()> {
+        let ctx = Arc::clone(&self.ctx);
+        // Connections queue on the bound listeners from here on.
+        notify::ready(&ctx, &self.local_addrs());
+        let result = self.accept().map_err(Error::from_anyhow);
+        notify::stopping(&ctx);
+        ctx.lifecycle.set_stopped();
+        result
+    }
+
+    fn accept(self) -> Result<()> {
+        #[cfg(all(feature = "tokio", unix))]
+        if self.ctx.config.str("runtime") == "tokio" {
+            return event_loop::run(self.ctx, self.listeners);
+        }
+        let pool = pool::Pool::start(&self.ctx);
+        let mut listeners = self.listeners.into_iter();
+        let main = listeners.next().expect("at least one listener");
+        let others: Vec<_> = listeners
+            .map(|listener| {
+                let ctx = Arc::clone(&self.ctx);
+                let pool = Arc::clone(&pool);
+                thread::Builder::new()
+                    .name("tinyserve-accept".into())
+                    .spawn(move || accept_on(&ctx, &pool, &listener))
+                    .context("cannot start an accept thread")
+            })
+            .collect::<Result<_>>()?;
+        self.ctx.lifecycle.set_ready();
+        accept_on(&self.ctx, &pool, &main);
+        for thread in others {
+            let _ = thread.join();
+        }
+        pool.close();
+        Ok(())
+    }
+}
+
+/// Hands the connections `listener` accepts to `pool` until shut down.
+fn accept_on(ctx: &Context, pool: &Arc<pool::Pool>, listener: &TcpListener) {
+    for stream in listener.incoming() {
+        if ctx.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => pool.submit(stream),
+            Err(e) => log::warn(&format!("accept failed: {e}")),
+        }
+    }
+}
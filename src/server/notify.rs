@@ -0,0 +1,124 @@
+//! Telling supervisors the server is up (`notify`, `readyFile`).
+//!
+//! With `notify`, a server started by systemd (`Type=notify`, which sets
+//! `NOTIFY_SOCKET`) sends `READY=1` once its listeners are bound, and
+//! `STOPPING=1` when it shuts down. `readyFile` names a file written at
+//! the same moment with the PID, bound addresses, URL and start time as
+//! JSON, for test harnesses and scripts. The file is replaced atomically,
+//! removed at startup so a stale one is never mistaken for this server's,
+//! and removed again when the server stops.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::http::date;
+use crate::log;
+use crate::writable;
+
+use super::Context;
+
+/// Removes a ready file left behind by an earlier run.
+pub(super) fn starting(ctx: &Context) {
+    if let Some(path) = ready_file(ctx) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Announces that `addrs` are accepting connections.
+pub(super) fn ready(ctx: &Context, addrs: &[SocketAddr]) {
+    let url = addrs
+        .first()
+        .map(|addr| format!("http://{addr}/"))
+        .unwrap_or_default();
+    if let Some(path) = ready_file(ctx) {
+        let state = json!({
+            "pid": process::id(),
+            "addresses": addrs.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "url": url,
+            "root": ctx.root,
+            "started_at": date::format_iso(SystemTime::now()),
+        });
+        let text = format!("{state}\n");
+        let written = writable::write_atomic(path, |file| {
+            file.write_all(text.as_bytes())?;
+            Ok(text.len() as u64)
+        });
+        if let Err(e) = written {
+            log::warn(&format!("cannot write {}: {e}", path.display()));
+        }
+    }
+    if ctx.config.bool("notify") {
+        let message = format!("READY=1\nMAINPID={}\nSTATUS=Serving {url}\n", process::id());
+        if let Err(e) = sys::send(&message) {
+            log::warn(&format!("cannot notify systemd: {e}"));
+        }
+    }
+}
+
+/// Announces shutdown and removes the ready file.
+pub(super) fn stopping(ctx: &Context) {
+    if ctx.config.bool("notify") {
+        let _ = sys::send("STOPPING=1\n");
+    }
+    starting(ctx);
+}
+
+fn ready_file(ctx: &Context) -> Option<&Path> {
+    match ctx.config.str("readyFile") {
+        "" => None,
+        path => Some(Path::new(path)),
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::env;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    /// Sends `message` to `$NOTIFY_SOCKET`; nothing when it is not set.
+    pub fn send(message: &str) -> io::Result<()> {
+        let Some(target) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(());
+        };
+        let socket = UnixDatagram::unbound()?;
+        match target.as_bytes().strip_prefix(b"@") {
+            Some(name) => send_abstract(&socket, name, message),
+            None => socket.send_to(message.as_bytes(), &target).map(drop),
+        }
+    }
+
+    /// An `@name` socket lives in Linux's abstract namespace.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn send_abstract(socket: &UnixDatagram, name: &[u8], message: &str) -> io::Result<()> {
+        #[cfg(target_os = "android")]
+        use std::os::android::net::SocketAddrExt;
+        #[cfg(target_os = "linux")]
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let addr = SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(message.as_bytes(), &addr).map(drop)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn send_abstract(_: &UnixDatagram, _: &[u8], _: &str) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+
+    /// There is no systemd to tell.
+    pub fn send(_message: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
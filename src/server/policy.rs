@@ -0,0 +1,188 @@
+//! Path policy: `policy` rules decide what may be asked for before any file
+//! is looked up, e.g. `*.map=deny` in production or
+//! `/admin/**=local-only`.
+//!
+//! Each rule reads `GLOB=ACTION` and the first rule whose glob matches the
+//! request path decides:
+//!
+//! - `allow` lets the request on, skipping the rules after it;
+//! - `deny` answers `403`;
+//! - `require-auth` demands credentials (`authUsers`, `authToken` or an
+//!   embedder's [`AuthProvider`](crate::auth::AuthProvider)), whatever
+//!   `authFor` says;
+//! - `local-only` answers `403` unless the peer is a loopback address.
+//!
+//! Requests no rule matches go on as usual. `COPY` and `MOVE` are also
+//! held to the rules of their `Destination`.
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::auth;
+use crate::core::Config;
+use crate::glob;
+use crate::http::{Request, Response};
+use crate::webdav;
+
+use super::{Context, debug};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+    RequireAuth,
+    LocalOnly,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: String,
+    action: Action,
+}
+
+#[derive(Debug)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// The rules configured by `policy`; `None` when there are none.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let rules = config
+            .list("policy")
+            .iter()
+            .map(|rule| parse(rule).with_context(|| format!("policy rule `{rule}`")))
+            .collect::<Result<Vec<_>>>()?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self { rules }))
+    }
+
+    /// Whether a rule demands credentials, which then have to be configured.
+    pub fn requires_auth(&self) -> bool {
+        self.rules.iter().any(|r| r.action == Action::RequireAuth)
+    }
+
+    /// The action of the first rule matching `path`, if any.
+    pub fn action(&self, path: &str) -> Option<Action> {
+        self.rules
+            .iter()
+            .find(|rule| glob::matches(&rule.glob, path))
+            .map(|rule| rule.action)
+    }
+}
+
+fn parse(rule: &str) -> Result<Rule> {
+    let (glob, action) = rule
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("expected GLOB=ACTION"))?;
+    let action = match action.trim() {
+        "allow" => Action::Allow,
+        "deny" => Action::Deny,
+        "require-auth" => Action::RequireAuth,
+        "local-only" => Action::LocalOnly,
+        other => bail!("unknown action `{other}` (allow, deny, require-auth, local-only)"),
+    };
+    let glob = glob.trim();
+    if glob.is_empty() {
+        bail!("the glob is empty");
+    }
+    Ok(Rule {
+        glob: glob.to_owned(),
+        action,
+    })
+}
+
+/// The response turning `req` away, if the policy does. `COPY` and `MOVE`
+/// must also pass the rules of their destination.
+pub fn check(ctx: &Context, req: &Request) -> Option<Response> {
+    ctx.policy.as_ref()?;
+    let destination = matches!(req.method.as_str(), "COPY" | "MOVE")
+        .then(|| webdav::destination(req))
+        .flatten();
+    std::iter::once(req.path.as_str())
+        .chain(destination.as_deref())
+        .find_map(|path| check_path(ctx, req, path))
+}
+
+/// The response turning `req` away from `path`, which need not be the
+/// request's own, e.g. a file a page includes.
+pub fn check_path(ctx: &Context, req: &Request, path: &str) -> Option<Response> {
+    let action = ctx.policy.as_ref()?.action(path)?;
+    debug::note(|| format!("policy for {path}: {action:?}"));
+    match action {
+        Action::Allow => None,
+        Action::Deny => Some(Response::error(403)),
+        Action::RequireAuth => auth::authenticate(ctx, req).err(),
+        Action::LocalOnly if req.peer.ip().to_canonical().is_loopback() => None,
+        Action::LocalOnly => Some(Response::error_with(403, "only local clients may see this")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{AUTH, TestServer};
+
+    fn policy(rules: &[&str]) -> Policy {
+        let rules = rules.iter().map(|r| parse(r).unwrap()).collect();
+        Policy { rules }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let policy = policy(&["/admin/health=allow", "/admin/**=local-only", "*.map=deny"]);
+        assert_eq!(policy.action("/admin/health"), Some(Action::Allow));
+        assert_eq!(policy.action("/admin/users"), Some(Action::LocalOnly));
+        assert_eq!(policy.action("/js/app.js.map"), Some(Action::Deny));
+        assert_eq!(policy.action("/js/app.js"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(parse("/x").is_err());
+        assert!(parse("=deny").is_err());
+        assert!(parse("/x=block").is_err());
+    }
+
+    #[test]
+    fn applies_however_the_path_is_spelled() {
+        let server = TestServer::with(|b| b.option("policy", "secret/**=deny")).unwrap();
+        server.write("secret/x", "hidden").unwrap();
+        server.write("open/x", "shown").unwrap();
+        for target in [
+            "/secret/x",
+            "/./secret/x",
+            "//secret/x",
+            "/%2e/secret/x",
+            "/secret/./x",
+            "/%2fsecret//x",
+        ] {
+            assert_eq!(server.get(target).unwrap().status, 403, "{target}");
+        }
+        assert_eq!(server.get("/./open/x").unwrap().text(), "shown");
+    }
+
+    #[test]
+    fn applies_to_the_destination() {
+        let server = TestServer::with_auth(|b| {
+            b.option("policy", "secret/**=deny")
+                .option("readOnly", false)
+                .option("webdav", true)
+        })
+        .unwrap();
+        server.write("open/x", "shown").unwrap();
+        let move_to = |dest: &str| {
+            let headers = [AUTH, ("Destination", dest)];
+            server
+                .request("MOVE", "/open/x", &headers, b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(move_to("/secret/x"), 403);
+        assert_eq!(move_to("/.//secret/x"), 403);
+        assert_eq!(move_to(&server.url("/secret/x")), 403);
+        assert_eq!(server.get("/open/x").unwrap().text(), "shown");
+        assert_eq!(move_to("/open/y"), 201);
+    }
+}
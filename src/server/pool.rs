@@ -0,0 +1,183 @@
+//! Pool of connection workers fed by a bounded accept queue.
+//!
+//! Workers are started as connections arrive, up to `workers`, and exit
+//! after 30 seconds without one, so an idle server holds few threads. When
+//! every worker is busy and no more may start, accepted connections wait in
+//! the queue. A full queue either turns new connections away with `503`
+//! (`backpressure=reject`) or holds the acceptor until space frees up
+//! (`backpressure=queue`); connections that waited longer than
+//! `queueTimeout` get `503` too.
+
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http::response::{Identity, WriteOptions};
+use crate::http::{Response, Version};
+use crate::log;
+
+use super::{Context, conn};
+
+/// How long a worker waits for a connection before exiting.
+const IDLE: Duration = Duration::from_secs(30);
+
+/// Most workers when `workers` is 0.
+pub fn default_workers() -> usize {
+    let cpus = thread::available_parallelism().map_or(4, |n| n.get());
+    (cpus * 64).clamp(64, 1024)
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<(TcpStream, Instant)>,
+    /// Workers running.
+    workers: usize,
+    /// Workers waiting for a connection.
+    idle: usize,
+    /// Numbers the next worker's thread.
+    spawned: usize,
+}
+
+pub(super) struct Pool {
+    ctx: Arc<Context>,
+    state: Mutex<State>,
+    /// Signalled when a connection is queued.
+    queued: Condvar,
+    /// Signalled when a worker takes a connection.
+    taken: Condvar,
+    max_workers: usize,
+    depth: usize,
+    timeout: Duration,
+    reject_when_full: bool,
+    closed: AtomicBool,
+    /// For the `503` sent to connections turned away.
+    identity: Identity,
+}
+
+impl Pool {
+    pub(super) fn start(ctx: &Arc<Context>) -> Arc<Self> {
+        let max_workers = match ctx.config.int("workers") {
+            0 => default_workers(),
+            n => n as usize,
+        };
+        Arc::new(Self {
+            ctx: Arc::clone(ctx),
+            state: Mutex::default(),
+            queued: Condvar::new(),
+            taken: Condvar::new(),
+            max_workers,
+            depth: ctx.config.int("acceptQueue").max(1) as usize,
+            timeout: Duration::from_millis(ctx.config.int("queueTimeout")),
+            reject_when_full: ctx.config.str("backpressure") == "reject",
+            closed: AtomicBool::new(false),
+            identity: ctx.identity.clone(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts a worker, counted in `state` until it exits.
+    fn spawn(self: &Arc<Self>, state: &mut State) {
+        let pool = Arc::clone(self);
+        let spawned = thread::Builder::new()
+            .name(format!("tinyserve-worker-{}", state.spawned))
+            .spawn(move || pool.work());
+        match spawned {
+            Ok(_) => {
+                state.workers += 1;
+                state.spawned += 1;
+            }
+            Err(e) => log::warn(&format!("cannot spawn worker thread: {e}")),
+        }
+    }
+
+    fn work(&self) {
+        let ctx = &*self.ctx;
+        loop {
+            let (stream, accepted) = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(next) = state.queue.pop_front() {
+                        break next;
+                    }
+                    if self.closed.load(Ordering::SeqCst) {
+                        state.workers -= 1;
+                        return;
+                    }
+                    state.idle += 1;
+                    let (next, waited) = self.queued.wait_timeout(state, IDLE).unwrap();
+                    state = next;
+                    state.idle -= 1;
+                    if waited.timed_out() && state.queue.is_empty() {
+                        state.workers -= 1;
+                        return;
+                    }
+                }
+            };
+            self.taken.notify_one();
+            if let Some(shed) = &ctx.shed {
+                shed.observe_queue_delay(accepted.elapsed());
+            }
+            if accepted.elapsed() > self.timeout {
+                self.unavailable(stream);
+            } else {
+                conn::serve(ctx, stream);
+            }
+        }
+    }
+
+    /// Lets workers exit once the queue is empty.
+    pub(super) fn close(&self) {
+        let _state = self.lock();
+        self.closed.store(true, Ordering::SeqCst);
+        self.queued.notify_all();
+    }
+
+    /// Hands `stream` to a worker, starting one if none is free, and
+    /// applies the backpressure policy.
+    pub(super) fn submit(self: &Arc<Self>, stream: TcpStream) {
+        let accepted = Instant::now();
+        let mut state = self.lock();
+        while state.queue.len() >= self.depth {
+            let waited = accepted.elapsed();
+            if self.reject_when_full || waited >= self.timeout {
+                drop(state);
+                self.unavailable(stream);
+                return;
+            }
+            state = self
+                .taken
+                .wait_timeout(state, self.timeout - waited)
+                .unwrap()
+                .0;
+        }
+        state.queue.push_back((stream, accepted));
+        if state.queue.len() > state.idle && state.workers < self.max_workers {
+            self.spawn(&mut state);
+        }
+        self.queued.notify_one();
+    }
+
+    /// Answers `503` and closes, without reading the request.
+    fn unavailable(&self, mut stream: TcpStream) {
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let opts = WriteOptions {
+            version: Version::Http11,
+            head_only: false,
+            keep_alive: false,
+            socket: None,
+            identity: &self.identity,
+        };
+        let _ = busy().write_to(&mut stream, opts);
+    }
+}
+
+/// `503` telling the client to retry shortly.
+pub(super) fn busy() -> Response {
+    Response::error_with(503, "the server is busy; try again shortly").header("Retry-After", "1")
+}
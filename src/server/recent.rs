@@ -0,0 +1,88 @@
+//! `/__tinyserve/last-requests`: the last `lastRequests` requests as JSON,
+//! newest first, so "what just happened?" has an answer without an access
+//! log. The status page shows them too.
+//!
+//! Each entry has the time, client, method, target, status, body bytes and
+//! milliseconds taken. Nothing is kept once the server stops.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::http::Response;
+
+use super::middleware::Completed;
+
+/// Path of the endpoint under `/__tinyserve/`.
+pub const PATH: &str = "last-requests";
+
+/// One finished request.
+pub struct Entry {
+    pub at: SystemTime,
+    pub client: String,
+    /// `METHOD target VERSION`.
+    pub request_line: String,
+    pub status: u16,
+    pub bytes: u64,
+    pub ms: f64,
+}
+
+/// A ring of the most recent requests.
+pub struct Recent {
+    capacity: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl Recent {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, done: &Completed<'_>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_back();
+        }
+        entries.push_front(Entry {
+            at: SystemTime::now(),
+            client: done.peer.ip().to_canonical().to_string(),
+            request_line: done.request_line.to_owned(),
+            status: done.status,
+            bytes: done.bytes,
+            ms: (done.elapsed.as_secs_f64() * 10_000.0).round() / 10.0,
+        });
+    }
+
+    /// Calls `f` with the entries, newest first.
+    pub fn with_entries<R>(&self, f: impl FnOnce(&VecDeque<Entry>) -> R) -> R {
+        f(&self.entries.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Answers the endpoint.
+pub fn json(recent: &Recent) -> Response {
+    let requests: Vec<Value> = recent.with_entries(|entries| {
+        entries
+            .iter()
+            .map(|e| {
+                let mut parts = e.request_line.splitn(3, ' ');
+                let at = e.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                json!({
+                    "at": at.as_millis() as u64,
+                    "client": e.client,
+                    "method": parts.next().unwrap_or_default(),
+                    "target": parts.next().unwrap_or_default(),
+                    "status": e.status,
+                    "bytes": e.bytes,
+                    "ms": e.ms,
+                })
+            })
+            .collect()
+    });
+    Response::json(200, &json!({ "requests": requests })).header("Cache-Control", "no-store")
+}
@@ -0,0 +1,387 @@
+//! Traffic recording: `record` writes every exchange to a HAR 1.2 file,
+//! which browser devtools and most HTTP tools can import.
+//!
+//! The file is rewritten in place so it is valid JSON after every entry.
+//! Bodies keep at most `recordBody` bytes; bodies that are not text, or are
+//! compressed, are left out with a comment. Recording stops once the file
+//! would outgrow `recordLimit`. Responses are captured as sent on the wire,
+//! so `sendfile` is bypassed while recording.
+
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context as _, Result};
+use serde_json::{Value, json};
+
+use crate::core::Config;
+use crate::http::body::Framing;
+use crate::http::request::percent_decode;
+use crate::http::response::{SERVER, Written};
+use crate::http::{Request, RequestBody, date, mime};
+use crate::log;
+
+/// Closes the `entries` array and the document; each entry is written over
+/// it and it is written again after.
+const TRAILER: &[u8] = b"\n]}}\n";
+
+struct Output {
+    file: File,
+    len: u64,
+    entries: usize,
+    /// Set once the limit is reached or writing failed.
+    stopped: bool,
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    max_body: usize,
+    limit: u64,
+    output: Mutex<Output>,
+}
+
+/// Up to a cap of the bytes that went by, and how many there were.
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    total: u64,
+}
+
+impl Captured {
+    fn keep(&mut self, buf: &[u8], max: usize) {
+        let room = max.saturating_sub(self.bytes.len());
+        self.bytes.extend_from_slice(&buf[..buf.len().min(room)]);
+        self.total += buf.len() as u64;
+    }
+}
+
+/// Copies what the handler reads of a request body.
+struct Tee {
+    inner: RequestBody,
+    seen: Arc<Mutex<Captured>>,
+    max: usize,
+}
+
+impl Read for Tee {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        lock(&self.seen).keep(&buf[..n], self.max);
+        Ok(n)
+    }
+}
+
+/// A request being recorded, from its head until its response is written.
+pub struct Exchange<'a> {
+    recorder: &'a Recorder,
+    started: SystemTime,
+    clock: Instant,
+    request: Value,
+    server: String,
+    body: Arc<Mutex<Captured>>,
+    mime_type: String,
+}
+
+/// Copies a response head and up to a cap of its body as they are written.
+pub struct Tap<'a> {
+    inner: &'a mut dyn Write,
+    head: Vec<u8>,
+    in_body: bool,
+    body: Captured,
+    max: usize,
+}
+
+impl Write for Tap<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let mut rest = &buf[..n];
+        while !self.in_body && !rest.is_empty() {
+            self.head.push(rest[0]);
+            rest = &rest[1..];
+            self.in_body = self.head.ends_with(b"\r\n\r\n");
+        }
+        self.body.keep(rest, self.max);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Recorder {
+    /// The recorder configured by `record`, if any. Truncates the file.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        match config.str("record") {
+            "" => Ok(None),
+            path => Self::create(Path::new(path), config).map(Some),
+        }
+    }
+
+    fn create(path: &Path, config: &Config) -> Result<Self> {
+        let mut file = File::create(path).with_context(|| format!("{}", path.display()))?;
+        let (name, version) = SERVER.split_once('/').unwrap_or((SERVER, ""));
+        let creator = json!({"name": name, "version": version});
+        let mut head =
+            format!(r#"{{"log":{{"version":"1.2","creator":{creator},"entries":["#).into_bytes();
+        head.extend_from_slice(TRAILER);
+        file.write_all(&head)
+            .with_context(|| format!("{}", path.display()))?;
+        Ok(Self {
+            path: path.to_owned(),
+            max_body: usize::try_from(config.int("recordBody")).unwrap_or(usize::MAX),
+            limit: config.int("recordLimit"),
+            output: Mutex::new(Output {
+                file,
+                len: head.len() as u64,
+                entries: 0,
+                stopped: false,
+            }),
+        })
+    }
+
+    /// Starts recording `req`, whose body is copied as the handler reads it.
+    pub fn start(&self, req: &mut Request) -> Exchange<'_> {
+        let body = Arc::new(Mutex::new(Captured::default()));
+        let inner = std::mem::replace(&mut req.body, RequestBody::empty());
+        req.body = RequestBody::from_reader(Tee {
+            inner,
+            seen: Arc::clone(&body),
+            max: self.max_body,
+        });
+        let host = match req.headers.get("host") {
+            Some(host) => host.to_owned(),
+            None => req.local.to_string(),
+        };
+        let query: Vec<Value> = req
+            .query
+            .as_deref()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                json!({"name": decode(name), "value": decode(value)})
+            })
+            .collect();
+        Exchange {
+            recorder: self,
+            started: SystemTime::now(),
+            clock: Instant::now(),
+            request: json!({
+                "method": req.method,
+                "url": format!("http://{host}{}", req.target),
+                "httpVersion": req.version.to_string(),
+                "cookies": [],
+                "headers": headers(req.headers.iter()),
+                "queryString": query,
+                "headersSize": -1,
+            }),
+            server: req.local.ip().to_string(),
+            body,
+            mime_type: req
+                .headers
+                .get("content-type")
+                .unwrap_or_default()
+                .to_owned(),
+        }
+    }
+
+    fn append(&self, entry: &Value) {
+        let mut out = lock(&self.output);
+        if out.stopped {
+            return;
+        }
+        let mut bytes = if out.entries == 0 { b"\n" } else { &b",\n"[..] }.to_vec();
+        bytes.extend_from_slice(entry.to_string().as_bytes());
+        bytes.extend_from_slice(TRAILER);
+        let len = out.len + bytes.len() as u64 - TRAILER.len() as u64;
+        if len > self.limit {
+            log::warn(&format!(
+                "record: {} reached recordLimit, recording stopped",
+                self.path.display()
+            ));
+            out.stopped = true;
+            return;
+        }
+        let at = out.len - TRAILER.len() as u64;
+        let written = out
+            .file
+            .seek(SeekFrom::Start(at))
+            .and_then(|_| out.file.write_all(&bytes));
+        match written {
+            Ok(()) => {
+                out.len = len;
+                out.entries += 1;
+            }
+            Err(e) => {
+                log::warn(&format!(
+                    "record: {}: {e}, recording stopped",
+                    self.path.display()
+                ));
+                out.stopped = true;
+            }
+        }
+    }
+}
+
+impl<'a> Exchange<'a> {
+    /// A writer to send the response through, so it can be recorded.
+    pub fn tap<'w>(&self, inner: &'w mut dyn Write) -> Tap<'w> {
+        Tap {
+            inner,
+            head: Vec::new(),
+            in_body: false,
+            body: Captured::default(),
+            max: self.recorder.max_body,
+        }
+    }
+
+    /// Reads what the handler left of the request body, up to the cap, so
+    /// it is recorded too.
+    pub fn read_rest(&self, body: &mut RequestBody) {
+        let max = self.recorder.max_body as u64;
+        let _ = io::copy(&mut body.take(max), &mut io::sink());
+    }
+
+    /// Records the exchange, given the response as written through `tap`
+    /// and the time the handler took.
+    pub fn finish(self, tap: Tap<'_>, wait: Duration, written: &io::Result<Written>) {
+        let total = self.clock.elapsed();
+        let mut request = self.request;
+        let seen = lock(&self.body);
+        request["bodySize"] = json!(seen.total);
+        if seen.total > 0 {
+            let mut post = content(&seen.bytes, seen.total, &self.mime_type, None);
+            post.remove("size");
+            request["postData"] = Value::Object(post);
+        }
+
+        let head = String::from_utf8_lossy(&tap.head);
+        let mut lines = head.lines();
+        let mut status_line = lines.next().unwrap_or_default().splitn(3, ' ');
+        let version = status_line.next().unwrap_or_default();
+        let status: u16 = status_line.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let status_text = status_line.next().unwrap_or_default();
+        let response_headers: Vec<(&str, &str)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        let header = |name: &str| {
+            response_headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| *v)
+        };
+        let chunked = header("transfer-encoding").is_some_and(|te| te.contains("chunked"));
+        let mut body = tap.body.bytes;
+        if chunked {
+            body = dechunk(&body);
+        }
+        let body_size = match written {
+            Ok(w) => w.bytes as i64,
+            Err(_) => tap.body.total as i64,
+        };
+        let size = if chunked {
+            body.len() as u64
+        } else {
+            body_size as u64
+        };
+        let content = content(
+            &body,
+            size,
+            header("content-type").unwrap_or_default(),
+            header("content-encoding").filter(|e| *e != "identity"),
+        );
+
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut entry = json!({
+            "startedDateTime": date::format_iso(self.started),
+            "time": ms(total),
+            "request": request,
+            "response": {
+                "status": status,
+                "statusText": status_text,
+                "httpVersion": version,
+                "cookies": [],
+                "headers": headers(response_headers.iter().copied()),
+                "content": content,
+                "redirectURL": header("location").unwrap_or_default(),
+                "headersSize": tap.head.len(),
+                "bodySize": body_size,
+            },
+            "cache": {},
+            "timings": {
+                "send": 0,
+                "wait": ms(wait),
+                "receive": ms(total.saturating_sub(wait)),
+            },
+            "serverIPAddress": self.server,
+        });
+        if let Err(e) = written {
+            entry["comment"] = json!(format!("response not completely sent: {e}"));
+        }
+        drop(seen);
+        self.recorder.append(&entry);
+    }
+}
+
+fn decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    match percent_decode(&s) {
+        Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        None => s,
+    }
+}
+
+fn headers<'h>(pairs: impl Iterator<Item = (&'h str, &'h str)>) -> Vec<Value> {
+    pairs
+        .map(|(name, value)| json!({"name": name, "value": value}))
+        .collect()
+}
+
+/// The decoded bytes of a chunked body, as far as they go.
+fn dechunk(wire: &[u8]) -> Vec<u8> {
+    let mut src = Cursor::new(wire);
+    let mut framing = Framing::Chunked { remaining: 0 };
+    let mut out = Vec::new();
+    let mut buf = [0; 8192];
+    while let Ok(n @ 1..) = framing.read(&mut src, &mut buf) {
+        out.extend_from_slice(&buf[..n]);
+    }
+    out
+}
+
+/// A HAR `content` (or, less `size`, `postData`) object.
+fn content(
+    bytes: &[u8],
+    size: u64,
+    mime_type: &str,
+    encoding: Option<&str>,
+) -> serde_json::Map<String, Value> {
+    let mut content = serde_json::Map::new();
+    content.insert("size".into(), json!(size));
+    content.insert("mimeType".into(), json!(mime_type));
+    let text =
+        mime::is_text(mime_type) || mime_type.starts_with("application/x-www-form-urlencoded");
+    let comment = if size == 0 {
+        None
+    } else if let Some(encoding) = encoding {
+        Some(format!("{encoding}-encoded body omitted"))
+    } else if !text {
+        Some("binary body omitted".to_owned())
+    } else {
+        content.insert("text".into(), json!(String::from_utf8_lossy(bytes)));
+        ((bytes.len() as u64) < size)
+            .then(|| format!("body cut to the first {} bytes", bytes.len()))
+    };
+    if let Some(comment) = comment {
+        content.insert("comment".into(), json!(comment));
+    }
+    content
+}
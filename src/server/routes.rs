@@ -0,0 +1,66 @@
+//! Handlers registered by an embedder for exact paths or path prefixes,
+//! answering before the filesystem is consulted.
+//!
+//! An exact route wins over any prefix; among prefixes the longest wins.
+//! Routes run as the last middleware stage, so authentication and
+//! embedder middleware apply to them.
+
+use crate::http::{Request, Response};
+
+use super::Context;
+use super::middleware::Middleware;
+
+/// A dynamic endpoint.
+pub type Handler = Box<dyn Fn(&Context, &mut Request) -> Response + Send + Sync>;
+
+#[derive(Default)]
+pub struct Routes {
+    exact: Vec<(String, Handler)>,
+    /// Kept longest first.
+    prefixes: Vec<(String, Handler)>,
+}
+
+impl Routes {
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.prefixes.is_empty()
+    }
+
+    /// Answers requests for exactly `path`. A later registration for the same
+    /// path replaces the earlier one.
+    pub fn add(&mut self, path: &str, handler: Handler) {
+        self.exact.retain(|(p, _)| p != path);
+        self.exact.push((path.to_owned(), handler));
+    }
+
+    /// Answers requests for `prefix` and every path below it.
+    pub fn add_prefix(&mut self, prefix: &str, handler: Handler) {
+        self.prefixes.retain(|(p, _)| p != prefix);
+        let at = self
+            .prefixes
+            .partition_point(|(p, _)| p.len() >= prefix.len());
+        self.prefixes.insert(at, (prefix.to_owned(), handler));
+    }
+
+    fn find(&self, path: &str) -> Option<&Handler> {
+        let exact = self.exact.iter().find(|(p, _)| p == path);
+        exact
+            .or_else(|| self.prefixes.iter().find(|(prefix, _)| under(path, prefix)))
+            .map(|(_, handler)| handler)
+    }
+}
+
+/// Whether `path` is `prefix` or below it, on segment boundaries: `/api`
+/// covers `/api/x` but not `/apis`.
+fn under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+impl Middleware for Routes {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        let handler = self.find(&req.path)?;
+        Some(handler(ctx, req))
+    }
+}
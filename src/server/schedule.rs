@@ -0,0 +1,401 @@
+//! Access schedule: `schedule` rules keep paths reachable only at set
+//! times, e.g. a classroom server that closes after hours.
+//!
+//! Each rule reads `GLOB=WINDOWS` and the first rule whose glob matches the
+//! request path decides. `WINDOWS` is a list of day sets and time ranges:
+//! `mon-fri 08:00-18:00 sat 10:00-12:00`. A time range applies to the day
+//! set before it (every day when there is none), a day set without a range
+//! means the whole day, and a range ending before it starts runs past
+//! midnight. Days are `mon` to `sun`, joined with `+` or spanning with `-`
+//! (`mon+wed`, `fri-mon`). `always` exempts a path from later rules, e.g.
+//! `/__tinyserve/**=always` ahead of `/**=mon-fri 08:00-18:00`.
+//!
+//! Times are the server's local time (UTC where it cannot be told, and on
+//! platforms other than Unix). Outside its windows a path gets
+//! `scheduleStatus`: `503` with `Retry-After` set to the next opening, or
+//! `403`. `schedulePage` replaces the built-in error page.
+//!
+//! Requests no rule matches go on as usual.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::core::Config;
+use crate::glob;
+use crate::http::{Request, Response};
+use crate::webdav;
+
+use super::{Context, debug};
+
+const DAY: u32 = 24 * 60;
+const WEEK: u32 = 7 * DAY;
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// A stretch of the week, in minutes from Monday 00:00.
+#[derive(Clone, Copy, Debug)]
+struct Window {
+    start: u32,
+    len: u32,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: String,
+    /// As configured, for the error page.
+    text: String,
+    /// Empty for `always`.
+    windows: Vec<Window>,
+}
+
+#[derive(Debug)]
+pub struct Schedule {
+    rules: Vec<Rule>,
+    status: u16,
+    page: Option<String>,
+}
+
+impl Schedule {
+    /// The rules configured by `schedule`; `None` when there are none.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let rules = config
+            .list("schedule")
+            .iter()
+            .map(|rule| parse(rule).with_context(|| format!("schedule rule `{rule}`")))
+            .collect::<Result<Vec<_>>>()?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let page = match config.str("schedulePage") {
+            "" => None,
+            path => Some(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("schedulePage: cannot read {path}"))?,
+            ),
+        };
+        Ok(Some(Self {
+            rules,
+            status: if config.str("scheduleStatus") == "403" {
+                403
+            } else {
+                503
+            },
+            page,
+        }))
+    }
+
+    /// The response turning away a request for `path` at minute `now` of
+    /// the week, if `path` is closed then.
+    fn closed(&self, path: &str, now: u32, second: u32) -> Option<Response> {
+        let rule = self.rules.iter().find(|r| glob::matches(&r.glob, path))?;
+        if rule.windows.is_empty()
+            || rule
+                .windows
+                .iter()
+                .any(|w| (now + WEEK - w.start) % WEEK < w.len)
+        {
+            return None;
+        }
+        debug::note(|| format!("schedule: closed, open {}", rule.text));
+        let mut resp = match &self.page {
+            Some(page) => Response::html(self.status, page.clone()),
+            None => Response::error_with(
+                self.status,
+                &format!("this is available {} (server time)", rule.text),
+            ),
+        };
+        if self.status == 503 {
+            let wait = rule
+                .windows
+                .iter()
+                .map(|w| (w.start + WEEK - now) % WEEK)
+                .min()
+                .unwrap_or_default();
+            let secs = (wait * 60).saturating_sub(second).max(1);
+            resp = resp.header("Retry-After", secs.to_string());
+        }
+        Some(resp.header("Cache-Control", "no-store"))
+    }
+}
+
+fn parse(rule: &str) -> Result<Rule> {
+    let (glob, text) = rule
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("expected GLOB=WINDOWS"))?;
+    let (glob, text) = (glob.trim(), text.trim());
+    if glob.is_empty() {
+        bail!("the glob is empty");
+    }
+    let mut windows = Vec::new();
+    if text != "always" {
+        // The day set last seen, and whether a time range has used it.
+        let mut days: Option<(Vec<u32>, bool)> = None;
+        for token in text.split_whitespace() {
+            if token.starts_with(|c: char| c.is_ascii_digit()) {
+                let (start, end) = time_range(token)?;
+                let (set, used) = days.get_or_insert_with(|| ((0..7).collect(), false));
+                *used = true;
+                let len = if end > start {
+                    end - start
+                } else {
+                    end + DAY - start
+                };
+                windows.extend(set.iter().map(|day| Window {
+                    start: day * DAY + start,
+                    len,
+                }));
+            } else {
+                whole_days(days.take(), &mut windows);
+                days = Some((day_set(token)?, false));
+            }
+        }
+        whole_days(days, &mut windows);
+        if windows.is_empty() {
+            bail!("expected days and times like `mon-fri 08:00-18:00`, or `always`");
+        }
+    }
+    Ok(Rule {
+        glob: glob.to_owned(),
+        text: text.to_owned(),
+        windows,
+    })
+}
+
+/// Opens the whole of each day in a set no time range followed.
+fn whole_days(days: Option<(Vec<u32>, bool)>, windows: &mut Vec<Window>) {
+    if let Some((set, false)) = days {
+        windows.extend(set.iter().map(|day| Window {
+            start: day * DAY,
+            len: DAY,
+        }));
+    }
+}
+
+/// `mon`, `mon+wed`, `mon-fri` or `fri-mon`, as day numbers from Monday.
+/// Names may be spelled out.
+fn day_set(token: &str) -> Result<Vec<u32>> {
+    let day = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        DAYS.iter()
+            .position(|d| name.len() >= 3 && d.starts_with(&name))
+            .map(|i| i as u32)
+            .ok_or_else(|| anyhow!("unknown day `{name}` (mon, tue, ... sun)"))
+    };
+    let mut set = Vec::new();
+    for part in token.split('+') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                set.extend((0..=(to + 7 - from) % 7).map(|i| (from + i) % 7));
+            }
+            None => set.push(day(part)?),
+        }
+    }
+    set.sort_unstable();
+    set.dedup();
+    Ok(set)
+}
+
+/// `HH:MM-HH:MM` as minutes from midnight; the end may be `24:00`.
+fn time_range(token: &str) -> Result<(u32, u32)> {
+    let time = |t: &str| {
+        let (h, m) = t.split_once(':')?;
+        if m.len() != 2 {
+            return None;
+        }
+        let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+        (m < 60 && h <= 24 && h * 60 + m <= DAY).then_some(h * 60 + m)
+    };
+    let bad = || anyhow!("expected a time range like 08:00-18:00, got `{token}`");
+    let (start, end) = token.split_once('-').ok_or_else(bad)?;
+    let (start, end) = (time(start).ok_or_else(bad)?, time(end).ok_or_else(bad)?);
+    if start == DAY || start == end {
+        bail!("`{token}` is empty");
+    }
+    Ok((start, end))
+}
+
+/// The response turning `req` away, if its path is outside its windows.
+/// The path is the normalized one, so `//closed/x` is `/closed/x`; `COPY`
+/// and `MOVE` must find their destination open as well.
+pub fn check(ctx: &Context, req: &Request) -> Option<Response> {
+    ctx.schedule.as_ref()?;
+    let destination = matches!(req.method.as_str(), "COPY" | "MOVE")
+        .then(|| webdav::destination(req))
+        .flatten();
+    std::iter::once(req.path.as_str())
+        .chain(destination.as_deref())
+        .find_map(|path| check_path(ctx, path))
+}
+
+/// The response turning away a request for `path` if it is closed now.
+pub fn check_path(ctx: &Context, path: &str) -> Option<Response> {
+    let schedule = ctx.schedule.as_ref()?;
+    let (minute, second) = now();
+    schedule.closed(path, minute, second)
+}
+
+/// The local minute of the week, from Monday 00:00, and second of the minute.
+fn now() -> (u32, u32) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let local = secs + sys::utc_offset(secs);
+    // 1970-01-01 was a Thursday.
+    let minute = (local.div_euclid(86_400) + 3).rem_euclid(7) * i64::from(DAY)
+        + local.rem_euclid(86_400) / 60;
+    (minute as u32, local.rem_euclid(60) as u32)
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::mem;
+
+    /// Seconds the local time zone is ahead of UTC at `secs`.
+    pub fn utc_offset(secs: i64) -> i64 {
+        let time = secs as libc::time_t;
+        // SAFETY: all-zero is a valid `tm`.
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        // SAFETY: both pointers are to live locals; localtime_r is the
+        // thread-safe variant.
+        if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    /// Schedules follow UTC here.
+    pub fn utc_offset(_secs: i64) -> i64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::normalize_path;
+    use crate::test::{AUTH, TestServer};
+
+    fn schedule(rules: &[&str], status: u16) -> Schedule {
+        let rules = rules.iter().map(|r| parse(r).unwrap()).collect();
+        Schedule {
+            rules,
+            status,
+            page: None,
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> u32 {
+        day * DAY + hour * 60 + minute
+    }
+
+    #[test]
+    fn parses_days_and_ranges() {
+        assert_eq!(day_set("mon-fri").unwrap(), [0, 1, 2, 3, 4]);
+        assert_eq!(day_set("fri-mon").unwrap(), [0, 4, 5, 6]);
+        assert_eq!(day_set("Tuesday+thu").unwrap(), [1, 3]);
+        assert!(day_set("mo").is_err());
+        assert_eq!(time_range("08:00-24:00").unwrap(), (480, DAY));
+        for bad in [
+            "8-9",
+            "9:5-10:00",
+            "25:00-26:00",
+            "09:00-09:00",
+            "24:00-01:00",
+        ] {
+            assert!(time_range(bad).is_err(), "{bad}");
+        }
+        assert!(parse("/x=").is_err());
+        assert!(parse("=mon").is_err());
+    }
+
+    #[test]
+    fn opens_only_inside_the_windows() {
+        let s = schedule(&["/always/**=always", "/**=mon-fri 08:00-18:00 sat"], 503);
+        assert!(s.closed("/a", at(0, 8, 0), 0).is_none());
+        assert!(s.closed("/a", at(4, 17, 59), 0).is_none());
+        assert!(s.closed("/a", at(5, 23, 0), 0).is_none());
+        assert!(s.closed("/always/a", at(6, 3, 0), 0).is_none());
+
+        let resp = s.closed("/a", at(0, 18, 0), 0).unwrap();
+        assert_eq!(resp.status, 503);
+        assert_eq!(resp.headers.get("retry-after"), Some("50400"));
+        let resp = s.closed("/a", at(6, 23, 59), 30).unwrap();
+        assert_eq!(resp.headers.get("retry-after"), Some("28830"));
+    }
+
+    #[test]
+    fn ranges_past_midnight_wrap() {
+        let s = schedule(&["/**=sun 22:00-02:00"], 403);
+        assert!(s.closed("/a", at(6, 23, 0), 0).is_none());
+        assert!(s.closed("/a", at(0, 1, 59), 0).is_none());
+        let resp = s.closed("/a", at(0, 2, 0), 0).unwrap();
+        assert_eq!(resp.status, 403);
+        assert_eq!(resp.headers.get("retry-after"), None);
+    }
+
+    #[test]
+    fn matches_the_normalized_path() {
+        let s = schedule(&["/closed/**=mon 08:00-09:00"], 503);
+        for target in ["/closed/x", "/./closed/x", "//closed/x", "/closed//x"] {
+            let path = normalize_path(target);
+            assert!(s.closed(&path, at(2, 12, 0), 0).is_some(), "{target}");
+        }
+    }
+
+    #[test]
+    fn closes_paths_on_a_running_server() {
+        // Open from the day after tomorrow until four days later, so the
+        // test still sees it closed if midnight passes while it runs.
+        let today = now().0 / DAY;
+        let from = DAYS[((today + 2) % 7) as usize];
+        let to = DAYS[((today + 6) % 7) as usize];
+        let rule = format!("/closed/**={}-{}", &from[..3], &to[..3]);
+        let server = TestServer::with(|b| b.option("schedule", rule)).unwrap();
+        server.write("closed/x", "x").unwrap();
+        server.write("open/x", "x").unwrap();
+        for target in ["/closed/x", "/./closed/x", "//closed/x", "/%2e/closed/x"] {
+            let resp = server.get(target).unwrap();
+            assert_eq!(resp.status, 503, "{target}");
+            assert!(resp.header("retry-after").is_some());
+        }
+        assert_eq!(server.get("/open/x").unwrap().status, 200);
+    }
+
+    #[test]
+    fn closes_destinations_too() {
+        let today = now().0 / DAY;
+        let from = DAYS[((today + 2) % 7) as usize];
+        let to = DAYS[((today + 6) % 7) as usize];
+        let rule = format!("/closed/**={}-{}", &from[..3], &to[..3]);
+        let server = TestServer::with_auth(|b| {
+            b.option("schedule", rule)
+                .option("readOnly", false)
+                .option("webdav", true)
+        })
+        .unwrap();
+        server.write("open/x", "x").unwrap();
+        let move_to = |dest: &str| {
+            let headers = [AUTH, ("Destination", dest)];
+            server
+                .request("MOVE", "/open/x", &headers, b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(move_to("/closed/x"), 503);
+        assert_eq!(move_to("//closed/x"), 503);
+        assert_eq!(server.get("/open/x").unwrap().status, 200);
+        assert_eq!(move_to("/open/y"), 201);
+    }
+}
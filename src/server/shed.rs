@@ -0,0 +1,161 @@
+//! Load shedding: with `shed` on, requests are refused with `503` once the
+//! server is overloaded, instead of letting every request slow down.
+//!
+//! Three signals are watched: requests in flight (`shedInFlight`), how long
+//! connections recently waited for a worker (`shedQueueDelay`), and open file
+//! descriptors as a share of the process limit (`shedFdPercent`). Past 80% of
+//! any threshold, responses close their connection so keep-alive clients
+//! release workers; past the threshold, new requests get `503`.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::core::Config;
+
+use super::pool;
+
+/// How long a queue delay observation counts.
+const DELAY_WINDOW: Duration = Duration::from_secs(1);
+/// How often open descriptors are recounted.
+const FD_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    Normal,
+    /// Serve, but don't keep connections alive.
+    High,
+    /// Refuse with 503.
+    Overloaded,
+}
+
+impl Pressure {
+    fn of(value: u64, limit: u64) -> Self {
+        if limit == 0 || value * 5 < limit * 4 {
+            Pressure::Normal
+        } else if value < limit {
+            Pressure::High
+        } else {
+            Pressure::Overloaded
+        }
+    }
+}
+
+pub struct Shed {
+    max_in_flight: u64,
+    max_delay_ms: u64,
+    fd_percent: u64,
+    in_flight: AtomicUsize,
+    /// Longest queue delay seen in the current window, in milliseconds.
+    delay_ms: AtomicU64,
+    /// Start of the current window, in milliseconds since `epoch`.
+    delay_window: AtomicU64,
+    epoch: Instant,
+    fds: Mutex<(Instant, u64)>,
+}
+
+/// Counts a request as in flight until dropped.
+pub struct InFlight<'a>(&'a Shed);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Shed {
+    pub fn new(config: &Config) -> Self {
+        let max_in_flight = match config.int("shedInFlight") {
+            0 => match config.int("workers") {
+                0 => pool::default_workers() as u64,
+                n => n,
+            },
+            n => n,
+        };
+        Self {
+            max_in_flight,
+            max_delay_ms: config.int("shedQueueDelay"),
+            fd_percent: config.int("shedFdPercent").min(100),
+            in_flight: AtomicUsize::new(0),
+            delay_ms: AtomicU64::new(0),
+            delay_window: AtomicU64::new(0),
+            epoch: Instant::now(),
+            fds: Mutex::new((Instant::now() - FD_INTERVAL, 0)),
+        }
+    }
+
+    pub fn enter(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    /// Records how long a connection waited for a worker.
+    pub fn observe_queue_delay(&self, delay: Duration) {
+        let now = self.epoch.elapsed().as_millis() as u64;
+        let window = self.delay_window.load(Ordering::Relaxed);
+        if now.saturating_sub(window) > DELAY_WINDOW.as_millis() as u64 {
+            self.delay_window.store(now, Ordering::Relaxed);
+            self.delay_ms.store(0, Ordering::Relaxed);
+        }
+        self.delay_ms
+            .fetch_max(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn recent_delay_ms(&self) -> u64 {
+        let now = self.epoch.elapsed().as_millis() as u64;
+        let window = self.delay_window.load(Ordering::Relaxed);
+        if now.saturating_sub(window) > DELAY_WINDOW.as_millis() as u64 {
+            0
+        } else {
+            self.delay_ms.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Open descriptors in percent of the limit, recounted at most every
+    /// [`FD_INTERVAL`].
+    fn fd_usage(&self) -> u64 {
+        if self.fd_percent == 0 {
+            return 0;
+        }
+        let mut fds = self.fds.lock().unwrap_or_else(|e| e.into_inner());
+        if fds.0.elapsed() >= FD_INTERVAL {
+            *fds = (Instant::now(), fd_usage_percent().unwrap_or(0));
+        }
+        fds.1
+    }
+
+    /// Current pressure, for a request about to be served.
+    pub fn pressure(&self) -> Pressure {
+        let in_flight = self.in_flight.load(Ordering::Relaxed) as u64;
+        Pressure::of(in_flight, self.max_in_flight)
+            .max(Pressure::of(self.recent_delay_ms(), self.max_delay_ms))
+            .max(Pressure::of(self.fd_usage(), self.fd_percent))
+    }
+}
+
+#[cfg(unix)]
+fn fd_usage_percent() -> Option<u64> {
+    let dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    let open = std::fs::read_dir(dir).ok()?.count() as u64;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid out-pointer.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == 0 {
+        return None;
+    }
+    // `rlim_t` is narrower than u64 on some targets.
+    #[allow(clippy::useless_conversion)]
+    let cur = u64::try_from(limit.rlim_cur).unwrap_or(u64::MAX);
+    Some(open.saturating_mul(100) / cur)
+}
+
+#[cfg(not(unix))]
+fn fd_usage_percent() -> Option<u64> {
+    None
+}
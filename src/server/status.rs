@@ -0,0 +1,390 @@
+//! `/__tinyserve/status`: an HTML page with uptime, request totals, the
+//! heaviest clients and top-level paths, recent errors, the last requests
+//! (with `lastRequests`), cache hit rates and the options set away from their
+//! defaults, for a quick look at a small server without a metrics stack.
+//!
+//! Credentials among the options are shown as `(redacted)`. The page is
+//! behind the same authentication as everything else; a `policy` rule such
+//! as `/__tinyserve/**=local-only` keeps it to the machine itself.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+
+use crate::core::config::{Config, Layer, OPTIONS};
+use crate::files::listing::human_size;
+use crate::http::response::escape_html;
+use crate::http::{Response, date};
+
+use super::Context;
+use super::middleware::Completed;
+
+/// Path of the page under `/__tinyserve/`.
+pub const PATH: &str = "status";
+
+/// Errors kept for the page.
+const MAX_ERRORS: usize = 20;
+
+/// Clients and paths tracked at most; later ones are counted as `other`.
+const MAX_KEYS: usize = 1000;
+
+/// Rows of the client and path tables.
+const TOP: usize = 10;
+
+/// Options whose values are credentials, or may carry them.
+const SECRETS: &[&str] = &[
+    "authUsers",
+    "authToken",
+    "forwardAuth",
+    "headers",
+    "auditKey",
+];
+
+struct Failure {
+    at: SystemTime,
+    request_line: String,
+    status: u16,
+    error: Option<String>,
+}
+
+/// Counters behind the page, fed by every completed request.
+pub struct Status {
+    started: Instant,
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    /// Requests by status class, `1xx` to `5xx`.
+    classes: [AtomicU64; 5],
+    /// Server errors and failed sends, newest first.
+    errors: Mutex<VecDeque<Failure>>,
+    /// Requests and body bytes by client address and by top-level path.
+    traffic: Mutex<Traffic>,
+}
+
+/// Requests and body bytes.
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    requests: u64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+struct Traffic {
+    clients: HashMap<IpAddr, Usage>,
+    paths: HashMap<String, Usage>,
+    other_clients: Usage,
+    other_paths: Usage,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.requests += 1;
+        self.bytes += bytes;
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            classes: Default::default(),
+            errors: Mutex::new(VecDeque::new()),
+            traffic: Mutex::default(),
+        }
+    }
+}
+
+impl Status {
+    pub fn record(&self, done: &Completed<'_>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(done.bytes, Ordering::Relaxed);
+        if let Some(class) = self
+            .classes
+            .get(usize::from(done.status / 100).wrapping_sub(1))
+        {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        self.account(done);
+        if done.status < 500 && done.error.is_none() {
+            return;
+        }
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        if errors.len() == MAX_ERRORS {
+            errors.pop_back();
+        }
+        errors.push_front(Failure {
+            at: SystemTime::now(),
+            request_line: done.request_line.to_owned(),
+            status: done.status,
+            error: done.error.map(ToString::to_string),
+        });
+    }
+
+    fn account(&self, done: &Completed<'_>) {
+        let mut traffic = self.traffic.lock().unwrap_or_else(|e| e.into_inner());
+        let traffic = &mut *traffic;
+        let ip = done.peer.ip().to_canonical();
+        let full = traffic.clients.len() >= MAX_KEYS;
+        match traffic.clients.get_mut(&ip) {
+            Some(usage) => usage.add(done.bytes),
+            None if full => traffic.other_clients.add(done.bytes),
+            None => traffic.clients.entry(ip).or_default().add(done.bytes),
+        }
+        let prefix = top_level(done.request_line);
+        let full = traffic.paths.len() >= MAX_KEYS;
+        match traffic.paths.get_mut(prefix) {
+            Some(usage) => usage.add(done.bytes),
+            None if full => traffic.other_paths.add(done.bytes),
+            None => traffic
+                .paths
+                .entry(prefix.to_owned())
+                .or_default()
+                .add(done.bytes),
+        }
+    }
+}
+
+/// The first segment of the path in `request_line`: `/docs/` for
+/// `GET /docs/a/b.html HTTP/1.1`, `/` for files at the root.
+fn top_level(request_line: &str) -> &str {
+    let target = request_line.split(' ').nth(1).unwrap_or("/");
+    let path = target.split(['?', '#']).next().unwrap_or("/");
+    let rest = path.strip_prefix('/').unwrap_or(path);
+    match rest.find('/') {
+        Some(end) => &path[..end + 2],
+        None => "/",
+    }
+}
+
+/// The `TOP` heaviest entries by bytes, then the rest summed up.
+fn heaviest<K: ToString>(
+    usage: impl Iterator<Item = (K, Usage)>,
+    mut other: Usage,
+) -> Vec<(String, Usage)> {
+    let mut rows: Vec<(String, Usage)> = usage.map(|(k, u)| (k.to_string(), u)).collect();
+    rows.sort_by(|a, b| {
+        b.1.bytes
+            .cmp(&a.1.bytes)
+            .then(b.1.requests.cmp(&a.1.requests))
+    });
+    for (_, usage) in rows.drain(TOP.min(rows.len())..) {
+        other.requests += usage.requests;
+        other.bytes += usage.bytes;
+    }
+    if other.requests > 0 {
+        rows.push(("other".to_owned(), other));
+    }
+    rows
+}
+
+/// Renders the page.
+pub fn page(ctx: &Context, status: &Status) -> Response {
+    let mut body = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\"><title>tinyserve status</title>\
+         <style>body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;margin-bottom:1.5em}\
+         th,td{text-align:left;padding:.2em 1em .2em 0;vertical-align:top}\
+         td{font-family:monospace}</style></head>\n<body><h1>Status</h1>\n",
+    );
+
+    let requests = status.requests.load(Ordering::Relaxed);
+    let mut rows = vec![
+        ("Uptime", uptime(status.started.elapsed().as_secs())),
+        ("Root", ctx.root.display().to_string()),
+        ("Requests", requests.to_string()),
+        (
+            "Body bytes sent",
+            human_size(status.bytes.load(Ordering::Relaxed)),
+        ),
+    ];
+    for (i, class) in status.classes.iter().enumerate() {
+        let count = class.load(Ordering::Relaxed);
+        let share = percent(count, requests);
+        rows.push((
+            ["1xx", "2xx", "3xx", "4xx", "5xx"][i],
+            format!("{count} ({share})"),
+        ));
+    }
+    rows.push((
+        "In progress",
+        format!(
+            "{} requests, {} connections",
+            ctx.lifecycle.active(),
+            ctx.lifecycle.connections()
+        ),
+    ));
+    table(&mut body, "Traffic", &rows);
+
+    let traffic = status.traffic.lock().unwrap_or_else(|e| e.into_inner());
+    let clients = heaviest(
+        traffic.clients.iter().map(|(ip, u)| (ip, *u)),
+        traffic.other_clients,
+    );
+    let paths = heaviest(
+        traffic.paths.iter().map(|(p, u)| (p, *u)),
+        traffic.other_paths,
+    );
+    drop(traffic);
+    usage_table(&mut body, "Clients", &clients);
+    usage_table(&mut body, "Paths", &paths);
+
+    let caches: Vec<(&str, String)> = [
+        (
+            "File cache",
+            ctx.file_cache.as_ref().map(|c| c.hits_and_misses()),
+        ),
+        (
+            "Metadata cache",
+            ctx.stat_cache.as_ref().map(|c| c.hits_and_misses()),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, counts)| {
+        let text = match counts {
+            Some((hits, misses)) => format!(
+                "{} hit rate, {hits} hits, {misses} misses",
+                percent(hits, hits + misses)
+            ),
+            None => "off".to_owned(),
+        };
+        (name, text)
+    })
+    .collect();
+    table(&mut body, "Caches", &caches);
+
+    body.push_str("<h2>Recent errors</h2>\n");
+    let errors = status.errors.lock().unwrap_or_else(|e| e.into_inner());
+    if errors.is_empty() {
+        body.push_str("<p>None.</p>\n");
+    } else {
+        body.push_str(
+            "<table><tr><th>Time</th><th>Status</th><th>Request</th><th>Error</th></tr>\n",
+        );
+        for failure in errors.iter() {
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                date::format(failure.at),
+                failure.status,
+                escape_html(&failure.request_line),
+                escape_html(failure.error.as_deref().unwrap_or_default())
+            );
+        }
+        body.push_str("</table>\n");
+    }
+    drop(errors);
+
+    if let Some(recent) = &ctx.recent {
+        body.push_str("<h2>Last requests</h2>\n");
+        recent.with_entries(|entries| {
+            if entries.is_empty() {
+                body.push_str("<p>None.</p>\n");
+                return;
+            }
+            body.push_str(
+                "<table><tr><th>Time</th><th>Client</th><th>Status</th><th>Request</th>\
+                 <th>Bytes</th><th>Time taken</th></tr>\n",
+            );
+            for entry in entries {
+                let _ = writeln!(
+                    body,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td></tr>",
+                    date::format(entry.at),
+                    escape_html(&entry.client),
+                    entry.status,
+                    escape_html(&entry.request_line),
+                    human_size(entry.bytes),
+                    entry.ms
+                );
+            }
+            body.push_str("</table>\n");
+        });
+    }
+
+    let options = options(&ctx.config);
+    let rows: Vec<(&str, String)> = options
+        .iter()
+        .map(|(key, value, layer)| (*key, format!("{value} ({layer})")))
+        .collect();
+    table(&mut body, "Options set", &rows);
+
+    let _ = writeln!(body, "{}</body></html>", ctx.identity.signature());
+    Response::html(200, body).header("Cache-Control", "no-store")
+}
+
+/// The options not at their defaults, with credentials hidden. Values are
+/// JSON text.
+pub fn options(config: &Config) -> Vec<(&'static str, String, Layer)> {
+    OPTIONS
+        .iter()
+        .filter(|spec| config.layer(spec.key) != Layer::Default)
+        .map(|spec| {
+            let value = config.shown(spec.key);
+            let empty = value.as_str() == Some("")
+                || value.as_array().is_some_and(|items| items.is_empty());
+            let shown = if SECRETS.contains(&spec.key) && !empty {
+                "(redacted)".to_owned()
+            } else {
+                value.to_string()
+            };
+            (spec.key, shown, config.layer(spec.key))
+        })
+        .collect()
+}
+
+fn table(body: &mut String, heading: &str, rows: &[(&str, String)]) {
+    let _ = writeln!(body, "<h2>{heading}</h2>\n<table>");
+    for (name, value) in rows {
+        let _ = writeln!(
+            body,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape_html(name),
+            escape_html(value)
+        );
+    }
+    body.push_str("</table>\n");
+}
+
+fn usage_table(body: &mut String, heading: &str, rows: &[(String, Usage)]) {
+    let _ = writeln!(
+        body,
+        "<h2>{heading}</h2>\n<table><tr><th></th><th>Requests</th><th>Bytes</th></tr>"
+    );
+    for (name, usage) in rows {
+        let _ = writeln!(
+            body,
+            "<tr><th>{}</th><td>{}</td><td>{}</td></tr>",
+            escape_html(name),
+            usage.requests,
+            human_size(usage.bytes)
+        );
+    }
+    body.push_str("</table>\n");
+}
+
+/// `3d 4h 5m 6s`, leaving out leading zero units.
+pub fn uptime(secs: u64) -> String {
+    let parts = [
+        (secs / 86_400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let first = parts.iter().position(|(n, _)| *n > 0).unwrap_or(3);
+    parts[first..]
+        .iter()
+        .map(|(n, unit)| format!("{n}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn percent(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "-".to_owned();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / whole as f64)
+}
@@ -0,0 +1,155 @@
+//! Simulated slow networks: `throttle` rules delay responses and pace their
+//! bytes so pages can be tried on a bad connection.
+//!
+//! Each rule reads `[GLOB=]LATENCY[/RATE]`, e.g. `50ms/1Mbps` for every
+//! request or `*.mp4=200ms/512kbps` for matching paths. The first matching
+//! rule applies. Latency is added before the response is written; the rate
+//! (`bps`, `kbps`, `Mbps`, `Gbps`, or bytes with `B/s`, `KB/s`, `MB/s`)
+//! caps the response's throughput, head included.
+
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::core::Config;
+use crate::glob;
+
+#[derive(Debug)]
+pub struct Throttle {
+    /// Paths the rule applies to; `None` for all.
+    pub glob: Option<String>,
+    /// Added before the first byte.
+    pub latency: Duration,
+    /// Bytes per second, if limited.
+    pub rate: Option<u64>,
+}
+
+/// The rules configured by `throttle`.
+pub fn rules(config: &Config) -> Result<Vec<Throttle>> {
+    config
+        .list("throttle")
+        .iter()
+        .map(|rule| parse(rule).with_context(|| format!("throttle rule `{rule}`")))
+        .collect()
+}
+
+/// The first rule for `path`.
+pub fn find<'a>(rules: &'a [Throttle], path: &str) -> Option<&'a Throttle> {
+    rules
+        .iter()
+        .find(|rule| rule.glob.as_deref().is_none_or(|g| glob::matches(g, path)))
+}
+
+fn parse(rule: &str) -> Result<Throttle> {
+    let (glob, spec) = match rule.split_once('=') {
+        Some((glob, spec)) => (Some(glob.trim().to_owned()), spec.trim()),
+        None => (None, rule.trim()),
+    };
+    let mut throttle = Throttle {
+        glob,
+        latency: Duration::ZERO,
+        rate: None,
+    };
+    // `B/s` contains a slash of its own, so split on the first one only if
+    // it separates a latency.
+    let (latency, rate) = match spec.split_once('/') {
+        Some((latency, rate)) if latency.ends_with('s') && !rate.is_empty() => {
+            (Some(latency), Some(rate))
+        }
+        _ if spec.ends_with("ps") || spec.ends_with("/s") => (None, Some(spec)),
+        _ => (Some(spec), None),
+    };
+    if let Some(latency) = latency {
+        throttle.latency = parse_latency(latency)?;
+    }
+    if let Some(rate) = rate {
+        throttle.rate = Some(parse_rate(rate)?);
+    }
+    Ok(throttle)
+}
+
+fn parse_latency(raw: &str) -> Result<Duration> {
+    let (number, scale) = if let Some(ms) = raw.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = raw.strip_suffix('s') {
+        (s, 1.0)
+    } else {
+        bail!("latency `{raw}` needs a unit, like 50ms or 1s");
+    };
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("bad latency `{raw}`"))?;
+    Duration::try_from_secs_f64(value * scale).map_err(|_| anyhow!("bad latency `{raw}`"))
+}
+
+pub(super) fn parse_rate(raw: &str) -> Result<u64> {
+    let (number, bytes_per_unit) = match raw.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => {
+            let (number, unit) = raw.split_at(at);
+            let bytes = match unit {
+                "bps" => 1.0 / 8.0,
+                "kbps" | "Kbps" => 1e3 / 8.0,
+                "Mbps" | "mbps" => 1e6 / 8.0,
+                "Gbps" | "gbps" => 1e9 / 8.0,
+                "B/s" => 1.0,
+                "KB/s" | "kB/s" => 1024.0,
+                "MB/s" => 1024.0 * 1024.0,
+                _ => bail!("unknown rate unit in `{raw}`"),
+            };
+            (number, bytes)
+        }
+        None => bail!("rate `{raw}` needs a unit, like 1Mbps or 64KB/s"),
+    };
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("bad rate `{raw}`"))?;
+    let rate = (value * bytes_per_unit) as u64;
+    if rate == 0 {
+        bail!("rate `{raw}` is too low");
+    }
+    Ok(rate)
+}
+
+/// Writes through to `inner` no faster than `rate` bytes per second.
+pub struct Paced<'a> {
+    inner: &'a mut dyn Write,
+    rate: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl<'a> Paced<'a> {
+    pub fn new(inner: &'a mut dyn Write, rate: u64) -> Self {
+        Self {
+            inner,
+            rate,
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+}
+
+impl Write for Paced<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // About 20 writes a second keeps the pace smooth.
+        let slice = usize::try_from(self.rate / 20)
+            .unwrap_or(usize::MAX)
+            .max(512);
+        let n = self.inner.write(&buf[..buf.len().min(slice)])?;
+        self.inner.flush()?;
+        self.sent += n as u64;
+        let due = Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,204 @@
+//! Response rewriting: extra headers from the `headers` option, and body
+//! transforms for text responses, such as `injectHtml` or ones added with
+//! [`ServerBuilder::transform`](super::ServerBuilder::transform).
+//!
+//! Bodies are rewritten whole, so `Content-Length` and `ETag` describe what
+//! is actually sent; the new `ETag` is a hash of the rewritten body, and a
+//! client already holding it gets `304`. Range requests for files a transform
+//! may rewrite are answered in full. Streamed bodies and bodies over
+//! [`MAX_BODY`] are sent unchanged.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context as _, Result, bail};
+
+use crate::core::Config;
+use crate::files;
+use crate::http::{Body, Request, Response, mime};
+
+use super::middleware::Middleware;
+use super::{Context, debug};
+
+/// Largest body a transform is given.
+pub const MAX_BODY: u64 = 8 << 20;
+
+/// Rewrites the bodies of successful responses it applies to.
+pub trait Transform: Send + Sync {
+    /// Whether to rewrite a response of `content_type`; HTML by default.
+    fn applies(&self, req: &Request, content_type: &str) -> bool {
+        let _ = req;
+        content_type.starts_with("text/html")
+    }
+
+    fn transform(&self, req: &Request, body: Vec<u8>) -> Vec<u8>;
+}
+
+impl<F> Transform for F
+where
+    F: Fn(&Request, Vec<u8>) -> Vec<u8> + Send + Sync,
+{
+    fn transform(&self, req: &Request, body: Vec<u8>) -> Vec<u8> {
+        self(req, body)
+    }
+}
+
+/// Inserts a snippet before `</body>`, or at the end when there is none.
+pub struct InjectHtml {
+    snippet: String,
+}
+
+impl InjectHtml {
+    pub fn new(snippet: impl Into<String>) -> Self {
+        Self {
+            snippet: snippet.into(),
+        }
+    }
+
+    /// The `injectHtml` option: a snippet, or `@path` to read one from a
+    /// file.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let snippet = match config.str("injectHtml") {
+            "" => return Ok(None),
+            value => match value.strip_prefix('@') {
+                Some(path) => {
+                    fs::read_to_string(path).with_context(|| format!("injectHtml {path}"))?
+                }
+                None => value.to_owned(),
+            },
+        };
+        Ok(Some(Self::new(snippet)))
+    }
+}
+
+impl Transform for InjectHtml {
+    fn transform(&self, _req: &Request, mut body: Vec<u8>) -> Vec<u8> {
+        let at = body
+            .windows(7)
+            .rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+            .unwrap_or(body.len());
+        body.splice(at..at, self.snippet.bytes());
+        body
+    }
+}
+
+/// Parses the `headers` option's `Name: value` entries.
+pub fn header_rules(config: &Config) -> Result<Vec<(String, String)>> {
+    config
+        .list("headers")
+        .iter()
+        .map(|rule| match rule.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((name.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => bail!("headers: expected `Name: value`, got `{rule}`"),
+        })
+        .collect()
+}
+
+/// The body of `resp` as bytes, if it is small enough and not streamed.
+pub(super) fn take_body(resp: &mut Response) -> Option<Vec<u8>> {
+    if resp.body.len().is_none_or(|len| len > MAX_BODY) {
+        return None;
+    }
+    let body = std::mem::replace(&mut resp.body, Body::Empty);
+    let bytes = match body {
+        Body::Empty => Vec::new(),
+        Body::Bytes(b) => b,
+        Body::Shared(b) => b.to_vec(),
+        Body::Static(b) => b.to_vec(),
+        Body::Mapped { map, offset, len } => {
+            let mut buf = Vec::with_capacity(len);
+            map.read(offset, len, |chunk| {
+                buf.extend_from_slice(chunk);
+                Ok(())
+            })
+            .ok()?;
+            buf
+        }
+        Body::File {
+            mut file,
+            offset,
+            len,
+        } => {
+            let mut buf = Vec::with_capacity(len as usize);
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.take(len).read_to_end(&mut buf))
+                .ok()?;
+            buf
+        }
+        other => {
+            resp.body = other;
+            return None;
+        }
+    };
+    Some(bytes)
+}
+
+fn content_hash(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Applies header rules and body transforms.
+pub struct Transforms;
+
+impl Middleware for Transforms {
+    fn before_request(&self, ctx: &Context, req: &mut Request) -> Option<Response> {
+        if !ctx.transforms.is_empty() && req.headers.contains("range") {
+            let content_type = mime::from_path(Path::new(&req.path));
+            if ctx.transforms.iter().any(|t| t.applies(req, content_type)) {
+                req.headers.remove("range");
+            }
+        }
+        None
+    }
+
+    fn after_response(&self, ctx: &Context, req: &Request, resp: &mut Response) {
+        for (name, value) in &ctx.header_rules {
+            resp.headers.set(name.as_str(), value.as_str());
+        }
+        if resp.status != 200 || ctx.transforms.is_empty() {
+            return;
+        }
+        let content_type = resp
+            .headers
+            .get("content-type")
+            .unwrap_or_default()
+            .to_owned();
+        let applicable: Vec<_> = ctx
+            .transforms
+            .iter()
+            .filter(|t| t.applies(req, &content_type))
+            .collect();
+        if applicable.is_empty() {
+            return;
+        }
+        let Some(mut body) = take_body(resp) else {
+            return;
+        };
+        debug::note(|| format!("body rewritten by {} transforms", applicable.len()));
+        for transform in applicable {
+            body = transform.transform(req, body);
+        }
+        resp.headers.remove("content-length");
+        if let Some(etag) = resp.headers.get("etag") {
+            let weak = if etag.starts_with("W/") { "W/" } else { "" };
+            let etag = format!("{weak}\"{:016x}\"", content_hash(&body));
+            if req
+                .headers
+                .get("if-none-match")
+                .is_some_and(|inm| files::etag_matches(inm, &etag))
+            {
+                resp.status = 304;
+                body.clear();
+            }
+            resp.headers.set("ETag", etag);
+        }
+        resp.body = Body::Bytes(body);
+    }
+}
@@ -0,0 +1,50 @@
+//! Unicode policy for request paths, applied before any stage looks at
+//! them.
+//!
+//! `pathInvalidUtf8` decides what happens to escapes that do not decode to
+//! UTF-8: `reject` answers `400`, `replace` keeps the path with U+FFFD in
+//! their place. `pathNormalize` then puts the path in NFC or NFD, so `é`
+//! sent precomposed finds a file whose name was written decomposed (as
+//! macOS often does) or the other way round; `off` leaves it as sent.
+
+use anyhow::{Result, bail};
+
+use crate::core::Config;
+use crate::http::{Request, Response};
+
+/// Checks `pathNormalize` can be honoured by this build.
+pub fn check(config: &Config) -> Result<()> {
+    if config.str("pathNormalize") != "off" && !cfg!(feature = "unicode-normalization") {
+        bail!("pathNormalize needs the `unicode-normalization` feature");
+    }
+    Ok(())
+}
+
+/// Applies the policy to `req.path`, or answers `400` for a path that is
+/// not UTF-8 when that is rejected.
+pub fn apply(config: &Config, req: &mut Request) -> Option<Response> {
+    if req.path.contains('\u{FFFD}')
+        && config.str("pathInvalidUtf8") == "reject"
+        && !req.path_is_utf8()
+    {
+        return Some(Response::error_with(400, "path is not UTF-8"));
+    }
+    if !req.path.is_ascii() {
+        normalize(config.str("pathNormalize"), &mut req.path);
+    }
+    None
+}
+
+#[cfg(feature = "unicode-normalization")]
+fn normalize(form: &str, path: &mut String) {
+    use unicode_normalization::UnicodeNormalization;
+
+    match form {
+        "nfc" => *path = path.nfc().collect(),
+        "nfd" => *path = path.nfd().collect(),
+        _ => {}
+    }
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+fn normalize(_form: &str, _path: &mut String) {}
@@ -0,0 +1,431 @@
+//! `tinyserve service`: running as a Windows service, so a site is served
+//! without anyone logged in.
+//!
+//! ```text
+//! tinyserve service install [ROOT] [OPTIONS]
+//! tinyserve service start|stop|uninstall
+//! ```
+//!
+//! `install` freezes the effective configuration (config file, environment
+//! and command line) into `service.json` in the configs dir and registers a
+//! service that starts automatically with it, in the directory `install`
+//! ran from so relative paths keep their meaning. The service manager runs
+//! `tinyserve service run DIR --config FILE`, which is not meant to be
+//! typed.
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+use crate::core::Config;
+
+/// Name the service is registered under.
+pub const NAME: &str = "tinyserve";
+
+/// Frozen configuration the service runs with, inside the configs dir.
+pub const CONFIG_FILE: &str = "service.json";
+
+/// Runs `tinyserve service` with the arguments after it. `configure` turns
+/// serve arguments into the configuration they describe.
+pub fn run(
+    args: &[String],
+    configs_dir: &Path,
+    configure: impl Fn(&[String]) -> Result<Config>,
+) -> Result<()> {
+    let Some(command) = args.first() else {
+        bail!("usage: tinyserve service <install|start|stop|uninstall>");
+    };
+    #[cfg(windows)]
+    {
+        windows::run(command, &args[1..], configs_dir, configure)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (configs_dir, configure);
+        bail!("`tinyserve service {command}` is only available on Windows; see --daemon")
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::env;
+    use std::ffi::c_void;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock, mpsc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use anyhow::{Context as _, Result, bail};
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_NOT_ACTIVE, ERROR_SERVICE_SPECIFIC_ERROR,
+        NO_ERROR,
+    };
+    use windows_sys::Win32::System::Services::*;
+
+    use super::{CONFIG_FILE, NAME};
+    use crate::ServerBuilder;
+    use crate::core::Config;
+    use crate::log;
+
+    /// How long `start` and `stop` wait for the service to settle.
+    const SETTLE: Duration = Duration::from_secs(30);
+
+    /// The configuration `service run` hands to the service's main function.
+    static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+    /// Signals the service's main function to stop.
+    static STOP: OnceLock<Mutex<mpsc::Sender<()>>> = OnceLock::new();
+    /// The `SERVICE_STATUS_HANDLE` of the running service.
+    static STATUS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A service manager or service handle, closed on drop.
+    struct Handle(SC_HANDLE);
+
+    impl Handle {
+        fn new(handle: SC_HANDLE, what: &str) -> Result<Self> {
+            if handle.is_null() {
+                return Err(io::Error::last_os_error()).context(what.to_owned());
+            }
+            Ok(Self(handle))
+        }
+
+        fn manager(access: u32) -> Result<Self> {
+            // SAFETY: null machine and database names mean the local ones.
+            let handle = unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) };
+            Self::new(handle, "opening the service manager (run as administrator)")
+        }
+
+        fn service(access: u32) -> Result<Self> {
+            let manager = Self::manager(SC_MANAGER_CONNECT)?;
+            let name = wide(NAME);
+            // SAFETY: `name` is NUL-terminated and outlives the call.
+            let handle = unsafe { OpenServiceW(manager.0, name.as_ptr(), access) };
+            Self::new(handle, "opening the tinyserve service (is it installed?)")
+        }
+
+        fn state(&self) -> Result<u32> {
+            let mut status = zeroed_status();
+            // SAFETY: `status` is a valid SERVICE_STATUS to fill.
+            if unsafe { QueryServiceStatus(self.0, &mut status) } == 0 {
+                return Err(io::Error::last_os_error()).context("querying the service");
+            }
+            Ok(status.dwCurrentState)
+        }
+
+        /// Waits while the service is in `pending`, returning the state after.
+        fn settle(&self, pending: u32) -> Result<u32> {
+            let deadline = Instant::now() + SETTLE;
+            loop {
+                let state = self.state()?;
+                if state != pending || Instant::now() > deadline {
+                    return Ok(state);
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+        }
+
+        /// Asks the service to stop and waits until it has.
+        fn stop(&self) -> Result<bool> {
+            let mut status = zeroed_status();
+            // SAFETY: `status` is a valid SERVICE_STATUS to fill.
+            if unsafe { ControlService(self.0, SERVICE_CONTROL_STOP, &mut status) } == 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() == Some(ERROR_SERVICE_NOT_ACTIVE as i32) {
+                    return Ok(false);
+                }
+                return Err(e).context("stopping the service");
+            }
+            if self.settle(SERVICE_STOP_PENDING)? != SERVICE_STOPPED {
+                bail!("the service did not stop within {SETTLE:?}");
+            }
+            Ok(true)
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: the handle was opened by this wrapper.
+            unsafe { CloseServiceHandle(self.0) };
+        }
+    }
+
+    fn zeroed_status() -> SERVICE_STATUS {
+        SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: 0,
+            dwControlsAccepted: 0,
+            dwWin32ExitCode: NO_ERROR,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        }
+    }
+
+    /// `s` as a NUL-terminated UTF-16 string.
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// `arg` quoted for a Windows command line, so `CommandLineToArgvW` and
+    /// the Rust runtime read it back unchanged.
+    fn quote(arg: &str) -> String {
+        let mut out = String::from('"');
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    out.push_str(&"\\".repeat(backslashes * 2 + 1));
+                    backslashes = 0;
+                }
+                _ => {
+                    out.push_str(&"\\".repeat(backslashes));
+                    backslashes = 0;
+                }
+            }
+            if c != '\\' {
+                out.push(c);
+            }
+        }
+        out.push_str(&"\\".repeat(backslashes * 2));
+        out.push('"');
+        out
+    }
+
+    pub(super) fn run(
+        command: &str,
+        args: &[String],
+        configs_dir: &Path,
+        configure: impl Fn(&[String]) -> Result<Config>,
+    ) -> Result<()> {
+        match command {
+            "install" => install(&configure(args)?, configs_dir),
+            "start" => start(),
+            "stop" => {
+                let service = Handle::service(SERVICE_STOP | SERVICE_QUERY_STATUS)?;
+                if service.stop()? {
+                    println!("stopped the {NAME} service");
+                } else {
+                    println!("the {NAME} service was not running");
+                }
+                Ok(())
+            }
+            "uninstall" => {
+                let service = Handle::service(SERVICE_ALL_ACCESS)?;
+                service.stop()?;
+                // SAFETY: the handle is open with DELETE access.
+                if unsafe { DeleteService(service.0) } == 0 {
+                    return Err(io::Error::last_os_error()).context("removing the service");
+                }
+                println!("removed the {NAME} service");
+                Ok(())
+            }
+            "run" => {
+                let [dir, rest @ ..] = args else {
+                    bail!("usage: tinyserve service run DIR [OPTIONS]");
+                };
+                env::set_current_dir(dir).with_context(|| format!("entering {dir}"))?;
+                *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(configure(rest)?);
+                dispatch()
+            }
+            other => bail!("unknown service command `{other}` (install, start, stop, uninstall)"),
+        }
+    }
+
+    fn install(config: &Config, configs_dir: &Path) -> Result<()> {
+        let mut frozen = config.to_json();
+        if let Some(object) = frozen.as_object_mut() {
+            object.remove("daemon");
+            object.remove("tui");
+        }
+        let config_file = configs_dir.join(CONFIG_FILE);
+        fs::write(&config_file, serde_json::to_string_pretty(&frozen)?)
+            .with_context(|| format!("writing {}", config_file.display()))?;
+
+        let exe = env::current_exe().context("locating tinyserve")?;
+        let dir = env::current_dir()?;
+        let command_line = [
+            quote(&exe.to_string_lossy()),
+            "service".to_owned(),
+            "run".to_owned(),
+            quote(&dir.to_string_lossy()),
+            "--config".to_owned(),
+            quote(&config_file.to_string_lossy()),
+        ]
+        .join(" ");
+
+        let manager = Handle::manager(SC_MANAGER_CREATE_SERVICE)?;
+        let (name, command_line) = (wide(NAME), wide(&command_line));
+        // SAFETY: the strings are NUL-terminated and outlive the call; null
+        // group, dependencies and account mean none and LocalSystem.
+        let handle = unsafe {
+            CreateServiceW(
+                manager.0,
+                name.as_ptr(),
+                name.as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                command_line.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        let service = Handle::new(handle, "registering the service")?;
+        let mut description = wide(
+            env!("CARGO_PKG_DESCRIPTION")
+                .split(". ")
+                .next()
+                .unwrap_or(NAME),
+        );
+        let info = SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        // SAFETY: `info` points at a NUL-terminated string that outlives
+        // the call. A missing description is not worth failing over.
+        unsafe {
+            ChangeServiceConfig2W(
+                service.0,
+                SERVICE_CONFIG_DESCRIPTION,
+                (&raw const info).cast::<c_void>(),
+            )
+        };
+        println!(
+            "installed the {NAME} service serving {} from {}; start it with `tinyserve service start`",
+            config.str("root"),
+            dir.display()
+        );
+        Ok(())
+    }
+
+    fn start() -> Result<()> {
+        let service = Handle::service(SERVICE_START | SERVICE_QUERY_STATUS)?;
+        // SAFETY: no arguments are passed.
+        if unsafe { StartServiceW(service.0, 0, ptr::null()) } == 0 {
+            return Err(io::Error::last_os_error()).context("starting the service");
+        }
+        match service.settle(SERVICE_START_PENDING)? {
+            SERVICE_RUNNING => {
+                println!("started the {NAME} service");
+                Ok(())
+            }
+            SERVICE_START_PENDING => bail!("the service did not start within {SETTLE:?}"),
+            _ => bail!(
+                "the service stopped during startup; check its configuration with `tinyserve --config`"
+            ),
+        }
+    }
+
+    /// Hands the process to the service manager, which calls
+    /// [`service_main`] on another thread.
+    fn dispatch() -> Result<()> {
+        let mut name = wide(NAME);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: ptr::null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        // SAFETY: the table ends with a null entry and outlives the call,
+        // which returns once the service has stopped.
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            return Err(io::Error::last_os_error())
+                .context("`tinyserve service run` is started by the service manager");
+        }
+        Ok(())
+    }
+
+    fn report(state: u32, exit_code: u32) {
+        let handle = STATUS.load(Ordering::Acquire) as SERVICE_STATUS_HANDLE;
+        let status = SERVICE_STATUS {
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode: if exit_code == 0 {
+                NO_ERROR
+            } else {
+                ERROR_SERVICE_SPECIFIC_ERROR
+            },
+            dwServiceSpecificExitCode: exit_code,
+            dwWaitHint: if state == SERVICE_RUNNING || state == SERVICE_STOPPED {
+                0
+            } else {
+                SETTLE.as_millis() as u32
+            },
+            ..zeroed_status()
+        };
+        // SAFETY: `handle` came from RegisterServiceCtrlHandlerExW.
+        unsafe { SetServiceStatus(handle, &status) };
+    }
+
+    unsafe extern "system" fn control(
+        control: u32,
+        _event: u32,
+        _data: *mut c_void,
+        _context: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                report(SERVICE_STOP_PENDING, 0);
+                if let Some(stop) = STOP.get() {
+                    let _ = stop.lock().unwrap_or_else(|e| e.into_inner()).send(());
+                }
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut windows_sys::core::PWSTR) {
+        let (tx, rx) = mpsc::channel();
+        let _ = STOP.set(Mutex::new(tx));
+        let name = wide(NAME);
+        // SAFETY: `name` is NUL-terminated; `control` lives for the process.
+        let handle =
+            unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control), ptr::null()) };
+        if handle.is_null() {
+            return;
+        }
+        STATUS.store(handle as usize, Ordering::Release);
+        report(SERVICE_START_PENDING, 0);
+
+        let config = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let started = match config {
+            Some(config) => ServerBuilder::new(config).start(),
+            None => Err(anyhow::anyhow!("no configuration")),
+        };
+        let server = match started {
+            Ok(server) => server,
+            Err(e) => {
+                log::error(&format!("{e:#}"));
+                report(SERVICE_STOPPED, 1);
+                return;
+            }
+        };
+        report(SERVICE_RUNNING, 0);
+        let _ = rx.recv();
+        let code = match server.stop() {
+            Ok(()) => 0,
+            Err(e) => {
+                log::error(&format!("{e:#}"));
+                1
+            }
+        };
+        report(SERVICE_STOPPED, code);
+    }
+}
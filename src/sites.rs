@@ -0,0 +1,631 @@
+//! `tinyserve up`: several independent sites from one sites file, each in
+//! its own server process, supervised by one command.
+//!
+//! ```text
+//! tinyserve up [FILE] [--option[=value]]...
+//! tinyserve up status [FILE]
+//! ```
+//!
+//! `FILE` (default `sites.json`) maps site names to option objects, as in a
+//! config file. Each site is started as `tinyserve serve` with the options
+//! given to `up` under its own, and runs in the directory of the sites
+//! file, which relative paths are taken from. A site's effective
+//! configuration reaches it through a file only its user can read, next to
+//! the state below and removed when `up` exits, never through its command
+//! line, where any local user could read secrets such as `authToken`. Lines
+//! the sites print are passed on prefixed with `[name]`, or with a `site`
+//! field in JSON output.
+//!
+//! A site that exits with an error is restarted after a delay that doubles
+//! up to [`MAX_BACKOFF`], back to one second once it has stayed up for a
+//! minute; one that fails within its first second, before ever running,
+//! stops everything. `SIGINT` or `SIGTERM` stops every site, then `up`.
+//!
+//! While running, `up` records each site's process, state and restarts in
+//! the `sites` dir of the configs dir, which `tinyserve up status` shows
+//! together with whether each site accepts connections.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use serde_json::{Map, Value, json};
+
+use crate::core::config::ENV_PREFIX;
+use crate::core::dirs::SITES_DIR;
+use crate::core::{Config, Layer};
+use crate::daemon;
+use crate::files::fnv1a;
+use crate::log::{self, Style};
+use crate::server::status;
+
+/// Sites file read when none is named.
+pub const SITES_FILE: &str = "sites.json";
+
+/// How long a site must run before its exit no longer counts as a failed
+/// start.
+const STARTUP: Duration = Duration::from_secs(1);
+
+/// Running this long resets the restart delay.
+const STABLE: Duration = Duration::from_secs(60);
+
+/// Longest delay before restarting a site.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long stopping waits for the sites to exit before killing them.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the sites are checked on.
+const POLL: Duration = Duration::from_millis(200);
+
+/// Set by `SIGINT` or `SIGTERM`.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// A site as described in the sites file.
+struct Site {
+    name: String,
+    /// The options not left at their defaults, as a config file would
+    /// give them.
+    config: Value,
+    /// Where `config` is written for the site's process to read.
+    config_file: PathBuf,
+    root: String,
+    /// `host:port` as configured.
+    address: String,
+}
+
+/// A site's process and history.
+struct Supervised {
+    site: Site,
+    child: Option<Child>,
+    state: &'static str,
+    started: Instant,
+    since: SystemTime,
+    restarts: u32,
+    /// Delay before the next restart.
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+/// Runs `tinyserve up` with the arguments after it. `configure` turns serve
+/// arguments into the configuration they describe.
+pub fn run(
+    args: &[String],
+    configs_dir: &Path,
+    configure: impl Fn(&[String]) -> Result<Config>,
+) -> Result<()> {
+    let (show, args) = match args.first().map(String::as_str) {
+        Some("status") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    let (file, shared) = match args.first() {
+        Some(file) if !file.starts_with('-') => (file.as_str(), &args[1..]),
+        _ => (SITES_FILE, args),
+    };
+    let base = configure(shared)?;
+    log::set_style(Style::from_config(&base));
+    let file = Path::new(file)
+        .canonicalize()
+        .with_context(|| format!("reading {file}"))?;
+    let state = configs_dir.join(SITES_DIR).join(format!(
+        "{:016x}.json",
+        fnv1a(file.as_os_str().as_encoded_bytes())
+    ));
+    if show {
+        return show_status(&file, &state);
+    }
+    let sites = load(&file, shared, &state, &configure)?;
+    let dir = file.parent().unwrap_or(Path::new("."));
+    supervise(&file, dir, &state, sites, Style::from_config(&base).json)
+}
+
+/// The sites in `file`, with `shared` options under each site's own;
+/// their config files go next to `state`.
+fn load(
+    file: &Path,
+    shared: &[String],
+    state: &Path,
+    configure: &impl Fn(&[String]) -> Result<Config>,
+) -> Result<Vec<Site>> {
+    let text = fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+    let json: Value =
+        serde_json::from_str(&text).with_context(|| format!("parsing {}", file.display()))?;
+    let object = json
+        .as_object()
+        .filter(|o| !o.is_empty())
+        .ok_or_else(|| anyhow!("{}: expected an object of sites", file.display()))?;
+    let mut sites: Vec<Site> = Vec::new();
+    for (name, options) in object {
+        let options = options
+            .as_object()
+            .ok_or_else(|| anyhow!("site `{name}`: expected an object of options"))?;
+        let mut args = shared.to_vec();
+        for (key, value) in options {
+            args.push(flag(key, value).with_context(|| format!("site `{name}`"))?);
+        }
+        let config = configure(&args).with_context(|| format!("site `{name}`"))?;
+        if config.bool("daemon") || config.bool("tui") {
+            bail!("site `{name}`: daemon and tui cannot be used with `tinyserve up`");
+        }
+        let mut frozen = config.to_json();
+        if let Some(object) = frozen.as_object_mut() {
+            object.retain(|key, _| config.layer(key) != Layer::Default);
+        }
+        let stem = state.file_stem().unwrap_or_default().to_string_lossy();
+        let site = Site {
+            name: name.clone(),
+            config: frozen,
+            config_file: state
+                .with_file_name(format!("{stem}-{:016x}.json", fnv1a(name.as_bytes()))),
+            root: config.str("root").to_owned(),
+            address: format!("{}:{}", config.str("host"), config.int("port")),
+        };
+        if config.int("port") != 0
+            && let Some(other) = sites.iter().find(|s| s.address == site.address)
+        {
+            bail!(
+                "sites `{}` and `{name}` both listen on {}",
+                other.name,
+                site.address
+            );
+        }
+        sites.push(site);
+    }
+    Ok(sites)
+}
+
+/// `--key=value` for an option of a site.
+fn flag(key: &str, value: &Value) -> Result<String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(_) | Value::Number(_) => Ok(value.to_string()),
+        _ => Err(anyhow!("`{key}`: expected a string, number or boolean")),
+    };
+    let raw = match value {
+        Value::Array(items) => {
+            let items = items.iter().map(scalar).collect::<Result<Vec<_>>>()?;
+            if items.iter().any(|i| i.contains(',')) {
+                bail!("`{key}`: list items cannot contain commas");
+            }
+            items.join(",")
+        }
+        value => scalar(value)?,
+    };
+    Ok(format!("--{key}={raw}"))
+}
+
+fn supervise(file: &Path, dir: &Path, state: &Path, sites: Vec<Site>, json: bool) -> Result<()> {
+    let exe = env::current_exe().context("locating the tinyserve binary")?;
+    sys::on_stop();
+    let mut sites: Vec<Supervised> = sites
+        .into_iter()
+        .map(|site| Supervised {
+            site,
+            child: None,
+            state: "starting",
+            started: Instant::now(),
+            since: SystemTime::now(),
+            restarts: 0,
+            backoff: Duration::from_secs(1),
+            restart_at: None,
+        })
+        .collect();
+    for s in &mut sites {
+        if let Err(e) = start(&exe, dir, s, json) {
+            stop_all(&mut sites);
+            return Err(e);
+        }
+    }
+    log::info(&format!(
+        "started {} site(s) from {}",
+        sites.len(),
+        file.display()
+    ));
+    let result = watch(&exe, dir, file, state, &mut sites, json);
+    stop_all(&mut sites);
+    let _ = fs::remove_file(state);
+    for s in &sites {
+        let _ = fs::remove_file(&s.site.config_file);
+    }
+    result
+}
+
+/// Restarts sites that fail until stopped or every site has exited.
+fn watch(
+    exe: &Path,
+    dir: &Path,
+    file: &Path,
+    state: &Path,
+    sites: &mut [Supervised],
+    json: bool,
+) -> Result<()> {
+    let mut changed = true;
+    while !STOP.load(Ordering::SeqCst) {
+        if changed {
+            record(file, state, sites);
+            changed = false;
+        }
+        for s in sites.iter_mut() {
+            if let Some(at) = s.restart_at
+                && Instant::now() >= at
+            {
+                s.restart_at = None;
+                s.restarts += 1;
+                if let Err(e) = start(exe, dir, s, json) {
+                    log::error(&format!("site `{}`: {e:#}", s.site.name));
+                    s.state = "failed";
+                }
+                changed = true;
+                continue;
+            }
+            let Some(child) = &mut s.child else { continue };
+            let Some(exit) = child.try_wait()? else {
+                if s.state == "starting" && s.started.elapsed() >= STARTUP {
+                    s.state = "running";
+                    changed = true;
+                }
+                continue;
+            };
+            s.child = None;
+            changed = true;
+            if exit.success() {
+                log::info(&format!("site `{}` exited", s.site.name));
+                s.state = "exited";
+                continue;
+            }
+            if s.restarts == 0 && s.state == "starting" {
+                bail!(
+                    "site `{}` failed to start ({})",
+                    s.site.name,
+                    describe(exit)
+                );
+            }
+            if s.started.elapsed() >= STABLE {
+                s.backoff = Duration::from_secs(1);
+            }
+            log::warn(&format!(
+                "site `{}` {}, restarting in {}s",
+                s.site.name,
+                describe(exit),
+                s.backoff.as_secs()
+            ));
+            s.state = "restarting";
+            s.restart_at = Some(Instant::now() + s.backoff);
+            s.backoff = (s.backoff * 2).min(MAX_BACKOFF);
+        }
+        if sites
+            .iter()
+            .all(|s| s.child.is_none() && s.restart_at.is_none())
+        {
+            break;
+        }
+        thread::sleep(POLL);
+    }
+    Ok(())
+}
+
+fn describe(exit: ExitStatus) -> String {
+    match exit.code() {
+        Some(code) => format!("exited with status {code}"),
+        None => "was killed".to_owned(),
+    }
+}
+
+/// Starts the process of a site and passes its output on.
+fn start(exe: &Path, dir: &Path, s: &mut Supervised, json: bool) -> Result<()> {
+    let config_file = &s.site.config_file;
+    write_private(config_file, &serde_json::to_string_pretty(&s.site.config)?)
+        .with_context(|| format!("writing {}", config_file.display()))?;
+    let mut command = Command::new(exe);
+    command
+        .arg("serve")
+        .arg("--config")
+        .arg(config_file)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // The file already has what the environment gave `up`; left in place,
+    // the variables would override the site's own options.
+    for (name, _) in env::vars_os() {
+        if name.to_string_lossy().starts_with(ENV_PREFIX) {
+            command.env_remove(name);
+        }
+    }
+    sys::own_group(&mut command);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("starting site `{}`", s.site.name))?;
+    if let Some(out) = child.stdout.take() {
+        forward(&s.site.name, out, false, json);
+    }
+    if let Some(err) = child.stderr.take() {
+        forward(&s.site.name, err, true, json);
+    }
+    s.child = Some(child);
+    s.state = "starting";
+    s.started = Instant::now();
+    s.since = SystemTime::now();
+    Ok(())
+}
+
+/// Writes `contents` to a new `path` that only the current user can read.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    sys::private(&mut options);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Copies the lines of `output` to stdout or stderr, marked with `name`.
+fn forward(site: &str, output: impl Read + Send + 'static, stderr: bool, json: bool) {
+    let name = site.to_owned();
+    let spawned = thread::Builder::new()
+        .name(format!("tinyserve-site-{name}"))
+        .spawn(move || {
+            for line in BufReader::new(output).lines() {
+                let Ok(line) = line else { break };
+                let line = match serde_json::from_str::<Map<String, Value>>(&line) {
+                    Ok(mut object) if json => {
+                        object.insert("site".into(), Value::from(name.as_str()));
+                        Value::Object(object).to_string()
+                    }
+                    _ => format!("[{name}] {line}"),
+                };
+                if stderr {
+                    eprintln!("{line}");
+                } else {
+                    println!("{line}");
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn(&format!("cannot pass on the output of site `{site}`: {e}"));
+    }
+}
+
+/// Asks every running site to exit, killing those that take too long.
+fn stop_all(sites: &mut [Supervised]) {
+    for s in sites.iter_mut() {
+        s.restart_at = None;
+        if let Some(child) = &mut s.child {
+            sys::terminate(child);
+        }
+    }
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    for s in sites.iter_mut() {
+        let Some(mut child) = s.child.take() else {
+            continue;
+        };
+        while matches!(child.try_wait(), Ok(None)) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        if matches!(child.try_wait(), Ok(None)) {
+            log::warn(&format!("site `{}` did not stop; killing it", s.site.name));
+            let _ = child.kill();
+        }
+        let _ = child.wait();
+        s.state = "exited";
+    }
+}
+
+/// Writes what `tinyserve up status` shows.
+fn record(file: &Path, state: &Path, sites: &[Supervised]) {
+    let sites: Vec<Value> = sites
+        .iter()
+        .map(|s| {
+            json!({
+                "name": s.site.name,
+                "state": s.state,
+                "pid": s.child.as_ref().map(Child::id),
+                "address": s.site.address,
+                "root": s.site.root,
+                "restarts": s.restarts,
+                "since": s.since.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            })
+        })
+        .collect();
+    let record = json!({"file": file, "pid": std::process::id(), "sites": sites});
+    let written = state
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(state, record.to_string()));
+    if let Err(e) = written {
+        log::warn(&format!("cannot write {}: {e}", state.display()));
+    }
+}
+
+/// `tinyserve up status`: the sites of the `up` running `file`.
+fn show_status(file: &Path, state: &Path) -> Result<()> {
+    let not_running = || anyhow!("no `tinyserve up` is running {}", file.display());
+    let text = match fs::read_to_string(state) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_running()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", state.display())),
+    };
+    let mut record: Value =
+        serde_json::from_str(&text).with_context(|| format!("parsing {}", state.display()))?;
+    let pid = record["pid"].as_u64().unwrap_or(0) as u32;
+    if cfg!(unix) && !daemon::alive(pid) {
+        return Err(not_running());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut lines = vec![format!("{} (pid {pid})", file.display())];
+    let sites = record["sites"]
+        .as_array_mut()
+        .map_or(&mut [][..], Vec::as_mut_slice);
+    let width = sites
+        .iter()
+        .filter_map(|s| s["name"].as_str())
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    for site in sites.iter_mut() {
+        let address = site["address"].as_str().unwrap_or_default().to_owned();
+        let listening = site["pid"].is_u64() && accepts(&address);
+        site["listening"] = Value::from(listening);
+        let up = now.saturating_sub(site["since"].as_u64().unwrap_or(now));
+        lines.push(format!(
+            "  {:<width$}  {:<10}  {:<21}  {:<13}  up {}, {} restart(s), {}",
+            site["name"].as_str().unwrap_or_default(),
+            site["state"].as_str().unwrap_or_default(),
+            address,
+            if listening {
+                "listening"
+            } else {
+                "not listening"
+            },
+            status::uptime(up),
+            site["restarts"],
+            site["root"].as_str().unwrap_or_default(),
+        ));
+    }
+    log::outcome(record, &lines.join("\n"));
+    Ok(())
+}
+
+/// Whether something accepts connections at `address`, the wildcard
+/// addresses standing for loopback.
+fn accepts(address: &str) -> bool {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return false;
+    };
+    let host = match host.trim_matches(['[', ']']) {
+        "" | "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        host => host,
+    };
+    let Ok(mut addrs) = (host, port.parse::<u16>().unwrap_or(0)).to_socket_addrs() else {
+        return false;
+    };
+    addrs.any(|addr| TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_ok())
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+    use std::sync::atomic::Ordering;
+
+    use super::STOP;
+
+    extern "C" fn stop(_: libc::c_int) {
+        STOP.store(true, Ordering::SeqCst);
+    }
+
+    /// Sets [`STOP`] on `SIGINT` and `SIGTERM`.
+    pub fn on_stop() {
+        let handler = stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: the handler only stores to an atomic.
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    }
+
+    /// Keeps a terminal's `^C` from reaching the site directly, so that it
+    /// is stopped like the rest rather than seen to crash.
+    pub fn own_group(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    /// Files created with `options` are readable by their owner only.
+    pub fn private(options: &mut OpenOptions) {
+        options.mode(0o600);
+    }
+
+    pub fn terminate(child: &mut Child) {
+        if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+            // SAFETY: plain kill(2) on our own child.
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::fs::OpenOptions;
+    use std::process::{Child, Command};
+
+    pub fn on_stop() {}
+
+    pub fn own_group(_command: &mut Command) {}
+
+    /// The configs dir is in the user's profile, which only they can read.
+    pub fn private(_options: &mut OpenOptions) {}
+
+    pub fn terminate(child: &mut Child) {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Aliases;
+
+    fn configure(args: &[String]) -> Result<Config> {
+        let aliases = Aliases::builtin();
+        let mut config = Config::default();
+        for arg in args {
+            let (key, raw) = arg.trim_start_matches('-').split_once('=').unwrap();
+            config.set_raw(aliases.resolve(key).unwrap(), raw, Layer::Cli)?;
+        }
+        Ok(config)
+    }
+
+    #[test]
+    fn site_options_reach_the_site_in_a_private_file() {
+        let dir = std::env::temp_dir().join(format!("tinyserve-sites-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(SITES_FILE);
+        fs::write(
+            &file,
+            r#"{"docs": {"port": 8081, "authToken": "s3cret", "quiet": true}}"#,
+        )
+        .unwrap();
+        let state = dir.join("state.json");
+        let shared = ["--port=9000".to_owned(), "--authToken=shared".to_owned()];
+        let sites = load(&file, &shared, &state, &configure).unwrap();
+        let site = &sites[0];
+        assert_eq!(site.config["authToken"], "s3cret");
+        assert_eq!(site.config["port"], 8081);
+        assert!(site.config.get("host").is_none());
+        assert!(site.address.ends_with(":8081"));
+        assert_eq!(site.config_file.parent(), Some(dir.as_path()));
+
+        write_private(&site.config_file, "{}").unwrap();
+        write_private(&site.config_file, &site.config.to_string()).unwrap();
+        let written: Value =
+            serde_json::from_str(&fs::read_to_string(&site.config_file).unwrap()).unwrap();
+        assert_eq!(written, site.config);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&site.config_file)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
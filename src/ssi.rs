@@ -0,0 +1,328 @@
+//! Restricted server-side includes for tiny multi-page sites.
+//!
+//! Files with an extension listed in `ssiExtensions` are processed before
+//! being sent:
+//!
+//! * `<!--#include file="header.html" -->` inserts a file relative to the
+//!   including file; `virtual="/parts/nav.html"` is relative to the root.
+//!   Included files are processed too, up to [`MAX_DEPTH`] levels and
+//!   [`MAX_INCLUDES`] includes in all; a file that (indirectly) includes
+//!   itself, or that the [access rules](crate::access), `policy` or
+//!   `schedule` keep from the request, is an error. A page growing past
+//!   [`MAX_OUTPUT`] bytes is answered with `500` rather than sent.
+//! * `${TINYSERVE_NAME}` is replaced by `NAME` from `ssiVars`. Other
+//!   `${...}` sequences are left alone. The environment is never read, since
+//!   `TINYSERVE_*` variables also carry options such as `authToken`.
+
+use std::path::{Path, PathBuf};
+
+use crate::access;
+use crate::files::{self, resolve};
+use crate::http::{Request, Response, mime};
+use crate::log;
+use crate::server::{Context, policy, schedule};
+
+/// Nesting limit for includes.
+pub const MAX_DEPTH: usize = 8;
+/// Includes processed for one page at most.
+pub const MAX_INCLUDES: usize = 256;
+/// Largest page includes may build.
+pub const MAX_OUTPUT: usize = 8 * 1024 * 1024;
+
+const VAR_PREFIX: &str = "TINYSERVE_";
+const ERROR_TEXT: &str = "[an error occurred while processing this directive]";
+
+/// Whether `path` should be processed.
+pub fn applies(ctx: &Context, path: &Path) -> bool {
+    ctx.config.bool("ssi")
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| {
+                ctx.config
+                    .list("ssiExtensions")
+                    .iter()
+                    .any(|a| a.trim_start_matches('.').eq_ignore_ascii_case(ext))
+            })
+}
+
+/// Processes `path` and returns the result as an uncacheable response.
+pub fn serve(ctx: &Context, req: &Request, path: &Path) -> Response {
+    let source = match files::read(ctx, path) {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(e) => return files::io_error(&e),
+    };
+    let mut page = Page {
+        req,
+        stack: vec![path.to_path_buf()],
+        includes: 0,
+        out: String::with_capacity(source.len()),
+    };
+    if process(ctx, &source, path, &mut page).is_none() {
+        log::warn(&format!(
+            "{}: includes make the page larger than {MAX_OUTPUT} bytes",
+            path.display()
+        ));
+        return Response::error_with(500, "the page is too large");
+    }
+    Response::bytes(200, mime::from_path(path), page.out).header("Cache-Control", "no-cache")
+}
+
+/// A page being built.
+struct Page<'a> {
+    /// The request for it, which included files must be open to.
+    req: &'a Request,
+    /// The file being processed and everything that included it.
+    stack: Vec<PathBuf>,
+    /// Includes processed so far.
+    includes: usize,
+    out: String,
+}
+
+impl Page<'_> {
+    /// Appends `text`; `None` once the page would outgrow [`MAX_OUTPUT`].
+    fn push(&mut self, text: &str) -> Option<()> {
+        if self.out.len() + text.len() > MAX_OUTPUT {
+            return None;
+        }
+        self.out.push_str(text);
+        Some(())
+    }
+}
+
+/// Appends `source`, the contents of `path`, processed; `None` once the
+/// page is too large.
+fn process(ctx: &Context, source: &str, path: &Path, page: &mut Page) -> Option<()> {
+    let mut rest = source;
+    while let Some(start) = rest.find("<!--#") {
+        page.push(&substitute(ctx, &rest[..start]))?;
+        let Some(len) = rest[start..].find("-->") else {
+            rest = &rest[start..];
+            break;
+        };
+        let directive = &rest[start + 5..start + len];
+        run_directive(ctx, directive.trim(), path, page)?;
+        rest = &rest[start + len + 3..];
+    }
+    page.push(&substitute(ctx, rest))
+}
+
+fn run_directive(ctx: &Context, directive: &str, path: &Path, page: &mut Page) -> Option<()> {
+    let (command, args) = directive
+        .split_once(char::is_whitespace)
+        .unwrap_or((directive, ""));
+    if command != "include" {
+        log::warn(&format!(
+            "{}: unsupported SSI directive `{command}`",
+            path.display()
+        ));
+        return page.push(ERROR_TEXT);
+    }
+    match include(ctx, args, path, page) {
+        Included::Done => Some(()),
+        Included::Refused => {
+            log::warn(&format!("{}: cannot include {args}", path.display()));
+            page.push(ERROR_TEXT)
+        }
+        Included::TooLarge => None,
+    }
+}
+
+enum Included {
+    Done,
+    Refused,
+    TooLarge,
+}
+
+fn include(ctx: &Context, args: &str, path: &Path, page: &mut Page) -> Included {
+    let Some(target) = include_target(ctx, args, path) else {
+        return Included::Refused;
+    };
+    if page.stack.len() > MAX_DEPTH
+        || page.includes >= MAX_INCLUDES
+        || page.stack.contains(&target)
+        || !files::contains(ctx, &target)
+        || !open_to(ctx, page.req, &target)
+    {
+        return Included::Refused;
+    }
+    let Ok(bytes) = files::read(ctx, &target) else {
+        return Included::Refused;
+    };
+    page.includes += 1;
+    let text = String::from_utf8_lossy(&bytes).into_owned();
+    page.stack.push(target.clone());
+    let processed = process(ctx, &text, &target, page);
+    page.stack.pop();
+    match processed {
+        Some(()) => Included::Done,
+        None => Included::TooLarge,
+    }
+}
+
+/// Whether the access rules, `policy` and `schedule` let `req` read
+/// `target`, as they would if it were asked for directly.
+fn open_to(ctx: &Context, req: &Request, target: &Path) -> bool {
+    let Ok(rest) = target.strip_prefix(&ctx.root) else {
+        return false;
+    };
+    let name = target.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let url_path = format!("/{}", rest.to_string_lossy().replace('\\', "/"));
+    !access::is_access_file(name)
+        && access::check_path(ctx, req, &url_path).is_none()
+        && policy::check_path(ctx, req, &url_path).is_none()
+        && schedule::check_path(ctx, &url_path).is_none()
+}
+
+/// The file `args` (`file="...` or `virtual="..."`) names.
+fn include_target(ctx: &Context, args: &str, path: &Path) -> Option<PathBuf> {
+    let (kind, value) = args.split_once('=')?;
+    let value = value.trim().trim_matches('"');
+    let show_hidden = ctx.config.bool("showHidden");
+    match kind.trim() {
+        "file" if !value.starts_with('/') => resolve::resolve(path.parent()?, value, show_hidden),
+        "virtual" => resolve::resolve(&ctx.root, value, show_hidden),
+        _ => None,
+    }
+}
+
+fn substitute(ctx: &Context, text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find('}').and_then(|end| {
+            let name = after[..end].strip_prefix(VAR_PREFIX)?;
+            Some((variable(ctx, name)?, end))
+        });
+        match value {
+            Some((value, end)) => {
+                out.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn variable(ctx: &Context, name: &str) -> Option<String> {
+    ctx.config.list("ssiVars").iter().find_map(|entry| {
+        let (key, value) = entry.split_once('=')?;
+        (key.trim() == name).then(|| value.to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestServer;
+
+    fn server() -> TestServer {
+        TestServer::with_auth(|b| b.option("ssi", true).option("ssiVars", "SITE=Demo")).unwrap()
+    }
+
+    #[test]
+    fn substitutes_only_configured_variables() {
+        let server = server();
+        server
+            .write(
+                "page.shtml",
+                "${TINYSERVE_SITE} ${TINYSERVE_AUTH_TOKEN} ${TINYSERVE_AUTHTOKEN} ${HOME}",
+            )
+            .unwrap();
+        let text = server.get("/page.shtml").unwrap().text();
+        assert_eq!(
+            text,
+            "Demo ${TINYSERVE_AUTH_TOKEN} ${TINYSERVE_AUTHTOKEN} ${HOME}"
+        );
+    }
+
+    #[test]
+    fn includes_files_and_stops_on_cycles() {
+        let server = server();
+        server
+            .write("page.shtml", "<!--#include virtual=\"/parts/a.html\" -->!")
+            .unwrap();
+        server
+            .write("parts/a.html", "a<!--#include file=\"b.html\" -->")
+            .unwrap();
+        server.write("parts/b.html", "b").unwrap();
+        assert_eq!(server.get("/page.shtml").unwrap().text(), "ab!");
+
+        server
+            .write("loop.shtml", "<!--#include virtual=\"/loop.shtml\" -->")
+            .unwrap();
+        assert!(server.get("/loop.shtml").unwrap().text().contains("error"));
+    }
+
+    #[test]
+    fn limits_includes_per_page() {
+        let server = server();
+        server.write("x.html", "x").unwrap();
+        let include = "<!--#include virtual=\"/x.html\" -->";
+        server
+            .write("page.shtml", include.repeat(MAX_INCLUDES + 2))
+            .unwrap();
+        let text = server.get("/page.shtml").unwrap().text();
+        assert_eq!(text.matches('x').count(), MAX_INCLUDES);
+        assert_eq!(text.matches(ERROR_TEXT).count(), 2);
+    }
+
+    #[test]
+    fn refuses_pages_over_the_output_limit() {
+        let server = server();
+        server
+            .write("big.html", "x".repeat(MAX_OUTPUT / 4 + 1))
+            .unwrap();
+        let include = "<!--#include virtual=\"/big.html\" -->";
+        server.write("page.shtml", include.repeat(4)).unwrap();
+        assert_eq!(server.get("/page.shtml").unwrap().status, 500);
+        server.write("page.shtml", include.repeat(3)).unwrap();
+        assert_eq!(server.get("/page.shtml").unwrap().status, 200);
+    }
+
+    #[test]
+    fn includes_follow_the_access_rules() {
+        let server =
+            TestServer::with(|b| b.option("ssi", true).option("accessFiles", true)).unwrap();
+        server
+            .write("private/.tinyserve-access", r#"{"token": "team"}"#)
+            .unwrap();
+        server.write("private/key.txt", "key").unwrap();
+        server
+            .write(
+                "page.shtml",
+                "<!--#include virtual=\"/private/key.txt\" -->",
+            )
+            .unwrap();
+        let text = server.get("/page.shtml").unwrap().text();
+        assert_eq!(text, ERROR_TEXT);
+        let resp = server
+            .request(
+                "GET",
+                "/page.shtml",
+                &[("Authorization", "Bearer team")],
+                b"",
+            )
+            .unwrap();
+        assert_eq!(resp.text(), "key");
+    }
+
+    #[test]
+    fn includes_follow_the_policy() {
+        let server =
+            TestServer::with(|b| b.option("ssi", true).option("policy", "*.map=deny")).unwrap();
+        server.write("app.js.map", "secret").unwrap();
+        server
+            .write("page.shtml", "<!--#include virtual=\"/app.js.map\" -->")
+            .unwrap();
+        assert_eq!(server.get("/app.js.map").unwrap().status, 403);
+        assert_eq!(server.get("/page.shtml").unwrap().text(), ERROR_TEXT);
+    }
+}
@@ -0,0 +1,199 @@
+//! `/__tinyserve/tail/<file>`: `tail -f` over Server-Sent Events.
+//!
+//! Only files inside the `tailDir` directory whose names match `tailFiles`
+//! can be followed. Each appended line is sent as a `line` event; if the file
+//! shrinks (rotation, truncation) a `truncate` event is sent and reading
+//! restarts from the beginning. `?lines=N` first replays the last N lines,
+//! read back from the end of the file rather than from its start. The
+//! stream ends when the server shuts down.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use crate::files::resolve;
+use crate::glob;
+use crate::http::sse::{self, Slot, Streams};
+use crate::http::{Request, Response};
+
+/// Path prefix of the endpoint below the internal prefix.
+pub const PREFIX: &str = "tail/";
+
+/// Upper bound for `?lines=N`.
+const MAX_REPLAY: usize = 1000;
+/// How far back from the end replaying looks for lines.
+const MAX_REPLAY_BYTES: u64 = 1024 * 1024;
+/// Bytes read at a time while looking back.
+const BLOCK: u64 = 64 * 1024;
+
+/// Handles `GET /__tinyserve/tail/<rel>` against the canonical `dir`.
+pub fn handle(
+    streams: &Arc<Streams>,
+    dir: &Path,
+    patterns: &[String],
+    rel: &str,
+    req: &Request,
+) -> Response {
+    if !glob::matches_any(patterns, rel) {
+        return Response::error(404);
+    }
+    let Some(path) = resolve::resolve(dir, rel, false) else {
+        return Response::error(404);
+    };
+    if !path.is_file() || !resolve::is_within(dir, &path) {
+        return Response::error(404);
+    }
+    let replay = req
+        .query_param("lines")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(0)
+        .min(MAX_REPLAY);
+    let slot = match streams.open() {
+        Ok(slot) => slot,
+        Err(resp) => return resp,
+    };
+    sse::response(move |w| follow(&slot, &path, replay, w))
+}
+
+fn follow(slot: &Slot, path: &PathBuf, replay: usize, w: &mut dyn io::Write) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    if replay > 0 {
+        for line in last_lines(&mut file, replay)? {
+            sse::write_event(w, Some("line"), &line)?;
+        }
+    }
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    let mut file = BufReader::new(file);
+    sse::write_comment(w, "following")?;
+
+    let mut partial = Vec::new();
+    let mut last_write = Instant::now();
+    while !slot.closed() {
+        let len = std::fs::metadata(path).map_or(0, |m| m.len());
+        if len < pos {
+            // Truncated or replaced: start over with whatever is there now.
+            file = BufReader::new(File::open(path)?);
+            pos = 0;
+            partial.clear();
+            sse::write_event(w, Some("truncate"), "")?;
+            last_write = Instant::now();
+        }
+        loop {
+            let n = file.read_until(b'\n', &mut partial)?;
+            pos += n as u64;
+            if n == 0 || !partial.ends_with(b"\n") {
+                break;
+            }
+            let line = String::from_utf8_lossy(&partial);
+            sse::write_event(w, Some("line"), line.trim_end_matches(['\r', '\n']))?;
+            partial.clear();
+            last_write = Instant::now();
+        }
+        if last_write.elapsed() >= sse::KEEPALIVE {
+            sse::write_comment(w, "ping")?;
+            last_write = Instant::now();
+        }
+        thread::sleep(sse::CHECK);
+    }
+    Ok(())
+}
+
+/// The last `n` lines of `file`, read back from its end a block at a time
+/// and no further than [`MAX_REPLAY_BYTES`], so that a large log costs no
+/// more than its tail.
+fn last_lines(file: &mut File, n: usize) -> io::Result<Vec<String>> {
+    let end = file.seek(SeekFrom::End(0))?;
+    let mut start = end;
+    let mut tail = Vec::new();
+    while start > 0 && end - start < MAX_REPLAY_BYTES {
+        let step = BLOCK.min(start);
+        start -= step;
+        file.seek(SeekFrom::Start(start))?;
+        let mut block = vec![0; step as usize];
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&tail);
+        tail = block;
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if body.iter().filter(|&&b| b == b'\n').count() >= n {
+            break;
+        }
+    }
+    let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    if body.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut lines: Vec<&[u8]> = body.split(|&b| b == b'\n').collect();
+    if start > 0 {
+        // Begins mid-line.
+        lines.remove(0);
+    }
+    let lines = &lines[lines.len().saturating_sub(n)..];
+    Ok(lines
+        .iter()
+        .map(|line| {
+            let line = String::from_utf8_lossy(line);
+            line.trim_end_matches('\r').to_owned()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn tail_of(name: &str, contents: &[u8], n: usize) -> Vec<String> {
+        let path =
+            std::env::temp_dir().join(format!("tinyserve-tail-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let lines = last_lines(&mut File::open(&path).unwrap(), n).unwrap();
+        fs::remove_file(&path).unwrap();
+        lines
+    }
+
+    #[test]
+    fn replays_the_last_lines() {
+        let log: String = (0..20_000).map(|i| format!("line {i}\r\n")).collect();
+        assert_eq!(
+            tail_of("long", log.as_bytes(), 3),
+            ["line 19997", "line 19998", "line 19999"]
+        );
+        assert_eq!(tail_of("short", b"a\nb", 5), ["a", "b"]);
+        assert_eq!(tail_of("blank", b"a\n\nb\n", 2), ["", "b"]);
+        assert!(tail_of("empty", b"", 5).is_empty());
+    }
+
+    #[test]
+    fn looks_back_a_bounded_distance() {
+        let mut log = vec![b'x'; 2 * MAX_REPLAY_BYTES as usize];
+        log.extend_from_slice(b"\nlast\n");
+        assert_eq!(tail_of("huge", &log, 2), ["last"]);
+    }
+
+    #[test]
+    fn follows_until_the_server_shuts_down() {
+        let path =
+            std::env::temp_dir().join(format!("tinyserve-tail-{}-follow", std::process::id()));
+        fs::write(&path, "a\n").unwrap();
+        let streams = Streams::new(1);
+        let slot = streams.open().unwrap();
+        assert_eq!(streams.open().err().unwrap().status, 503);
+        let following = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut out = Vec::new();
+                follow(&slot, &path, 1, &mut out).map(|()| out)
+            })
+        };
+        thread::sleep(sse::CHECK);
+        streams.close();
+        let out = String::from_utf8(following.join().unwrap().unwrap()).unwrap();
+        assert!(out.starts_with("event: line\ndata: a\n"), "{out}");
+        assert!(streams.open().is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+//! Testing against a real server: [`TestServer`] serves a fresh temporary
+//! directory, or a [`MemoryFs`], on an ephemeral loopback port, and speaks
+//! just enough HTTP/1.1 to check what comes back.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! let server = tinyserve::test::TestServer::new()?;
+//! server.write("index.html", "<h1>hi</h1>")?;
+//! let resp = server.get("/")?;
+//! assert_eq!(resp.status, 200);
+//! assert_eq!(resp.header("content-type"), Some("text/html; charset=utf-8"));
+//! assert_eq!(resp.text(), "<h1>hi</h1>");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+use crate::http::Headers;
+use crate::http::body::Framing;
+use crate::server::{ServerBuilder, ServerHandle};
+use crate::vfs::MemoryFs;
+
+/// How long a test request may take before failing.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long dropping a server waits for requests in progress.
+const DRAIN: Duration = Duration::from_secs(1);
+
+#[cfg(test)]
+macro_rules! token {
+    () => {
+        "s3cret"
+    };
+}
+
+/// The `authToken` of servers from [`TestServer::with_auth`].
+#[cfg(test)]
+pub(crate) const TOKEN: &str = token!();
+/// The header that gets requests past [`TestServer::with_auth`] servers.
+#[cfg(test)]
+pub(crate) const AUTH: (&str, &str) = ("Authorization", concat!("Bearer ", token!()));
+
+enum Files {
+    /// A temporary directory, removed on drop.
+    Dir(PathBuf),
+    Memory(Arc<MemoryFs>),
+}
+
+/// A server on `127.0.0.1:<ephemeral>`, stopped when dropped.
+pub struct TestServer {
+    handle: ServerHandle,
+    files: Files,
+}
+
+/// A response as received by [`TestServer::request`], body decoded from
+/// any chunked framing.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body as text, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// A new, empty directory under the system temp dir.
+fn temp_dir() -> Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let name = format!(
+        "tinyserve-test-{}-{}-{nanos}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let dir = env::temp_dir().join(name);
+    fs::create_dir(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    Ok(dir)
+}
+
+impl TestServer {
+    /// Serves an empty temporary directory with default options.
+    pub fn new() -> Result<Self> {
+        Self::with(|builder| builder)
+    }
+
+    /// Serves an empty temporary directory, with options set by `configure`.
+    pub fn with(configure: impl FnOnce(ServerBuilder) -> ServerBuilder) -> Result<Self> {
+        let dir = temp_dir()?;
+        let builder = configure(crate::Server::builder().bind("127.0.0.1:0")).root(&dir);
+        match Self::start(builder, Files::Dir(dir.clone())) {
+            Ok(server) => Ok(server),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    /// Serves an empty temporary directory that wants [`AUTH`] for writes,
+    /// with other options set by `configure`.
+    #[cfg(test)]
+    pub(crate) fn with_auth(
+        configure: impl FnOnce(ServerBuilder) -> ServerBuilder,
+    ) -> Result<Self> {
+        Self::with(|b| configure(b.option("authToken", TOKEN).option("authFor", "writes")))
+    }
+
+    /// Serves an empty [`MemoryFs`] with default options.
+    pub fn memory() -> Result<Self> {
+        Self::memory_with(|builder| builder)
+    }
+
+    /// Serves an empty [`MemoryFs`], with options set by `configure`.
+    pub fn memory_with(configure: impl FnOnce(ServerBuilder) -> ServerBuilder) -> Result<Self> {
+        let fs = Arc::new(MemoryFs::new());
+        let builder = configure(crate::Server::builder().bind("127.0.0.1:0")).vfs(Arc::clone(&fs));
+        Self::start(builder, Files::Memory(fs))
+    }
+
+    fn start(builder: ServerBuilder, files: Files) -> Result<Self> {
+        let handle = builder.start()?;
+        handle.wait_ready()?;
+        Ok(Self { handle, files })
+    }
+
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// The served directory; `None` for a [`MemoryFs`].
+    pub fn root(&self) -> Option<&Path> {
+        match &self.files {
+            Files::Dir(dir) => Some(dir),
+            Files::Memory(_) => None,
+        }
+    }
+
+    /// `http://addr` followed by `path`, which should start with `/`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr())
+    }
+
+    /// Creates or replaces the file at `path` (relative to the root),
+    /// creating parent directories as needed.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+        let path = path.trim_start_matches('/');
+        match &self.files {
+            Files::Dir(dir) => {
+                let file = dir.join(path);
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&file, contents).with_context(|| format!("writing {path}"))?;
+            }
+            Files::Memory(fs) => fs
+                .insert(path, contents.as_ref())
+                .with_context(|| format!("writing {path}"))?,
+        }
+        self.forget(path);
+        Ok(())
+    }
+
+    /// Deletes the file at `path`, if there is one.
+    pub fn remove(&self, path: &str) -> Result<()> {
+        let path = path.trim_start_matches('/');
+        match &self.files {
+            Files::Dir(dir) => match fs::remove_file(dir.join(path)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("removing {path}"));
+                }
+                _ => {}
+            },
+            Files::Memory(fs) => {
+                fs.remove(path);
+            }
+        }
+        self.forget(path);
+        Ok(())
+    }
+
+    /// Drops anything the server cached about `path`.
+    fn forget(&self, path: &str) {
+        let ctx = self.handle.context();
+        ctx.invalidate(&ctx.root.join(path));
+    }
+
+    pub fn get(&self, path: &str) -> Result<TestResponse> {
+        self.request("GET", path, &[], b"")
+    }
+
+    /// Sends one request on a fresh connection. `target` is sent as given,
+    /// so it must already be percent-encoded.
+    pub fn request(
+        &self,
+        method: &str,
+        target: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<TestResponse> {
+        let stream = TcpStream::connect(self.addr())?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut head = format!(
+            "{method} {target} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            self.addr()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+        (&stream).write_all(head.as_bytes())?;
+        (&stream).write_all(body)?;
+        read_response(BufReader::new(stream), method == "HEAD")
+    }
+}
+
+fn read_response(mut reader: BufReader<TcpStream>, head_only: bool) -> Result<TestResponse> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("bad status line {line:?}"))?;
+    let mut headers = Headers::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("connection closed in the response head");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.append(name.trim(), value.trim());
+        }
+    }
+    let mut body = Vec::new();
+    let mut framing = if head_only || status < 200 || status == 204 || status == 304 {
+        Some(Framing::Done)
+    } else if headers.has_token("transfer-encoding", "chunked") {
+        Some(Framing::Chunked { remaining: 0 })
+    } else {
+        headers
+            .get("content-length")
+            .and_then(|len| len.parse().ok())
+            .map(Framing::Length)
+    };
+    match &mut framing {
+        Some(framing) => {
+            let mut buf = [0; 8192];
+            loop {
+                let n = framing.read(&mut reader, &mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                body.extend_from_slice(&buf[..n]);
+            }
+        }
+        // Delimited by the end of the connection.
+        None => {
+            reader.read_to_end(&mut body)?;
+        }
+    }
+    Ok(TestResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.handle.shutdown(DRAIN);
+        if let Files::Dir(dir) = &self.files {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! The `--tui` dashboard: a full-screen live view of recent requests, the
+//! status code breakdown, throughput and open connections, in place of the
+//! scrolling access log.
+//!
+//! Keys: `p` pauses the request list (counters keep running), `v` toggles
+//! the peer, size and timing columns and a taller message pane, `c` clears
+//! the list, `q` or Ctrl-C stops the server.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::core::Config;
+use crate::server::ServerBuilder;
+use crate::server::middleware::Completed;
+
+/// Requests kept for the list.
+const MAX_ROWS: usize = 500;
+/// Messages kept for the message pane.
+const MAX_MESSAGES: usize = 100;
+/// Span the rates are averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+/// Pause between redraws.
+const REFRESH: Duration = Duration::from_millis(250);
+
+struct Row {
+    peer: SocketAddr,
+    request_line: String,
+    status: u16,
+    bytes: u64,
+    elapsed: Duration,
+    failed: bool,
+}
+
+#[derive(Default)]
+struct State {
+    rows: VecDeque<Row>,
+    messages: VecDeque<String>,
+    requests: u64,
+    /// Requests by status class, `1xx` to `5xx`.
+    classes: [u64; 5],
+    /// Completion time and body bytes of the requests in the rate window.
+    recent: VecDeque<(Instant, u64)>,
+    paused: bool,
+    verbose: bool,
+}
+
+/// What the dashboard shows, fed by the server's callbacks and the log.
+#[derive(Default)]
+struct Dashboard(Mutex<State>);
+
+impl Dashboard {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn completed(&self, done: &Completed<'_>) {
+        let mut state = self.lock();
+        state.requests += 1;
+        if let Some(class) = state
+            .classes
+            .get_mut(usize::from(done.status / 100).wrapping_sub(1))
+        {
+            *class += 1;
+        }
+        state.recent.push_back((Instant::now(), done.bytes));
+        if !state.paused {
+            if state.rows.len() == MAX_ROWS {
+                state.rows.pop_back();
+            }
+            state.rows.push_front(Row {
+                peer: done.peer,
+                request_line: done.request_line.to_owned(),
+                status: done.status,
+                bytes: done.bytes,
+                elapsed: done.elapsed,
+                failed: done.error.is_some(),
+            });
+        }
+    }
+
+    fn message(&self, line: &str) {
+        let mut state = self.lock();
+        if state.messages.len() == MAX_MESSAGES {
+            state.messages.pop_front();
+        }
+        state.messages.push_back(line.to_owned());
+    }
+}
+
+/// Serves with the dashboard on the terminal until the user quits.
+pub fn run(config: Config) -> Result<()> {
+    #[cfg(unix)]
+    {
+        unix::run(config)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = config;
+        anyhow::bail!("--tui is only available on Unix terminals")
+    }
+}
+
+/// `1.5 MB` style sizes.
+fn human(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{value:.0} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// `s` cut to `width` characters.
+fn fit(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+/// ANSI color for a status code.
+fn color(status: u16) -> &'static str {
+    match status {
+        200..=299 => "\x1b[32m",
+        300..=399 => "\x1b[36m",
+        400..=499 => "\x1b[33m",
+        _ => "\x1b[31m",
+    }
+}
+
+/// The lines of one frame, `height` of them at most.
+fn frame(
+    state: &mut State,
+    url: &str,
+    uptime: Duration,
+    connections: usize,
+    active: usize,
+    (width, height): (usize, usize),
+) -> Vec<String> {
+    let now = Instant::now();
+    while state
+        .recent
+        .front()
+        .is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW)
+    {
+        state.recent.pop_front();
+    }
+    let window = RATE_WINDOW.min(uptime).as_secs_f64().max(1.0);
+    let per_sec = state.recent.len() as f64 / window;
+    let bytes_per_sec = state.recent.iter().map(|(_, b)| *b as f64).sum::<f64>() / window;
+
+    let secs = uptime.as_secs();
+    let mut flags = String::new();
+    if state.paused {
+        flags.push_str("  \x1b[7m PAUSED \x1b[0m");
+    }
+    if state.verbose {
+        flags.push_str("  \x1b[7m VERBOSE \x1b[0m");
+    }
+    let mut lines = vec![
+        format!(
+            "\x1b[1mtinyserve\x1b[0m  {url}  up {:02}:{:02}:{:02}{flags}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        ),
+        format!(
+            "{} requests   {}   {per_sec:.1} req/s  {}/s   {connections} connections, {active} active",
+            state.requests,
+            state
+                .classes
+                .iter()
+                .enumerate()
+                .map(|(i, n)| format!("{}{}xx\x1b[0m {n}", color((i as u16 + 1) * 100), i + 1))
+                .collect::<Vec<_>>()
+                .join("  "),
+            human(bytes_per_sec),
+        ),
+        String::new(),
+    ];
+    lines.push(if state.verbose {
+        format!(
+            "\x1b[1m{:<6} {:<21} {:>9} {:>9}  REQUEST\x1b[0m",
+            "STATUS", "PEER", "BYTES", "TIME"
+        )
+    } else {
+        "\x1b[1mSTATUS REQUEST\x1b[0m".to_owned()
+    });
+
+    let shown = if state.verbose { height / 4 } else { 3 };
+    let messages = state.messages.len().min(shown);
+    let footer = 1 + if messages > 0 { messages + 1 } else { 0 };
+    let room = height.saturating_sub(lines.len() + footer);
+    for row in state.rows.iter().take(room) {
+        let status = if row.failed {
+            format!("{}{:<3}!\x1b[0m  ", color(row.status), row.status)
+        } else {
+            format!("{}{:<6}\x1b[0m", color(row.status), row.status)
+        };
+        let text = if state.verbose {
+            let ms = format!("{:.1}ms", row.elapsed.as_secs_f64() * 1000.0);
+            format!(
+                "{:<21} {:>9} {ms:>9}  {}",
+                row.peer.to_string(),
+                row.bytes,
+                row.request_line
+            )
+        } else {
+            row.request_line.clone()
+        };
+        lines.push(format!("{status} {}", fit(&text, width.saturating_sub(7))));
+    }
+    while lines.len() < height.saturating_sub(footer) {
+        lines.push(String::new());
+    }
+    if messages > 0 {
+        lines.push("\x1b[1mMESSAGES\x1b[0m".to_owned());
+        let skip = state.messages.len() - messages;
+        for message in state.messages.iter().skip(skip) {
+            lines.push(fit(message, width));
+        }
+    }
+    lines.push("\x1b[2mp pause   v verbose   c clear   q quit\x1b[0m".to_owned());
+    lines.truncate(height);
+    lines
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{self, Read, Write};
+    use std::mem::MaybeUninit;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use anyhow::{Result, bail};
+
+    use super::{Dashboard, REFRESH, ServerBuilder, frame};
+    use crate::core::Config;
+    use crate::log;
+
+    /// The terminal in raw mode on the alternate screen, restored on drop.
+    struct Terminal {
+        saved: libc::termios,
+    }
+
+    impl Terminal {
+        fn enter() -> Result<Self> {
+            // SAFETY: isatty only inspects the descriptors.
+            let tty = unsafe { libc::isatty(0) == 1 && libc::isatty(1) == 1 };
+            if !tty {
+                bail!("--tui needs an interactive terminal");
+            }
+            let mut termios = MaybeUninit::uninit();
+            // SAFETY: tcgetattr fills `termios` when it returns 0.
+            if unsafe { libc::tcgetattr(0, termios.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // SAFETY: initialized by the successful tcgetattr.
+            let saved = unsafe { termios.assume_init() };
+            let mut raw = saved;
+            // No echo, no line buffering, Ctrl-C read as a key; reads
+            // return after a tenth of a second without input.
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            // SAFETY: `raw` is a valid termios.
+            if unsafe { libc::tcsetattr(0, libc::TCSANOW, &raw) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            print!("\x1b[?1049h\x1b[?25l\x1b[?7l");
+            io::stdout().flush()?;
+            Ok(Self { saved })
+        }
+
+        /// Columns and rows.
+        fn size() -> (usize, usize) {
+            let mut size = MaybeUninit::<libc::winsize>::zeroed();
+            // SAFETY: TIOCGWINSZ fills a winsize.
+            let ok = unsafe { libc::ioctl(1, libc::TIOCGWINSZ, size.as_mut_ptr()) } == 0;
+            // SAFETY: zeroed or filled in, both valid.
+            let size = unsafe { size.assume_init() };
+            if ok && size.ws_col > 0 && size.ws_row > 0 {
+                (usize::from(size.ws_col), usize::from(size.ws_row))
+            } else {
+                (80, 24)
+            }
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            print!("\x1b[?7h\x1b[?25h\x1b[?1049l");
+            let _ = io::stdout().flush();
+            // SAFETY: restores the settings read in `enter`.
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, &self.saved) };
+        }
+    }
+
+    pub(super) fn run(config: Config) -> Result<()> {
+        let terminal = Terminal::enter()?;
+        let dashboard = Arc::new(Dashboard::default());
+        let sink = Arc::clone(&dashboard);
+        log::redirect(Some(Box::new(move |line| sink.message(line))));
+        let result = serve(config, &dashboard);
+        log::redirect(None);
+        drop(terminal);
+        result
+    }
+
+    fn serve(config: Config, dashboard: &Arc<Dashboard>) -> Result<()> {
+        let feed = Arc::clone(dashboard);
+        let handle = ServerBuilder::new(config)
+            .on_response(move |done| feed.completed(done))
+            .start()?;
+        show(dashboard, &handle)?;
+        Ok(handle.stop()?)
+    }
+
+    fn show(dashboard: &Dashboard, handle: &crate::ServerHandle) -> Result<()> {
+        let started = Instant::now();
+        let url = handle.url();
+        let lifecycle = &handle.context().lifecycle;
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout().lock();
+        let mut drawn = None::<Instant>;
+        loop {
+            let mut key = [0; 1];
+            if stdin.read(&mut key)? == 1 {
+                let mut state = dashboard.lock();
+                match key[0] {
+                    b'q' | b'Q' | 3 => return Ok(()),
+                    b'p' | b'P' => state.paused = !state.paused,
+                    b'v' | b'V' => state.verbose = !state.verbose,
+                    b'c' | b'C' => state.rows.clear(),
+                    _ => continue,
+                }
+                drawn = None;
+            }
+            if drawn.is_some_and(|at| at.elapsed() < REFRESH) {
+                continue;
+            }
+            let lines = frame(
+                &mut dashboard.lock(),
+                &url,
+                started.elapsed(),
+                lifecycle.connections(),
+                lifecycle.active(),
+                Terminal::size(),
+            );
+            let mut out = String::from("\x1b[H");
+            for (i, line) in lines.iter().enumerate() {
+                if i > 0 {
+                    out.push_str("\r\n");
+                }
+                out.push_str(line);
+                out.push_str("\x1b[K");
+            }
+            out.push_str("\x1b[J");
+            stdout.write_all(out.as_bytes())?;
+            stdout.flush()?;
+            drawn = Some(Instant::now());
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Serving the contents of a zip or tar archive.
+//!
+//! The archive is indexed once when opened: each entry's data offset, sizes
+//! and compression. Entries stored uncompressed (all of a tar, and stored
+//! zip entries) are sent straight from the archive file, so ranges and
+//! `sendfile` work as for plain files; deflated zip entries are inflated as
+//! they are sent, a buffer at a time, reading past whatever precedes a
+//! requested range.
+//!
+//! [`Limits`] refuse archives whose entries add up to too many bytes or
+//! nest too deep, such as a zip bomb.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use miniz_oxide::inflate::stream::{InflateState, inflate};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use crate::http::date::days_from_civil;
+
+use super::tree::{self, Node, Tree};
+use super::{Contents, DirEntry, Metadata, Vfs};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Method {
+    Stored,
+    Deflated,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// Where the entry's data starts in the archive.
+    offset: u64,
+    /// Bytes of data in the archive.
+    stored_len: u64,
+    len: u64,
+    method: Method,
+    modified: Option<SystemTime>,
+}
+
+/// The largest end-of-central-directory record: fixed part plus comment.
+const MAX_EOCD: u64 = 22 + 0xffff;
+/// The largest tar long-name or pax header read; real ones are a few
+/// hundred bytes.
+const MAX_TAR_HEADER: u64 = 1024 * 1024;
+
+impl Node for Entry {
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+/// What an archive may hold; `0` means no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// Total uncompressed size of all entries.
+    pub max_bytes: u64,
+    /// Directories an entry may be nested in.
+    pub max_depth: usize,
+}
+
+pub struct Archive {
+    path: PathBuf,
+    tree: Tree<Entry>,
+    limits: Limits,
+    /// Uncompressed bytes indexed so far.
+    total: u64,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn u16_at(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// MS-DOS date and time, taken as UTC.
+fn dos_time(date: u16, time: u16) -> Option<SystemTime> {
+    let (year, month, day) = (
+        1980 + i64::from(date >> 9),
+        u32::from((date >> 5) & 0xf),
+        u32::from(date & 0x1f),
+    );
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400
+        + i64::from(time >> 11) * 3600
+        + i64::from((time >> 5) & 0x3f) * 60
+        + i64::from(time & 0x1f) * 2;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+impl Archive {
+    /// Indexes a `.zip` or `.tar` archive.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with(path, Limits::default())
+    }
+
+    /// Indexes a `.zip` or `.tar` archive, refusing it if it is over `limits`.
+    pub fn open_with(path: impl AsRef<Path>, limits: Limits) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let mut archive = Self {
+            path: path.to_path_buf(),
+            tree: Tree::new(file.metadata()?.modified().ok()),
+            limits,
+            total: 0,
+        };
+        let mut magic = [0; 4];
+        let is_zip = file.read(&mut magic)? == 4
+            && (u32::from_le_bytes(magic) == 0x0403_4b50
+                || u32::from_le_bytes(magic) == 0x0605_4b50);
+        if is_zip {
+            archive.index_zip(&mut file)?;
+        } else {
+            archive.index_tar(&mut file)?;
+        }
+        Ok(archive)
+    }
+
+    /// Whether `path` looks like an archive this backend can serve.
+    pub fn is_archive(path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("zip") || e.eq_ignore_ascii_case("tar"))
+    }
+
+    /// Fails if `name` is nested deeper than allowed.
+    fn check_depth(&self, name: &str) -> io::Result<()> {
+        let depth = tree::key(name).map_or(0, |k| k.matches('/').count());
+        if self.limits.max_depth > 0 && depth > self.limits.max_depth {
+            return Err(invalid("archive entry nested too deep"));
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &str, entry: Entry) -> io::Result<()> {
+        self.check_depth(name)?;
+        self.total = self.total.saturating_add(entry.len);
+        if self.limits.max_bytes > 0 && self.total > self.limits.max_bytes {
+            return Err(invalid("archive contents too large"));
+        }
+        self.tree.add_file(name, entry);
+        Ok(())
+    }
+
+    fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) -> io::Result<()> {
+        self.check_depth(name)?;
+        self.tree.add_dir(name, modified);
+        Ok(())
+    }
+
+    fn index_zip(&mut self, file: &mut File) -> io::Result<()> {
+        let size = file.metadata()?.len();
+        let tail_len = size.min(MAX_EOCD);
+        let tail = read_at(file, size - tail_len, tail_len as usize)?;
+        let eocd = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&i| u32_at(&tail, i) == 0x0605_4b50)
+            .ok_or_else(|| invalid("no zip end of central directory"))?;
+        let mut count = u64::from(u16_at(&tail, eocd + 10));
+        let mut cd_size = u64::from(u32_at(&tail, eocd + 12));
+        let mut cd_offset = u64::from(u32_at(&tail, eocd + 16));
+        // Zip64: a locator just before the record points at the real one.
+        if (count == 0xffff || cd_offset == 0xffff_ffff)
+            && eocd >= 20
+            && u32_at(&tail, eocd - 20) == 0x0706_4b50
+        {
+            let record = read_at(file, u64_at(&tail, eocd - 12), 56)?;
+            if u32_at(&record, 0) != 0x0606_4b50 {
+                return Err(invalid("bad zip64 end of central directory"));
+            }
+            count = u64_at(&record, 32);
+            cd_size = u64_at(&record, 40);
+            cd_offset = u64_at(&record, 48);
+        }
+        if cd_offset.saturating_add(cd_size) > size {
+            return Err(invalid("zip central directory out of bounds"));
+        }
+        let cd = read_at(file, cd_offset, cd_size as usize)?;
+        let mut at = 0;
+        for _ in 0..count {
+            if at + 46 > cd.len() || u32_at(&cd, at) != 0x0201_4b50 {
+                return Err(invalid("bad zip central directory entry"));
+            }
+            let flags = u16_at(&cd, at + 8);
+            let method = u16_at(&cd, at + 10);
+            let mut modified = dos_time(u16_at(&cd, at + 14), u16_at(&cd, at + 12));
+            let mut stored_len = u64::from(u32_at(&cd, at + 20));
+            let mut len = u64::from(u32_at(&cd, at + 24));
+            let name_len = usize::from(u16_at(&cd, at + 28));
+            let extra_len = usize::from(u16_at(&cd, at + 30));
+            let comment_len = usize::from(u16_at(&cd, at + 32));
+            let mut header = u64::from(u32_at(&cd, at + 42));
+            let name_at = at + 46;
+            let extra_at = name_at + name_len;
+            let next = extra_at + extra_len + comment_len;
+            if next > cd.len() {
+                return Err(invalid("bad zip central directory entry"));
+            }
+            let name = String::from_utf8_lossy(&cd[name_at..extra_at]).into_owned();
+            let mut extra = &cd[extra_at..extra_at + extra_len];
+            while extra.len() >= 4 {
+                let (id, field_len) = (u16_at(extra, 0), usize::from(u16_at(extra, 2)));
+                let field = &extra[4..(4 + field_len).min(extra.len())];
+                match id {
+                    // Zip64 sizes, present only for the fields that overflowed.
+                    0x0001 => {
+                        let mut values = field.chunks_exact(8).map(|c| u64_at(c, 0));
+                        if len == 0xffff_ffff {
+                            len = values.next().unwrap_or(len);
+                        }
+                        if stored_len == 0xffff_ffff {
+                            stored_len = values.next().unwrap_or(stored_len);
+                        }
+                        if header == 0xffff_ffff {
+                            header = values.next().unwrap_or(header);
+                        }
+                    }
+                    // Extended timestamp: flags, then the Unix mtime.
+                    0x5455 if field.len() >= 5 && field[0] & 1 != 0 => {
+                        let secs = u32_at(field, 1);
+                        modified = Some(UNIX_EPOCH + Duration::from_secs(secs.into()));
+                    }
+                    _ => {}
+                }
+                extra = &extra[(4 + field_len).min(extra.len())..];
+            }
+            at = next;
+
+            if name.ends_with('/') {
+                self.add_dir(&name, modified)?;
+                continue;
+            }
+            let method = match method {
+                0 => Method::Stored,
+                8 => Method::Deflated,
+                // Other compression methods can't be served.
+                _ => continue,
+            };
+            // Encrypted.
+            if flags & 1 != 0 {
+                continue;
+            }
+            let local = read_at(file, header, 30)?;
+            if u32_at(&local, 0) != 0x0403_4b50 {
+                return Err(invalid("bad zip local header"));
+            }
+            let offset = header
+                .saturating_add(30)
+                .saturating_add(u64::from(u16_at(&local, 26)))
+                .saturating_add(u64::from(u16_at(&local, 28)));
+            if offset.saturating_add(stored_len) > size {
+                return Err(invalid("zip entry out of bounds"));
+            }
+            self.add_file(
+                &name,
+                Entry {
+                    offset,
+                    stored_len,
+                    len,
+                    method,
+                    modified,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn index_tar(&mut self, file: &mut File) -> io::Result<()> {
+        let size = file.metadata()?.len();
+        let mut offset = 0;
+        // Name from a GNU long-name or pax header, for the next entry.
+        let mut long_name: Option<String> = None;
+        let mut pax_size: Option<u64> = None;
+        while offset + 512 <= size {
+            let header = read_at(file, offset, 512)?;
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+            let sum: u32 = header
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        u32::from(b)
+                    }
+                })
+                .sum();
+            if octal(&header[148..156]) != Some(u64::from(sum)) {
+                return Err(invalid("bad tar header checksum"));
+            }
+            let field = |range: std::ops::Range<usize>| {
+                let raw = &header[range];
+                let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+                String::from_utf8_lossy(&raw[..end]).into_owned()
+            };
+            let len = pax_size
+                .take()
+                .or_else(|| octal(&header[124..136]))
+                .ok_or_else(|| invalid("bad tar entry size"))?;
+            let data = offset + 512;
+            let end = len
+                .div_ceil(512)
+                .checked_mul(512)
+                .and_then(|padded| data.checked_add(padded))
+                .filter(|_| data + len <= size)
+                .ok_or_else(|| invalid("tar entry out of bounds"))?;
+            if matches!(header[156], b'L' | b'x') && len > MAX_TAR_HEADER {
+                return Err(invalid("tar header too large"));
+            }
+            let modified = octal(&header[136..148]).map(|s| UNIX_EPOCH + Duration::from_secs(s));
+            let mut name = field(0..100);
+            if &header[257..262] == b"ustar" {
+                let prefix = field(345..500);
+                if !prefix.is_empty() {
+                    name = format!("{prefix}/{name}");
+                }
+            }
+            if let Some(long) = long_name.take() {
+                name = long;
+            }
+            match header[156] {
+                b'0' | 0 | b'7' => self.add_file(
+                    &name,
+                    Entry {
+                        offset: data,
+                        stored_len: len,
+                        len,
+                        method: Method::Stored,
+                        modified,
+                    },
+                )?,
+                b'5' => self.add_dir(&name, modified)?,
+                b'L' => {
+                    let raw = read_at(file, data, len as usize)?;
+                    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+                    long_name = Some(String::from_utf8_lossy(&raw[..end]).into_owned());
+                }
+                b'x' => {
+                    let raw = read_at(file, data, len as usize)?;
+                    for (key, value) in pax_records(&raw) {
+                        match key {
+                            "path" => long_name = Some(value.to_owned()),
+                            "size" => pax_size = value.parse().ok(),
+                            _ => {}
+                        }
+                    }
+                }
+                // Links, devices, global pax headers and the like.
+                _ => {}
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+}
+
+/// A numeric tar field: octal, or base-256 when the high bit is set.
+fn octal(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        return Some(
+            field[1..]
+                .iter()
+                .fold(u64::from(field[0] & 0x7f), |n, &b| (n << 8) | u64::from(b)),
+        );
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// `len key=value\n` records of a pax extended header.
+fn pax_records(mut raw: &[u8]) -> Vec<(&str, &str)> {
+    let mut records = Vec::new();
+    while let Some(space) = raw.iter().position(|&b| b == b' ') {
+        let Some(len) = std::str::from_utf8(&raw[..space])
+            .ok()
+            .and_then(|l| l.parse::<usize>().ok())
+            .filter(|&l| l > space && l <= raw.len())
+        else {
+            break;
+        };
+        if let Ok(record) = std::str::from_utf8(&raw[space + 1..len])
+            && let Some((key, value)) = record.trim_end_matches('\n').split_once('=')
+        {
+            records.push((key, value));
+        }
+        raw = &raw[len..];
+    }
+    records
+}
+
+/// A deflated zip entry, inflated as it is read.
+struct Inflate {
+    input: BufReader<io::Take<File>>,
+    state: Box<InflateState>,
+    /// Inflated bytes still to come.
+    left: u64,
+}
+
+impl Read for Inflate {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let want = buf
+            .len()
+            .min(usize::try_from(self.left).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+        loop {
+            let input = self.input.fill_buf()?;
+            let at_end = input.is_empty();
+            let result = inflate(&mut self.state, input, &mut buf[..want], MZFlush::None);
+            self.input.consume(result.bytes_consumed);
+            if result.bytes_written > 0 {
+                self.left -= result.bytes_written as u64;
+                return Ok(result.bytes_written);
+            }
+            match result.status {
+                Ok(MZStatus::StreamEnd) => return Err(invalid("zip entry size mismatch")),
+                Ok(_) | Err(MZError::Buf) if !at_end => {}
+                Ok(_) | Err(MZError::Buf) => return Err(invalid("truncated deflate stream")),
+                Err(_) => return Err(invalid("corrupt deflate stream")),
+            }
+        }
+    }
+}
+
+impl Vfs for Archive {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.tree.metadata(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        let entry = *self.tree.get(path)?;
+        let mut file = File::open(&self.path)?;
+        match entry.method {
+            Method::Stored => Ok(Contents::Region {
+                file,
+                offset: entry.offset,
+                len: entry.len,
+            }),
+            Method::Deflated => {
+                file.seek(SeekFrom::Start(entry.offset))?;
+                Ok(Contents::Reader {
+                    reader: Box::new(Inflate {
+                        input: BufReader::new(file.take(entry.stored_len)),
+                        state: InflateState::new_boxed(DataFormat::Raw),
+                        left: entry.len,
+                    }),
+                    len: entry.len,
+                })
+            }
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.tree.read_dir(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test::TestServer;
+
+    /// A zip holding `name`, deflated.
+    fn zip(name: &str, data: &[u8]) -> Vec<u8> {
+        let packed = miniz_oxide::deflate::compress_to_vec(data, 6);
+        let sizes = |out: &mut Vec<u8>| {
+            out.extend(0u32.to_le_bytes()); // crc, unchecked here
+            out.extend((packed.len() as u32).to_le_bytes());
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend((name.len() as u16).to_le_bytes());
+            out.extend(0u16.to_le_bytes());
+        };
+        let mut out = Vec::new();
+        out.extend(0x0403_4b50u32.to_le_bytes());
+        out.extend([20, 0, 0, 0, 8, 0, 0, 0, 0x21, 0]);
+        sizes(&mut out);
+        out.extend(name.as_bytes());
+        out.extend(&packed);
+        let cd_offset = out.len();
+        out.extend(0x0201_4b50u32.to_le_bytes());
+        out.extend([20, 0, 20, 0, 0, 0, 8, 0, 0, 0, 0x21, 0]);
+        sizes(&mut out);
+        out.extend([0; 14]);
+        out.extend(name.as_bytes());
+        let cd_size = out.len() - cd_offset;
+        out.extend(0x0605_4b50u32.to_le_bytes());
+        out.extend([0, 0, 0, 0, 1, 0, 1, 0]);
+        out.extend((cd_size as u32).to_le_bytes());
+        out.extend((cd_offset as u32).to_le_bytes());
+        out.extend([0, 0]);
+        out
+    }
+
+    /// A tar header block with the size field `size`.
+    fn tar_header(name: &str, kind: u8, size: [u8; 12]) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..136].copy_from_slice(&size);
+        header[148..156].fill(b' ');
+        header[156] = kind;
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        header
+    }
+
+    /// Writes `bytes` to a temporary file named `name`.
+    fn write(name: &str, bytes: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tinyserve-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn open(name: &str, bytes: &[u8]) -> io::Result<Archive> {
+        let path = write(name, bytes);
+        let archive = Archive::open(&path);
+        fs::remove_file(&path).unwrap();
+        archive
+    }
+
+    #[test]
+    fn inflates_entries_as_they_are_sent() {
+        let data: Vec<u8> = (0..300_000u32)
+            .flat_map(|i| format!("{} ", i % 977).into_bytes())
+            .collect();
+        let path = write("site.zip", &zip("big.txt", &data));
+        let archive = Archive::open(&path).unwrap();
+        let Contents::Reader { len, .. } = archive.open(Path::new("big.txt")).unwrap() else {
+            panic!("deflated entries should be streamed");
+        };
+        assert_eq!(len, data.len() as u64);
+
+        let server = TestServer::with(|b| b.vfs(archive)).unwrap();
+        assert_eq!(server.get("/big.txt").unwrap().body, data);
+        let resp = server
+            .request("GET", "/big.txt", &[("Range", "bytes=700000-700099")], b"")
+            .unwrap();
+        assert_eq!(resp.status, 206);
+        assert_eq!(resp.body, &data[700_000..700_100]);
+        let resp = server
+            .request(
+                "GET",
+                "/big.txt",
+                &[("Range", "bytes=10-19,900000-900009")],
+                b"",
+            )
+            .unwrap();
+        assert_eq!(resp.status, 206);
+        let body = String::from_utf8_lossy(&resp.body);
+        assert!(body.contains(std::str::from_utf8(&data[10..20]).unwrap()));
+        assert!(body.contains(std::str::from_utf8(&data[900_000..900_010]).unwrap()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_ranges_of_stored_entries_in_place() {
+        let data: Vec<u8> = (0..2000u32)
+            .flat_map(|i| format!("{i:05} ").into_bytes())
+            .collect();
+        let mut tar = tar_header("a.txt", b'0', *b"00000027340\0"); // 12000 bytes
+        tar.extend(&data);
+        tar.resize(tar.len().next_multiple_of(512) + 1024, 0);
+        let path = write("site.tar", &tar);
+        let archive = Archive::open(&path).unwrap();
+
+        let server = TestServer::with(|b| b.vfs(archive)).unwrap();
+        let resp = server
+            .request("GET", "/a.txt", &[("Range", "bytes=0-5,6000-6005")], b"")
+            .unwrap();
+        assert_eq!(resp.status, 206);
+        let body = String::from_utf8_lossy(&resp.body);
+        assert!(body.contains("Content-Range: bytes 0-5/12000\r\n\r\n00000 "));
+        assert!(body.contains("Content-Range: bytes 6000-6005/12000\r\n\r\n01000 "));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_archives_over_the_limits() {
+        let path = write("limits.zip", &zip("a/b/c/big.txt", &[b'x'; 10_000]));
+        let limits = |max_bytes, max_depth| Limits {
+            max_bytes,
+            max_depth,
+        };
+        assert!(Archive::open_with(&path, limits(10_000, 3)).is_ok());
+        let err = Archive::open_with(&path, limits(9_999, 0)).err().unwrap();
+        assert_eq!(err.to_string(), "archive contents too large");
+        let err = Archive::open_with(&path, limits(0, 2)).err().unwrap();
+        assert_eq!(err.to_string(), "archive entry nested too deep");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_oversized_tar_headers() {
+        let mut huge = [0x80; 12];
+        huge[1..].fill(0xff);
+        let err = open("a.tar", &tar_header("a", b'0', huge)).err().unwrap();
+        assert_eq!(err.to_string(), "tar entry out of bounds");
+
+        let mut tar = tar_header("././@LongLink", b'L', *b"00010000000\0");
+        tar.resize(tar.len() + 3 * 1024 * 1024, b'a');
+        let err = open("b.tar", &tar).err().unwrap();
+        assert_eq!(err.to_string(), "tar header too large");
+    }
+}
@@ -0,0 +1,160 @@
+//! Assets compiled into the binary.
+//!
+//! [`embed!`](crate::embed) includes files listed by name; with the
+//! `rust-embed` feature, [`Embedded::from_rust_embed`] serves a folder
+//! embedded with `#[derive(RustEmbed)]`. Either way the bytes are served in
+//! place, without copying, and each file's `ETag` is a hash of its
+//! contents.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::tree::{Node, Tree};
+use super::{Contents, DirEntry, Metadata, Vfs};
+
+enum Data {
+    Static(&'static [u8]),
+    #[cfg_attr(not(feature = "rust-embed"), allow(dead_code))]
+    Shared(Arc<[u8]>),
+}
+
+struct Asset {
+    data: Data,
+    modified: Option<SystemTime>,
+    etag: String,
+}
+
+impl Asset {
+    fn bytes(&self) -> &[u8] {
+        match &self.data {
+            Data::Static(b) => b,
+            Data::Shared(b) => b,
+        }
+    }
+}
+
+impl Node for Asset {
+    fn len(&self) -> u64 {
+        self.bytes().len() as u64
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+pub struct Embedded {
+    tree: Tree<Asset>,
+    /// Stands in for modification times the assets don't carry.
+    started: SystemTime,
+}
+
+impl Default for Embedded {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn content_etag(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+impl Embedded {
+    pub fn new() -> Self {
+        let started = SystemTime::now();
+        Self {
+            tree: Tree::new(Some(started)),
+            started,
+        }
+    }
+
+    /// Adds `data` as the file `path` (e.g. `assets/app.js`).
+    pub fn file(mut self, path: &str, data: &'static [u8]) -> Self {
+        let asset = Asset {
+            etag: content_etag(data),
+            data: Data::Static(data),
+            modified: Some(self.started),
+        };
+        self.tree.add_file(path, asset);
+        self
+    }
+
+    /// Every file of a `#[derive(RustEmbed)]` folder.
+    #[cfg(feature = "rust-embed")]
+    pub fn from_rust_embed<A: rust_embed::RustEmbed>() -> Self {
+        use std::borrow::Cow;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut embedded = Self::new();
+        for name in A::iter() {
+            let Some(file) = A::get(&name) else { continue };
+            let hash = file.metadata.sha256_hash();
+            let etag: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+            let modified = file
+                .metadata
+                .last_modified()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .or(Some(embedded.started));
+            let data = match file.data {
+                Cow::Borrowed(b) => Data::Static(b),
+                // Debug builds read the folder at run time.
+                Cow::Owned(b) => Data::Shared(b.into()),
+            };
+            let asset = Asset {
+                data,
+                modified,
+                etag: format!("\"{etag}\""),
+            };
+            embedded.tree.add_file(&name, asset);
+        }
+        embedded
+    }
+}
+
+impl Vfs for Embedded {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.tree.metadata(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        Ok(match &self.tree.get(path)?.data {
+            Data::Static(b) => Contents::Static(b),
+            Data::Shared(b) => Contents::Bytes(Arc::clone(b)),
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.tree.read_dir(path)
+    }
+
+    fn etag(&self, path: &Path, _meta: &Metadata) -> Option<String> {
+        self.tree.get(path).ok().map(|asset| asset.etag.clone())
+    }
+}
+
+/// Builds an [`Embedded`](crate::vfs::Embedded) backend from files under a
+/// directory of the calling crate, read at compile time:
+///
+/// ```ignore
+/// let assets = tinyserve::embed!("web/dist" => ["index.html", "assets/app.js"]);
+/// let handle = tinyserve::Server::builder().vfs(assets).start()?;
+/// ```
+///
+/// The directory is relative to the calling crate's `Cargo.toml`, and each
+/// file is served under its name relative to it.
+#[macro_export]
+macro_rules! embed {
+    ($dir:literal => [$($file:literal),* $(,)?]) => {
+        $crate::vfs::Embedded::new()
+            $(.file(
+                $file,
+                include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $file)),
+            ))*
+    };
+}
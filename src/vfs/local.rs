@@ -0,0 +1,59 @@
+//! The local filesystem below a root directory.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{Contents, DirEntry, Metadata, Vfs};
+
+pub struct LocalFs {
+    /// Canonical, so `contains` can compare resolved paths against it.
+    root: PathBuf,
+}
+
+impl LocalFs {
+    /// Serves `root`, which must exist.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            root: root.as_ref().canonicalize()?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Vfs for LocalFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(self.root.join(path)).map(|m| Metadata::from(&m))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        File::open(self.root.join(path)).map(Contents::File)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.root.join(path))? {
+            let entry = entry?;
+            let Ok(meta) = entry.metadata() else { continue };
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                meta: Metadata::from(&meta),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(path))
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.root
+            .join(path)
+            .canonicalize()
+            .is_ok_and(|p| p.starts_with(&self.root))
+    }
+}
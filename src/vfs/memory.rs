@@ -0,0 +1,190 @@
+//! Files held in memory, added and replaced while the server runs.
+//!
+//! Directories are implied by the files below them.
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Component, Path};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::SystemTime;
+
+use super::{Contents, DirEntry, Metadata, Vfs};
+
+struct File {
+    data: Arc<[u8]>,
+    modified: SystemTime,
+    etag: String,
+}
+
+#[derive(Default)]
+pub struct MemoryFs {
+    /// Keyed by `/`-separated path without a leading slash.
+    files: RwLock<BTreeMap<String, File>>,
+}
+
+/// `path` as a key, or `None` if it names nothing that could be stored.
+fn key(path: &Path) -> Option<String> {
+    let mut key = String::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(segment) => {
+                if !key.is_empty() {
+                    key.push('/');
+                }
+                key.push_str(segment.to_str()?);
+            }
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(key)
+}
+
+fn not_found() -> io::Error {
+    io::ErrorKind::NotFound.into()
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read_files(&self) -> RwLockReadGuard<'_, BTreeMap<String, File>> {
+        self.files.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds or replaces the file at `path` (e.g. `assets/app.js`).
+    pub fn insert(&self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) -> io::Result<()> {
+        let key = key(path.as_ref())
+            .filter(|k| !k.is_empty())
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let data: Arc<[u8]> = data.into().into();
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let file = File {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            data,
+            modified: SystemTime::now(),
+        };
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        let dir = format!("{key}/");
+        if files
+            .range(dir.clone()..)
+            .next()
+            .is_some_and(|(k, _)| k.starts_with(&dir))
+        {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        let mut parents = key.match_indices('/').map(|(i, _)| &key[..i]);
+        if parents.any(|parent| files.contains_key(parent)) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        files.insert(key, file);
+        Ok(())
+    }
+
+    /// Removes the file at `path`, or everything below it if it is a
+    /// directory. Returns whether anything was removed.
+    pub fn remove(&self, path: impl AsRef<Path>) -> bool {
+        let Some(key) = key(path.as_ref()) else {
+            return false;
+        };
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        let before = files.len();
+        let dir = format!("{key}/");
+        files.retain(|k, _| !(key.is_empty() || k == &key || k.starts_with(&dir)));
+        files.len() != before
+    }
+
+    pub fn clear(&self) {
+        self.files
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let key = key(path).ok_or_else(not_found)?;
+        let files = self.read_files();
+        if let Some(file) = files.get(&key) {
+            return Ok(Metadata {
+                is_dir: false,
+                is_file: true,
+                len: file.data.len() as u64,
+                modified: Some(file.modified),
+            });
+        }
+        let prefix = if key.is_empty() { key } else { key + "/" };
+        // The directory is as new as its newest file.
+        let modified = files
+            .range(prefix.clone()..)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .map(|(_, f)| f.modified)
+            .max();
+        if modified.is_none() && !prefix.is_empty() {
+            return Err(not_found());
+        }
+        Ok(Metadata {
+            is_dir: true,
+            is_file: false,
+            len: 0,
+            modified,
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        let key = key(path).ok_or_else(not_found)?;
+        match self.read_files().get(&key) {
+            Some(file) => Ok(Contents::Bytes(Arc::clone(&file.data))),
+            None if self.metadata(path).is_ok() => Err(io::ErrorKind::IsADirectory.into()),
+            None => Err(not_found()),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let meta = self.metadata(path)?;
+        if !meta.is_dir {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        let key = key(path).ok_or_else(not_found)?;
+        let prefix = if key.is_empty() { key } else { key + "/" };
+        let files = self.read_files();
+        let mut entries: Vec<DirEntry> = Vec::new();
+        for (k, file) in files
+            .range(prefix.clone()..)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+        {
+            let rest = &k[prefix.len()..];
+            let (name, is_dir) = match rest.split_once('/') {
+                Some((dir, _)) => (dir, true),
+                None => (rest, false),
+            };
+            // Keys are sorted, so a directory's files are contiguous.
+            if let Some(last) = entries.last_mut()
+                && last.name == name
+            {
+                last.meta.modified = last.meta.modified.max(Some(file.modified));
+                continue;
+            }
+            entries.push(DirEntry {
+                name: name.to_owned(),
+                meta: Metadata {
+                    is_dir,
+                    is_file: !is_dir,
+                    len: if is_dir { 0 } else { file.data.len() as u64 },
+                    modified: Some(file.modified),
+                },
+            });
+        }
+        Ok(entries)
+    }
+
+    fn etag(&self, path: &Path, _meta: &Metadata) -> Option<String> {
+        let key = key(path)?;
+        self.read_files().get(&key).map(|f| f.etag.clone())
+    }
+}
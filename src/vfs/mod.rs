@@ -0,0 +1,260 @@
+//! Virtual filesystem: the storage the static file handlers read from.
+//!
+//! Request handling resolves URL paths to paths under the served root and
+//! asks the [`Vfs`] for metadata, directory entries and contents, so other
+//! backends can stand in for the local filesystem. Paths handed to a backend
+//! are relative to its root, with the root itself being the empty path.
+//!
+//! Only reads go through the VFS. Uploads, WebDAV changes, FastCGI, log
+//! tailing and change notifications work on the local filesystem.
+
+mod archive;
+mod embedded;
+mod local;
+mod memory;
+mod remote;
+#[cfg(feature = "s3")]
+mod s3;
+mod tree;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub use archive::{Archive, Limits as ArchiveLimits};
+pub use embedded::Embedded;
+pub use local::LocalFs;
+pub use memory::MemoryFs;
+pub use remote::Remote;
+
+/// What a backend knows about a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// A regular file (not a directory, device or socket).
+    pub is_file: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl From<&std::fs::Metadata> for Metadata {
+    fn from(meta: &std::fs::Metadata) -> Self {
+        Self {
+            is_dir: meta.is_dir(),
+            is_file: meta.is_file(),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
+}
+
+/// One entry of a directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub meta: Metadata,
+}
+
+/// The contents of an opened file.
+pub enum Contents {
+    /// An open file, sent with `sendfile` or `mmap` where enabled.
+    File(File),
+    /// `len` bytes of `file` starting at `offset`, such as an entry stored
+    /// uncompressed in an archive.
+    Region {
+        file: File,
+        offset: u64,
+        len: u64,
+    },
+    Bytes(Arc<[u8]>),
+    /// Bytes compiled into the binary.
+    Static(&'static [u8]),
+    /// `len` bytes produced as they are read, such as a deflated archive
+    /// entry; reaching a range means reading past what comes before it.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        len: u64,
+    },
+}
+
+impl Contents {
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Contents::File(mut file) => {
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+            Contents::Region {
+                mut file,
+                offset,
+                len,
+            } => {
+                let mut buf = Vec::new();
+                file.seek(SeekFrom::Start(offset))?;
+                file.take(len).read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+            Contents::Bytes(bytes) => Ok(bytes.to_vec()),
+            Contents::Static(bytes) => Ok(bytes.to_vec()),
+            Contents::Reader { reader, len } => {
+                let mut buf = Vec::new();
+                reader.take(len).read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// `len` bytes of a file starting at `offset`, read and seeked as if they
+/// were the whole file.
+pub struct Window {
+    file: File,
+    offset: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Window {
+    pub fn new(mut file: File, offset: u64, len: u64) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            file,
+            offset,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for Window {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = usize::try_from(self.len.saturating_sub(self.pos)).unwrap_or(usize::MAX);
+        let want = buf.len().min(left);
+        let n = self.file.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Window {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let pos = match to {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        self.file
+            .seek(SeekFrom::Start(self.offset.saturating_add(pos)))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+/// A reader that can only seek forward, by reading and dropping what it
+/// skips; enough to send ranges in ascending order from a stream.
+pub struct Forward<R> {
+    reader: R,
+    pos: u64,
+}
+
+impl<R: Read> Forward<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, pos: 0 }
+    }
+}
+
+impl<R: Read> Read for Forward<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for Forward<R> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let pos = match to {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(_) => None,
+        }
+        .filter(|&pos| pos >= self.pos)
+        .ok_or(io::ErrorKind::Unsupported)?;
+        let skip = pos - self.pos;
+        if io::copy(&mut self.take(skip), &mut io::sink())? < skip {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(pos)
+    }
+}
+
+/// A read-only file tree served by the file handlers.
+pub trait Vfs: Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    fn open(&self, path: &Path) -> io::Result<Contents>;
+
+    /// Entries of a directory, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.open(path)?.into_bytes()
+    }
+
+    /// Whether `path`, which exists, really lies inside the tree; the local
+    /// filesystem refuses symlinks pointing outside the root.
+    fn contains(&self, path: &Path) -> bool {
+        let _ = path;
+        true
+    }
+
+    /// An entity tag for `path` (quoted, as sent), when the backend knows a
+    /// better validator than modification time and length, such as a content
+    /// hash.
+    fn etag(&self, path: &Path, meta: &Metadata) -> Option<String> {
+        let _ = (path, meta);
+        None
+    }
+
+    /// Drops what the backend itself cached for `path` and everything below
+    /// it, such as copies pulled from an origin.
+    fn invalidate(&self, path: &Path) {
+        let _ = path;
+    }
+}
+
+/// Lets a backend be shared, e.g. to keep adding files to a [`MemoryFs`]
+/// while it is served.
+impl<T: Vfs + ?Sized> Vfs for Arc<T> {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        (**self).metadata(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        (**self).open(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        (**self).read_dir(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        (**self).contains(path)
+    }
+
+    fn etag(&self, path: &Path, meta: &Metadata) -> Option<String> {
+        (**self).etag(path, meta)
+    }
+
+    fn invalidate(&self, path: &Path) {
+        (**self).invalidate(path)
+    }
+}
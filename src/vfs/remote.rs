@@ -0,0 +1,810 @@
+//! A pull-through cache of an upstream HTTP origin or S3 bucket.
+//!
+//! Files are fetched on first request and kept in a local cache directory,
+//! then served from there like local files. After `ttl` an entry is
+//! revalidated with the origin's `ETag` or `Last-Modified`; if the origin
+//! can't be reached, the cached copy keeps being served. With
+//! [`honor_cache_control`](Remote::honor_cache_control), the origin's
+//! `Cache-Control` or `Expires` decides how long instead, as for a shared
+//! cache. [`Vfs::invalidate`] purges entries from memory and disk.
+//!
+//! `http://` origins always work. `https://` origins need the `https`
+//! feature and are verified against the Mozilla root certificates.
+//! `s3://bucket[/prefix]` origins need the `s3` feature; requests are
+//! signed with the credentials in the environment (see [`s3`](super::s3)),
+//! and AWS itself is reached over `https://`.
+//!
+//! Concurrent requests for a path that needs the origin share one request
+//! to it ([coalesced](crate::coalesce)).
+//!
+//! Origins have no directory listings. A path is a directory only if the
+//! origin redirects it to the same path with a trailing slash.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::{Value, json};
+
+use crate::coalesce::Flights;
+use crate::http::body::Framing;
+use crate::http::request::percent_encode_path;
+use crate::http::response::SERVER;
+use crate::http::{client, date};
+use crate::log;
+
+#[cfg(feature = "s3")]
+use super::s3;
+use super::tree;
+use super::{Contents, DirEntry, Metadata, Vfs};
+
+/// How long to wait on the origin.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Shortest freshness taken from the origin, so one request (which looks a
+/// file up several times) asks the origin only once.
+const MIN_FRESH: Duration = Duration::from_secs(1);
+
+/// A file as last seen at the origin.
+#[derive(Clone, Debug)]
+struct Object {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    len: u64,
+}
+
+#[derive(Clone, Debug)]
+enum Lookup {
+    Missing,
+    Dir,
+    File(Object),
+}
+
+struct Checked {
+    at: Instant,
+    /// How long the lookup stays fresh.
+    ttl: Duration,
+    lookup: Lookup,
+}
+
+/// A connection to the origin.
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+pub struct Remote {
+    /// `host:port` to connect to.
+    addr: String,
+    /// Value of the `Host` header.
+    host: String,
+    #[cfg(feature = "https")]
+    tls: bool,
+    #[cfg(feature = "s3")]
+    signer: Option<s3::Signer>,
+    /// Path prefix on the origin, without a trailing slash.
+    prefix: String,
+    cache_dir: PathBuf,
+    ttl: Duration,
+    cache_control: bool,
+    checked: Mutex<HashMap<String, Checked>>,
+    /// Origin requests in progress, by key.
+    fetches: Flights<String, Lookup>,
+}
+
+/// What a response from the origin said about one path.
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    reader: BufReader<Box<dyn Stream>>,
+    framing: Option<Framing>,
+}
+
+impl Reply {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl Read for Reply {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.framing {
+            Some(framing) => framing.read(&mut self.reader, buf),
+            None => self.reader.read(buf),
+        }
+    }
+}
+
+fn hash(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Remote {
+    /// Where files of `origin` are cached when no directory is given.
+    pub fn default_cache_dir(origin: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tinyserve-origin-{:016x}", hash(origin)))
+    }
+
+    /// Pulls from `origin` (`http://host[:port][/prefix]`, `https://...` or
+    /// `s3://bucket[/prefix]`), caching files in `cache_dir`, or in a
+    /// directory under the system temp dir if `None`.
+    pub fn new(origin: &str, cache_dir: Option<PathBuf>, ttl: Duration) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned());
+        let cache_dir = cache_dir.unwrap_or_else(|| Self::default_cache_dir(origin));
+        if origin.starts_with("s3://") {
+            #[cfg(feature = "s3")]
+            return Self::s3(origin, cache_dir, ttl);
+            #[cfg(not(feature = "s3"))]
+            return Err(invalid("s3:// origins need the s3 feature"));
+        }
+        let (tls, rest) = if let Some(rest) = origin.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = origin.strip_prefix("https://") {
+            if cfg!(not(feature = "https")) {
+                return Err(invalid("https:// origins need the https feature"));
+            }
+            (true, rest)
+        } else {
+            return Err(invalid(
+                "only http://, https:// and s3:// origins are supported",
+            ));
+        };
+        let (authority, prefix) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if authority.is_empty() {
+            return Err(invalid("origin URL has no host"));
+        }
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, p)| !p.contains(']'));
+        let addr = match (has_port, tls) {
+            (true, _) => authority.to_owned(),
+            (false, false) => format!("{authority}:80"),
+            (false, true) => format!("{authority}:443"),
+        };
+        #[cfg(feature = "https")]
+        if tls {
+            tls::check(authority)?;
+        }
+        fs::create_dir_all(&cache_dir)?;
+        Ok(Self {
+            addr,
+            host: authority.to_owned(),
+            #[cfg(feature = "https")]
+            tls,
+            #[cfg(feature = "s3")]
+            signer: None,
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            cache_dir,
+            ttl,
+            cache_control: false,
+            checked: Mutex::new(HashMap::new()),
+            fetches: Flights::default(),
+        })
+    }
+
+    /// Pulls from the bucket named by `origin` (`s3://bucket[/prefix]`).
+    #[cfg(feature = "s3")]
+    fn s3(origin: &str, cache_dir: PathBuf, ttl: Duration) -> io::Result<Self> {
+        let rest = &origin["s3://".len()..];
+        let (bucket, prefix) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if bucket.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "origin URL has no bucket",
+            ));
+        }
+        let url = format!("{}{prefix}", s3::endpoint(bucket, &s3::region()));
+        let mut remote = Self::new(&url, Some(cache_dir), ttl)?;
+        remote.signer = s3::Signer::from_env();
+        Ok(remote)
+    }
+
+    /// Takes freshness from the origin's `Cache-Control` (`s-maxage`,
+    /// `max-age`, `no-cache`) or `Expires`, falling back to `ttl` when it
+    /// gives none. `no-store` and `private` responses are revalidated like
+    /// `no-cache` ones, at most once a second, and not kept across restarts.
+    pub fn honor_cache_control(mut self, on: bool) -> Self {
+        self.cache_control = on;
+        self
+    }
+
+    /// How long what `reply` said stays fresh.
+    fn freshness(&self, reply: &Reply) -> Duration {
+        if !self.cache_control {
+            return self.ttl;
+        }
+        self.origin_freshness(reply).max(MIN_FRESH)
+    }
+
+    fn origin_freshness(&self, reply: &Reply) -> Duration {
+        let directives = cache_control(reply.header("cache-control").unwrap_or_default());
+        let has = |name: &str| directives.iter().any(|(n, _)| n == name);
+        if has("no-store") || has("private") || has("no-cache") {
+            return Duration::ZERO;
+        }
+        let seconds = |name: &str| {
+            directives
+                .iter()
+                .find(|(n, _)| n == name)
+                .and_then(|(_, v)| v.as_deref()?.parse::<u64>().ok())
+        };
+        let age = reply
+            .header("age")
+            .and_then(|a| a.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        if let Some(max_age) = seconds("s-maxage").or_else(|| seconds("max-age")) {
+            return Duration::from_secs(max_age.saturating_sub(age));
+        }
+        if let Some(expires) = reply.header("expires") {
+            // An invalid date, such as `0`, means already expired.
+            let expires = date::parse(expires).unwrap_or(SystemTime::UNIX_EPOCH);
+            let now = reply
+                .header("date")
+                .and_then(date::parse)
+                .unwrap_or_else(SystemTime::now);
+            return expires.duration_since(now).unwrap_or_default();
+        }
+        self.ttl
+    }
+
+    /// Whether `reply` may be kept across restarts.
+    fn storable(&self, reply: &Reply) -> bool {
+        !self.cache_control
+            || !cache_control(reply.header("cache-control").unwrap_or_default())
+                .iter()
+                .any(|(n, _)| n == "no-store" || n == "private")
+    }
+
+    fn body_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(format!("{:016x}", hash(key)))
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(format!("{:016x}.json", hash(key)))
+    }
+
+    /// The object cached on disk for `key` by an earlier run, if any.
+    fn load(&self, key: &str) -> Option<Object> {
+        let meta: Value = serde_json::from_slice(&fs::read(self.meta_path(key)).ok()?).ok()?;
+        if meta["path"].as_str() != Some(key) {
+            return None;
+        }
+        let len = meta["len"].as_u64()?;
+        if fs::metadata(self.body_path(key)).ok()?.len() != len {
+            return None;
+        }
+        Some(Object {
+            etag: meta["etag"].as_str().map(str::to_owned),
+            last_modified: meta["lastModified"].as_str().map(str::to_owned),
+            len,
+        })
+    }
+
+    fn forget(&self, key: &str) {
+        let _ = fs::remove_file(self.body_path(key));
+        let _ = fs::remove_file(self.meta_path(key));
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        let stream = client::connect(&self.addr, TIMEOUT)?;
+        #[cfg(feature = "https")]
+        if self.tls {
+            return tls::wrap(stream, &self.host);
+        }
+        Ok(Box::new(stream))
+    }
+
+    fn request(&self, key: &str, cached: Option<&Object>) -> io::Result<Reply> {
+        let mut stream = self.connect()?;
+        let path = format!("{}/{}", self.prefix, percent_encode_path(key));
+        let mut head = format!(
+            "GET {path} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {SERVER}\r\n\
+             Accept-Encoding: identity\r\nConnection: close\r\n",
+            self.host
+        );
+        #[cfg(feature = "s3")]
+        if let Some(signer) = &self.signer {
+            for (name, value) in signer.headers(&self.host, &path, SystemTime::now()) {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        if let Some(etag) = cached.and_then(|o| o.etag.as_deref()) {
+            head.push_str(&format!("If-None-Match: {etag}\r\n"));
+        } else if let Some(modified) = cached.and_then(|o| o.last_modified.as_deref()) {
+            head.push_str(&format!("If-Modified-Since: {modified}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad origin response"))?;
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        let mut reply = Reply {
+            status,
+            headers,
+            reader,
+            framing: None,
+        };
+        if reply
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+        {
+            reply.framing = Some(Framing::Chunked { remaining: 0 });
+        } else if let Some(len) = reply.header("content-length").and_then(|l| l.parse().ok()) {
+            reply.framing = Some(Framing::Length(len));
+        }
+        Ok(reply)
+    }
+
+    /// Stores a `200` response body for `key`.
+    fn store(&self, key: &str, mut reply: Reply) -> io::Result<Object> {
+        static SEQ: AtomicU64 = AtomicU64::new(0);
+        let tmp = self.cache_dir.join(format!(
+            ".tmp-{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = (|| {
+            let mut file = File::create(&tmp)?;
+            let len = io::copy(&mut reply, &mut file)?;
+            file.sync_data()?;
+            let object = Object {
+                etag: reply.header("etag").map(str::to_owned),
+                last_modified: reply.header("last-modified").map(str::to_owned),
+                len,
+            };
+            if self.storable(&reply) {
+                let meta = json!({
+                    "path": key,
+                    "etag": object.etag,
+                    "lastModified": object.last_modified,
+                    "len": len,
+                });
+                fs::write(self.meta_path(key), meta.to_string())?;
+            } else {
+                let _ = fs::remove_file(self.meta_path(key));
+            }
+            fs::rename(&tmp, self.body_path(key))?;
+            Ok(object)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    /// Asks the origin about `key`, revalidating `cached`; also returns how
+    /// long the answer stays fresh.
+    fn fetch(&self, key: &str, cached: Option<Object>) -> io::Result<(Lookup, Duration)> {
+        let reply = self.request(key, cached.as_ref())?;
+        let ttl = self.freshness(&reply);
+        let lookup = match (reply.status, cached) {
+            (200, _) => self.store(key, reply).map(Lookup::File),
+            (304, Some(object)) => Ok(Lookup::File(object)),
+            (404 | 410, _) => {
+                self.forget(key);
+                Ok(Lookup::Missing)
+            }
+            (301 | 302 | 307 | 308, _)
+                if reply
+                    .header("location")
+                    .is_some_and(|l| l.ends_with(&format!("{key}/"))) =>
+            {
+                Ok(Lookup::Dir)
+            }
+            (status, _) => Err(io::Error::other(format!(
+                "origin answered {status} for /{key}"
+            ))),
+        }?;
+        Ok((lookup, ttl))
+    }
+
+    fn lookup(&self, path: &Path) -> io::Result<Lookup> {
+        let key = path
+            .to_str()
+            .and_then(tree::key)
+            .ok_or(io::ErrorKind::NotFound)?;
+        if key.is_empty() {
+            return Ok(Lookup::Dir);
+        }
+        let fresh = |key: &str| {
+            let checked = self.checked.lock().unwrap_or_else(|e| e.into_inner());
+            checked
+                .get(key)
+                .filter(|c| c.at.elapsed() < c.ttl)
+                .map(|c| c.lookup.clone())
+        };
+        if let Some(lookup) = fresh(&key) {
+            return Ok(lookup);
+        }
+        self.fetches.run(&key, || match fresh(&key) {
+            // Fetched by a flight that landed just before this one began.
+            Some(lookup) => Ok(lookup),
+            None => self.refresh(&key),
+        })
+    }
+
+    /// Asks the origin about `key` and records the answer.
+    fn refresh(&self, key: &str) -> io::Result<Lookup> {
+        let lock = || self.checked.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = lock().get(key).map(|c| c.lookup.clone());
+        let cached = match previous {
+            Some(Lookup::File(object)) => Some(object),
+            Some(_) => None,
+            None => self.load(key),
+        };
+        let (lookup, ttl) = match self.fetch(key, cached.clone()) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let Some(object) = cached else { return Err(e) };
+                log::warn(&format!("serving cached /{key}: {e}"));
+                (Lookup::File(object), self.ttl)
+            }
+        };
+        lock().insert(
+            key.to_owned(),
+            Checked {
+                at: Instant::now(),
+                ttl,
+                lookup: lookup.clone(),
+            },
+        );
+        Ok(lookup)
+    }
+}
+
+impl Vfs for Remote {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.lookup(path)? {
+            Lookup::Missing => Err(io::ErrorKind::NotFound.into()),
+            Lookup::Dir => Ok(Metadata {
+                is_dir: true,
+                is_file: false,
+                len: 0,
+                modified: None,
+            }),
+            Lookup::File(object) => Ok(Metadata {
+                is_dir: false,
+                is_file: true,
+                len: object.len,
+                modified: object.last_modified.as_deref().and_then(date::parse),
+            }),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Contents> {
+        match self.lookup(path)? {
+            Lookup::File(_) => {
+                let key = path.to_str().and_then(tree::key).unwrap_or_default();
+                File::open(self.body_path(&key)).map(Contents::File)
+            }
+            Lookup::Dir => Err(io::ErrorKind::IsADirectory.into()),
+            Lookup::Missing => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn read_dir(&self, _path: &Path) -> io::Result<Vec<DirEntry>> {
+        // Origins can't be listed.
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    fn etag(&self, path: &Path, _meta: &Metadata) -> Option<String> {
+        match self.lookup(path).ok()? {
+            Lookup::File(object) => object.etag.filter(|e| !e.starts_with("W/")),
+            _ => None,
+        }
+    }
+
+    fn invalidate(&self, path: &Path) {
+        let Some(prefix) = path.to_str().and_then(tree::key) else {
+            return;
+        };
+        let below = |key: &str| {
+            prefix.is_empty()
+                || key == prefix
+                || key
+                    .strip_prefix(&prefix)
+                    .is_some_and(|r| r.starts_with('/'))
+        };
+        self.checked
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !below(key));
+        let Ok(entries) = fs::read_dir(&self.cache_dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !name.to_string_lossy().ends_with(".json") {
+                continue;
+            }
+            let key = fs::read(entry.path())
+                .ok()
+                .and_then(|data| serde_json::from_slice::<Value>(&data).ok())
+                .and_then(|meta| meta["path"].as_str().map(str::to_owned));
+            if let Some(key) = key.filter(|k| below(k)) {
+                self.forget(&key);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "https")]
+mod tls {
+    use std::io;
+    use std::net::TcpStream;
+    use std::sync::{Arc, OnceLock};
+
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+    use super::Stream;
+    use crate::Error;
+
+    /// A failure to set up TLS, as the I/O error the origin code passes on.
+    fn failure(message: String) -> io::Error {
+        io::Error::other(Error::Tls(message))
+    }
+
+    fn config() -> io::Result<Arc<ClientConfig>> {
+        static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+        if let Some(config) = CONFIG.get() {
+            return Ok(Arc::clone(config));
+        }
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| failure(format!("cannot set up TLS: {e}")))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Arc::clone(CONFIG.get_or_init(|| Arc::new(config))))
+    }
+
+    /// The name the certificate of `authority` (`host[:port]`) must carry.
+    fn server_name(authority: &str) -> io::Result<ServerName<'static>> {
+        let host = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => host,
+            _ => authority,
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        ServerName::try_from(host)
+            .map(|name| name.to_owned())
+            .map_err(|e| {
+                failure(format!(
+                    "`{host}` cannot be checked against a certificate: {e}"
+                ))
+            })
+    }
+
+    /// Fails now, rather than on the first request, if TLS to `authority`
+    /// cannot be set up.
+    pub(super) fn check(authority: &str) -> io::Result<()> {
+        config()?;
+        server_name(authority).map(drop)
+    }
+
+    /// Starts TLS on `stream` to `authority`.
+    pub(super) fn wrap(stream: TcpStream, authority: &str) -> io::Result<Box<dyn Stream>> {
+        let conn = ClientConnection::new(config()?, server_name(authority)?)
+            .map_err(|e| failure(format!("TLS to {authority}: {e}")))?;
+        Ok(Box::new(StreamOwned::new(conn, stream)))
+    }
+}
+
+/// The directives of a `Cache-Control` value, names lowercased, with their
+/// arguments unquoted.
+fn cache_control(header: &str) -> Vec<(String, Option<String>)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let (name, value) = match item.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"').to_owned())),
+                None => (item, None),
+            };
+            let name = name.trim().to_ascii_lowercase();
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    /// An origin serving `a.txt` (tagged `"v1"`) and a `dir/`, recording
+    /// each request line, conditional header and `Authorization` it gets.
+    fn origin() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/site", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if request.is_empty()
+                        || line.starts_with("If-None-Match")
+                        || line.starts_with("Authorization")
+                    {
+                        request.push_str(line.trim_end());
+                        request.push(' ');
+                    }
+                    line.clear();
+                }
+                let request = request.trim_end().to_owned();
+                let reply = if request.starts_with("GET /site/a.txt ") {
+                    if request.ends_with("If-None-Match: \"v1\"") {
+                        "304 Not Modified\r\nETag: \"v1\"\r\nContent-Length: 0".to_owned()
+                    } else {
+                        "200 OK\r\nETag: \"v1\"\r\nContent-Length: 5\r\n\r\nhello".to_owned()
+                    }
+                } else if request.starts_with("GET /site/dir ") {
+                    "301 Moved\r\nLocation: /site/dir/\r\nContent-Length: 0".to_owned()
+                } else {
+                    "404 Not Found\r\nContent-Length: 0".to_owned()
+                };
+                log.lock().unwrap().push(request);
+                let reply = if reply.contains("\r\n\r\n") {
+                    format!("HTTP/1.1 {reply}")
+                } else {
+                    format!("HTTP/1.1 {reply}\r\n\r\n")
+                };
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        (url, seen)
+    }
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tinyserve-remote-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn fetches_once_while_fresh() {
+        let (url, seen) = origin();
+        let dir = cache_dir("fresh");
+        let remote = Remote::new(&url, Some(dir.clone()), Duration::from_secs(60)).unwrap();
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+        assert!(remote.metadata(Path::new("dir")).unwrap().is_dir);
+        let missing = remote.metadata(Path::new("missing.txt")).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "GET /site/a.txt HTTP/1.1",
+                "GET /site/dir HTTP/1.1",
+                "GET /site/missing.txt HTTP/1.1"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn revalidates_stale_copies_and_outlives_the_origin() {
+        let (url, seen) = origin();
+        let dir = cache_dir("stale");
+        let remote = Remote::new(&url, Some(dir.clone()), Duration::ZERO).unwrap();
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+        assert_eq!(
+            seen.lock().unwrap()[1],
+            "GET /site/a.txt HTTP/1.1 If-None-Match: \"v1\""
+        );
+
+        // A later run finds the copy on disk and serves it with the origin
+        // gone.
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let gone = format!("http://{}/site", closed.local_addr().unwrap());
+        drop(closed);
+        let remote = Remote::new(&gone, Some(dir.clone()), Duration::ZERO).unwrap();
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+
+        remote.invalidate(Path::new(""));
+        assert!(remote.read(Path::new("a.txt")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_unknown_schemes() {
+        let dir = cache_dir("scheme");
+        let err = Remote::new("ftp://example.test/", Some(dir), Duration::ZERO)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn speaks_tls_to_https_origins() {
+        let (url, seen) = origin();
+        let dir = cache_dir("https");
+        let url = url.replace("http://", "https://");
+        let remote = Remote::new(&url, Some(dir.clone()), Duration::ZERO).unwrap();
+        // The plain HTTP origin can't make sense of the handshake.
+        assert!(remote.read(Path::new("a.txt")).is_err());
+        assert!(seen.lock().unwrap().iter().all(|r| !r.contains("a.txt")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn unusable_tls_names_fail_at_startup() {
+        let dir = cache_dir("tls-name");
+        let err = crate::Server::builder()
+            .bind("127.0.0.1:0")
+            .option("originCache", dir.to_string_lossy().into_owned())
+            .root("https://not a host/")
+            .start()
+            .err()
+            .unwrap();
+        match err {
+            crate::Error::Tls(message) => assert!(message.contains("`not a host`"), "{message}"),
+            other => panic!("not a TLS error: {other:?}"),
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn signs_requests_to_buckets() {
+        let (url, seen) = origin();
+        let dir = cache_dir("s3");
+        let mut remote = Remote::new(&url, Some(dir.clone()), Duration::ZERO).unwrap();
+        remote.signer = Some(s3::Signer::new("eu-west-1", "AKID", "secret"));
+        assert_eq!(remote.read(Path::new("a.txt")).unwrap(), b"hello");
+        let request = seen.lock().unwrap()[0].clone();
+        assert!(
+            request.starts_with(
+                "GET /site/a.txt HTTP/1.1 Authorization: AWS4-HMAC-SHA256 Credential=AKID/"
+            ),
+            "{request}"
+        );
+        assert!(request.contains(
+            "/eu-west-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, "
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
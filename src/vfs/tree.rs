@@ -0,0 +1,146 @@
+//! The index shared by read-only backends built up front: files keyed by
+//! `/`-separated path, plus every directory they imply.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::{DirEntry, Metadata};
+
+/// What the index needs to know about a file.
+pub(super) trait Node {
+    fn len(&self) -> u64;
+    fn modified(&self) -> Option<SystemTime>;
+}
+
+pub(super) struct Tree<T> {
+    files: BTreeMap<String, T>,
+    /// Every directory, explicit or implied, except the root.
+    dirs: BTreeMap<String, Option<SystemTime>>,
+    /// Of the root directory.
+    modified: Option<SystemTime>,
+}
+
+/// A member name as a key: no leading `./` or `/`, and `None` for names
+/// that would climb out of the tree.
+pub(super) fn key(name: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in name.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s => segments.push(s),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+fn path_key(path: &Path) -> io::Result<String> {
+    path.to_str()
+        .and_then(key)
+        .ok_or_else(|| io::ErrorKind::NotFound.into())
+}
+
+fn dir_meta(modified: Option<SystemTime>) -> Metadata {
+    Metadata {
+        is_dir: true,
+        is_file: false,
+        len: 0,
+        modified,
+    }
+}
+
+impl<T: Node> Tree<T> {
+    pub(super) fn new(modified: Option<SystemTime>) -> Self {
+        Self {
+            files: BTreeMap::new(),
+            dirs: BTreeMap::new(),
+            modified,
+        }
+    }
+
+    /// Adds a file; directories above it take its modification time if it
+    /// is newer.
+    pub(super) fn add_file(&mut self, name: &str, node: T) {
+        let Some(key) = key(name).filter(|k| !k.is_empty()) else {
+            return;
+        };
+        for (i, _) in key.match_indices('/') {
+            let dir = self.dirs.entry(key[..i].to_owned()).or_default();
+            *dir = (*dir).max(node.modified());
+        }
+        self.files.insert(key, node);
+    }
+
+    pub(super) fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) {
+        let Some(key) = key(name).filter(|k| !k.is_empty()) else {
+            return;
+        };
+        for (i, _) in key.match_indices('/') {
+            self.dirs.entry(key[..i].to_owned()).or_default();
+        }
+        let dir = self.dirs.entry(key).or_default();
+        *dir = (*dir).max(modified);
+    }
+
+    pub(super) fn get(&self, path: &Path) -> io::Result<&T> {
+        self.files
+            .get(&path_key(path)?)
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    pub(super) fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let key = path_key(path)?;
+        if let Some(node) = self.files.get(&key) {
+            return Ok(Metadata {
+                is_dir: false,
+                is_file: true,
+                len: node.len(),
+                modified: node.modified(),
+            });
+        }
+        if key.is_empty() {
+            return Ok(dir_meta(self.modified));
+        }
+        match self.dirs.get(&key) {
+            Some(&modified) => Ok(dir_meta(modified)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    pub(super) fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let key = path_key(path)?;
+        if !key.is_empty() && !self.dirs.contains_key(&key) {
+            return Err(if self.files.contains_key(&key) {
+                io::ErrorKind::NotADirectory.into()
+            } else {
+                io::ErrorKind::NotFound.into()
+            });
+        }
+        let prefix = if key.is_empty() { key } else { key + "/" };
+        let child = |k: &str| {
+            k.strip_prefix(prefix.as_str())
+                .filter(|rest| !rest.contains('/'))
+                .map(str::to_owned)
+        };
+        let dirs = self.dirs.iter().filter_map(|(k, &modified)| {
+            Some(DirEntry {
+                name: child(k)?,
+                meta: dir_meta(modified),
+            })
+        });
+        let files = self.files.iter().filter_map(|(k, node)| {
+            Some(DirEntry {
+                name: child(k)?,
+                meta: Metadata {
+                    is_dir: false,
+                    is_file: true,
+                    len: node.len(),
+                    modified: node.modified(),
+                },
+            })
+        });
+        Ok(dirs.chain(files).collect())
+    }
+}
@@ -0,0 +1,161 @@
+//! Polling file watcher for the served tree.
+//!
+//! The tree is rescanned every interval and compared with the previous scan.
+//! Polling keeps tinyserve free of platform notification APIs and behaves the
+//! same on network filesystems, at the cost of latency up to one interval.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+/// A change to one file or directory.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Path relative to the watched root.
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+impl Change {
+    /// The path as a URL path (`/a/b.txt`).
+    pub fn url_path(&self) -> String {
+        let mut url = String::new();
+        for part in self.path.components() {
+            url.push('/');
+            url.push_str(&part.as_os_str().to_string_lossy());
+        }
+        url
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    is_dir: bool,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+type Snapshot = HashMap<PathBuf, Stamp>;
+
+/// Fans out changes under a root to any number of subscribers.
+pub struct Watcher {
+    subscribers: Mutex<Vec<Sender<Change>>>,
+}
+
+impl Watcher {
+    /// Starts polling `root` every `interval` on a background thread.
+    pub fn spawn(root: PathBuf, interval: Duration, include_hidden: bool) -> Arc<Self> {
+        let watcher = Arc::new(Self {
+            subscribers: Mutex::new(Vec::new()),
+        });
+        let weak = Arc::downgrade(&watcher);
+        thread::Builder::new()
+            .name("tinyserve-watch".into())
+            .spawn(move || {
+                let mut previous = scan(&root, include_hidden);
+                loop {
+                    thread::sleep(interval);
+                    let Some(watcher) = weak.upgrade() else { break };
+                    let current = scan(&root, include_hidden);
+                    for change in diff(&previous, &current) {
+                        watcher.publish(change);
+                    }
+                    previous = current;
+                }
+            })
+            .expect("spawning watcher thread");
+        watcher
+    }
+
+    /// Receives every change detected from now on.
+    pub fn subscribe(&self) -> Receiver<Change> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn publish(&self, change: Change) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(change.clone()).is_ok());
+    }
+}
+
+fn scan(root: &Path, include_hidden: bool) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(rel) = stack.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&rel)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !include_hidden && name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else { continue };
+            let path = rel.join(&name);
+            if meta.is_dir() {
+                stack.push(path.clone());
+            }
+            let stamp = Stamp {
+                is_dir: meta.is_dir(),
+                modified: meta.modified().ok(),
+                len: meta.len(),
+            };
+            snapshot.insert(path, stamp);
+        }
+    }
+    snapshot
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (path, stamp) in new {
+        let kind = match old.get(path) {
+            None => ChangeKind::Create,
+            Some(prev) if prev.is_dir != stamp.is_dir => ChangeKind::Create,
+            Some(prev) if !stamp.is_dir && prev != stamp => ChangeKind::Modify,
+            Some(_) => continue,
+        };
+        changes.push(Change {
+            kind,
+            path: path.clone(),
+            is_dir: stamp.is_dir,
+        });
+    }
+    for (path, stamp) in old {
+        if !new.contains_key(path) {
+            changes.push(Change {
+                kind: ChangeKind::Delete,
+                path: path.clone(),
+                is_dir: stamp.is_dir,
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
@@ -0,0 +1,611 @@
+//! WebDAV class 1 (RFC 4918): PROPFIND, PROPPATCH, COPY and MOVE on top of
+//! the writable-mode PUT, DELETE and MKCOL, so the served tree can be
+//! mounted by Finder, Explorer or davfs2.
+//!
+//! Only live properties derived from the filesystem are reported; dead
+//! properties are not stored, so PROPPATCH answers 403 for each property.
+//! Locking (class 2) is not implemented.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::files::{self, listing, resolve};
+use crate::http::request::{normalize_path, percent_decode, percent_encode_path};
+use crate::http::response::{escape_html, reason};
+use crate::http::{Request, Response, date, mime};
+use crate::server::Context;
+use crate::vfs::Metadata;
+use crate::writable;
+
+/// Largest request body accepted for PROPFIND/PROPPATCH.
+const MAX_XML_BODY: u64 = 64 * 1024;
+
+/// Methods added by WebDAV, for `Allow`.
+pub const METHODS: &str = "PROPFIND, PROPPATCH, COPY, MOVE";
+
+/// A property name: namespace URI and local name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PropName {
+    ns: String,
+    local: String,
+}
+
+enum PropRequest {
+    All,
+    Names,
+    Some(Vec<PropName>),
+}
+
+const LIVE_PROPS: &[&str] = &[
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "resourcetype",
+    "supportedlock",
+];
+
+fn read_xml_body(req: &mut Request) -> io::Result<String> {
+    let mut body = Vec::new();
+    (&mut req.body)
+        .take(MAX_XML_BODY + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_XML_BODY {
+        return Err(io::ErrorKind::FileTooLarge.into());
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Iterates the start tags of `xml` as (qualified name, attribute text, depth).
+fn start_tags(xml: &str) -> Vec<(String, String, usize)> {
+    let mut tags = Vec::new();
+    let mut depth = 0usize;
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find('>') else { break };
+        let tag = &rest[..close];
+        rest = &rest[close + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if tag.starts_with('/') {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        tags.push((name.to_owned(), attrs.to_owned(), depth));
+        if !self_closing {
+            depth += 1;
+        }
+    }
+    tags
+}
+
+/// Namespace URI of a `prefix` declared anywhere in the document
+/// (`xmlns:prefix="uri"`, or `xmlns="uri"` for the empty prefix).
+fn namespace(tags: &[(String, String, usize)], prefix: &str) -> String {
+    let attr = if prefix.is_empty() {
+        "xmlns=".to_owned()
+    } else {
+        format!("xmlns:{prefix}=")
+    };
+    tags.iter()
+        .rev()
+        .find_map(|(_, attrs, _)| {
+            let at = attrs.find(&attr)?;
+            let value = &attrs[at + attr.len()..];
+            let quote = value.chars().next()?;
+            value[1..].split(quote).next().map(str::to_owned)
+        })
+        .unwrap_or_default()
+}
+
+fn split_qname(qname: &str) -> (&str, &str) {
+    qname.split_once(':').unwrap_or(("", qname))
+}
+
+fn parse_propfind(xml: &str) -> PropRequest {
+    let tags = start_tags(xml);
+    let mut props = Vec::new();
+    let mut prop_depth = None;
+    for (qname, _, depth) in &tags {
+        let (prefix, local) = split_qname(qname);
+        match (local, prop_depth) {
+            ("allprop", _) => return PropRequest::All,
+            ("propname", _) => return PropRequest::Names,
+            ("prop", None) => prop_depth = Some(*depth),
+            (_, Some(d)) if *depth == d + 1 => props.push(PropName {
+                ns: namespace(&tags, prefix),
+                local: local.to_owned(),
+            }),
+            (_, Some(d)) if *depth <= d => prop_depth = None,
+            _ => {}
+        }
+    }
+    if props.is_empty() {
+        PropRequest::All
+    } else {
+        PropRequest::Some(props)
+    }
+}
+
+fn live_prop(ctx: &Context, name: &str, path: &Path, meta: &Metadata) -> Option<String> {
+    let value = match name {
+        "displayname" => escape_html(&path.file_name()?.to_string_lossy()),
+        "getcontentlength" if !meta.is_dir => meta.len.to_string(),
+        "getcontenttype" if !meta.is_dir => mime::from_path(path).to_owned(),
+        "getetag" if !meta.is_dir => escape_html(&files::etag(ctx, path, meta)?),
+        "getlastmodified" => date::format(files::modified(ctx, meta)?),
+        "resourcetype" if meta.is_dir => "<D:collection/>".to_owned(),
+        "resourcetype" | "supportedlock" => String::new(),
+        _ => return None,
+    };
+    Some(format!("<D:{name}>{value}</D:{name}>"))
+}
+
+fn propstat(props: &str, status: u16) -> String {
+    format!(
+        "<D:propstat><D:prop>{props}</D:prop><D:status>HTTP/1.1 {status} {}</D:status></D:propstat>",
+        reason(status)
+    )
+}
+
+fn response_xml(
+    ctx: &Context,
+    href: &str,
+    path: &Path,
+    meta: &Metadata,
+    request: &PropRequest,
+) -> String {
+    let mut out = format!(
+        "<D:response><D:href>{}</D:href>",
+        escape_html(&percent_encode_path(href))
+    );
+    match request {
+        PropRequest::All => {
+            let props: String = LIVE_PROPS
+                .iter()
+                .filter_map(|name| live_prop(ctx, name, path, meta))
+                .collect();
+            out.push_str(&propstat(&props, 200));
+        }
+        PropRequest::Names => {
+            let props: String = LIVE_PROPS
+                .iter()
+                .map(|name| format!("<D:{name}/>"))
+                .collect();
+            out.push_str(&propstat(&props, 200));
+        }
+        PropRequest::Some(names) => {
+            let mut found = String::new();
+            let mut missing = String::new();
+            for name in names {
+                let value = (name.ns == "DAV:")
+                    .then(|| live_prop(ctx, &name.local, path, meta))
+                    .flatten();
+                match value {
+                    Some(value) => found.push_str(&value),
+                    None if name.ns == "DAV:" => missing.push_str(&format!("<D:{}/>", name.local)),
+                    None => missing.push_str(&format!(
+                        "<X:{} xmlns:X=\"{}\"/>",
+                        name.local,
+                        escape_html(&name.ns)
+                    )),
+                }
+            }
+            if !found.is_empty() {
+                out.push_str(&propstat(&found, 200));
+            }
+            if !missing.is_empty() {
+                out.push_str(&propstat(&missing, 404));
+            }
+        }
+    }
+    out.push_str("</D:response>");
+    out
+}
+
+fn multistatus(responses: &str) -> Response {
+    Response::bytes(
+        207,
+        "application/xml; charset=utf-8",
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">{responses}</D:multistatus>\n"
+        ),
+    )
+}
+
+/// `PROPFIND` with `Depth: 0` or `1`. Like a `GET`, `Depth: 1` lists a
+/// collection's members only with `showDir` on, and leaves out those that
+/// lead outside the root.
+pub fn propfind(ctx: &Context, req: &mut Request) -> Response {
+    let depth = req
+        .headers
+        .get("depth")
+        .unwrap_or("infinity")
+        .trim()
+        .to_owned();
+    if depth.eq_ignore_ascii_case("infinity") {
+        return Response::error_with(403, "Depth: infinity is not supported");
+    }
+    let xml = match read_xml_body(req) {
+        Ok(xml) => xml,
+        Err(_) => return Response::error(413),
+    };
+    let request = parse_propfind(&xml);
+
+    let show_hidden = ctx.config.bool("showHidden");
+    let Some(path) = resolve::resolve(&ctx.root, &req.path, show_hidden) else {
+        return Response::error(404);
+    };
+    let meta = match files::metadata(ctx, &path) {
+        Ok(meta) if files::contains(ctx, &path) => meta,
+        Ok(_) => return Response::error(404),
+        Err(e) => return files::io_error(&e),
+    };
+    let mut href = req.path.clone();
+    if meta.is_dir && !href.ends_with('/') {
+        href.push('/');
+    }
+    let mut body = response_xml(ctx, &href, &path, &meta, &request);
+    if meta.is_dir && depth == "1" && ctx.config.bool("showDir") {
+        let entries = match listing::read_entries(ctx, &path, show_hidden) {
+            Ok(entries) => entries,
+            Err(resp) => return resp,
+        };
+        for entry in entries {
+            let child = path.join(&entry.name);
+            let Ok(child_meta) = files::metadata(ctx, &child) else {
+                continue;
+            };
+            if !files::contains(ctx, &child) {
+                continue;
+            }
+            let slash = if entry.is_dir { "/" } else { "" };
+            let child_href = format!("{href}{}{slash}", entry.name);
+            body.push_str(&response_xml(
+                ctx,
+                &child_href,
+                &child,
+                &child_meta,
+                &request,
+            ));
+        }
+    }
+    multistatus(&body)
+}
+
+/// `PROPPATCH`: dead properties are not stored, so every change is refused.
+pub fn proppatch(ctx: &Context, req: &mut Request) -> Response {
+    let xml = match read_xml_body(req) {
+        Ok(xml) => xml,
+        Err(_) => return Response::error(413),
+    };
+    let Some(path) = resolve::resolve(&ctx.root, &req.path, ctx.config.bool("showHidden")) else {
+        return Response::error(404);
+    };
+    if !path.exists() {
+        return Response::error(404);
+    }
+    let tags = start_tags(&xml);
+    let mut props = String::new();
+    let mut prop_depth = None;
+    for (qname, _, depth) in &tags {
+        let (prefix, local) = split_qname(qname);
+        match prop_depth {
+            None if local == "prop" => prop_depth = Some(*depth),
+            Some(d) if *depth == d + 1 => props.push_str(&format!(
+                "<X:{local} xmlns:X=\"{}\"/>",
+                escape_html(&namespace(&tags, prefix))
+            )),
+            Some(d) if *depth <= d => prop_depth = (local == "prop").then_some(*depth),
+            _ => {}
+        }
+    }
+    let href = escape_html(&percent_encode_path(&req.path));
+    multistatus(&format!(
+        "<D:response><D:href>{href}</D:href>{}</D:response>",
+        propstat(&props, 403)
+    ))
+}
+
+/// Decodes the `Destination` header to a path on this server, normalized
+/// like a request path; `None` if it is missing, malformed or on another
+/// server.
+pub fn destination(req: &Request) -> Option<String> {
+    let raw = req.headers.get("destination")?;
+    let path = match raw.split_once("://") {
+        Some((_, rest)) if !raw.starts_with('/') => {
+            let (authority, path) = rest.split_at(rest.find('/')?);
+            if !same_host(req, authority) {
+                return None;
+            }
+            path
+        }
+        _ => raw,
+    };
+    let path = path.split('?').next()?;
+    let path = String::from_utf8(percent_decode(path)?).ok()?;
+    Some(normalize_path(&path))
+}
+
+/// Whether `Destination` is an absolute URL naming a server other than the
+/// one the request was sent to.
+fn elsewhere(req: &Request) -> bool {
+    let Some(raw) = req.headers.get("destination") else {
+        return false;
+    };
+    match raw.split_once("://") {
+        Some((_, rest)) if !raw.starts_with('/') => {
+            let authority = rest.split(['/', '?']).next().unwrap_or_default();
+            !same_host(req, authority)
+        }
+        _ => false,
+    }
+}
+
+fn same_host(req: &Request, authority: &str) -> bool {
+    req.headers
+        .get("host")
+        .is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
+
+/// Copies a file, or a directory with (if `recursive`) its contents.
+/// Symlinks are copied as links rather than followed, so a link inside the
+/// root never brings in what it points to.
+fn copy_tree(from: &Path, to: &Path, recursive: bool) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        return copy_link(from, to);
+    }
+    if !meta.is_dir() {
+        return fs::copy(from, to).map(drop);
+    }
+    fs::create_dir(to)?;
+    if recursive {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()), true)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_link(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "cannot copy symbolic links",
+    ))
+}
+
+fn remove_any(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// `COPY` and `MOVE`, honoring `Destination`, `Overwrite` and, for
+/// collections, `Depth: 0` on COPY.
+pub fn copy_or_move(ctx: &Context, req: &Request) -> Response {
+    let moving = req.method == "MOVE";
+    if elsewhere(req) {
+        return Response::error_with(502, "the destination is on another server");
+    }
+    let Some(dest_url) = destination(req) else {
+        return Response::error_with(400, "missing or invalid Destination header");
+    };
+    let src = match writable::target(ctx, req.path.trim_end_matches('/')) {
+        Ok(path) if !path.exists() => return Response::error(404),
+        Ok(path) if !resolve::is_within(&ctx.root, &path) => return Response::error(403),
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    let dest = match writable::target(ctx, dest_url.trim_end_matches('/')) {
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    if dest == src || dest.starts_with(&src) {
+        return Response::error_with(403, "destination is the source or lies inside it");
+    }
+    if !src.is_dir() && !writable::extension_allowed(ctx, &dest_url) {
+        return Response::error_with(415, "file type not accepted");
+    }
+
+    let overwrite = !req
+        .headers
+        .get("overwrite")
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("F"));
+    let dest_meta = fs::symlink_metadata(&dest).ok();
+    let existed = dest_meta.is_some();
+    if let Some(meta) = dest_meta {
+        if !overwrite {
+            return Response::error(412);
+        }
+        // A symlink is removed itself; anything else must be inside the root.
+        if !meta.file_type().is_symlink() && !resolve::is_within(&ctx.root, &dest) {
+            return Response::error(403);
+        }
+        if let Err(e) = remove_any(&dest) {
+            return files::io_error(&e);
+        }
+    }
+    let result = if moving {
+        fs::rename(&src, &dest)
+    } else {
+        let recursive = req.headers.get("depth").is_none_or(|d| d.trim() != "0");
+        copy_tree(&src, &dest, recursive)
+    };
+    match result {
+        Ok(()) if existed => Response::new(204),
+        Ok(()) => Response::new(201).header("Location", percent_encode_path(&dest_url)),
+        Err(e) => files::io_error(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test::{AUTH, TestServer};
+
+    fn server() -> TestServer {
+        TestServer::with_auth(|b| b.option("readOnly", false).option("webdav", true)).unwrap()
+    }
+
+    fn copy(server: &TestServer, from: &str, to: &str, extra: &[(&str, &str)]) -> u16 {
+        let dest = server.url(to);
+        let mut headers = vec![AUTH, ("Destination", dest.as_str())];
+        headers.extend_from_slice(extra);
+        server.request("COPY", from, &headers, b"").unwrap().status
+    }
+
+    #[test]
+    fn copies_and_moves_files() {
+        let server = server();
+        server.write("a.txt", "one").unwrap();
+        assert_eq!(copy(&server, "/a.txt", "/b.txt", &[]), 201);
+        assert_eq!(server.get("/b.txt").unwrap().text(), "one");
+        assert_eq!(
+            copy(&server, "/a.txt", "/b.txt", &[("Overwrite", "F")]),
+            412
+        );
+        assert_eq!(copy(&server, "/a.txt", "/b.txt", &[]), 204);
+
+        let dest = server.url("/c.txt");
+        let resp = server
+            .request("MOVE", "/a.txt", &[AUTH, ("Destination", &dest)], b"")
+            .unwrap();
+        assert_eq!(resp.status, 201);
+        assert_eq!(server.get("/a.txt").unwrap().status, 404);
+        assert_eq!(server.get("/c.txt").unwrap().text(), "one");
+    }
+
+    #[test]
+    fn refuses_a_destination_on_another_server() {
+        let server = server();
+        server.write("a.txt", "one").unwrap();
+        let copy_to = |dest: &str| {
+            let headers = [AUTH, ("Destination", dest)];
+            server
+                .request("COPY", "/a.txt", &headers, b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(copy_to("http://other.test/b.txt"), 502);
+        assert_eq!(server.get("/b.txt").unwrap().status, 404);
+        assert_eq!(copy_to("/b.txt"), 201);
+        assert_eq!(server.get("/b.txt").unwrap().text(), "one");
+    }
+
+    #[test]
+    fn copies_directories_unless_depth_zero() {
+        let server = server();
+        server.write("dir/inner.txt", "x").unwrap();
+        assert_eq!(copy(&server, "/dir/", "/all/", &[]), 201);
+        assert_eq!(server.get("/all/inner.txt").unwrap().text(), "x");
+        assert_eq!(copy(&server, "/dir/", "/empty/", &[("Depth", "0")]), 201);
+        assert_eq!(server.get("/empty/inner.txt").unwrap().status, 404);
+    }
+
+    #[test]
+    fn refuses_a_destination_inside_the_source() {
+        let server = server();
+        server.write("dir/inner.txt", "x").unwrap();
+        assert_eq!(copy(&server, "/dir/", "/dir/sub/", &[]), 403);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_copy_through_symlinks_leading_outside() {
+        use std::os::unix::fs::symlink;
+
+        let server = server();
+        let outside = TestServer::new().unwrap();
+        outside.write("secret.txt", "secret").unwrap();
+        let root = server.root().unwrap();
+        let outside_dir = outside.root().unwrap();
+
+        symlink(outside_dir, root.join("link")).unwrap();
+        assert_eq!(copy(&server, "/link", "/stolen/", &[]), 403);
+        assert!(!root.join("stolen").exists());
+
+        server.write("dir/plain.txt", "x").unwrap();
+        symlink(outside_dir, root.join("dir/nested")).unwrap();
+        assert_eq!(copy(&server, "/dir/", "/copy/", &[]), 201);
+        let copied = root.join("copy/nested");
+        assert!(
+            fs::symlink_metadata(&copied)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_link(&copied).unwrap(), outside_dir);
+        assert_eq!(server.get("/copy/nested/secret.txt").unwrap().status, 404);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn propfind_lists_members_like_a_listing_would() {
+        use std::os::unix::fs::symlink;
+
+        let outside = TestServer::new().unwrap();
+        outside.write("secret.txt", "secret").unwrap();
+        let propfind = |show_dir: bool| {
+            let server =
+                TestServer::with(|b| b.option("webdav", true).option("showDir", show_dir)).unwrap();
+            server.write("dir/plain.txt", "x").unwrap();
+            let root = server.root().unwrap();
+            symlink(
+                outside.root().unwrap().join("secret.txt"),
+                root.join("dir/leak.txt"),
+            )
+            .unwrap();
+            let resp = server
+                .request("PROPFIND", "/dir/", &[("Depth", "1")], b"")
+                .unwrap();
+            assert_eq!(resp.status, 207);
+            resp.text()
+        };
+
+        let listed = propfind(true);
+        assert!(
+            listed.contains("<D:href>/dir/plain.txt</D:href>"),
+            "{listed}"
+        );
+        assert!(!listed.contains("leak.txt"), "{listed}");
+
+        let unlisted = propfind(false);
+        assert!(unlisted.contains("<D:href>/dir/</D:href>"), "{unlisted}");
+        assert!(!unlisted.contains("plain.txt"), "{unlisted}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn overwriting_a_symlink_replaces_only_the_link() {
+        use std::os::unix::fs::symlink;
+
+        let server = server();
+        let outside = TestServer::new().unwrap();
+        outside.write("keep.txt", "keep").unwrap();
+        let root = server.root().unwrap();
+        symlink(outside.root().unwrap(), root.join("link")).unwrap();
+
+        server.write("dir/a.txt", "a").unwrap();
+        assert_eq!(copy(&server, "/dir/", "/link/", &[]), 204);
+        assert!(outside.root().unwrap().join("keep.txt").exists());
+        assert_eq!(server.get("/link/a.txt").unwrap().text(), "a");
+    }
+}
@@ -0,0 +1,490 @@
+//! Writable mode (`readOnly=false`): uploads via PUT and multipart POST,
+//! plus DELETE and MKCOL, which additionally require authentication.
+//!
+//! Files are written to a hidden temporary file in the destination directory
+//! and renamed into place once complete, so readers never observe a partial
+//! upload and a failed upload leaves the previous version intact. With
+//! `resumableUploads`, `PUT` keeps that file for a later resume instead; see
+//! [`resumable`].
+//!
+//! `PUT` and `DELETE` honor `If-Match`, `If-Unmodified-Since` and
+//! `If-None-Match: *`, answering `412` when the file is not the one the
+//! client last saw, so concurrent editors don't overwrite each other. The
+//! condition is checked again just before the new file is renamed into
+//! place, under a lock every write takes for its rename.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use serde_json::json;
+
+use crate::access;
+use crate::fastcgi;
+use crate::files::{self, resolve};
+use crate::http::body;
+use crate::http::multipart::{self, Multipart};
+use crate::http::request::percent_encode_path;
+use crate::http::{Headers, Request, Response, date};
+use crate::resumable;
+use crate::server::Context;
+use crate::ssi;
+use crate::vfs::Metadata;
+
+/// Held while a write checks its precondition and renames into place.
+static COMMIT: Mutex<()> = Mutex::new(());
+
+/// Whether a file may be written at `url_path`: its extension must be
+/// listed in `uploadExtensions`. An empty list accepts anything but the
+/// files the server would run, SSI pages and FastCGI scripts, while those
+/// are on.
+pub fn extension_allowed(ctx: &Context, url_path: &str) -> bool {
+    let allowed = ctx.config.list("uploadExtensions");
+    if allowed.is_empty() {
+        return !ssi::applies(ctx, Path::new(url_path)) && !fastcgi::runs(ctx, url_path);
+    }
+    let ext = Path::new(url_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    allowed
+        .iter()
+        .any(|a| a.trim_start_matches('.').eq_ignore_ascii_case(ext))
+}
+
+/// Writes `dest` atomically from `fill`, which receives the temporary file.
+pub fn write_atomic(
+    dest: &Path,
+    fill: impl FnOnce(&mut File) -> io::Result<u64>,
+) -> io::Result<u64> {
+    write_atomic_if(dest, fill, || Ok(())).map(|written| written.unwrap_or(0))
+}
+
+/// [`write_atomic`], but `check` runs just before the rename, both under
+/// [`lock`]; if it fails the write is discarded and `Ok(Err(..))` carries
+/// its response.
+fn write_atomic_if(
+    dest: &Path,
+    fill: impl FnOnce(&mut File) -> io::Result<u64>,
+    check: impl FnOnce() -> Result<(), Response>,
+) -> io::Result<Result<u64, Response>> {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let dir = dest.parent().unwrap_or(Path::new("."));
+    let tmp = dir.join(format!(
+        ".tinyserve-{}-{}.tmp",
+        process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&tmp).and_then(|mut file| {
+        let n = fill(&mut file)?;
+        file.sync_all()?;
+        Ok(n)
+    });
+    let committed = result.and_then(|n| {
+        let _guard = lock();
+        match check() {
+            Ok(()) => fs::rename(&tmp, dest).map(|()| Ok(n)),
+            Err(resp) => Ok(Err(resp)),
+        }
+    });
+    if !matches!(committed, Ok(Ok(_))) {
+        let _ = fs::remove_file(&tmp);
+    }
+    committed
+}
+
+/// Whether `headers` make a write conditional on the current file.
+pub(crate) fn conditional(headers: &Headers) -> bool {
+    ["if-match", "if-unmodified-since", "if-none-match"]
+        .iter()
+        .any(|name| headers.contains(name))
+}
+
+/// Serializes writes from their last precondition check to their commit.
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    COMMIT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Checks `If-Match`, `If-Unmodified-Since` and `If-None-Match` against the
+/// file now at `path`, failing with `412`.
+pub(crate) fn precondition(ctx: &Context, headers: &Headers, path: &Path) -> Result<(), Response> {
+    // Straight from disk: a cached stat may predate another client's write.
+    let meta = fs::metadata(path).ok().map(|m| Metadata::from(&m));
+    let etag = || meta.as_ref().and_then(|m| files::etag(ctx, path, m));
+    let failed = |why: &str| Err(Response::error_with(412, why));
+    if let Some(list) = headers.get("if-match") {
+        let matched = meta.is_some()
+            && (list.trim() == "*" || etag().is_some_and(|tag| strong_match(list, &tag)));
+        if !matched {
+            return failed("the file has changed");
+        }
+    } else if let Some(since) = headers.get("if-unmodified-since").and_then(date::parse)
+        && let Some(modified) = meta.as_ref().and_then(|m| files::modified(ctx, m))
+        && date::truncate(modified) > since
+    {
+        return failed("the file has changed");
+    }
+    if let Some(list) = headers.get("if-none-match")
+        && meta.is_some()
+        && (list.trim() == "*" || etag().is_some_and(|tag| files::etag_matches(list, &tag)))
+    {
+        return failed("the file already exists");
+    }
+    Ok(())
+}
+
+/// Strong comparison: weak tags never match.
+fn strong_match(list: &str, etag: &str) -> bool {
+    !etag.starts_with("W/") && list.split(',').any(|t| t.trim() == etag)
+}
+
+/// Copies at most `limit` bytes, failing with `FileTooLarge` beyond that.
+pub(crate) fn copy_limited(src: &mut dyn Read, dst: &mut dyn Write, limit: u64) -> io::Result<u64> {
+    let n = io::copy(&mut src.take(limit), dst)?;
+    if n == limit && src.read(&mut [0u8])? > 0 {
+        return Err(io::ErrorKind::FileTooLarge.into());
+    }
+    Ok(n)
+}
+
+pub(crate) fn write_error(e: &io::Error) -> Response {
+    match e.kind() {
+        _ if body::trailers_too_large(e) => Response::error(431),
+        io::ErrorKind::FileTooLarge => Response::error(413),
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Response::error(400),
+        io::ErrorKind::StorageFull => Response::error(507),
+        _ => files::io_error(e),
+    }
+}
+
+/// Resolves a write target: the path must map under the root and its parent
+/// directory must already exist.
+pub fn target(ctx: &Context, url_path: &str) -> Result<PathBuf, Response> {
+    let path = resolve::resolve(&ctx.root, url_path, ctx.config.bool("showHidden"))
+        .filter(|p| p != &ctx.root)
+        .ok_or_else(|| Response::error(403))?;
+    let parent = path.parent().unwrap_or(&ctx.root);
+    if !parent.is_dir() || !resolve::is_within(&ctx.root, parent) {
+        return Err(Response::error_with(409, "parent directory does not exist"));
+    }
+    Ok(path)
+}
+
+/// `PUT /path`: creates or replaces one file with the request body.
+pub fn put(ctx: &Context, req: &mut Request) -> Response {
+    if req.path.ends_with('/') {
+        return Response::error_with(405, "cannot PUT a directory");
+    }
+    let path = match target(ctx, &req.path) {
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    if !extension_allowed(ctx, &req.path) {
+        return Response::error_with(415, "file type not accepted");
+    }
+    if path.is_dir() {
+        return Response::error_with(409, "a directory exists at this path");
+    }
+    let limit = ctx.config.int("maxUploadSize");
+    let declared = req
+        .headers
+        .get("content-length")
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|n| n > limit) {
+        return Response::error(413);
+    }
+
+    let conditional = conditional(&req.headers);
+    if conditional && let Err(resp) = precondition(ctx, &req.headers, &path) {
+        return resp;
+    }
+
+    if let Some(uploads) = &ctx.uploads {
+        return resumable::put(ctx, req, uploads, &path);
+    }
+    let existed = path.exists();
+    let headers = &req.headers;
+    let body = &mut req.body;
+    let written = write_atomic_if(
+        &path,
+        |file| copy_limited(body, file, limit),
+        || match conditional {
+            true => precondition(ctx, headers, &path),
+            false => Ok(()),
+        },
+    );
+    match written {
+        Ok(Ok(_)) if existed => Response::new(204),
+        Ok(Ok(_)) => Response::new(201).header("Location", percent_encode_path(&req.path)),
+        Ok(Err(resp)) => resp,
+        Err(e) => write_error(&e),
+    }
+}
+
+/// Reduces a client-supplied file name to a safe single path segment.
+fn sanitize_filename(ctx: &Context, raw: &str) -> Option<String> {
+    let name = raw.rsplit(['/', '\\']).next()?.trim();
+    let hidden = name.starts_with('.') && !ctx.config.bool("showHidden");
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || hidden
+        || access::is_access_file(name)
+        || name.chars().any(char::is_control)
+    {
+        return None;
+    }
+    Some(name.to_owned())
+}
+
+/// `POST /dir/` with `multipart/form-data`: stores every file field in `dir`.
+pub fn post(ctx: &Context, req: &mut Request) -> Response {
+    let Some(dir) = resolve::resolve(&ctx.root, &req.path, ctx.config.bool("showHidden")) else {
+        return Response::error(403);
+    };
+    if !dir.is_dir() || !resolve::is_within(&ctx.root, &dir) {
+        return Response::error_with(405, "uploads must be posted to a directory");
+    }
+    let Some(boundary) = req
+        .headers
+        .get("content-type")
+        .and_then(multipart::boundary)
+    else {
+        return Response::error_with(415, "expected multipart/form-data");
+    };
+    let limit = ctx.config.int("maxUploadSize");
+    let base = if req.path.ends_with('/') {
+        req.path.clone()
+    } else {
+        format!("{}/", req.path)
+    };
+
+    let mut stored = Vec::new();
+    let mut parts = Multipart::new(&mut req.body, &boundary);
+    loop {
+        let part = match parts.next_part() {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(e) => return write_error(&e),
+        };
+        let Some(raw) = part.filename.as_deref().filter(|f| !f.is_empty()) else {
+            continue;
+        };
+        let Some(name) = sanitize_filename(ctx, raw) else {
+            return Response::error_with(400, &format!("invalid file name `{raw}`"));
+        };
+        if !extension_allowed(ctx, &format!("{base}{name}")) {
+            return Response::error_with(415, &format!("file type of `{name}` not accepted"));
+        }
+        let dest = dir.join(&name);
+        if dest.is_dir() {
+            return Response::error_with(409, &format!("`{name}` is a directory"));
+        }
+        if let Err(e) = write_atomic(&dest, |file| parts.copy_part(file, limit)) {
+            return write_error(&e);
+        }
+        stored.push(format!("{base}{name}"));
+    }
+
+    let from_form = req
+        .headers
+        .get("accept")
+        .is_some_and(|a| a.contains("text/html"));
+    if from_form {
+        Response::redirect(303, &percent_encode_path(&base))
+    } else {
+        Response::json(201, &json!({ "files": stored }))
+    }
+}
+
+/// `DELETE /path`: removes a file or an empty directory.
+pub fn delete(ctx: &Context, req: &Request) -> Response {
+    let path = match target(ctx, req.path.trim_end_matches('/')) {
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    let guard = conditional(&req.headers).then(lock);
+    if guard.is_some()
+        && let Err(resp) = precondition(ctx, &req.headers, &path)
+    {
+        return resp;
+    }
+    let meta = match fs::symlink_metadata(&path) {
+        Ok(meta) => meta,
+        Err(e) => return files::io_error(&e),
+    };
+    let removed = if meta.is_dir() {
+        fs::remove_dir(&path)
+    } else {
+        fs::remove_file(&path)
+    };
+    match removed {
+        Ok(()) => Response::new(204),
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {
+            Response::error_with(409, "directory is not empty")
+        }
+        Err(e) => files::io_error(&e),
+    }
+}
+
+/// `MKCOL /path/`: creates one directory whose parent already exists.
+pub fn mkcol(ctx: &Context, req: &mut Request) -> Response {
+    if req.body.read(&mut [0u8]).unwrap_or(0) > 0 {
+        return Response::error_with(415, "MKCOL does not take a body");
+    }
+    let path = match target(ctx, req.path.trim_end_matches('/')) {
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    match fs::create_dir(&path) {
+        Ok(()) => Response::new(201).header("Location", percent_encode_path(&req.path)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            Response::error_with(405, "a file or directory already exists at this path")
+        }
+        Err(e) => files::io_error(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{AUTH, TestServer};
+
+    fn server(etag: &str) -> TestServer {
+        TestServer::with_auth(|b| b.option("readOnly", false).option("etag", etag)).unwrap()
+    }
+
+    fn etag(server: &TestServer, path: &str) -> String {
+        let resp = server.request("HEAD", path, &[], b"").unwrap();
+        resp.header("etag").unwrap().to_owned()
+    }
+
+    fn put(server: &TestServer, path: &str, header: (&str, &str), body: &str) -> u16 {
+        server
+            .request("PUT", path, &[AUTH, header], body.as_bytes())
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn if_match_guards_against_lost_updates() {
+        let server = server("strong");
+        server.write("a.txt", "one").unwrap();
+        let seen = etag(&server, "/a.txt");
+        // Lengths differ, so the tags do even where timestamps are coarse.
+        assert!(put(&server, "/a.txt", ("If-Match", &seen), "second") < 300);
+        assert_eq!(put(&server, "/a.txt", ("If-Match", &seen), "third"), 412);
+        assert_eq!(server.get("/a.txt").unwrap().text(), "second");
+        let list = format!("\"other\", {}", etag(&server, "/a.txt"));
+        assert!(put(&server, "/a.txt", ("If-Match", &list), "four") < 300);
+        assert_eq!(put(&server, "/new.txt", ("If-Match", "*"), "x"), 412);
+        assert_eq!(server.get("/new.txt").unwrap().status, 404);
+    }
+
+    #[test]
+    fn weak_tags_never_match() {
+        let server = server("weak");
+        server.write("a.txt", "one").unwrap();
+        let seen = etag(&server, "/a.txt");
+        assert!(seen.starts_with("W/"));
+        assert_eq!(put(&server, "/a.txt", ("If-Match", &seen), "two"), 412);
+        assert!(put(&server, "/a.txt", ("If-Match", "*"), "two") < 300);
+    }
+
+    #[test]
+    fn if_none_match_star_only_creates() {
+        let server = server("strong");
+        assert!(put(&server, "/a.txt", ("If-None-Match", "*"), "one") < 300);
+        assert_eq!(put(&server, "/a.txt", ("If-None-Match", "*"), "two"), 412);
+        assert_eq!(server.get("/a.txt").unwrap().text(), "one");
+    }
+
+    #[test]
+    fn if_unmodified_since_compares_dates() {
+        let server = server("strong");
+        server.write("a.txt", "one").unwrap();
+        let past = ("If-Unmodified-Since", "Sat, 01 Jan 2000 00:00:00 GMT");
+        assert_eq!(put(&server, "/a.txt", past, "two"), 412);
+        let future = ("If-Unmodified-Since", "Fri, 01 Jan 2100 00:00:00 GMT");
+        assert!(put(&server, "/a.txt", future, "two") < 300);
+        assert_eq!(server.get("/a.txt").unwrap().text(), "two");
+    }
+
+    #[test]
+    fn delete_honors_preconditions() {
+        let server = server("strong");
+        server.write("a.txt", "one").unwrap();
+        let delete = |tag: &str| {
+            server
+                .request("DELETE", "/a.txt", &[AUTH, ("If-Match", tag)], b"")
+                .unwrap()
+                .status
+        };
+        assert_eq!(delete("\"stale\""), 412);
+        assert_eq!(server.get("/a.txt").unwrap().status, 200);
+        assert_eq!(delete(&etag(&server, "/a.txt")), 204);
+        assert_eq!(server.get("/a.txt").unwrap().status, 404);
+    }
+
+    #[test]
+    fn refuses_files_the_server_would_run() {
+        let server = TestServer::with_auth(|b| {
+            b.option("readOnly", false)
+                .option("authFor", "all")
+                .option("ssi", true)
+                .option("fastcgi", "127.0.0.1:9")
+        })
+        .unwrap();
+        let put = |path: &str| {
+            server
+                .request("PUT", path, &[AUTH], b"<!--#include virtual=\"/x\" -->")
+                .unwrap()
+                .status
+        };
+        assert_eq!(put("/page.shtml"), 415);
+        assert_eq!(put("/run.php"), 415);
+        assert!(put("/page.html") < 300);
+
+        let server = TestServer::with_auth(|b| {
+            b.option("readOnly", false)
+                .option("authFor", "all")
+                .option("ssi", true)
+                .option("uploadExtensions", "shtml")
+        })
+        .unwrap();
+        let resp = server.request("PUT", "/page.shtml", &[AUTH], b"x").unwrap();
+        assert!(resp.status < 300);
+    }
+
+    #[test]
+    fn uploads_with_too_many_trailers_are_refused() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        use crate::http::request::MAX_HEADERS;
+
+        let server = server("strong");
+        let put = |trailers: usize| {
+            let mut stream = TcpStream::connect(server.addr()).unwrap();
+            let (name, value) = AUTH;
+            let request = format!(
+                "PUT /t.txt HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{name}: {value}\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n{}\r\n",
+                server.addr(),
+                "X-Sum: 1\r\n".repeat(trailers)
+            );
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+        let refused = put(MAX_HEADERS + 1);
+        assert!(refused.starts_with("HTTP/1.1 431 "), "{refused}");
+        assert_eq!(server.get("/t.txt").unwrap().status, 404);
+        let stored = put(1);
+        assert!(stored.starts_with("HTTP/1.1 201 "), "{stored}");
+        assert_eq!(server.get("/t.txt").unwrap().text(), "hi");
+    }
+}